@@ -0,0 +1,172 @@
+//! Abbreviation definitions (`*[HTML]: HyperText Markup Language`)
+//!
+//! Definitions are collected from the source before parsing; every later
+//! occurrence of a defined term in document text is wrapped in
+//! `<abbr title="...">`. Code spans and code blocks are left untouched.
+
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Abbreviation {
+    pub term: String,
+    pub expansion: String,
+}
+
+/// Collect abbreviation definitions, returning them together with the source
+/// with definition lines removed (when `strip` is set). Lines inside fenced
+/// code blocks are never treated as definitions.
+pub fn collect_definitions(content: &str, strip: bool) -> (Vec<Abbreviation>, String) {
+    let mut abbreviations: Vec<Abbreviation> = Vec::new();
+    let mut output = String::with_capacity(content.len());
+    let mut fence: Option<String> = None;
+
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if let Some(marker) = &fence {
+            if trimmed.starts_with(marker.as_str()) {
+                fence = None;
+            }
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(trimmed[..3].to_string());
+        } else if let Some(abbr) = parse_definition(line) {
+            // Later definitions of the same term win
+            abbreviations.retain(|a| a.term != abbr.term);
+            abbreviations.push(abbr);
+            if strip {
+                continue;
+            }
+        }
+        output.push_str(line);
+    }
+
+    // Longest terms first so "HTML5" wins over "HTML"
+    abbreviations.sort_by(|a, b| b.term.len().cmp(&a.term.len()).then_with(|| a.term.cmp(&b.term)));
+    (abbreviations, output)
+}
+
+fn parse_definition(line: &str) -> Option<Abbreviation> {
+    let rest = line.trim_end().strip_prefix("*[")?;
+    let close = rest.find("]:")?;
+    let term = rest[..close].trim();
+    if term.is_empty() {
+        return None;
+    }
+    Some(Abbreviation {
+        term: term.to_string(),
+        expansion: rest[close + 2..].trim().to_string(),
+    })
+}
+
+/// Wrap abbreviation occurrences in text events with `<abbr>` elements
+pub fn apply<'a>(events: Vec<Event<'a>>, abbreviations: &[Abbreviation]) -> Vec<Event<'a>> {
+    if abbreviations.is_empty() {
+        return events;
+    }
+
+    let mut output = Vec::with_capacity(events.len());
+    let mut code_depth = 0usize;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                code_depth += 1;
+                output.push(event);
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                code_depth = code_depth.saturating_sub(1);
+                output.push(event);
+            }
+            Event::Text(text) if code_depth == 0 => wrap_text(&text, abbreviations, &mut output),
+            other => output.push(other),
+        }
+    }
+
+    output
+}
+
+fn wrap_text<'a>(text: &str, abbreviations: &[Abbreviation], output: &mut Vec<Event<'a>>) {
+    let mut plain_start = 0;
+    let mut i = 0;
+
+    while i < text.len() {
+        let at_boundary = text[..i].chars().next_back().is_none_or(|c| !c.is_alphanumeric());
+        let matched = if at_boundary {
+            abbreviations.iter().find(|abbr| {
+                text[i..].starts_with(abbr.term.as_str())
+                    && text[i + abbr.term.len()..].chars().next().is_none_or(|c| !c.is_alphanumeric())
+            })
+        } else {
+            None
+        };
+
+        match matched {
+            Some(abbr) => {
+                if plain_start < i {
+                    output.push(Event::Text(CowStr::from(text[plain_start..i].to_string())));
+                }
+                output.push(Event::InlineHtml(CowStr::from(format!(
+                    "<abbr title=\"{}\">",
+                    escape_attribute(&abbr.expansion)
+                ))));
+                output.push(Event::Text(CowStr::from(abbr.term.clone())));
+                output.push(Event::InlineHtml(CowStr::from("</abbr>")));
+                i += abbr.term.len();
+                plain_start = i;
+            }
+            None => {
+                i += text[i..].chars().next().map(char::len_utf8).unwrap_or(1);
+            }
+        }
+    }
+
+    if plain_start < text.len() {
+        output.push(Event::Text(CowStr::from(text[plain_start..].to_string())));
+    }
+}
+
+fn escape_attribute(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    fn render(content: &str, strip: bool) -> String {
+        let (abbreviations, source) = collect_definitions(content, strip);
+        let events = apply(Parser::new(&source).collect(), &abbreviations);
+        let mut out = String::new();
+        html::push_html(&mut out, events.into_iter());
+        out
+    }
+
+    #[test]
+    fn test_collect_definitions() {
+        let (abbrs, rest) = collect_definitions("Text\n*[HTML]: HyperText Markup Language\n*[W3C]:  World Wide Web Consortium\n", true);
+        assert_eq!(rest, "Text\n");
+        assert_eq!(abbrs.len(), 2);
+        assert_eq!(abbrs[0].term, "HTML");
+        assert_eq!(abbrs[1].expansion, "World Wide Web Consortium");
+    }
+
+    #[test]
+    fn test_wraps_occurrences() {
+        let html = render("The HTML spec, not HTMLX.\n\n*[HTML]: Hyper \"Text\"\n", true);
+        assert_eq!(
+            html,
+            "<p>The <abbr title=\"Hyper &quot;Text&quot;\">HTML</abbr> spec, not HTMLX.</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_skips_code_and_can_keep_definitions() {
+        let html = render("`HTML` and\n\n```\nHTML\n```\n\n*[HTML]: HyperText\n", false);
+        assert!(html.contains("<code>HTML</code>"));
+        assert!(html.contains("<pre><code>HTML\n</code></pre>"));
+        assert!(html.contains("*[<abbr title=\"HyperText\">HTML</abbr>]: HyperText"));
+    }
+}
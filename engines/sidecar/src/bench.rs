@@ -0,0 +1,373 @@
+//! `fastmd-sidecar bench [corpus]`: compare markdown engines on a corpus
+//!
+//! Every markdown document under the corpus directory is rendered by each
+//! engine fastmd can use: pulldown-cmark (the sidecar's), and markdown-rs
+//! and comrak (the wasm engine's alternatives), with extensions as close to
+//! the sidecar's as each allows. A serial pass times documents one at a
+//! time; a parallel pass spreads them over as many threads as the worker
+//! pool is configured for. Reported per engine: throughput of both passes,
+//! latency by document size, and how much HTML comes out.
+//!
+//! Only rendering is timed. Documents are read and their frontmatter
+//! stripped up front, and the rest of the transform pipeline (caching,
+//! highlighting, module wrapping) is left out, since it's the same whichever
+//! engine renders.
+
+use std::fmt::Write as _;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::limits;
+use crate::metrics::MethodStats;
+use crate::parallel::{self, ParallelConfig};
+use crate::scan::{self, ScanOptions};
+use crate::source;
+use crate::transform::{parser_options, split_frontmatter};
+
+/// Upper bounds (exclusive) of the document size buckets latency is
+/// reported by
+const SIZE_BUCKETS: &[(&str, usize)] = &[
+    ("<1KB", 1024),
+    ("1-10KB", 10 * 1024),
+    ("10-100KB", 100 * 1024),
+    (">=100KB", usize::MAX),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Engine {
+    Pulldown,
+    MarkdownRs,
+    Comrak,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Directory of markdown documents
+    #[arg(default_value = ".")]
+    pub corpus: PathBuf,
+    /// Engines to compare (default: all)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub engines: Vec<Engine>,
+    /// Times each pass renders the whole corpus
+    #[arg(long, default_value_t = 3)]
+    pub iterations: usize,
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchReport {
+    pub files: usize,
+    pub bytes: usize,
+    pub iterations: usize,
+    pub workers: usize,
+    pub engines: Vec<EngineReport>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineReport {
+    pub engine: Engine,
+    pub serial: Throughput,
+    pub parallel: Throughput,
+    /// Serial latency by document size; sizes the corpus has no documents
+    /// of are left out
+    pub latency: Vec<SizeLatency>,
+    /// HTML rendered from the whole corpus once
+    pub output_bytes: usize,
+    /// `output_bytes` over the corpus size
+    pub output_ratio: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Throughput {
+    pub elapsed_ms: f64,
+    pub files_per_sec: f64,
+    pub mb_per_sec: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SizeLatency {
+    pub size: &'static str,
+    pub files: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Each engine's options, built once so only rendering is timed
+struct Renderers {
+    pulldown: pulldown_cmark::Options,
+    markdown_rs: markdown::Options,
+    comrak: comrak::Options<'static>,
+}
+
+impl Renderers {
+    fn new() -> Self {
+        let mut markdown_rs = markdown::Options::gfm();
+        markdown_rs.compile.allow_dangerous_html = true;
+        // markdown-rs has no smart punctuation
+        let mut comrak = comrak::Options::default();
+        comrak.extension.table = true;
+        comrak.extension.footnotes = true;
+        comrak.extension.strikethrough = true;
+        comrak.extension.tasklist = true;
+        comrak.parse.smart = true;
+        comrak.render.unsafe_ = true;
+        Renderers {
+            pulldown: parser_options(false),
+            markdown_rs,
+            comrak,
+        }
+    }
+
+    fn render(&self, engine: Engine, markdown: &str) -> String {
+        match engine {
+            Engine::Pulldown => {
+                let mut html = String::with_capacity(markdown.len() * 3 / 2);
+                pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new_ext(markdown, self.pulldown));
+                html
+            }
+            // Only MDX syntax fails, and it's off
+            Engine::MarkdownRs => markdown::to_html_with_options(markdown, &self.markdown_rs).unwrap_or_default(),
+            Engine::Comrak => comrak::markdown_to_html(markdown, &self.comrak),
+        }
+    }
+}
+
+/// Run the benchmark and print its report
+pub fn run(args: &BenchArgs) -> Result<()> {
+    let report = bench(args)?;
+    let output = if args.json {
+        serde_json::to_string_pretty(&report)? + "\n"
+    } else {
+        format_report(&report)
+    };
+    print!("{}", output);
+    Ok(())
+}
+
+pub fn bench(args: &BenchArgs) -> Result<BenchReport> {
+    if args.iterations == 0 {
+        bail!("--iterations must be at least 1");
+    }
+    let patterns = ["**/*.md".to_string(), "**/*.markdown".to_string()];
+    let paths = scan::expand(&patterns, &args.corpus, &ScanOptions::default()).map_err(|e| anyhow!(e))?;
+    let documents = paths
+        .iter()
+        .map(|path| {
+            let source = source::read_source(path, limits::limits().max_input_bytes)
+                .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+            Ok(split_frontmatter(&source).map(|(_, body)| body).unwrap_or(source))
+        })
+        .collect::<Result<Vec<String>>>()?;
+    if documents.is_empty() {
+        bail!("No markdown documents in {}", args.corpus.display());
+    }
+    let bytes: usize = documents.iter().map(String::len).sum();
+
+    let workers = ParallelConfig::from_env().num_workers.unwrap_or_else(parallel::recommended_workers);
+    let threads = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .thread_name(|i| format!("fastmd-bench-{}", i))
+        .build()?;
+    let renderers = Renderers::new();
+    let engines = if args.engines.is_empty() {
+        Engine::value_variants().to_vec()
+    } else {
+        args.engines.clone()
+    };
+
+    let engines = engines
+        .into_iter()
+        .map(|engine| {
+            // Untimed, so allocations and lazily built tables are warm
+            let output_bytes: usize = documents.iter().map(|document| renderers.render(engine, document).len()).sum();
+
+            let mut by_size: Vec<MethodStats> = vec![MethodStats::default(); SIZE_BUCKETS.len()];
+            let mut serial = Duration::ZERO;
+            for _ in 0..args.iterations {
+                for document in &documents {
+                    let started = Instant::now();
+                    black_box(renderers.render(engine, black_box(document)));
+                    let elapsed = started.elapsed();
+                    serial += elapsed;
+                    by_size[size_bucket(document.len())].record(elapsed, true);
+                }
+            }
+
+            let started = Instant::now();
+            threads.install(|| {
+                for _ in 0..args.iterations {
+                    // markdown-rs and comrak options hold callbacks that
+                    // can't be shared between threads
+                    documents.par_iter().for_each_init(Renderers::new, |renderers, document| {
+                        black_box(renderers.render(engine, black_box(document)));
+                    });
+                }
+            });
+            let parallel = started.elapsed();
+
+            let throughput = |elapsed: Duration| {
+                let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+                let iterations = args.iterations as f64;
+                Throughput {
+                    elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+                    files_per_sec: documents.len() as f64 * iterations / secs,
+                    mb_per_sec: bytes as f64 * iterations / secs / (1024.0 * 1024.0),
+                }
+            };
+            let latency = SIZE_BUCKETS
+                .iter()
+                .zip(&by_size)
+                .filter(|(_, stats)| stats.calls > 0)
+                .map(|((size, _), stats)| {
+                    let summary = stats.summary();
+                    SizeLatency {
+                        size,
+                        files: stats.calls as usize / args.iterations,
+                        p50_ms: summary.p50_ms,
+                        p95_ms: summary.p95_ms,
+                        max_ms: summary.max_ms,
+                    }
+                })
+                .collect();
+            EngineReport {
+                engine,
+                serial: throughput(serial),
+                parallel: throughput(parallel),
+                latency,
+                output_bytes,
+                output_ratio: output_bytes as f64 / bytes.max(1) as f64,
+            }
+        })
+        .collect();
+
+    Ok(BenchReport {
+        files: documents.len(),
+        bytes,
+        iterations: args.iterations,
+        workers,
+        engines,
+    })
+}
+
+fn size_bucket(len: usize) -> usize {
+    SIZE_BUCKETS
+        .iter()
+        .position(|(_, limit)| len < *limit)
+        .unwrap_or(SIZE_BUCKETS.len() - 1)
+}
+
+/// The report as tables: throughput and output size, then p95 latency
+fn format_report(report: &BenchReport) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{} documents, {:.1} KB, {} iterations, {} workers\n",
+        report.files,
+        report.bytes as f64 / 1024.0,
+        report.iterations,
+        report.workers
+    );
+    let _ = writeln!(
+        out,
+        "{:<12} {:>12} {:>12} {:>14} {:>14} {:>8}",
+        "engine", "serial MB/s", "files/s", "parallel MB/s", "files/s", "output"
+    );
+    for engine in &report.engines {
+        let _ = writeln!(
+            out,
+            "{:<12} {:>12.1} {:>12.0} {:>14.1} {:>14.0} {:>7.2}x",
+            engine_name(engine.engine),
+            engine.serial.mb_per_sec,
+            engine.serial.files_per_sec,
+            engine.parallel.mb_per_sec,
+            engine.parallel.files_per_sec,
+            engine.output_ratio
+        );
+    }
+
+    let _ = write!(out, "\n{:<12}", "p95 ms");
+    for (size, _) in SIZE_BUCKETS {
+        let _ = write!(out, " {:>10}", size);
+    }
+    out.push('\n');
+    for engine in &report.engines {
+        let _ = write!(out, "{:<12}", engine_name(engine.engine));
+        for (size, _) in SIZE_BUCKETS {
+            match engine.latency.iter().find(|latency| latency.size == *size) {
+                Some(latency) => {
+                    let _ = write!(out, " {:>10.3}", latency.p95_ms);
+                }
+                None => {
+                    let _ = write!(out, " {:>10}", "-");
+                }
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn engine_name(engine: Engine) -> String {
+    engine.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_engines_agree_on_basics() {
+        let renderers = Renderers::new();
+        let markdown = "# Title\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n~~gone~~ <span>raw</span>\n";
+        for engine in Engine::value_variants() {
+            let html = renderers.render(*engine, markdown);
+            for expected in ["<h1>Title</h1>", "<table>", "<del>gone</del>", "<span>raw</span>"] {
+                assert!(html.contains(expected), "{:?} lacks {}: {}", engine, expected, html);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bench() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("small.md"), "---\ntitle: Small\n---\n# Small\n").unwrap();
+        fs::write(dir.path().join("large.md"), "Some *text*.\n\n".repeat(200)).unwrap();
+        fs::write(dir.path().join("skip.txt"), "not markdown").unwrap();
+        let mut args = BenchArgs {
+            corpus: dir.path().to_path_buf(),
+            engines: vec![Engine::Pulldown, Engine::Comrak],
+            iterations: 2,
+            json: false,
+        };
+        let report = bench(&args).unwrap();
+        assert_eq!((report.files, report.iterations), (2, 2));
+        // Frontmatter isn't rendered
+        assert_eq!(report.bytes, "# Small".len() + 14 * 200);
+        let engines: Vec<Engine> = report.engines.iter().map(|engine| engine.engine).collect();
+        assert_eq!(engines, vec![Engine::Pulldown, Engine::Comrak]);
+        let sizes: Vec<(&str, usize)> = report.engines[0].latency.iter().map(|l| (l.size, l.files)).collect();
+        assert_eq!(sizes, vec![("<1KB", 1), ("1-10KB", 1)]);
+        assert!(report.engines.iter().all(|engine| engine.output_ratio > 1.0));
+
+        let table = format_report(&report);
+        assert!(table.contains("comrak") && !table.contains("markdown-rs"), "{}", table);
+
+        args.corpus = dir.path().join("missing");
+        assert!(bench(&args).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Block-level render cache for large documents
+//!
+//! Editing one paragraph of a long document changes one top-level block,
+//! but turning the events back into HTML covered every block. Large
+//! documents are rendered block by block instead, and each block's HTML is
+//! kept per file under a hash of its source and of everything elsewhere in
+//! the document that can change it: options, abbreviations, heading ids and
+//! resolved link targets. The next render of the file only renders blocks
+//! whose hash changed and reassembles the rest. Parsing still covers the
+//! whole document, since lint, headings and dependencies need all of it.
+//!
+//! Footnotes are numbered across the whole document, so documents that use
+//! them are always rendered whole.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use dashmap::DashMap;
+use pulldown_cmark::{Event, Tag};
+
+use crate::sourcemap::LineMap;
+use crate::transform::{next_depth, render_html, starts_block};
+
+/// Documents with fewer top-level blocks are rendered whole
+pub const MIN_BLOCKS: usize = 32;
+
+/// Bytes of fragments kept across all files; the files rendered longest
+/// ago are dropped first
+const MAX_FRAGMENT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Fragments from the last render of a file, by block hash
+struct Fragments {
+    blocks: HashMap<blake3::Hash, String>,
+    bytes: usize,
+    rendered_at: Instant,
+}
+
+static FRAGMENTS: OnceLock<DashMap<String, Fragments>> = OnceLock::new();
+
+fn fragments() -> &'static DashMap<String, Fragments> {
+    FRAGMENTS.get_or_init(DashMap::new)
+}
+
+/// Whether `events` can be rendered block by block
+pub fn applies(events: &[Event], blocks: usize) -> bool {
+    blocks >= MIN_BLOCKS
+        && !events
+            .iter()
+            .any(|event| matches!(event, Event::FootnoteReference(_) | Event::Start(Tag::FootnoteDefinition(_))))
+}
+
+/// Render the top-level blocks of `content`, starting at byte offsets
+/// `starts` and source lines `lines`, reusing fragments from the previous
+/// render of `file`. `context` must cover the options and definitions the
+/// events were produced with. Also returns how many blocks were reused.
+pub fn render(
+    file: &str,
+    context: &str,
+    content: &str,
+    starts: &[usize],
+    lines: &[usize],
+    events: Vec<Event<'_>>,
+) -> (String, LineMap, usize) {
+    let previous = fragments().remove(file).map(|(_, fragments)| fragments.blocks).unwrap_or_default();
+    let mut current = HashMap::with_capacity(starts.len());
+    let mut html = String::new();
+    let mut line_map = Vec::with_capacity(lines.len());
+    let mut newlines = 0;
+    let mut reused = 0;
+
+    for (index, block) in split(events).into_iter().enumerate() {
+        let end = starts.get(index + 1).copied().unwrap_or(content.len());
+        let source = starts.get(index).map_or("", |start| &content[*start..end]);
+        let key = block_key(context, source, &block);
+        let fragment = match previous.get(&key) {
+            Some(fragment) => {
+                reused += 1;
+                fragment.clone()
+            }
+            None => render_html(block, &[]).0,
+        };
+        if let Some(line) = lines.get(index) {
+            line_map.push((newlines, *line));
+        }
+        newlines += fragment.bytes().filter(|b| *b == b'\n').count();
+        html.push_str(&fragment);
+        current.insert(key, fragment);
+    }
+    let bytes = current.values().map(String::len).sum();
+    let current = Fragments {
+        blocks: current,
+        bytes,
+        rendered_at: Instant::now(),
+    };
+    keep(fragments(), file.to_string(), current, MAX_FRAGMENT_BYTES);
+    (html, line_map, reused)
+}
+
+/// Store a file's fragments, first dropping the oldest files' until
+/// everything fits in `budget`
+fn keep(all: &DashMap<String, Fragments>, file: String, fragments: Fragments, budget: usize) {
+    let mut total: usize = all.iter().filter(|entry| *entry.key() != file).map(|entry| entry.bytes).sum();
+    while total + fragments.bytes > budget {
+        let oldest = all
+            .iter()
+            .filter(|entry| *entry.key() != file)
+            .min_by_key(|entry| entry.rendered_at)
+            .map(|entry| entry.key().clone());
+        match oldest.and_then(|oldest| all.remove(&oldest)) {
+            Some((_, dropped)) => total -= dropped.bytes,
+            None => break,
+        }
+    }
+    all.insert(file, fragments);
+}
+
+/// Drop the fragments of the files `matches` accepts; returns how many
+/// files had fragments
+pub fn forget(matches: impl Fn(&str) -> bool) -> usize {
+    let before = fragments().len();
+    fragments().retain(|file, _| !matches(file));
+    before - fragments().len()
+}
+
+/// Group events by top-level block
+fn split(events: Vec<Event<'_>>) -> Vec<Vec<Event<'_>>> {
+    let mut blocks: Vec<Vec<Event>> = Vec::new();
+    let mut depth = 0usize;
+    for event in events {
+        if (depth == 0 && starts_block(&event)) || blocks.is_empty() {
+            blocks.push(Vec::new());
+        }
+        depth = next_depth(depth, &event);
+        if let Some(block) = blocks.last_mut() {
+            block.push(event);
+        }
+    }
+    blocks
+}
+
+fn block_key(context: &str, source: &str, block: &[Event]) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+    let mut update = |part: &str| {
+        hasher.update(&(part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    };
+    update(context);
+    update(source);
+    // Assigned ids and reference links depend on the rest of the document
+    for event in block {
+        match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => update(id),
+            Event::Start(Tag::Link { dest_url, title, .. }) | Event::Start(Tag::Image { dest_url, title, .. }) => {
+                update(dest_url);
+                update(title);
+            }
+            _ => {}
+        }
+    }
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::Parser;
+
+    use crate::output::OutputFormat;
+    use crate::transform::{parser_options, transform_markdown, TransformOptions};
+
+    fn document(edited: &str) -> String {
+        let mut doc = String::from("# Title\n\nSee [the guide][guide].\n\n");
+        for i in 0..MIN_BLOCKS {
+            doc.push_str(&format!("## Section\n\nParagraph {} with *emphasis*.\n\n", i));
+        }
+        doc.push_str(&format!("- one\n- two\n\n  loose\n\n```rust\nfn main() {{}}\n```\n\n{}\n\n", edited));
+        doc.push_str("| a | b |\n|---|---|\n| 1 | 2 |\n\n<div>\nraw\n</div>\n\n> quote\n\n---\n\n[guide]: /guide\n");
+        doc
+    }
+
+    /// Events and top-level block offsets of `content`
+    fn parse(content: &str) -> (Vec<Event<'_>>, Vec<usize>) {
+        let mut events = Vec::new();
+        let mut starts = Vec::new();
+        let mut depth = 0;
+        for (event, range) in Parser::new(content).into_offset_iter() {
+            if depth == 0 && starts_block(&event) {
+                starts.push(range.start);
+            }
+            depth = next_depth(depth, &event);
+            events.push(event);
+        }
+        (events, starts)
+    }
+
+    #[test]
+    fn test_reuses_unchanged_blocks() {
+        let before = document("Before.");
+        let after = document("After.");
+        let (events, starts) = parse(&before);
+        let (html, _, reused) = render("a.md", "", &before, &starts, &[], events.clone());
+        assert_eq!(html, render_html(events, &[]).0);
+        assert_eq!(reused, 0);
+
+        let (events, starts) = parse(&after);
+        let (html, _, reused) = render("a.md", "", &after, &starts, &[], events.clone());
+        assert_eq!(html, render_html(events, &[]).0);
+        assert_eq!(reused, starts.len() - 1);
+
+        // Another context invalidates every block
+        let (events, starts) = parse(&after);
+        let (_, _, reused) = render("a.md", "{\"minify\":true}", &after, &starts, &[], events);
+        assert_eq!(reused, 0);
+    }
+
+    #[test]
+    fn test_fragments_are_bounded() {
+        let started = Instant::now();
+        let fragments = |i: u64| Fragments {
+            blocks: HashMap::new(),
+            bytes: 10,
+            rendered_at: started + std::time::Duration::from_millis(i),
+        };
+        let all = DashMap::new();
+        for i in 0..5 {
+            keep(&all, format!("{}.md", i), fragments(i), 30);
+        }
+        assert_eq!(all.len(), 3);
+        assert!(!all.contains_key("0.md") && !all.contains_key("1.md"));
+        // Rendering a kept file again replaces its fragments in place
+        keep(&all, "2.md".to_string(), fragments(5), 30);
+        assert!(all.contains_key("2.md") && all.contains_key("3.md") && all.contains_key("4.md"));
+    }
+
+    #[test]
+    fn test_transform_output_is_unchanged() {
+        let options = TransformOptions {
+            format: Some(OutputFormat::Html),
+            ..Default::default()
+        };
+        let doc = document("Edited.");
+        for _ in 0..2 {
+            let rendered = transform_markdown(&doc, "b.md", &options, None).unwrap();
+            let events = Parser::new_ext(&doc, parser_options(false)).collect();
+            assert_eq!(rendered.code, render_html(events, &[]).0);
+        }
+    }
+}
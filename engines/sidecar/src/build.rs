@@ -0,0 +1,461 @@
+//! `fastmd-sidecar build <src> --out <dir>`: render content to static files
+//!
+//! A minimal static site generator for smoke tests, CI and simple sites.
+//! `<src>` is a content directory or a glob such as `'content/**/*.md'`,
+//! whose matches are laid out under `--out` relative to the glob's literal
+//! prefix. Documents are transformed in parallel, on as many threads as
+//! the worker pool is configured for, through the same path (and disk
+//! cache) as `transform` requests. Markdown becomes HTML pages wrapped in a
+//! layout, MDX stays a JS module, and every other file is copied as is.
+//! Files and directories starting with `_` (layouts, partials, cascaded
+//! defaults) are inputs to the build, not pages.
+//!
+//! Layouts are HTML files in `<src>/_layouts`: `default.html`, or the one
+//! named by a document's `layout` frontmatter key. `{{ content }}` is
+//! replaced by the rendered page, and `{{ key }}` by the escaped value of a
+//! (dot-separated) frontmatter key, with `title` falling back to the file
+//! name.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use tracing::{error, info};
+
+use crate::canonical;
+use crate::fsutil;
+use crate::limits;
+use crate::handlers::build_document;
+use crate::ordering::lookup;
+use crate::output::OutputFormat;
+use crate::parallel::{self, ParallelConfig};
+use crate::scan::{self, Patterns, ScanOptions};
+use crate::source;
+use crate::transform::TransformOptions;
+use crate::utils::is_document;
+
+/// Layout used when the site has none
+const BUILTIN_LAYOUT: &str = "<!doctype html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{{ title }}</title>\n</head>\n<body>\n{{ content }}\n</body>\n</html>\n";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum BuildFormat {
+    /// HTML pages wrapped in layouts
+    #[default]
+    Html,
+    /// ES modules, as a bundler would import them
+    Js,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BuildArgs {
+    /// Content directory, or a glob of files to build (quoted, so the
+    /// shell leaves it alone)
+    pub src: String,
+    /// Output directory; the source tree layout is mirrored
+    #[arg(short, long)]
+    pub out: PathBuf,
+    /// What markdown documents are rendered to
+    #[arg(long, value_enum, default_value_t = BuildFormat::Html)]
+    pub format: BuildFormat,
+    /// Layout directory (default: `_layouts` in the content directory)
+    #[arg(long)]
+    pub layouts: Option<PathBuf>,
+    /// Prefix for root-relative link and image URLs
+    #[arg(long)]
+    pub base_url: Option<String>,
+    /// Collapse insignificant whitespace in generated HTML
+    #[arg(long)]
+    pub minify: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BuildReport {
+    pub pages: usize,
+    /// Pages whose output came from the cache
+    pub cache_hits: usize,
+    pub assets: usize,
+    pub failed: usize,
+}
+
+/// Build the site and log a summary; fails if any document failed
+pub fn run(args: &BuildArgs) -> Result<()> {
+    let started = Instant::now();
+    let report = build(args)?;
+    report.log(&args.out, started);
+    if report.failed > 0 {
+        bail!("{} documents failed to build", report.failed);
+    }
+    Ok(())
+}
+
+impl BuildReport {
+    pub fn log(&self, out: &Path, started: Instant) {
+        info!(
+            "Built {} pages ({} from cache) and copied {} assets to {} in {} ms",
+            self.pages,
+            self.cache_hits,
+            self.assets,
+            out.display(),
+            started.elapsed().as_millis()
+        );
+    }
+}
+
+pub fn build(args: &BuildArgs) -> Result<BuildReport> {
+    Site::new(args)?.build()
+}
+
+/// A document written by [`Site::build_documents`]
+pub struct BuiltPage {
+    /// Whether the output came from the cache
+    pub cached: bool,
+    pub elapsed: Duration,
+}
+
+/// The files a build covers and how each is rendered
+pub struct Site {
+    /// Directory outputs are laid out relative to, with symlinks resolved
+    pub src: PathBuf,
+    pattern: String,
+    patterns: Patterns,
+    out: PathBuf,
+    /// `out` with symlinks resolved, to tell outputs apart from content
+    resolved_out: PathBuf,
+    format: BuildFormat,
+    options: TransformOptions,
+    pub layouts: Layouts,
+    threads: rayon::ThreadPool,
+}
+
+impl Site {
+    pub fn new(args: &BuildArgs) -> Result<Self> {
+        let (src, pattern) = if Path::new(&args.src).is_dir() {
+            (PathBuf::from(&args.src), "**/*".to_string())
+        } else {
+            split_glob(&args.src)
+        };
+        let src = fs::canonicalize(&src).with_context(|| format!("Source directory {} does not exist", src.display()))?;
+        if !src.is_dir() {
+            bail!("Source {} is not a directory", src.display());
+        }
+        let options = TransformOptions {
+            format: Some(match args.format {
+                BuildFormat::Html => OutputFormat::Html,
+                BuildFormat::Js => OutputFormat::Esm,
+            }),
+            base_url: args.base_url.clone(),
+            minify: args.minify.then_some(true),
+            ..Default::default()
+        };
+        let layouts = args.layouts.clone().unwrap_or_else(|| src.join("_layouts"));
+        let config = ParallelConfig::from_env();
+        let threads = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.num_workers.unwrap_or_else(parallel::recommended_workers))
+            .thread_name(|i| format!("fastmd-build-{}", i))
+            .build()?;
+        Ok(Site {
+            patterns: Patterns::new(std::slice::from_ref(&pattern)).map_err(|e| anyhow!(e))?,
+            pattern,
+            src,
+            resolved_out: canonical::resolve(&args.out),
+            out: args.out.clone(),
+            format: args.format,
+            options,
+            layouts: Layouts::new(canonical::resolve(&layouts)),
+            threads,
+        })
+    }
+
+    /// Documents and assets to build, in path order
+    pub fn files(&self) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+        let scan_options = ScanOptions {
+            respect_ignore: true,
+            hidden: false,
+        };
+        let files = scan::expand(std::slice::from_ref(&self.pattern), &self.src, &scan_options).map_err(|e| anyhow!(e))?;
+        Ok(files
+            .into_iter()
+            .filter(|path| !self.is_excluded(path))
+            .partition(|path| is_document(path)))
+    }
+
+    /// Whether `path` (absolute, symlinks resolved) is content of the site
+    pub fn contains(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.src)
+            .is_ok_and(|relative| self.patterns.is_match(relative) && !is_hidden(relative))
+            && !self.is_excluded(path)
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.src).unwrap_or(path);
+        let private = relative.components().any(|part| part.as_os_str().to_string_lossy().starts_with('_'));
+        // An output directory inside the source tree isn't content
+        private || canonical::resolve(path).starts_with(&self.resolved_out)
+    }
+
+    pub fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.src).unwrap_or(path)
+    }
+
+    /// Where the output of `path` goes
+    pub fn target(&self, path: &Path) -> PathBuf {
+        let target = self.out.join(self.relative(path));
+        if !is_document(path) {
+            target
+        } else if self.is_page(path) {
+            target.with_extension("html")
+        } else {
+            target.with_extension("js")
+        }
+    }
+
+    fn is_page(&self, path: &Path) -> bool {
+        // MDX always compiles to a module
+        self.format == BuildFormat::Html && path.extension().is_none_or(|ext| ext != "mdx")
+    }
+
+    /// Build everything
+    pub fn build(&self) -> Result<BuildReport> {
+        let (documents, assets) = self.files()?;
+        let mut report = BuildReport::default();
+        for (path, result) in documents.iter().zip(self.build_documents(&documents)) {
+            match result {
+                Ok(page) => {
+                    report.pages += 1;
+                    report.cache_hits += usize::from(page.cached);
+                }
+                Err(e) => {
+                    error!("{}: {}", path.display(), e);
+                    report.failed += 1;
+                }
+            }
+        }
+        for path in &assets {
+            self.copy_asset(path)?;
+            report.assets += 1;
+        }
+        Ok(report)
+    }
+
+    /// Build documents in parallel
+    pub fn build_documents(&self, documents: &[PathBuf]) -> Vec<Result<BuiltPage, String>> {
+        self.threads
+            .install(|| documents.par_iter().map(|path| self.build_document(path)).collect())
+    }
+
+    fn build_document(&self, path: &Path) -> Result<BuiltPage, String> {
+        let started = Instant::now();
+        let content = source::read_source(path, limits::limits().max_input_bytes).map_err(|e| e.to_string())?;
+        let built = build_document(&path.to_string_lossy(), content, &self.options).map_err(|e| e.to_string())?;
+        let output = if self.is_page(path) {
+            self.layouts.apply(built.frontmatter.as_ref(), self.relative(path), &built.code)?
+        } else {
+            built.code
+        };
+        let target = self.target(path);
+        fsutil::write_atomic(&target, output.as_bytes()).map_err(|e| format!("Failed to write {}: {}", target.display(), e))?;
+        Ok(BuiltPage {
+            cached: built.cached,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    pub fn copy_asset(&self, path: &Path) -> Result<()> {
+        let target = self.target(path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(path, &target).with_context(|| format!("Failed to copy {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Remove the output of a file that was removed
+    pub fn remove_output(&self, path: &Path) -> Result<()> {
+        let target = self.target(path);
+        match fs::remove_file(&target) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", target.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Dot-files aren't content; the scan skips them too
+fn is_hidden(relative: &Path) -> bool {
+    relative.components().any(|part| part.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// Directory a glob's matches are laid out relative to, and the glob
+/// relative to it: `content/**/*.md` is `**/*.md` under `content`. A glob
+/// without a directory part is relative to the working directory.
+fn split_glob(pattern: &str) -> (PathBuf, String) {
+    let parts: Vec<&str> = pattern.trim_start_matches("./").split('/').collect();
+    // The last part names files even when it's a literal file name
+    let literal = parts[..parts.len() - 1]
+        .iter()
+        .take_while(|part| !part.contains(['*', '?', '[', ']', '{', '}']))
+        .count();
+    let dir = if literal == 0 { ".".to_string() } else { parts[..literal].join("/") };
+    (PathBuf::from(dir), parts[literal..].join("/"))
+}
+
+/// Layout templates, read once per build
+pub struct Layouts {
+    pub dir: PathBuf,
+    loaded: parking_lot::Mutex<HashMap<String, Option<String>>>,
+}
+
+impl Layouts {
+    fn new(dir: PathBuf) -> Self {
+        Layouts {
+            dir,
+            loaded: Default::default(),
+        }
+    }
+
+    fn template(&self, name: &str) -> Option<String> {
+        let mut loaded = self.loaded.lock();
+        loaded
+            .entry(name.to_string())
+            .or_insert_with(|| fs::read_to_string(self.dir.join(format!("{}.html", name))).ok())
+            .clone()
+    }
+
+    /// Read templates again the next time they're used
+    pub fn forget(&self) {
+        self.loaded.lock().clear();
+    }
+
+    /// `html` wrapped in the document's layout
+    fn apply(&self, frontmatter: Option<&Value>, relative: &Path, html: &str) -> Result<String, String> {
+        let layout = frontmatter.and_then(|frontmatter| frontmatter.get("layout")).and_then(Value::as_str);
+        let template = match layout {
+            Some(name) => self
+                .template(name)
+                .ok_or_else(|| format!("Layout '{}' not found in {}", name, self.dir.display()))?,
+            None => self.template("default").unwrap_or_else(|| BUILTIN_LAYOUT.to_string()),
+        };
+        let mut values = frontmatter.cloned().filter(Value::is_object).unwrap_or_else(|| json!({}));
+        if values.get("title").is_none() {
+            let stem = relative.file_stem().map(|stem| stem.to_string_lossy().into_owned());
+            values["title"] = json!(stem);
+        }
+        Ok(render_template(&template, &values, html))
+    }
+}
+
+/// Fill `{{ content }}` with `content` and `{{ key }}` with escaped values
+fn render_template(template: &str, values: &Value, content: &str) -> String {
+    let mut rendered = String::with_capacity(template.len() + content.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        match rest[start + 2..start + end].trim() {
+            "content" => rendered.push_str(content),
+            key => match lookup(values, key) {
+                Some(Value::String(s)) => rendered.push_str(&escape_html(s)),
+                Some(Value::Null) | None => {}
+                Some(other) => rendered.push_str(&escape_html(&other.to_string())),
+            },
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_template() {
+        let values = json!({ "title": "A & B", "meta": { "year": 2024 } });
+        let template = "<title>{{ title }}</title>{{meta.year}}{{ missing }}<main>{{ content }}</main>{{ open";
+        assert_eq!(
+            render_template(template, &values, "<p>hi</p>"),
+            "<title>A &amp; B</title>2024<main><p>hi</p></main>{{ open"
+        );
+    }
+
+    #[test]
+    fn test_build() {
+        let src = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = src.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("index.md", "# Home\n");
+        write("guide/intro.md", "---\ntitle: Intro\nlayout: doc\n---\nHello\n");
+        write("guide/widget.mdx", "export const x = 1;\n\n# Widget\n");
+        write("guide/logo.png", "png");
+        write("_layouts/doc.html", "<h1>{{ title }}</h1>{{ content }}");
+        write("_partials/note.md", "Not a page\n");
+        write("broken.md", "---\nlayout: missing\n---\nBody\n");
+
+        let mut args = BuildArgs {
+            src: src.path().to_string_lossy().into_owned(),
+            out: out.path().to_path_buf(),
+            format: BuildFormat::Html,
+            layouts: None,
+            base_url: None,
+            minify: false,
+        };
+        let report = build(&args).unwrap();
+        assert_eq!(report.pages, 3);
+        assert_eq!((report.assets, report.failed), (1, 1));
+
+        let read = |name: &str| fs::read_to_string(out.path().join(name)).unwrap();
+        assert!(read("index.html").starts_with("<!doctype html>"));
+        assert!(read("index.html").contains("<title>index</title>"));
+        assert_eq!(read("guide/intro.html"), "<h1>Intro</h1><p>Hello</p>\n");
+        assert!(read("guide/widget.js").contains("export const x = 1;"));
+        assert_eq!(read("guide/logo.png"), "png");
+        assert!(!out.path().join("_partials").exists());
+        assert!(!out.path().join("broken.html").exists());
+
+        // A glob builds only its matches, laid out relative to its directory
+        let globbed = tempfile::tempdir().unwrap();
+        args.src = format!("{}/guide/*.md", src.path().display());
+        args.out = globbed.path().to_path_buf();
+        args.layouts = Some(src.path().join("_layouts"));
+        let report = build(&args).unwrap();
+        assert_eq!((report.pages, report.assets, report.failed), (1, 0, 0));
+        assert!(globbed.path().join("intro.html").exists());
+    }
+
+    #[test]
+    fn test_split_glob() {
+        assert_eq!(split_glob("content/**/*.md"), (PathBuf::from("content"), "**/*.md".to_string()));
+        assert_eq!(split_glob("./content/docs/*.md"), (PathBuf::from("content/docs"), "*.md".to_string()));
+        assert_eq!(split_glob("*.md"), (PathBuf::from("."), "*.md".to_string()));
+        assert_eq!(split_glob("content/index.md"), (PathBuf::from("content"), "index.md".to_string()));
+        assert_eq!(split_glob("/site/{a,b}/*.md"), (PathBuf::from("/site"), "{a,b}/*.md".to_string()));
+    }
+}
@@ -0,0 +1,1035 @@
+//! Persistent transform cache under `--cache-dir`
+//!
+//! Entries are keyed by a hash of everything that determines a transform's
+//! output: the normalized source, the resolved options, the frontmatter
+//! after cascading, the file path and the sidecar version. A restarted
+//! sidecar (or a second one sharing the directory) reuses earlier results
+//! instead of rendering again.
+//!
+//! Entry files are binary:
+//!
+//! ```text
+//! "FMDC" | format version (u16 LE) | header length (u32 LE) | header
+//!        | BLAKE3 of header and payload (32 bytes) | payload
+//! ```
+//!
+//! The header and payload are MessagePack. The header holds the key, the
+//! engine/options fingerprint and the source document, so listing or
+//! filtering entries never touches the payload, which is zstd-compressed
+//! unless compression is turned off. Entries are written atomically, and a
+//! wrong magic, version, key or fingerprint, or a checksum mismatch, makes
+//! the entry a miss that gets removed rather than served.
+//!
+//! A hit bumps the entry's mtime, which garbage collection then uses as its
+//! last-use time: entries unused for longer than the age limit go first,
+//! then the least recently used ones until the cache fits its size budget.
+//!
+//! `cacheExport` packs every entry into one archive that `cacheImport`
+//! unpacks elsewhere, so CI jobs can carry the cache between runs:
+//!
+//! ```text
+//! "FMDA" | archive version (u16 LE) | entry files, back to back
+//!        | manifest (JSON) | manifest length (u32 LE)
+//! ```
+//!
+//! The manifest lists each entry's path and size in order. Entries are
+//! copied verbatim and checked again on import; ones that fail their
+//! checksum or come from another format version are skipped.
+//!
+//! Failures are remembered too, in memory and for [`FAILURE_TTL`] only: a
+//! document with a syntax error is requested on every HMR ping until the
+//! fix is saved, and failing it again from the key alone spares re-parsing.
+//! The TTL bounds how long inputs outside the key (files the document links
+//! to, say) can go unnoticed.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::canonical::canonical_path;
+use crate::diagnostics::{Diagnostic, TransformError};
+use crate::fsutil;
+use crate::transform::TransformOptions;
+
+/// Subdirectory of the cache directory holding transform entries
+const ENTRIES_DIR: &str = "transforms";
+/// Leading bytes of every entry file
+const MAGIC: &[u8; 4] = b"FMDC";
+/// Bumped whenever the entry layout changes
+const FORMAT_VERSION: u16 = 5;
+/// Entry file extension
+const ENTRY_EXTENSION: &str = "entry";
+/// Bytes before the header: magic, version and header length
+const PREAMBLE_LEN: usize = 10;
+const CHECKSUM_LEN: usize = 32;
+/// Leading bytes of a cache archive
+const ARCHIVE_MAGIC: &[u8; 4] = b"FMDA";
+const ARCHIVE_VERSION: u16 = 1;
+/// zstd level used unless configured otherwise
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+/// Writes between automatic collections
+const GC_INTERVAL_WRITES: u64 = 256;
+/// How long a failed transform is served from memory
+pub const FAILURE_TTL: Duration = Duration::from_secs(30);
+/// Failures remembered at most; expired ones are pruned first, then the
+/// oldest
+const MAX_FAILURES: usize = 1024;
+
+/// A cached transform result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub code: String,
+    pub map: Option<Value>,
+    pub dependencies: Vec<String>,
+    /// Render warnings, already positioned in the original source
+    pub warnings: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Compression {
+    None,
+    Zstd,
+}
+
+/// What an entry is looked up by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    /// Hash of everything that determines the output; names the file
+    pub hash: String,
+    /// Hash of the engine version and options alone, checked separately so
+    /// entries from another sidecar build are never served
+    pub fingerprint: String,
+}
+
+/// Entry metadata, stored uncompressed
+#[derive(Serialize, Deserialize)]
+struct Header {
+    key: String,
+    fingerprint: String,
+    /// Document the entry was rendered from, for filtered clears
+    file: String,
+    /// When the entry was written, in Unix milliseconds
+    stored_ms: u64,
+    compression: Compression,
+}
+
+/// An entry found in the cache
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub entry: Entry,
+    /// When the entry was written, in Unix milliseconds
+    pub stored_ms: u64,
+}
+
+/// Where a transform result came from, reported in its metadata so hosts
+/// can see cache effectiveness per file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    pub hit: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<CacheSource>,
+    pub key: String,
+    /// Unix milliseconds the entry was written, for hits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stored_at: Option<u64>,
+}
+
+/// Where a hit was served from. The frontmatter memo only spares parsing
+/// and never supplies a result, so it isn't a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CacheSource {
+    /// Failures remembered for [`FAILURE_TTL`]
+    Memory,
+    /// The project cache under `--cache-dir`
+    Disk,
+    /// The store shared across projects
+    Shared,
+}
+
+impl Provenance {
+    pub fn hit(key: &CacheKey, source: CacheSource, hit: &Hit) -> Self {
+        Provenance {
+            hit: true,
+            source: Some(source),
+            key: key.hash.clone(),
+            stored_at: Some(hit.stored_ms),
+        }
+    }
+
+    pub fn miss(key: &CacheKey) -> Self {
+        Provenance {
+            hit: false,
+            source: None,
+            key: key.hash.clone(),
+            stored_at: None,
+        }
+    }
+}
+
+/// Table of contents of a cache archive
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    sidecar: String,
+    format_version: u16,
+    created_ms: u64,
+    entries: Vec<ArchivedFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedFile {
+    /// Relative to the entries directory, with `/` separators
+    path: String,
+    bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportReport {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Already present and kept
+    pub skipped: usize,
+    /// Corrupt, misnamed or from another format version
+    pub invalid: usize,
+}
+
+/// Limits garbage collection enforces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcPolicy {
+    /// Total size of entries to shrink to
+    pub max_bytes: Option<u64>,
+    /// Remove entries not used for this long
+    pub max_age: Option<Duration>,
+}
+
+impl GcPolicy {
+    fn is_unbounded(&self) -> bool {
+        self.max_bytes.is_none() && self.max_age.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcReport {
+    pub scanned: usize,
+    pub removed: usize,
+    pub freed_bytes: u64,
+    pub remaining_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    writes: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub writes: u64,
+    /// Hits per lookup since the sidecar started, 0 before any lookup
+    pub hit_rate: f64,
+}
+
+/// What the cache directory currently holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheUsage {
+    pub entries: usize,
+    pub bytes: u64,
+    /// Last use of the least recently used entry, in Unix milliseconds
+    pub oldest_used_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct DiskCache {
+    dir: PathBuf,
+    policy: GcPolicy,
+    /// zstd level for payloads; 0 stores them uncompressed
+    compression_level: i32,
+    counters: Counters,
+    collecting: Arc<AtomicBool>,
+}
+
+static CACHE: OnceLock<Option<DiskCache>> = OnceLock::new();
+/// A remembered failure, by key hash
+struct Failure {
+    failed_at: Instant,
+    /// When the failure was recorded, in Unix milliseconds
+    failed_ms: u64,
+    file: String,
+    error: TransformError,
+}
+
+static FAILURES: OnceLock<DashMap<String, Failure>> = OnceLock::new();
+
+/// Enable the cache when a cache directory is configured. Must be called
+/// before the first use of [`global`] to take effect.
+pub fn init(cache_dir: Option<&Path>, policy: GcPolicy, compression_level: i32) {
+    let cache = CACHE.get_or_init(|| {
+        cache_dir.map(|dir| DiskCache::new(dir.join(ENTRIES_DIR), policy).with_compression(compression_level))
+    });
+    // Enforce the budget left over from earlier sessions
+    if let Some(cache) = cache {
+        cache.collect_in_background();
+    }
+}
+
+/// The process-wide cache, if one is configured
+pub fn global() -> Option<&'static DiskCache> {
+    CACHE.get_or_init(|| None).as_ref()
+}
+
+fn failures() -> &'static DashMap<String, Failure> {
+    FAILURES.get_or_init(DashMap::new)
+}
+
+/// Whether any failure is remembered, so callers can skip computing keys
+pub fn has_failures() -> bool {
+    FAILURES.get().is_some_and(|failures| !failures.is_empty())
+}
+
+/// The error a transform under `key` failed with recently, if it did,
+/// with the memory hit as its provenance
+pub fn failure(key: &CacheKey) -> Option<TransformError> {
+    let failures = failures();
+    let recent = failures
+        .get(&key.hash)
+        .filter(|failure| failure.failed_at.elapsed() < FAILURE_TTL)
+        .map(|failure| TransformError {
+            cache: Some(Box::new(Provenance {
+                hit: true,
+                source: Some(CacheSource::Memory),
+                key: key.hash.clone(),
+                stored_at: Some(failure.failed_ms),
+            })),
+            ..failure.error.clone()
+        });
+    if recent.is_none() {
+        failures.remove(&key.hash);
+    }
+    recent
+}
+
+pub fn record_failure(key: &CacheKey, file: &str, error: &TransformError) {
+    let failure = Failure {
+        failed_at: Instant::now(),
+        failed_ms: unix_ms(SystemTime::now()),
+        file: file.to_string(),
+        error: TransformError { cache: None, ..error.clone() },
+    };
+    remember(failures(), key.hash.clone(), failure);
+}
+
+fn remember(failures: &DashMap<String, Failure>, key: String, failure: Failure) {
+    if failures.len() >= MAX_FAILURES && !failures.contains_key(&key) {
+        failures.retain(|_, failure| failure.failed_at.elapsed() < FAILURE_TTL);
+        while failures.len() >= MAX_FAILURES {
+            let oldest = failures.iter().min_by_key(|entry| entry.failed_at).map(|entry| entry.key().clone());
+            match oldest {
+                Some(oldest) => failures.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+    failures.insert(key, failure);
+}
+
+/// Forget the failures of documents `matches` accepts; returns how many
+pub fn forget_failures(matches: impl Fn(&str) -> bool) -> usize {
+    let Some(failures) = FAILURES.get() else {
+        return 0;
+    };
+    let before = failures.len();
+    failures.retain(|_, failure| !matches(&failure.file));
+    before - failures.len()
+}
+
+/// Cache key for transforming `source` as `file`. Spellings of the same
+/// path (`./a.md` and `a.md`, `C:/a.md` and `c:/a.md`, a path through a
+/// symlink and its target) share a key.
+pub fn key(file: &str, source: &str, options: &TransformOptions, frontmatter: Option<&Value>) -> CacheKey {
+    digest(options, json!({ "file": canonical_path(file), "frontmatter": frontmatter }), source)
+}
+
+/// Key for the shared store: like [`key`], but only the file's name
+/// counts, so the same document under the same name in any directory has
+/// the same key. The name stays in the key because output mentions it.
+pub fn content_key(file: &str, source: &str, options: &TransformOptions, frontmatter: Option<&Value>) -> CacheKey {
+    let name = Path::new(file).file_name().map(|name| name.to_string_lossy());
+    digest(options, json!({ "name": name, "frontmatter": frontmatter }), source)
+}
+
+fn digest(options: &TransformOptions, document: Value, source: &str) -> CacheKey {
+    let engine = json!({
+        "sidecar": env!("CARGO_PKG_VERSION"),
+        "renderer": "pulldown-cmark",
+        "format": FORMAT_VERSION,
+        "options": options,
+    })
+    .to_string();
+    let fingerprint = format!("{:x}", Sha256::digest(engine.as_bytes()));
+
+    let content = fastmd_text::normalize(source, &Default::default()).content;
+    let document = document.to_string();
+    let mut hasher = Sha256::new();
+    for part in [fingerprint.as_str(), &document, &content] {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\n");
+    }
+    CacheKey {
+        hash: format!("{:x}", hasher.finalize()),
+        fingerprint,
+    }
+}
+
+impl DiskCache {
+    pub fn new(dir: PathBuf, policy: GcPolicy) -> Self {
+        DiskCache {
+            dir,
+            policy,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            counters: Counters::default(),
+            collecting: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_compression(mut self, level: i32) -> Self {
+        self.compression_level = level;
+        self
+    }
+
+    pub fn policy(&self) -> GcPolicy {
+        self.policy
+    }
+
+    /// Entries are spread over 256 subdirectories to keep listings short
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(&key.hash[..2]).join(format!("{}.{}", key.hash, ENTRY_EXTENSION))
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Hit> {
+        let hit = self.read(key);
+        let counter = if hit.is_some() { &self.counters.hits } else { &self.counters.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if hit.is_some() {
+            // Record the use for LRU eviction; failing to is harmless
+            let _ = File::options()
+                .write(true)
+                .open(self.path(key))
+                .and_then(|file| file.set_modified(SystemTime::now()));
+        }
+        hit
+    }
+
+    fn read(&self, key: &CacheKey) -> Option<Hit> {
+        let path = self.path(key);
+        let bytes = fs::read(&path).ok()?;
+        match decode(&bytes, key) {
+            Some(hit) => Some(hit),
+            None => {
+                tracing::warn!("Discarding unreadable cache entry {}", path.display());
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    pub fn put(&self, key: &CacheKey, file: &str, entry: &Entry) -> io::Result<()> {
+        let header = Header {
+            key: key.hash.clone(),
+            fingerprint: key.fingerprint.clone(),
+            file: file.to_string(),
+            stored_ms: unix_ms(SystemTime::now()),
+            compression: if self.compression_level == 0 { Compression::None } else { Compression::Zstd },
+        };
+        let bytes = encode(&header, entry, self.compression_level)?;
+        fsutil::write_atomic(&self.path(key), &bytes)?;
+        let writes = self.counters.writes.fetch_add(1, Ordering::Relaxed) + 1;
+        if writes.is_multiple_of(GC_INTERVAL_WRITES) {
+            self.collect_in_background();
+        }
+        Ok(())
+    }
+
+    /// Run [`DiskCache::collect`] with the configured policy on a
+    /// background thread, unless a collection is already running
+    pub fn collect_in_background(&self) {
+        if self.policy.is_unbounded() || self.collecting.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let (dir, policy, collecting) = (self.dir.clone(), self.policy, Arc::clone(&self.collecting));
+        std::thread::spawn(move || {
+            match collect(&dir, policy) {
+                Ok(report) if report.removed > 0 => tracing::info!(
+                    "Cache GC removed {} entries ({} bytes)",
+                    report.removed,
+                    report.freed_bytes
+                ),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Cache GC failed: {}", e),
+            }
+            collecting.store(false, Ordering::Release);
+        });
+    }
+
+    /// Remove entries older than `policy.max_age`, then least recently used
+    /// entries until the rest fit in `policy.max_bytes`
+    pub fn collect(&self, policy: GcPolicy) -> io::Result<GcReport> {
+        collect(&self.dir, policy)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let hits = self.counters.hits.load(Ordering::Relaxed);
+        let misses = self.counters.misses.load(Ordering::Relaxed);
+        CacheStats {
+            hits,
+            misses,
+            writes: self.counters.writes.load(Ordering::Relaxed),
+            hit_rate: if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 },
+        }
+    }
+
+    pub fn usage(&self) -> io::Result<CacheUsage> {
+        let entries = entry_files(&self.dir)?;
+        Ok(CacheUsage {
+            entries: entries.len(),
+            bytes: entries.iter().map(|entry| entry.size).sum(),
+            oldest_used_ms: entries
+                .iter()
+                .map(|entry| entry.used)
+                .min()
+                .and_then(|used| used.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|since| since.as_millis() as u64),
+        })
+    }
+
+    /// Pack every entry into an archive at `path`
+    pub fn export(&self, path: &Path) -> io::Result<ExportReport> {
+        let mut report = ExportReport::default();
+        fsutil::write_atomic_streamed(path, fsutil::durability(), |file| {
+            let mut out = BufWriter::new(file);
+            out.write_all(ARCHIVE_MAGIC)?;
+            out.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+            let mut entries = Vec::new();
+            for entry in entry_files(&self.dir)? {
+                let bytes = match fs::read(&entry.path) {
+                    Ok(bytes) => bytes,
+                    // Evicted since the listing
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                };
+                let Ok(relative) = entry.path.strip_prefix(&self.dir) else {
+                    continue;
+                };
+                out.write_all(&bytes)?;
+                report.bytes += bytes.len() as u64;
+                entries.push(ArchivedFile {
+                    path: relative.to_string_lossy().replace('\\', "/"),
+                    bytes: bytes.len() as u64,
+                });
+            }
+            report.entries = entries.len();
+            let manifest = Manifest {
+                sidecar: env!("CARGO_PKG_VERSION").to_string(),
+                format_version: FORMAT_VERSION,
+                created_ms: unix_ms(SystemTime::now()),
+                entries,
+            };
+            let manifest = serde_json::to_vec(&manifest).map_err(io::Error::other)?;
+            out.write_all(&manifest)?;
+            out.write_all(&(manifest.len() as u32).to_le_bytes())?;
+            out.flush()
+        })?;
+        Ok(report)
+    }
+
+    /// Unpack an archive from [`DiskCache::export`], keeping entries that
+    /// already exist unless `overwrite` is set
+    pub fn import(&self, path: &Path, overwrite: bool) -> io::Result<ImportReport> {
+        let invalid_archive = || io::Error::new(io::ErrorKind::InvalidData, "not a cache archive");
+        let mut file = BufReader::new(File::open(path)?);
+        let mut preamble = [0; 6];
+        file.read_exact(&mut preamble)?;
+        if &preamble[..4] != ARCHIVE_MAGIC || preamble[4..] != ARCHIVE_VERSION.to_le_bytes() {
+            return Err(invalid_archive());
+        }
+        let end = file.seek(SeekFrom::End(-4))?;
+        let mut length = [0; 4];
+        file.read_exact(&mut length)?;
+        let length = u32::from_le_bytes(length) as u64;
+        // Lengths are checked against what the file holds before allocating
+        let manifest_start = end.checked_sub(length).filter(|start| *start >= 6).ok_or_else(invalid_archive)?;
+        file.seek(SeekFrom::Start(manifest_start))?;
+        let mut manifest = vec![0; length as usize];
+        file.read_exact(&mut manifest)?;
+        let manifest: Manifest = serde_json::from_slice(&manifest).map_err(|_| invalid_archive())?;
+
+        let mut report = ImportReport::default();
+        file.seek(SeekFrom::Start(6))?;
+        // Entries sit between the preamble and the manifest
+        let mut remaining = manifest_start - 6;
+        for archived in manifest.entries {
+            remaining = remaining.checked_sub(archived.bytes).ok_or_else(invalid_archive)?;
+            let mut bytes = vec![0; archived.bytes as usize];
+            file.read_exact(&mut bytes)?;
+            let Some(key) = archived_key(&archived.path) else {
+                report.invalid += 1;
+                continue;
+            };
+            if verify(&bytes).is_none_or(|(header, _)| header.key != key) {
+                report.invalid += 1;
+                continue;
+            }
+            let target = self.dir.join(&key[..2]).join(format!("{}.{}", key, ENTRY_EXTENSION));
+            if !overwrite && target.exists() {
+                report.skipped += 1;
+                continue;
+            }
+            fsutil::write_atomic(&target, &bytes)?;
+            report.imported += 1;
+        }
+        Ok(report)
+    }
+
+    /// Remove the entries rendered from documents `matches` accepts, or all
+    /// of them; returns how many were removed
+    pub fn clear(&self, matches: Option<&dyn Fn(&str) -> bool>) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in entry_files(&self.dir)? {
+            if let Some(matches) = matches {
+                // Unreadable entries would be discarded on lookup anyway
+                let file = read_header(&entry.path).map(|header| header.file);
+                if file.is_some_and(|file| !matches(&file)) {
+                    continue;
+                }
+            }
+            match fs::remove_file(&entry.path) {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn encode(header: &Header, entry: &Entry, compression_level: i32) -> io::Result<Vec<u8>> {
+    let header_bytes = rmp_serde::to_vec_named(header).map_err(io::Error::other)?;
+    let mut payload = rmp_serde::to_vec_named(entry).map_err(io::Error::other)?;
+    if header.compression == Compression::Zstd {
+        payload = zstd::encode_all(payload.as_slice(), compression_level)?;
+    }
+    let mut checksum = blake3::Hasher::new();
+    checksum.update(&header_bytes);
+    checksum.update(&payload);
+
+    let mut bytes = Vec::with_capacity(PREAMBLE_LEN + header_bytes.len() + CHECKSUM_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(header_bytes.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&header_bytes);
+    bytes.extend_from_slice(checksum.finalize().as_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Header length from the preamble, if it is one this version writes
+fn header_len(preamble: &[u8]) -> Option<usize> {
+    if preamble.len() < PREAMBLE_LEN || &preamble[..4] != MAGIC || preamble[4..6] != FORMAT_VERSION.to_le_bytes() {
+        return None;
+    }
+    Some(u32::from_le_bytes(preamble[6..10].try_into().ok()?) as usize)
+}
+
+/// Header and payload of an entry file, if it is intact
+fn verify(bytes: &[u8]) -> Option<(Header, &[u8])> {
+    let header_end = PREAMBLE_LEN.checked_add(header_len(bytes)?)?;
+    let payload_start = header_end.checked_add(CHECKSUM_LEN)?;
+    let header_bytes = bytes.get(PREAMBLE_LEN..header_end)?;
+    let checksum = bytes.get(header_end..payload_start)?;
+    let payload = &bytes[payload_start..];
+
+    let mut expected = blake3::Hasher::new();
+    expected.update(header_bytes);
+    expected.update(payload);
+    if expected.finalize().as_bytes() != checksum {
+        return None;
+    }
+    Some((rmp_serde::from_slice(header_bytes).ok()?, payload))
+}
+
+/// The payload of an entry file, if it is intact and stored under `key`
+fn decode(bytes: &[u8], key: &CacheKey) -> Option<Hit> {
+    let (header, payload) = verify(bytes)?;
+    if header.key != key.hash || header.fingerprint != key.fingerprint {
+        return None;
+    }
+    let entry = match header.compression {
+        Compression::None => rmp_serde::from_slice(payload).ok()?,
+        Compression::Zstd => rmp_serde::from_slice(&zstd::decode_all(payload).ok()?).ok()?,
+    };
+    Some(Hit {
+        entry,
+        stored_ms: header.stored_ms,
+    })
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Read just the header of an entry file
+fn read_header(path: &Path) -> Option<Header> {
+    let mut file = File::open(path).ok()?;
+    let mut preamble = [0; PREAMBLE_LEN];
+    file.read_exact(&mut preamble).ok()?;
+    let mut header = vec![0; header_len(&preamble)?];
+    file.read_exact(&mut header).ok()?;
+    rmp_serde::from_slice(&header).ok()
+}
+
+fn collect(dir: &Path, policy: GcPolicy) -> io::Result<GcReport> {
+    let mut entries = entry_files(dir)?;
+    let mut report = GcReport {
+        scanned: entries.len(),
+        ..Default::default()
+    };
+    // Oldest first
+    entries.sort_by_key(|entry| entry.used);
+    let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let cutoff = policy.max_age.and_then(|age| SystemTime::now().checked_sub(age));
+
+    for entry in entries {
+        let expired = cutoff.is_some_and(|cutoff| entry.used < cutoff);
+        let over_budget = policy.max_bytes.is_some_and(|max| total > max);
+        if !expired && !over_budget {
+            break;
+        }
+        match fs::remove_file(&entry.path) {
+            Ok(()) => {}
+            // Removed concurrently, e.g. by another sidecar
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        total -= entry.size;
+        report.removed += 1;
+        report.freed_bytes += entry.size;
+    }
+    report.remaining_bytes = total;
+    Ok(report)
+}
+
+fn entry_files(dir: &Path) -> io::Result<Vec<StoredFile>> {
+    let mut entries = Vec::new();
+    let shards = match fs::read_dir(dir) {
+        Ok(shards) => shards,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+        Err(e) => return Err(e),
+    };
+    for shard in shards {
+        let shard = shard?;
+        if !shard.file_type()?.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(shard.path())? {
+            let file = file?;
+            let path = file.path();
+            if path.extension().is_none_or(|ext| ext != ENTRY_EXTENSION) {
+                continue;
+            }
+            let metadata = file.metadata()?;
+            entries.push(StoredFile {
+                path,
+                size: metadata.len(),
+                used: metadata.modified()?,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// The key an archived entry is stored under, if its path is one the
+/// cache writes: `<first two hex digits>/<hex key>.entry`
+fn archived_key(path: &str) -> Option<&str> {
+    let (shard, name) = path.split_once('/')?;
+    let key = name.strip_suffix(ENTRY_EXTENSION)?.strip_suffix('.')?;
+    let well_formed = key.len() > 2 && key.starts_with(shard) && shard.len() == 2;
+    (well_formed && key.bytes().all(|b| b.is_ascii_hexdigit())).then_some(key)
+}
+
+/// An entry file found while scanning the cache directory
+struct StoredFile {
+    path: PathBuf,
+    size: u64,
+    /// Last write or hit
+    used: SystemTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> Entry {
+        Entry {
+            code: "export default `<p>x</p>`;\n".to_string(),
+            map: None,
+            dependencies: vec!["a.png".to_string()],
+            warnings: vec![Diagnostic::new("empty-alt-text", "Image has no alt text")],
+        }
+    }
+
+    #[test]
+    fn test_round_trip_and_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path().to_path_buf(), GcPolicy::default());
+        let key = key("a.md", "x\r\n", &TransformOptions::default(), None);
+        assert_eq!(key, super::key("a.md", "x\n", &TransformOptions::default(), None));
+
+        assert!(cache.get(&key).is_none());
+        cache.put(&key, "a.md", &entry()).unwrap();
+        let hit = cache.get(&key).unwrap();
+        assert_eq!(hit.entry, entry());
+        assert!(hit.stored_ms > 0 && hit.stored_ms <= unix_ms(SystemTime::now()));
+
+        // A torn entry is a miss and gets removed
+        let bytes = fs::read(cache.path(&key)).unwrap();
+        fs::write(cache.path(&key), &bytes[..bytes.len() - 4]).unwrap();
+        assert!(cache.get(&key).is_none());
+        assert!(!cache.path(&key).exists());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.writes), (1, 2, 1));
+        assert!((stats.hit_rate - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_integrity_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path().to_path_buf(), GcPolicy::default());
+        let key = key("a.md", "x", &TransformOptions::default(), None);
+        cache.put(&key, "a.md", &entry()).unwrap();
+        let bytes = fs::read(cache.path(&key)).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(decode(&bytes, &key).map(|hit| hit.entry), Some(entry()));
+
+        // A flipped payload bit fails the checksum
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(decode(&flipped, &key).is_none());
+
+        // So does an entry from another format version
+        let mut old = bytes.clone();
+        old[4..6].copy_from_slice(&(FORMAT_VERSION - 1).to_le_bytes());
+        assert!(decode(&old, &key).is_none());
+
+        // An intact entry written under other engine options is not served
+        let foreign = CacheKey {
+            fingerprint: "0".repeat(64),
+            ..key.clone()
+        };
+        assert!(decode(&bytes, &foreign).is_none());
+    }
+
+    #[test]
+    fn test_collect_evicts_expired_then_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path().to_path_buf(), GcPolicy::default());
+        let now = SystemTime::now();
+        let keys: Vec<CacheKey> = (0..4).map(|i| key(&format!("{}.md", i), "x", &TransformOptions::default(), None)).collect();
+        for (age_days, key) in [30, 3, 2, 1].into_iter().zip(&keys) {
+            cache.put(key, "doc.md", &entry()).unwrap();
+            let used = now - Duration::from_secs(age_days * 86_400);
+            File::options().write(true).open(cache.path(key)).unwrap().set_modified(used).unwrap();
+        }
+        let size = fs::metadata(cache.path(&keys[0])).unwrap().len();
+
+        // A hit makes the 3-day-old entry the most recently used
+        assert!(cache.get(&keys[1]).is_some());
+        let report = cache
+            .collect(GcPolicy {
+                max_bytes: Some(size * 2),
+                max_age: Some(Duration::from_secs(7 * 86_400)),
+            })
+            .unwrap();
+        assert_eq!(report.scanned, 4);
+        assert_eq!(report.removed, 2);
+        assert_eq!(report.remaining_bytes, size * 2);
+        let kept: Vec<bool> = keys.iter().map(|key| cache.path(key).exists()).collect();
+        assert_eq!(kept, vec![false, true, false, true]);
+    }
+
+    #[test]
+    fn test_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let compressed = DiskCache::new(dir.path().join("zstd"), GcPolicy::default());
+        let plain = DiskCache::new(dir.path().join("plain"), GcPolicy::default()).with_compression(0);
+        let mut large = entry();
+        large.code = "<p>Lorem ipsum dolor sit amet</p>\n".repeat(200);
+        let key = key("a.md", "x", &TransformOptions::default(), None);
+        compressed.put(&key, "a.md", &large).unwrap();
+        plain.put(&key, "a.md", &large).unwrap();
+
+        assert_eq!(compressed.get(&key).map(|hit| hit.entry), Some(large.clone()));
+        assert_eq!(plain.get(&key).map(|hit| hit.entry), Some(large));
+        let size = |cache: &DiskCache| fs::metadata(cache.path(&key)).unwrap().len();
+        assert!(size(&compressed) * 5 < size(&plain));
+        assert_eq!(read_header(&compressed.path(&key)).unwrap().file, "a.md");
+    }
+
+    #[test]
+    fn test_usage_and_filtered_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path().to_path_buf(), GcPolicy::default());
+        assert_eq!(cache.usage().unwrap(), CacheUsage::default());
+        for file in ["blog/a.md", "blog/b.md", "docs/c.md"] {
+            cache.put(&key(file, "x", &TransformOptions::default(), None), file, &entry()).unwrap();
+        }
+
+        let usage = cache.usage().unwrap();
+        assert_eq!(usage.entries, 3);
+        assert!(usage.bytes > 0 && usage.oldest_used_ms.is_some());
+
+        let in_blog = |file: &str| file.starts_with("blog/");
+        assert_eq!(cache.clear(Some(&in_blog)).unwrap(), 2);
+        assert_eq!(cache.usage().unwrap().entries, 1);
+        assert_eq!(cache.clear(None).unwrap(), 1);
+        assert_eq!(cache.usage().unwrap().entries, 0);
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = DiskCache::new(dir.path().join("source"), GcPolicy::default());
+        let keys: Vec<CacheKey> = ["a.md", "b.md"].iter().map(|file| key(file, "x", &TransformOptions::default(), None)).collect();
+        for key in &keys {
+            source.put(key, "doc.md", &entry()).unwrap();
+        }
+        let archive = dir.path().join("cache.fmda");
+        let exported = source.export(&archive).unwrap();
+        assert_eq!(exported.entries, 2);
+
+        let target = DiskCache::new(dir.path().join("target"), GcPolicy::default());
+        target.put(&keys[0], "doc.md", &entry()).unwrap();
+        let report = target.import(&archive, false).unwrap();
+        assert_eq!(report, ImportReport { imported: 1, skipped: 1, invalid: 0 });
+        assert_eq!(target.get(&keys[1]).map(|hit| hit.entry), Some(entry()));
+
+        // A damaged entry is left out, the rest still imports
+        let mut bytes = fs::read(&archive).unwrap();
+        bytes[6 + 20] ^= 1;
+        fs::write(&archive, &bytes).unwrap();
+        let fresh = DiskCache::new(dir.path().join("fresh"), GcPolicy::default());
+        assert_eq!(fresh.import(&archive, false).unwrap(), ImportReport { imported: 1, skipped: 0, invalid: 1 });
+
+        fs::write(&archive, b"not an archive").unwrap();
+        assert!(fresh.import(&archive, false).is_err());
+
+        // An entry longer than the file is refused, not allocated
+        let manifest = Manifest {
+            sidecar: String::new(),
+            format_version: FORMAT_VERSION,
+            created_ms: 0,
+            entries: vec![ArchivedFile { path: "ab/abc1.entry".to_string(), bytes: 1 << 50 }],
+        };
+        let manifest = serde_json::to_vec(&manifest).unwrap();
+        let mut bytes = ARCHIVE_MAGIC.to_vec();
+        bytes.extend_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&manifest);
+        bytes.extend_from_slice(&(manifest.len() as u32).to_le_bytes());
+        fs::write(&archive, &bytes).unwrap();
+        let error = fresh.import(&archive, false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(archived_key("ab/abc1.entry"), Some("abc1"));
+        assert_eq!(archived_key("../abc1.entry"), None);
+    }
+
+    #[test]
+    fn test_failures_are_remembered_per_key() {
+        let broken = key("broken.md", "---\ntitle: [\n", &TransformOptions::default(), None);
+        let fixed = key("broken.md", "---\ntitle: x\n---\n", &TransformOptions::default(), None);
+        let error = TransformError::new(crate::diagnostics::ErrorKind::Frontmatter, "Invalid YAML");
+
+        assert!(failure(&broken).is_none());
+        record_failure(&broken, "broken.md", &error);
+        assert!(has_failures());
+        let remembered = failure(&broken).unwrap();
+        assert_eq!(remembered.message, "Invalid YAML");
+        let provenance = remembered.cache.unwrap();
+        assert_eq!(provenance.source, Some(CacheSource::Memory));
+        assert_eq!(provenance.key, broken.hash);
+        assert!(provenance.stored_at.is_some_and(|stored| stored > 0));
+        assert!(failure(&fixed).is_none());
+
+        assert_eq!(forget_failures(|file| file == "other.md"), 0);
+        assert_eq!(forget_failures(|file| file == "broken.md"), 1);
+        assert!(failure(&broken).is_none());
+
+        record_failure(&broken, "broken.md", &error);
+        failures().get_mut(&broken.hash).unwrap().failed_at = Instant::now() - FAILURE_TTL;
+        assert!(failure(&broken).is_none());
+        assert!(!failures().contains_key(&broken.hash));
+    }
+
+    #[test]
+    fn test_failures_are_bounded() {
+        let error = TransformError::new(crate::diagnostics::ErrorKind::Render, "Broken");
+        let started = Instant::now();
+        let failure = |i: u64| Failure {
+            failed_at: started + Duration::from_millis(i),
+            failed_ms: i,
+            file: format!("{}.md", i),
+            error: error.clone(),
+        };
+        let failures = DashMap::new();
+        for i in 0..MAX_FAILURES as u64 + 2 {
+            remember(&failures, i.to_string(), failure(i));
+        }
+        assert_eq!(failures.len(), MAX_FAILURES);
+        assert!(!failures.contains_key("0") && !failures.contains_key("1"));
+        assert!(failures.contains_key("2") && failures.contains_key(&(MAX_FAILURES + 1).to_string()));
+    }
+
+    #[test]
+    fn test_key_covers_options_and_frontmatter() {
+        let minified = TransformOptions {
+            minify: Some(true),
+            ..Default::default()
+        };
+        let base = key("a.md", "x", &TransformOptions::default(), None);
+        assert_ne!(base, key("a.md", "x", &minified, None));
+        assert_ne!(base.fingerprint, key("a.md", "x", &minified, None).fingerprint);
+        assert_eq!(base.fingerprint, key("b.md", "y", &TransformOptions::default(), None).fingerprint);
+        assert_ne!(base, key("b.md", "x", &TransformOptions::default(), None));
+        assert_eq!(base, key("./docs/../a.md", "x", &TransformOptions::default(), None));
+        assert_eq!(key("C:/a.md", "x", &TransformOptions::default(), None), key("c:/a.md", "x", &TransformOptions::default(), None));
+        assert_ne!(base, key("a.md", "x", &TransformOptions::default(), Some(&json!({ "layout": "post" }))));
+
+        let shared = content_key("/site/a.md", "x", &TransformOptions::default(), None);
+        assert_eq!(shared, content_key("/other/checkout/a.md", "x", &TransformOptions::default(), None));
+        assert_ne!(shared, content_key("/other/checkout/b.md", "x", &TransformOptions::default(), None));
+        assert_ne!(shared, content_key("/site/a.mdx", "x", &TransformOptions::default(), None));
+        assert_ne!(shared, content_key("/site/a.md", "y", &TransformOptions::default(), None));
+    }
+}
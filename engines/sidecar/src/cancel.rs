@@ -0,0 +1,140 @@
+//! Request cancellation
+//!
+//! Each request running off the read loop registers a token under its id.
+//! The `cancel` RPC flips the token; long-running handlers poll
+//! [`is_cancelled`] between units of work and stop submitting new tasks;
+//! tasks already submitted carry the token ([`current`]), so workers skip
+//! them or stop rendering at the next safe point.
+//! Ids are scoped to the connection, so clients can only cancel their own
+//! requests even when their id schemes collide.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use dashmap::DashMap;
+
+use crate::protocol::RpcId;
+use crate::session;
+
+/// Request id qualified by the connection that sent it
+type Key = (u64, RpcId);
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    /// Cancelling the parent cancels this token too, but not the reverse
+    parent: Option<Arc<CancelToken>>,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst) || self.parent.as_ref().is_some_and(|parent| parent.is_cancelled())
+    }
+
+    /// A token for part of this one's work, which can be cancelled on its
+    /// own
+    pub fn child(&self) -> CancelToken {
+        CancelToken {
+            cancelled: Arc::default(),
+            parent: Some(Arc::new(self.clone())),
+        }
+    }
+
+    fn same(&self, other: &CancelToken) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+}
+
+static IN_FLIGHT: OnceLock<DashMap<Key, CancelToken>> = OnceLock::new();
+
+fn in_flight() -> &'static DashMap<Key, CancelToken> {
+    IN_FLIGHT.get_or_init(DashMap::new)
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+/// Register a request as in flight and make its token current for this thread
+pub fn register(id: &RpcId) -> CancelToken {
+    let token = CancelToken::default();
+    in_flight().insert((session::connection_id(), id.clone()), token.clone());
+    CURRENT.with(|current| *current.borrow_mut() = Some(token.clone()));
+    token
+}
+
+/// Forget a finished request. A newer request reusing the id keeps its token.
+pub fn unregister(id: &RpcId, token: &CancelToken) {
+    in_flight().remove_if(&(session::connection_id(), id.clone()), |_, registered| registered.same(token));
+    CURRENT.with(|current| *current.borrow_mut() = None);
+}
+
+/// Signal this connection's request with `id` to stop; returns whether it
+/// was in flight
+pub fn cancel(id: &RpcId) -> bool {
+    match in_flight().get(&(session::connection_id(), id.clone())) {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Run `f` on behalf of the request `token` belongs to, so
+/// [`is_cancelled`] checks it; for work done on another thread
+pub fn scope<T>(token: Option<CancelToken>, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.replace(token));
+    let result = f();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Token of the request running on this thread, to hand to work done
+/// elsewhere on its behalf
+pub fn current() -> Option<CancelToken> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Whether the request running on this thread has been cancelled
+pub fn is_cancelled() -> bool {
+    CURRENT.with(|current| current.borrow().as_ref().is_some_and(CancelToken::is_cancelled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_in_flight_request() {
+        let id = RpcId::String("cancel-test".to_string());
+        let token = register(&id);
+        assert!(!is_cancelled());
+        assert!(current().is_some_and(|current| current.same(&token)));
+
+        assert!(cancel(&id));
+        assert!(is_cancelled());
+
+        unregister(&id, &token);
+        assert!(!is_cancelled());
+        assert!(!cancel(&id));
+    }
+
+    #[test]
+    fn test_cancel_is_scoped_to_connection() {
+        let id = RpcId::Number(1);
+        let owner = session::Context::new();
+        let other = session::Context::new();
+        let token = owner.enter(|| register(&id));
+        
+        assert!(!other.enter(|| cancel(&id)));
+        assert!(owner.enter(|| cancel(&id)));
+        assert!(token.is_cancelled());
+        owner.enter(|| unregister(&id, &token));
+    }
+}
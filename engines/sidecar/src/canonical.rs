@@ -0,0 +1,109 @@
+//! Canonical spellings of file paths
+//!
+//! Hosts name the same file in different ways: relative to some working
+//! directory, through a symlinked directory, with `\` or `/`. Anything
+//! keyed by path (cache keys, digests, the dependency graph) uses the
+//! canonical form instead, so all of those spellings land on one key:
+//! symlinks resolved, relative to the project root (the working directory
+//! when `--project-root` isn't set), `/`-separated. Files outside the root
+//! keep their absolute path.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::sandbox;
+use crate::utils::{normalize_path_with, PathStyle};
+
+/// Drive letters are case-insensitive, so they're lowercased in keys
+const STYLE: PathStyle = PathStyle {
+    lowercase_drive: true,
+    ..PathStyle::NATIVE
+};
+
+/// The directory canonical paths are relative to
+pub fn base() -> &'static Path {
+    static CWD: OnceLock<PathBuf> = OnceLock::new();
+    sandbox::root().unwrap_or_else(|| {
+        CWD.get_or_init(|| std::env::current_dir().map(|cwd| resolve(&cwd)).unwrap_or_default())
+    })
+}
+
+/// Canonical form of `path`
+pub fn canonical_path(path: impl AsRef<Path>) -> String {
+    canonical_path_in(base(), path.as_ref())
+}
+
+/// Absolute form of a canonical path, for matching against paths as hosts
+/// spell them
+pub fn absolute_path(canonical: &str) -> String {
+    normalize_path_with(&base().join(canonical).to_string_lossy(), STYLE)
+}
+
+fn canonical_path_in(base: &Path, path: &Path) -> String {
+    let resolved = resolve(path);
+    let relative = resolved.strip_prefix(base).unwrap_or(&resolved);
+    if relative.as_os_str().is_empty() {
+        return ".".to_string();
+    }
+    normalize_path_with(&relative.to_string_lossy(), STYLE)
+}
+
+/// `path` made absolute with symlinks resolved as far as it exists. The
+/// part that doesn't exist (yet) is appended with `.` and `..` applied
+/// lexically.
+pub fn resolve(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let components: Vec<Component> = absolute.components().collect();
+    for existing in (0..=components.len()).rev() {
+        let Ok(mut resolved) = components[..existing].iter().collect::<PathBuf>().canonicalize() else {
+            continue;
+        };
+        for component in &components[existing..] {
+            match component {
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(part) => resolved.push(part),
+                _ => {}
+            }
+        }
+        return resolved;
+    }
+    absolute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_canonical_path_in() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("site/docs")).unwrap();
+        fs::write(dir.path().join("site/docs/a.md"), "# A").unwrap();
+        let base = resolve(&dir.path().join("site"));
+
+        assert_eq!(canonical_path_in(&base, &base.join("docs/a.md")), "docs/a.md");
+        assert_eq!(canonical_path_in(&base, &base.join("docs/./../docs/a.md")), "docs/a.md");
+        // Files that don't exist yet still get a key
+        assert_eq!(canonical_path_in(&base, &base.join("docs/new.md")), "docs/new.md");
+        assert_eq!(canonical_path_in(&base, &base), ".");
+        let outside = resolve(dir.path()).join("other.md");
+        assert_eq!(canonical_path_in(&base, &outside), outside.to_string_lossy().replace('\\', "/"));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(base.join("docs"), dir.path().join("linked")).unwrap();
+            assert_eq!(canonical_path_in(&base, &dir.path().join("linked/a.md")), "docs/a.md");
+        }
+    }
+
+    #[test]
+    fn test_relative_to_working_directory() {
+        assert_eq!(canonical_path("a.md"), "a.md");
+        assert_eq!(canonical_path("./docs/../a.md"), "a.md");
+        assert_eq!(canonical_path(base().join("a.md")), "a.md");
+        assert_eq!(absolute_path("a.md"), normalize_path_with(&base().join("a.md").to_string_lossy(), STYLE));
+    }
+}
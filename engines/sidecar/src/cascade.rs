@@ -0,0 +1,178 @@
+//! Cascading frontmatter defaults
+//!
+//! `_defaults.yaml` / `_defaults.json` files in the directories above a
+//! document supply frontmatter values it doesn't set itself. Nearer files
+//! win over farther ones and the document's own frontmatter wins over all
+//! of them. The search stops at the project root, the first directory
+//! holding `package.json` or `.git`, and never leaves `--project-root`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::diagnostics::Diagnostic;
+use crate::sandbox;
+
+const DEFAULTS_FILES: &[&str] = &["_defaults.yaml", "_defaults.json"];
+const ROOT_MARKERS: &[&str] = &["package.json", ".git"];
+
+/// Frontmatter after applying directory defaults
+#[derive(Debug, Default)]
+pub struct Cascaded {
+    pub frontmatter: Option<Value>,
+    /// Defaults files that contributed, nearest last
+    pub files: Vec<String>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Merge the defaults files found above `document` under its frontmatter
+pub fn apply(document: &Path, frontmatter: Option<Value>) -> Cascaded {
+    let mut cascaded = Cascaded::default();
+    let mut merged: Option<Value> = None;
+
+    for path in defaults_files(document) {
+        let display = path.to_string_lossy().into_owned();
+        match read_defaults(&path) {
+            Ok(Value::Object(values)) => {
+                let base = merged.get_or_insert_with(|| Value::Object(Default::default()));
+                deep_merge(base, Value::Object(values));
+                cascaded.files.push(display);
+            }
+            Ok(_) => cascaded.warnings.push(Diagnostic::new(
+                "invalid-defaults",
+                format!("{} must contain a mapping", display),
+            )),
+            Err(e) => cascaded.warnings.push(Diagnostic::new(
+                "invalid-defaults",
+                format!("Failed to read {}: {}", display, e),
+            )),
+        }
+    }
+
+    cascaded.frontmatter = match (merged, frontmatter) {
+        (Some(mut base), Some(own)) if own.is_object() => {
+            deep_merge(&mut base, own);
+            Some(base)
+        }
+        // Non-mapping frontmatter is already reported; leave it alone
+        (_, Some(own)) => Some(own),
+        (base, None) => base,
+    };
+    cascaded
+}
+
+/// Defaults files from the project root down to the document's directory
+fn defaults_files(document: &Path) -> Vec<PathBuf> {
+    defaults_files_within(document, sandbox::root())
+}
+
+/// Defaults files above `document`, none of them outside `sandbox_root`
+fn defaults_files_within(document: &Path, sandbox_root: Option<&Path>) -> Vec<PathBuf> {
+    let inside = |path: &Path| sandbox_root.is_none_or(|root| sandbox::check_in(root, path).is_ok());
+    let mut found = Vec::new();
+    let mut dir = document.parent();
+    while let Some(current) = dir {
+        let current_or_cwd = if current.as_os_str().is_empty() { Path::new(".") } else { current };
+        // Every directory further up is outside too
+        if !inside(current_or_cwd) {
+            break;
+        }
+        for name in DEFAULTS_FILES.iter().rev() {
+            let candidate = current_or_cwd.join(name);
+            // A symlink may lead out of the sandbox
+            if candidate.is_file() && inside(&candidate) {
+                found.push(candidate);
+            }
+        }
+        if ROOT_MARKERS.iter().any(|marker| current_or_cwd.join(marker).exists()) {
+            break;
+        }
+        dir = current.parent();
+    }
+    found.reverse();
+    found
+}
+
+fn read_defaults(path: &Path) -> Result<Value, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&text).map_err(|e| e.to_string())
+    } else {
+        serde_yaml::from_str(&text).map_err(|e| e.to_string())
+    }
+}
+
+/// Merge `overlay` into `base`; mappings merge key by key, anything else
+/// in `overlay` replaces what `base` had
+fn deep_merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nearest_defaults_win() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("package.json"), "{}").unwrap();
+        fs::write(root.join("_defaults.yaml"), "layout: base\nseo:\n  index: true\n  lang: en\n").unwrap();
+        fs::create_dir_all(root.join("blog/2024")).unwrap();
+        fs::write(root.join("blog/_defaults.json"), r#"{"layout": "post", "seo": {"lang": "ja"}}"#).unwrap();
+        let document = root.join("blog/2024/hello.md");
+
+        let cascaded = apply(&document, Some(json!({ "title": "Hello", "seo": { "index": false } })));
+        assert_eq!(
+            cascaded.frontmatter,
+            Some(json!({ "layout": "post", "title": "Hello", "seo": { "index": false, "lang": "ja" } }))
+        );
+        assert_eq!(cascaded.files.len(), 2);
+        assert!(cascaded.files[1].ends_with("_defaults.json"));
+        assert!(cascaded.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_stops_at_project_root() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("_defaults.yaml"), "layout: outside\n").unwrap();
+        let project = dir.path().join("site");
+        fs::create_dir_all(project.join(".git")).unwrap();
+        fs::write(project.join("_defaults.yaml"), "- not a mapping\n").unwrap();
+
+        let cascaded = apply(&project.join("index.md"), None);
+        assert_eq!(cascaded.frontmatter, None);
+        assert!(cascaded.files.is_empty());
+        assert_eq!(cascaded.warnings[0].code, "invalid-defaults");
+    }
+
+    #[test]
+    fn test_stays_inside_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("_defaults.yaml"), "layout: outside\n").unwrap();
+        let sandbox_root = dir.path().join("site");
+        fs::create_dir_all(sandbox_root.join("docs")).unwrap();
+        fs::write(sandbox_root.join("docs/_defaults.yaml"), "layout: docs\n").unwrap();
+        let sandbox_root = sandbox_root.canonicalize().unwrap();
+
+        // No project marker, so only the sandbox root stops the walk
+        let files = defaults_files_within(&sandbox_root.join("docs/intro.md"), Some(&sandbox_root));
+        assert_eq!(files, vec![sandbox_root.join("docs/_defaults.yaml")]);
+        let files = defaults_files_within(&sandbox_root.join("docs/intro.md"), None);
+        assert_eq!(files.len(), 2);
+    }
+}
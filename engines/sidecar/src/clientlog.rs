@@ -0,0 +1,250 @@
+//! Warnings and errors forwarded to the client as `log` notifications
+//!
+//! A host plugin would rather show the sidecar's problems in its own console
+//! than scrape stderr. A connection that sends `configure` with
+//! `logNotifications: "warn"` (or `"error"`) also gets every warning (or
+//! error) logged on its behalf as a notification:
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "method": "log", "params": {"level": "warn",
+//!   "message": "...", "method": "transformBatch", "file": "docs/a.md"}}
+//! ```
+//!
+//! Events are attributed through the spans they happen in: the request's
+//! `rpc` span names the method and the connection it came from, also on
+//! pool workers, and the innermost span with a `file` field names the
+//! document. Events outside any request go to the connection served on the
+//! logging thread, if any. Logging to stderr or `--log-file` is unchanged.
+
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::filter::dynamic_filter_fn;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::outbox::Outbox;
+use crate::session;
+
+/// Which events a connection is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ForwardLevel {
+    #[default]
+    Off,
+    /// Warnings and errors
+    Warn,
+    Error,
+}
+
+impl ForwardLevel {
+    fn includes(self, level: &Level) -> bool {
+        match self {
+            ForwardLevel::Off => false,
+            ForwardLevel::Warn => *level <= Level::WARN,
+            ForwardLevel::Error => *level == Level::ERROR,
+        }
+    }
+}
+
+struct Forward {
+    level: ForwardLevel,
+    outbox: Outbox,
+}
+
+static CONNECTIONS: OnceLock<DashMap<u64, Forward>> = OnceLock::new();
+/// Whether any connection wants events; until one does, spans aren't
+/// looked at
+static FORWARDING: AtomicBool = AtomicBool::new(false);
+
+fn connections() -> &'static DashMap<u64, Forward> {
+    CONNECTIONS.get_or_init(DashMap::new)
+}
+
+/// Send `connection_id` the events at `level` through `outbox`
+pub fn set(connection_id: u64, level: ForwardLevel, outbox: &Outbox) {
+    let connections = connections();
+    if level == ForwardLevel::Off {
+        connections.remove(&connection_id);
+    } else {
+        connections.insert(connection_id, Forward { level, outbox: outbox.clone() });
+    }
+    FORWARDING.store(!connections.is_empty(), Ordering::Relaxed);
+}
+
+/// Events `connection_id` is sent
+pub fn level(connection_id: u64) -> ForwardLevel {
+    connections().get(&connection_id).map_or(ForwardLevel::Off, |forward| forward.level)
+}
+
+/// Stop sending events to a closed connection, releasing its outbox
+pub fn stop_connection(connection_id: u64) {
+    connections().remove(&connection_id);
+    FORWARDING.store(!connections().is_empty(), Ordering::Relaxed);
+}
+
+/// The layer forwarding events, filtered so it costs nothing while no
+/// connection asked for them
+pub fn layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let filter = dynamic_filter_fn(|metadata, _| FORWARDING.load(Ordering::Relaxed) && relevant(metadata))
+        .with_callsite_filter(|metadata| if relevant(metadata) { Interest::sometimes() } else { Interest::never() });
+    ClientLogLayer.with_filter(filter)
+}
+
+/// Warnings and errors, and the spans that may attribute them
+fn relevant(metadata: &Metadata<'_>) -> bool {
+    if metadata.is_span() {
+        *metadata.level() <= Level::INFO || metadata.fields().field("file").is_some()
+    } else {
+        *metadata.level() <= Level::WARN
+    }
+}
+
+/// What a span tells about the events inside it
+#[derive(Debug, Default)]
+struct Origin {
+    connection: Option<u64>,
+    method: Option<String>,
+    file: Option<String>,
+}
+
+impl Visit for Origin {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "method" => self.method = Some(value.to_string()),
+            "file" => self.file = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        // `%` fields print as Display through Debug
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+/// An event's message, followed by its other fields
+#[derive(Default)]
+struct Message(String);
+
+impl Visit for Message {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0.insert_str(0, value);
+        } else {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+struct ClientLogLayer;
+
+impl<S> Layer<S> for ClientLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut origin = Origin::default();
+        attrs.record(&mut origin);
+        // Request spans are created on the thread serving the connection
+        if attrs.metadata().name() == "rpc" {
+            origin.connection = Some(session::connection_id());
+        }
+        if origin.connection.is_some() || origin.method.is_some() || origin.file.is_some() {
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut().insert(origin);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut origin = Origin::default();
+        // Innermost first
+        for span in ctx.event_scope(event).into_iter().flatten() {
+            if let Some(outer) = span.extensions().get::<Origin>() {
+                origin.connection = origin.connection.or(outer.connection);
+                origin.method = origin.method.or_else(|| outer.method.clone());
+                origin.file = origin.file.or_else(|| outer.file.clone());
+            }
+        }
+        let connection = origin.connection.unwrap_or_else(session::connection_id);
+        let level = event.metadata().level();
+        let outbox = match connections().get(&connection) {
+            Some(forward) if forward.level.includes(level) => forward.outbox.clone(),
+            _ => return,
+        };
+
+        let mut message = Message::default();
+        event.record(&mut message);
+        let mut params = json!({
+            "level": level.as_str().to_lowercase(),
+            "message": message.0,
+        });
+        if let Some(method) = origin.method {
+            params["method"] = Value::String(method);
+        }
+        if let Some(file) = origin.file {
+            params["file"] = Value::String(file);
+        }
+        outbox.notify("log", params);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+    use crate::session::Context as SessionContext;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_forward_events() {
+        let (outbox, notifications) = Outbox::new(Codec::Json);
+        let context = SessionContext::new();
+        let other = SessionContext::new();
+        set(context.connection_id, ForwardLevel::Warn, &outbox);
+        assert_eq!(level(context.connection_id), ForwardLevel::Warn);
+        assert_eq!(level(other.connection_id), ForwardLevel::Off);
+
+        let subscriber = tracing_subscriber::registry().with(layer());
+        tracing::subscriber::with_default(subscriber, || {
+            let rpc = context.enter(|| tracing::info_span!("rpc", id = 1, method = "transformBatch"));
+            // As on a pool worker: no connection on this thread
+            rpc.in_scope(|| {
+                let _task = tracing::trace_span!("task", file = %"docs/a.md").entered();
+                tracing::warn!(worker = 2, "Task {} timed out", 3);
+                tracing::info!("Not forwarded");
+            });
+            context.enter(|| tracing::error!("Outside any request"));
+            other.enter(|| tracing::error!("Another connection's"));
+        });
+        stop_connection(context.connection_id);
+
+        let received: Vec<Value> = notifications.try_iter().map(|body| serde_json::from_slice(&body).unwrap()).collect();
+        assert_eq!(received.len(), 2, "{:?}", received);
+        assert_eq!(received[0]["method"], "log");
+        assert_eq!(received[0]["params"], json!({
+            "level": "warn",
+            "message": "Task 3 timed out worker=2",
+            "method": "transformBatch",
+            "file": "docs/a.md",
+        }));
+        assert_eq!(received[1]["params"], json!({ "level": "error", "message": "Outside any request" }));
+        assert_eq!(level(context.connection_id), ForwardLevel::Off);
+    }
+}
@@ -0,0 +1,68 @@
+//! Message body encoding
+//!
+//! JSON is the default. MessagePack avoids stringifying and re-parsing
+//! multi-megabyte markdown content on both sides of the pipe; since its
+//! bodies are binary it is always sent with Content-Length framing.
+
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    MessagePack,
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Codec::Json),
+            "msgpack" => Ok(Codec::MessagePack),
+            other => Err(format!("unknown codec '{}' (expected json or msgpack)", other)),
+        }
+    }
+}
+
+impl Codec {
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Codec::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            // Named encoding keeps structs as maps, matching the JSON shape
+            Codec::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Codec::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Codec::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    use crate::protocol::{create_response, RpcId};
+
+    #[test]
+    fn test_msgpack_roundtrip() {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "transform", "params": { "content": "# Hi\n" } });
+        let bytes = Codec::MessagePack.encode(&request).unwrap();
+        assert_eq!(Codec::MessagePack.decode::<Value>(&bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_msgpack_response_is_a_map() {
+        let response = create_response(RpcId::Null, json!({ "pong": true }));
+        let bytes = Codec::MessagePack.encode(&response).unwrap();
+        let decoded: Value = Codec::MessagePack.decode(&bytes).unwrap();
+        assert_eq!(decoded, json!({ "jsonrpc": "2.0", "id": null, "result": { "pong": true } }));
+    }
+}
@@ -0,0 +1,306 @@
+//! Project configuration file
+//!
+//! `fastmd.toml` (or `fastmd.json`) in the project root, or the file named
+//! by `--config`, holds settings a project wants every host to share:
+//!
+//! ```toml
+//! [defaults]        # transform options, as in `configure`
+//! baseUrl = "/docs"
+//!
+//! [rules]           # warning code -> "off" | "warn"
+//! missing-alt = "off"
+//!
+//! [cache]
+//! dir = ".fastmd-cache"
+//! maxMb = 512
+//!
+//! [pool]
+//! workers = 4
+//! minWorkers = 1
+//! ```
+//!
+//! Command-line flags (and `FASTMD_*` variables for the pool) override the
+//! file; `configure` requests override its defaults and rules for their
+//! session.
+//!
+//! While a `watch` is running, edits to the file are picked up: the new
+//! file is validated and its defaults and rules replace the old ones for
+//! every session at once. An invalid file leaves the old configuration in
+//! place. Cache and pool settings are only read at startup.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::canonical;
+use crate::session::{self, RuleLevel};
+use crate::transform::TransformOptions;
+
+/// Names looked for in the project root, in order
+pub const FILE_NAMES: &[&str] = &["fastmd.toml", "fastmd.json"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Default transform options of every session
+    #[serde(default)]
+    pub defaults: TransformOptions,
+    /// Warning rules of every session
+    #[serde(default)]
+    pub rules: BTreeMap<String, RuleLevel>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub pool: PoolConfig,
+}
+
+/// Counterparts of the `--cache-*` flags
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Relative to the config file
+    pub dir: Option<PathBuf>,
+    pub max_mb: Option<u64>,
+    pub max_age_days: Option<u64>,
+    pub compression_level: Option<i32>,
+}
+
+/// Counterparts of `FASTMD_WORKERS` and `FASTMD_MIN_WORKERS`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PoolConfig {
+    pub workers: Option<usize>,
+    pub min_workers: Option<usize>,
+}
+
+impl ProjectConfig {
+    /// Read a config file, as JSON if it's named `*.json` and TOML otherwise
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut config: ProjectConfig = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        }
+        .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        if let (Some(dir), Some(parent)) = (&config.cache.dir, path.parent()) {
+            config.cache.dir = Some(parent.join(dir));
+        }
+        Ok(config)
+    }
+}
+
+/// The config file in `dir`, if there is one
+pub fn find(dir: &Path) -> Option<PathBuf> {
+    FILE_NAMES.iter().map(|name| dir.join(name)).find(|path| path.is_file())
+}
+
+/// Where the config file comes from and what was last loaded from it
+struct Active {
+    /// Named by `--config`
+    explicit: Option<PathBuf>,
+    /// Searched for [`FILE_NAMES`] otherwise
+    dir: PathBuf,
+    config: ProjectConfig,
+}
+
+static ACTIVE: OnceLock<Mutex<Active>> = OnceLock::new();
+
+impl Active {
+    fn path(&self) -> Option<PathBuf> {
+        self.explicit.clone().or_else(|| find(&self.dir))
+    }
+}
+
+/// Load the config file named by `explicit`, or the one in `dir`, make its
+/// defaults and rules the project's and remember where it came from for
+/// [`reload`]. No file is an empty config.
+pub fn init(explicit: Option<&Path>, dir: &Path) -> Result<ProjectConfig, String> {
+    let active = Active {
+        explicit: explicit.map(canonical::resolve),
+        dir: canonical::resolve(dir),
+        config: ProjectConfig::default(),
+    };
+    let config = match active.path() {
+        Some(path) => {
+            tracing::info!("Loading config from {}", path.display());
+            ProjectConfig::load(&path)?
+        }
+        None => ProjectConfig::default(),
+    };
+    apply(&config);
+    let _ = ACTIVE.set(Mutex::new(Active { config: config.clone(), ..active }));
+    Ok(config)
+}
+
+fn apply(config: &ProjectConfig) {
+    session::set_project(session::Project {
+        defaults: config.defaults.clone(),
+        rules: config.rules.clone(),
+    });
+}
+
+/// Whether a change to `path` may change the config
+pub fn is_config_file(path: &Path) -> bool {
+    let Some(active) = ACTIVE.get() else {
+        return false;
+    };
+    let active = active.lock();
+    match &active.explicit {
+        Some(explicit) => path == explicit,
+        None => path.parent() == Some(active.dir.as_path())
+            && path.file_name().is_some_and(|name| FILE_NAMES.iter().any(|candidate| name == *candidate)),
+    }
+}
+
+/// Directory watches observe for [`is_config_file`] changes. Editors
+/// often save by replacing the file, so the file itself isn't watched.
+pub fn watched_dir() -> Option<PathBuf> {
+    let active = ACTIVE.get()?.lock();
+    match &active.explicit {
+        Some(explicit) => explicit.parent().map(Path::to_path_buf),
+        None => Some(active.dir.clone()),
+    }
+}
+
+/// What a reload changed
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reload {
+    /// The file now in effect, if any
+    pub path: Option<PathBuf>,
+    /// Default transform options that changed
+    pub options: Vec<String>,
+    /// Warning rules that changed
+    pub rules: Vec<String>,
+    /// Changed sections that only take effect after a restart
+    pub restart_required: Vec<&'static str>,
+}
+
+impl Reload {
+    fn between(old: &ProjectConfig, new: &ProjectConfig, path: Option<PathBuf>) -> Self {
+        let mut restart_required = Vec::new();
+        if old.cache != new.cache {
+            restart_required.push("cache");
+        }
+        if old.pool != new.pool {
+            restart_required.push("pool");
+        }
+        Reload {
+            path,
+            options: changed_keys(&json_object(&old.defaults), &json_object(&new.defaults)),
+            rules: changed_keys(&old.rules, &new.rules),
+            restart_required,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.options.is_empty() && self.rules.is_empty() && self.restart_required.is_empty()
+    }
+
+    /// Whether documents may render differently; only frontmatter
+    /// validation and rules change nothing but warnings
+    pub fn affects_output(&self) -> bool {
+        self.options.iter().any(|option| option != "validateFrontmatter")
+    }
+}
+
+/// Options as a map of the ones that are set
+fn json_object(options: &TransformOptions) -> BTreeMap<String, Value> {
+    match serde_json::to_value(options) {
+        Ok(Value::Object(map)) => map.into_iter().filter(|(_, value)| !value.is_null()).collect(),
+        _ => BTreeMap::new(),
+    }
+}
+
+fn changed_keys<V: PartialEq>(old: &BTreeMap<String, V>, new: &BTreeMap<String, V>) -> Vec<String> {
+    let mut keys: Vec<String> = old
+        .iter()
+        .filter(|(key, value)| new.get(*key) != Some(value))
+        .map(|(key, _)| key.clone())
+        .collect();
+    keys.extend(new.keys().filter(|key| !old.contains_key(*key)).cloned());
+    keys.sort();
+    keys
+}
+
+/// Read the config file again and, if it's valid and changed anything,
+/// make it the active one. `None` if nothing changed, e.g. because
+/// another watch got to the same edit first.
+pub fn reload() -> Result<Option<Reload>, String> {
+    let Some(active) = ACTIVE.get() else {
+        return Ok(None);
+    };
+    let mut active = active.lock();
+    let path = active.path();
+    let config = match &path {
+        Some(path) => ProjectConfig::load(path)?,
+        None => ProjectConfig::default(),
+    };
+    let reload = Reload::between(&active.config, &config, path);
+    if reload.is_empty() {
+        return Ok(None);
+    }
+    apply(&config);
+    active.config = config;
+    Ok(Some(reload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml = dir.path().join("fastmd.toml");
+        fs::write(
+            &toml,
+            "[defaults]\nbaseUrl = \"/docs\"\nminify = true\n\n[rules]\nmissing-alt = \"off\"\n\n\
+             [cache]\ndir = \"cache\"\nmaxMb = 64\n\n[pool]\nworkers = 2\n",
+        )
+        .unwrap();
+        assert_eq!(find(dir.path()), Some(toml.clone()));
+
+        let config = ProjectConfig::load(&toml).unwrap();
+        assert_eq!(config.defaults.base_url.as_deref(), Some("/docs"));
+        assert_eq!(config.defaults.minify, Some(true));
+        assert_eq!(config.rules.get("missing-alt"), Some(&RuleLevel::Off));
+        assert_eq!(config.cache.dir, Some(dir.path().join("cache")));
+        assert_eq!(config.cache.max_mb, Some(64));
+        assert_eq!((config.pool.workers, config.pool.min_workers), (Some(2), None));
+
+        let json = dir.path().join("fastmd.json");
+        fs::write(&json, r#"{ "pool": { "minWorkers": 1 } }"#).unwrap();
+        assert_eq!(ProjectConfig::load(&json).unwrap().pool.min_workers, Some(1));
+
+        // Typos are errors rather than silently ignored settings
+        fs::write(&json, r#"{ "pool": { "worker": 1 } }"#).unwrap();
+        assert!(ProjectConfig::load(&json).unwrap_err().contains("unknown field"));
+    }
+
+    #[test]
+    fn test_reload_diff() {
+        let parse = |text: &str| -> ProjectConfig { toml::from_str(text).unwrap() };
+        let old = parse("[defaults]\nbaseUrl = \"/docs\"\nminify = true\n\n[rules]\nmissing-alt = \"off\"\n");
+        assert!(Reload::between(&old, &old.clone(), None).is_empty());
+
+        let new = parse("[defaults]\nbaseUrl = \"/guide\"\nminify = true\nsourcemap = true\n\n[pool]\nworkers = 2\n");
+        let reload = Reload::between(&old, &new, None);
+        assert_eq!(reload.options, vec!["baseUrl", "sourcemap"]);
+        assert_eq!(reload.rules, vec!["missing-alt"]);
+        assert_eq!(reload.restart_required, vec!["pool"]);
+        assert!(reload.affects_output());
+
+        // Frontmatter validation only changes warnings
+        let validating = parse("[defaults]\nbaseUrl = \"/docs\"\nminify = true\nvalidateFrontmatter = true\n");
+        let reload = Reload::between(&old, &validating, None);
+        assert_eq!(reload.options, vec!["validateFrontmatter"]);
+        assert!(!reload.affects_output());
+    }
+}
@@ -0,0 +1,339 @@
+//! One client connection
+//!
+//! Decodes incoming message bodies, answers cheap requests in order, runs
+//! the rest on their own threads (so they can be cancelled), and replies
+//! through the connection's [`Outbox`]. Transports only move bytes: stdio
+//! and sockets go through [`serve`], WebSockets feed [`Connection`] directly.
+//!
+//! At most [`MAX_RUNNING_REQUESTS`] requests run off the read loop at once,
+//! across all connections; beyond that they are answered with `POOL_BUSY`
+//! so a client pipelining requests can't exhaust threads.
+
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, error};
+
+use crate::cancel;
+use crate::clientlog;
+use crate::codec::Codec;
+use crate::framing::{Framing, Incoming};
+use crate::outbox::{self, Outbox};
+use crate::parallel;
+use crate::prime;
+use crate::protocol::{self, RpcId, RpcMessage, RpcRequest, RpcResponse};
+use crate::session::Context;
+use crate::watch;
+use crate::watchdog;
+use crate::{handle_notification, handle_request};
+
+/// Requests (or batches) running on their own threads at most
+const MAX_RUNNING_REQUESTS: usize = 256;
+
+static RUNNING_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// A place among the running requests, given back when dropped
+struct Slot;
+
+impl Slot {
+    fn take() -> Option<Slot> {
+        RUNNING_REQUESTS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |running| (running < MAX_RUNNING_REQUESTS).then_some(running + 1))
+            .ok()
+            .map(|_| Slot)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        RUNNING_REQUESTS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// What a client's `shutdown` request stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownScope {
+    /// The whole sidecar, which serves only this client
+    Process,
+    /// This client's connection; the sidecar keeps serving the others
+    Connection,
+}
+
+pub struct Connection {
+    outbox: Outbox,
+    codec: Codec,
+    scope: ShutdownScope,
+    /// This client's session and cancellation scope
+    context: Context,
+    in_flight: Vec<thread::JoinHandle<()>>,
+}
+
+impl Connection {
+    pub fn new(outbox: Outbox, codec: Codec, scope: ShutdownScope) -> Self {
+        Connection {
+            outbox,
+            codec,
+            scope,
+            context: Context::new(),
+            in_flight: Vec::new(),
+        }
+    }
+
+    /// Handle one message body. Returns the `shutdown` request, if this was
+    /// one or a batch held one, so the transport can flush pending replies
+    /// before exiting or closing the connection.
+    pub fn handle_body(&mut self, body: &[u8]) -> Option<RpcRequest> {
+        let context = self.context.clone();
+        context.enter(|| self.handle_message(body))
+    }
+
+    fn handle_message(&mut self, body: &[u8]) -> Option<RpcRequest> {
+        if self.codec == Codec::Json {
+            debug!("Received: {}", String::from_utf8_lossy(body));
+        }
+        watchdog::touch();
+        
+        let value: Value = match self.codec.decode(body) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to parse message: {}", e);
+                self.outbox.send_response(&protocol::create_parse_error());
+                return None;
+            }
+        };
+        
+        // An array is a JSON-RPC batch, answered with one array
+        if let Value::Array(batch) = value {
+            if batch.is_empty() {
+                self.outbox.send_response(&protocol::create_invalid_request(RpcId::Null));
+                return None;
+            }
+            // Shutting down waits for the batch, which answers it in order
+            let shutdown = batch.iter().find_map(shutdown_request);
+            let slot = Slot::take();
+            if slot.is_none() && shutdown.is_none() {
+                refuse_batch(&batch, &self.outbox);
+                return None;
+            }
+            let handle = spawn_batch(batch, self.outbox.clone(), self.context.clone(), slot);
+            if shutdown.is_some() {
+                let _ = handle.join();
+            } else {
+                self.spawn(handle);
+            }
+            return shutdown;
+        }
+        
+        let message = match RpcMessage::deserialize(&value) {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Invalid request: {}", e);
+                // Answer with the message's own id when it has a usable one
+                self.outbox.send_response(&protocol::create_invalid_request(RpcId::from_malformed(&value)));
+                return None;
+            }
+        };
+        
+        match message {
+            RpcMessage::Request(req) if req.method == "shutdown" => {
+                // The process exits without answering; a connection is closed after
+                if self.scope == ShutdownScope::Connection {
+                    self.outbox.send_response(&acknowledge_shutdown(&req));
+                }
+                return Some(req);
+            }
+            RpcMessage::Request(req) if runs_inline(&req.method) => {
+                let response = handle_request(req, &self.outbox);
+                self.outbox.send_response(&response);
+            }
+            RpcMessage::Request(req) => match Slot::take() {
+                Some(slot) => self.spawn(spawn_request(req, self.outbox.clone(), self.context.clone(), slot)),
+                None => self.outbox.send_response(&busy(req.id)),
+            },
+            RpcMessage::Notification(notif) => handle_notification(notif),
+        }
+        None
+    }
+
+    /// Answer a message that was too large to read, so its id is unknown
+    pub fn refuse_too_large(&self, length: u64, limit: u64) {
+        let response = protocol::create_error_response(
+            RpcId::Null,
+            protocol::TRANSFORM_TOO_LARGE,
+            format!("Message is {} bytes, exceeding the {} byte limit", length, limit),
+            Some(json!({ "length": length, "limit": limit })),
+        );
+        self.outbox.send_response(&response);
+    }
+
+    fn spawn(&mut self, handle: thread::JoinHandle<()>) {
+        self.in_flight.retain(|handle| !handle.is_finished());
+        self.in_flight.push(handle);
+    }
+
+    /// Wait for running requests to answer, then release the outbox so the
+    /// writer can drain and stop. Once the transport has flushed the
+    /// replies, a `shutdown` scoped to the process ends it ([`exit_for`]).
+    pub fn finish(self) {
+        // Watches and primes hold outbox clones that would keep the writer alive
+        watch::unwatch_connection(self.context.connection_id);
+        prime::stop_connection(self.context.connection_id);
+        clientlog::stop_connection(self.context.connection_id);
+        for handle in self.in_flight {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Answer requests read from `reader` until end of input.
+/// Replies use the same framing as requests.
+pub fn serve<R, W>(mut reader: R, writer: W, framing: Framing, codec: Codec, scope: ShutdownScope) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write + Send + 'static,
+{
+    let framing = match codec {
+        // Binary bodies can contain newlines
+        Codec::MessagePack => Framing::ContentLength,
+        Codec::Json => framing.detect(&mut reader)?,
+    };
+    let (outbox, outgoing) = Outbox::new(codec);
+    let writer = outbox::spawn_writer(outgoing, writer, framing);
+    let mut connection = Connection::new(outbox, codec, scope);
+    let mut shutdown = None;
+    
+    loop {
+        let body = match framing.read(&mut reader) {
+            Ok(Some(Incoming::Message(b))) => b,
+            Ok(Some(Incoming::TooLarge { length, limit })) => {
+                error!("Skipped a message of {} bytes, over the {} byte limit", length, limit);
+                connection.refuse_too_large(length, limit);
+                continue;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read message: {}", e);
+                // A bad header leaves the stream at an unknown position
+                if framing == Framing::ContentLength {
+                    break;
+                }
+                continue;
+            }
+        };
+        
+        shutdown = connection.handle_body(&body);
+        if shutdown.is_some() {
+            break;
+        }
+    }
+    
+    connection.finish();
+    let _ = writer.join();
+    
+    // Requests already running have answered and been flushed; now exit
+    if let Some(req) = shutdown.filter(|_| scope == ShutdownScope::Process) {
+        exit_for(req, codec);
+    }
+    Ok(())
+}
+
+/// Run the `shutdown` request, which exits the process
+fn exit_for(req: RpcRequest, codec: Codec) {
+    let (outbox, _) = Outbox::new(codec);
+    handle_request(req, &outbox);
+}
+
+/// The `shutdown` request among a batch's members, if there is one
+fn shutdown_request(entry: &Value) -> Option<RpcRequest> {
+    match RpcMessage::deserialize(entry) {
+        Ok(RpcMessage::Request(req)) if req.method == "shutdown" => Some(req),
+        _ => None,
+    }
+}
+
+/// Answer to a `shutdown` that is carried out once its reply is flushed
+fn acknowledge_shutdown(req: &RpcRequest) -> RpcResponse {
+    protocol::create_response(req.id.clone(), Value::Null)
+}
+
+/// Cheap or ordering-sensitive methods are answered on the read loop;
+/// everything else runs on its own thread so it can be cancelled
+fn runs_inline(method: &str) -> bool {
+    matches!(method, "initialize" | "configure" | "cancel" | "ping" | "stats" | "resetStats" | "poolConfigure" | "watch" | "unwatch")
+}
+
+fn spawn_request(req: RpcRequest, outbox: Outbox, context: Context, slot: Slot) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let response = context.enter(|| run_cancellable(req, &outbox));
+        drop(slot);
+        outbox.send_response(&response);
+    })
+}
+
+/// Answer for a request arriving while [`MAX_RUNNING_REQUESTS`] are running
+fn busy(id: RpcId) -> RpcResponse {
+    let message = format!("{} requests are already running; retry later", MAX_RUNNING_REQUESTS);
+    protocol::create_error_response(id, protocol::POOL_BUSY, message, None)
+}
+
+/// Answer each request of a batch that can't be run now with [`busy`]
+fn refuse_batch(batch: &[Value], outbox: &Outbox) {
+    let responses: Vec<RpcResponse> = batch
+        .iter()
+        .filter_map(|entry| match RpcMessage::deserialize(entry) {
+            Ok(RpcMessage::Request(req)) => Some(busy(req.id)),
+            Ok(RpcMessage::Notification(_)) => None,
+            Err(_) => Some(protocol::create_invalid_request(RpcId::from_malformed(entry))),
+        })
+        .collect();
+    if !responses.is_empty() {
+        outbox.send_batch(&responses);
+    }
+}
+
+/// Handle the members of a batch in order and reply with a single array.
+/// Notifications get no entry; a batch of only notifications gets no reply.
+/// Without a `slot` only when it holds `shutdown`, which the read loop waits for.
+fn spawn_batch(batch: Vec<Value>, outbox: Outbox, context: Context, slot: Option<Slot>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut responses = Vec::with_capacity(batch.len());
+        context.enter(|| {
+            for entry in batch {
+                match RpcMessage::deserialize(&entry) {
+                    // Carried out by the transport once the batch is answered
+                    Ok(RpcMessage::Request(req)) if req.method == "shutdown" => responses.push(acknowledge_shutdown(&req)),
+                    Ok(RpcMessage::Request(req)) => responses.push(run_cancellable(req, &outbox)),
+                    Ok(RpcMessage::Notification(notif)) => handle_notification(notif),
+                    Err(_) => responses.push(protocol::create_invalid_request(RpcId::from_malformed(&entry))),
+                }
+            }
+        });
+        drop(slot);
+        if !responses.is_empty() {
+            outbox.send_batch(&responses);
+        }
+    })
+}
+
+/// Handle a request while it is registered for cancellation. A panicking
+/// handler is answered with an internal error rather than left hanging.
+fn run_cancellable(req: RpcRequest, outbox: &Outbox) -> RpcResponse {
+    let id = req.id.clone();
+    let token = cancel::register(&id);
+    let response = panic::catch_unwind(AssertUnwindSafe(|| handle_request(req, outbox)));
+    cancel::unregister(&id, &token);
+    
+    match response {
+        Ok(_) if token.is_cancelled() => protocol::create_cancelled(id),
+        Ok(response) => response,
+        Err(payload) => {
+            let message = parallel::panic_message(payload.as_ref());
+            error!("Request {} panicked: {}", id, message);
+            protocol::create_error_response(id, protocol::INTERNAL_ERROR, format!("Internal error: {}", message), None)
+        }
+    }
+}
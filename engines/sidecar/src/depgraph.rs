@@ -0,0 +1,237 @@
+//! Persistent document dependency graph
+//!
+//! Every transform records which files a document depends on. Together with
+//! the last seen file fingerprints this answers "which outputs must be rebuilt
+//! for this set of changed paths", which is what monorepo task runners need
+//! for incremental builds. Paths are stored in their
+//! [canonical](crate::canonical) form, which is also how documents are
+//! reported back.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::canonical::{absolute_path, canonical_path};
+use crate::fsutil;
+use crate::utils::join_relative;
+
+const GRAPH_FILE: &str = "depgraph.json";
+
+/// Size and modification time of a file as last reported by the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub mtime: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DependencyGraph {
+    /// Document path -> paths it depends on
+    documents: BTreeMap<String, BTreeSet<String>>,
+    /// Last known fingerprint of every tracked file
+    fingerprints: BTreeMap<String, Fingerprint>,
+}
+
+impl DependencyGraph {
+    /// Replace the recorded dependencies of a document
+    pub fn record(&mut self, document: &str, dependencies: impl IntoIterator<Item = String>) {
+        self.documents
+            .insert(canonical_path(document), dependencies.into_iter().map(canonical_path).collect());
+    }
+
+    /// Drop what is known about the paths `matches` accepts, as documents
+    /// and as fingerprinted files; returns how many documents were dropped
+    pub fn forget(&mut self, matches: impl Fn(&str) -> bool) -> usize {
+        let matches = |path: &str| matches(path) || matches(&absolute_path(path));
+        let before = self.documents.len();
+        self.documents.retain(|document, _| !matches(document));
+        self.fingerprints.retain(|path, _| !matches(path));
+        before - self.documents.len()
+    }
+
+    /// Compare fingerprints against the stored ones, returning the paths that
+    /// are new or differ, and remember the new values for the next delta.
+    pub fn diff_fingerprints(&mut self, files: impl IntoIterator<Item = (String, Fingerprint)>) -> Vec<String> {
+        let mut changed = Vec::new();
+        for (path, fingerprint) in files {
+            let path = canonical_path(path);
+            if self.fingerprints.insert(path.clone(), fingerprint) != Some(fingerprint) {
+                changed.push(path);
+            }
+        }
+        changed
+    }
+
+    /// Every document with recorded dependencies
+    pub fn documents(&self) -> Vec<String> {
+        self.documents.keys().cloned().collect()
+    }
+
+    /// Documents that must be rebuilt when `changed` paths change: the changed
+    /// documents themselves plus everything that transitively depends on them.
+    pub fn affected(&self, changed: &[String]) -> Vec<String> {
+        let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (document, deps) in &self.documents {
+            for dep in deps {
+                dependents.entry(dep.as_str()).or_default().push(document.as_str());
+            }
+        }
+
+        let changed: Vec<String> = changed.iter().map(canonical_path).collect();
+        let mut affected = BTreeSet::new();
+        let mut queue: VecDeque<&str> = changed.iter().map(|s| s.as_str()).collect();
+        while let Some(path) = queue.pop_front() {
+            if self.documents.contains_key(path) && !affected.insert(path.to_string()) {
+                continue;
+            }
+            for dependent in dependents.get(path).into_iter().flatten() {
+                if !affected.contains(*dependent) {
+                    queue.push_back(dependent);
+                }
+            }
+        }
+
+        affected.into_iter().collect()
+    }
+
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring corrupt dependency graph {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let bytes = serde_json::to_vec(self).map_err(std::io::Error::other)?;
+        fsutil::write_atomic(path, &bytes)
+    }
+}
+
+struct GraphStore {
+    graph: RwLock<DependencyGraph>,
+    path: Option<PathBuf>,
+}
+
+static STORE: OnceLock<GraphStore> = OnceLock::new();
+
+/// Load the persisted graph from the cache directory, if one is configured.
+/// Must be called before the first use of [`graph`] to take effect.
+pub fn init(cache_dir: Option<&Path>) {
+    STORE.get_or_init(|| {
+        let path = cache_dir.map(|dir| dir.join(GRAPH_FILE));
+        let graph = path.as_deref().map(DependencyGraph::load).unwrap_or_default();
+        GraphStore {
+            graph: RwLock::new(graph),
+            path,
+        }
+    });
+}
+
+/// Process-wide dependency graph
+pub fn graph() -> &'static RwLock<DependencyGraph> {
+    init(None);
+    &STORE.get().expect("dependency graph initialized").graph
+}
+
+/// Write the graph back to the cache directory
+pub fn persist() {
+    let Some(store) = STORE.get() else { return };
+    let Some(path) = &store.path else { return };
+    if let Err(e) = store.graph.read().save(path) {
+        tracing::error!("Failed to persist dependency graph: {}", e);
+    }
+}
+
+/// Resolve a relative specifier (`./x`, `../x`) against the importing file.
+/// Bare and absolute specifiers are returned as-is.
+pub fn resolve_specifier(file: &str, specifier: &str) -> String {
+    if specifier.starts_with("./") || specifier.starts_with("../") {
+        join_relative(file, specifier)
+    } else {
+        specifier.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fp(size: u64, mtime: u64) -> Fingerprint {
+        Fingerprint { size, mtime }
+    }
+
+    #[test]
+    fn test_affected_is_transitive() {
+        let mut graph = DependencyGraph::default();
+        graph.record("/docs/a.mdx", vec!["/docs/b.mdx".to_string()]);
+        graph.record("/docs/b.mdx", vec!["/components/Note.astro".to_string()]);
+        graph.record("/docs/c.md", vec![]);
+
+        assert_eq!(
+            graph.affected(&["/components/Note.astro".to_string()]),
+            vec!["/docs/a.mdx", "/docs/b.mdx"]
+        );
+        assert_eq!(graph.affected(&["/docs/c.md".to_string()]), vec!["/docs/c.md"]);
+        assert!(graph.affected(&["/unrelated.css".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_forget() {
+        let mut graph = DependencyGraph::default();
+        graph.record("/docs/a.mdx", vec!["/docs/b.mdx".to_string()]);
+        graph.record("/docs/b.mdx", vec![]);
+        graph.diff_fingerprints([("/docs/b.mdx".to_string(), fp(1, 1))]);
+
+        assert_eq!(graph.forget(|path| path == "/docs/b.mdx"), 1);
+        // Edges of other documents into it are kept
+        assert_eq!(graph.affected(&["/docs/b.mdx".to_string()]), vec!["/docs/a.mdx"]);
+        assert_eq!(graph.diff_fingerprints([("/docs/b.mdx".to_string(), fp(1, 1))]), vec!["/docs/b.mdx"]);
+    }
+
+    #[test]
+    fn test_paths_are_canonical() {
+        let mut graph = DependencyGraph::default();
+        graph.record("./docs/a.md", vec!["docs/../shared/note.md".to_string()]);
+        let note = crate::canonical::base().join("shared/note.md").to_string_lossy().into_owned();
+        assert_eq!(graph.affected(&[note]), vec!["docs/a.md"]);
+        // Hosts forget documents by the paths they know them as
+        let document = absolute_path("docs/a.md");
+        assert_eq!(graph.forget(|path| path == document), 1);
+    }
+
+    #[test]
+    fn test_fingerprint_deltas() {
+        let mut graph = DependencyGraph::default();
+        let changed = graph.diff_fingerprints(vec![("a.md".to_string(), fp(1, 1)), ("b.md".to_string(), fp(2, 2))]);
+        assert_eq!(changed, vec!["a.md", "b.md"]);
+
+        let changed = graph.diff_fingerprints(vec![("a.md".to_string(), fp(1, 1)), ("b.md".to_string(), fp(2, 3))]);
+        assert_eq!(changed, vec!["b.md"]);
+    }
+
+    #[test]
+    fn test_persistence_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(GRAPH_FILE);
+
+        let mut graph = DependencyGraph::default();
+        graph.record("a.mdx", vec!["b.md".to_string()]);
+        graph.save(&path).unwrap();
+
+        let loaded = DependencyGraph::load(&path);
+        assert_eq!(loaded.affected(&["b.md".to_string()]), vec!["a.mdx"]);
+    }
+
+    #[test]
+    fn test_resolve_specifier() {
+        assert_eq!(resolve_specifier("/site/docs/a.mdx", "./b.mdx"), "/site/docs/b.mdx");
+        assert_eq!(resolve_specifier("/site/docs/a.mdx", "../components/X.astro"), "/site/components/X.astro");
+        assert_eq!(resolve_specifier("/site/docs/a.mdx", "react"), "react");
+    }
+}
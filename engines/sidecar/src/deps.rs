@@ -0,0 +1,107 @@
+//! Watch dependencies of a document
+//!
+//! Collects the local files a document references so the host bundler can
+//! register them for HMR invalidation: images, links to other files, MDX
+//! import specifiers, and imported markdown partials.
+
+use std::collections::BTreeSet;
+
+use pulldown_cmark::{Event, Tag};
+
+use crate::depgraph::resolve_specifier;
+use crate::utils::join_relative;
+
+/// Local files referenced by links and images in a parsed document
+pub fn from_events<'a>(events: impl IntoIterator<Item = &'a Event<'a>>, file: &str) -> BTreeSet<String> {
+    events
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Link { dest_url, .. }) | Event::Start(Tag::Image { dest_url, .. }) => {
+                resolve_local(file, dest_url)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Resolved MDX import specifiers. Relative specifiers (including `.md`/`.mdx`
+/// partials) become paths; bare package specifiers are kept verbatim.
+pub fn from_imports(specifiers: &[String], file: &str) -> BTreeSet<String> {
+    specifiers.iter().map(|spec| resolve_specifier(file, spec)).collect()
+}
+
+/// Resolve a link target to a file path if it refers to a local file.
+/// External URLs, in-page anchors and root-relative routes are skipped.
+pub fn resolve_local(file: &str, url: &str) -> Option<String> {
+    if url.is_empty() || url.starts_with('#') || url.starts_with('/') || url.starts_with('\\') || has_scheme(url) {
+        return None;
+    }
+    let path = url.split(['#', '?']).next().unwrap_or(url);
+    if path.is_empty() {
+        return None;
+    }
+    Some(join_relative(file, &percent_decode(path)))
+}
+
+fn has_scheme(url: &str) -> bool {
+    match url.find(':') {
+        // A single letter before ':' is a Windows drive, not a scheme
+        Some(i) if i > 1 => url[..i]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.'),
+        _ => false,
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                decoded.push(hi << 4 | lo);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::Parser;
+
+    #[test]
+    fn test_from_events() {
+        let md = "![logo](./img/logo.png) [next](../guide/next.md#intro) [ext](https://example.com) [top](#top) [route](/docs/) ![](my%20file.png)";
+        let events: Vec<Event> = Parser::new(md).collect();
+        let deps: Vec<String> = from_events(&events, "/site/docs/index.md").into_iter().collect();
+        assert_eq!(
+            deps,
+            vec!["/site/docs/img/logo.png", "/site/docs/my file.png", "/site/guide/next.md"]
+        );
+    }
+
+    #[test]
+    fn test_from_imports() {
+        let specs = vec!["./Note.astro".to_string(), "../partials/intro.mdx".to_string(), "react".to_string()];
+        let deps: Vec<String> = from_imports(&specs, "/site/docs/a.mdx").into_iter().collect();
+        assert_eq!(deps, vec!["/site/docs/Note.astro", "/site/partials/intro.mdx", "react"]);
+    }
+
+    #[test]
+    fn test_scheme_detection() {
+        assert!(resolve_local("a.md", "mailto:x@example.com").is_none());
+        assert!(resolve_local("a.md", "data:image/png;base64,xyz").is_none());
+        assert_eq!(resolve_local("/a/b.md", "c.md?raw"), Some("/a/c.md".to_string()));
+    }
+}
@@ -0,0 +1,195 @@
+//! `fastmd-sidecar watch <src> --out <dir>`: build, then rebuild on change
+//!
+//! A minimal dev loop without a JS host. After a full [build](crate::build),
+//! the content directory (and the layout directory, if it's elsewhere) is
+//! watched the way a `watch` request would, and each debounced batch of
+//! changes rebuilds only what it touches: the changed documents, the
+//! documents depending on a changed file, and changed assets. Removed files
+//! have their output removed. An edited layout or project config rebuilds
+//! every page, mostly from the cache. Each rebuilt file is logged with how
+//! long it took.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde_json::Value;
+use tracing::{error, info};
+
+use crate::build::{BuildArgs, Site};
+use crate::canonical;
+use crate::codec::Codec;
+use crate::outbox::Outbox;
+use crate::utils::is_document;
+use crate::watch;
+
+#[derive(clap::Args, Debug)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub build: BuildArgs,
+    /// Milliseconds to wait for a burst of changes to settle
+    #[arg(long, default_value_t = watch::DEFAULT_DEBOUNCE_MS)]
+    pub debounce: u64,
+}
+
+/// Build the site, then rebuild it on change until interrupted.
+/// Interrupting is the usual way out, so `persist` is called after every
+/// build rather than on exit.
+pub fn run(args: &WatchArgs, persist: fn()) -> Result<()> {
+    let site = Site::new(&args.build)?;
+    let started = Instant::now();
+    site.build()?.log(&args.build.out, started);
+    persist();
+
+    let (outbox, notifications) = Outbox::new(Codec::Json);
+    let mut roots = vec![site.src.clone()];
+    if site.layouts.dir.is_dir() && !site.layouts.dir.starts_with(&site.src) {
+        roots.push(site.layouts.dir.clone());
+    }
+    let debounce = Duration::from_millis(args.debounce);
+    for root in &roots {
+        watch::watch(root, &["**/*".to_string()], debounce, 0, outbox.clone(), None).map_err(anyhow::Error::msg)?;
+    }
+    info!("Watching {} for changes", site.src.display());
+
+    for body in notifications {
+        let Ok(message) = serde_json::from_slice::<Value>(&body) else {
+            continue;
+        };
+        let params = &message["params"];
+        match message["method"].as_str() {
+            Some("filesChanged") => files_changed(&site, params),
+            // An invalid file was already reported and changes nothing
+            Some("configChanged") if params.get("error").is_none() => rebuild_all(&site),
+            _ => continue,
+        }
+        persist();
+    }
+    Ok(())
+}
+
+/// Rebuild what one batch of `filesChanged` changes touches
+fn files_changed(site: &Site, params: &Value) {
+    let mut documents = BTreeSet::new();
+    let mut layouts_changed = false;
+    for change in params["changes"].as_array().into_iter().flatten() {
+        let path = Path::new(change["path"].as_str().unwrap_or_default());
+        if path.starts_with(&site.layouts.dir) {
+            layouts_changed = true;
+        } else if !site.contains(path) {
+            continue;
+        } else if change["kind"] == "remove" {
+            match site.remove_output(path) {
+                Ok(()) => info!("Removed {}", site.target(path).display()),
+                Err(e) => error!("{:#}", e),
+            }
+        } else if is_document(path) {
+            documents.insert(path.to_path_buf());
+        } else {
+            let started = Instant::now();
+            match site.copy_asset(path) {
+                Ok(()) => info!("Copied {} in {}", site.relative(path).display(), millis(started.elapsed())),
+                Err(e) => error!("{:#}", e),
+            }
+        }
+    }
+    if layouts_changed {
+        return rebuild_all(site);
+    }
+    // Dependents are reported by canonical path
+    let dependents = params["affected"].as_array().into_iter().flatten().filter_map(Value::as_str);
+    documents.extend(
+        dependents
+            .map(|path| PathBuf::from(canonical::absolute_path(path)))
+            .filter(|path| path.is_file() && site.contains(path) && is_document(path)),
+    );
+    rebuild(site, &documents.into_iter().collect::<Vec<_>>());
+}
+
+fn rebuild_all(site: &Site) {
+    site.layouts.forget();
+    match site.files() {
+        Ok((documents, _)) => rebuild(site, &documents),
+        Err(e) => error!("{:#}", e),
+    }
+}
+
+fn rebuild(site: &Site, documents: &[PathBuf]) {
+    if documents.is_empty() {
+        return;
+    }
+    let started = Instant::now();
+    let results = site.build_documents(documents);
+    let mut failed = 0;
+    for (path, result) in documents.iter().zip(results) {
+        match result {
+            Ok(page) => info!(
+                "Built {} in {}{}",
+                site.relative(path).display(),
+                millis(page.elapsed),
+                if page.cached { " (cached)" } else { "" }
+            ),
+            Err(e) => {
+                error!("{}: {}", site.relative(path).display(), e);
+                failed += 1;
+            }
+        }
+    }
+    if documents.len() > 1 {
+        info!("Rebuilt {} of {} pages in {}", documents.len() - failed, documents.len(), millis(started.elapsed()));
+    }
+}
+
+fn millis(elapsed: Duration) -> String {
+    format!("{:.1} ms", elapsed.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::BuildFormat;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn test_files_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("content");
+        let out = dir.path().join("dist");
+        fs::create_dir_all(src.join("_layouts")).unwrap();
+        fs::write(src.join("a.md"), "# A\n").unwrap();
+        fs::write(src.join("logo.png"), "png").unwrap();
+        fs::write(src.join("_layouts/doc.html"), "<main>{{ content }}</main>").unwrap();
+        fs::write(src.join("b.md"), "---\nlayout: doc\n---\nB\n").unwrap();
+        let site = Site::new(&BuildArgs {
+            src: src.to_string_lossy().into_owned(),
+            out: out.clone(),
+            format: BuildFormat::Html,
+            layouts: None,
+            base_url: None,
+            minify: false,
+        })
+        .unwrap();
+        site.build().unwrap();
+
+        let path = |name: &str| site.src.join(name).to_string_lossy().into_owned();
+        fs::write(src.join("a.md"), "# A2\n").unwrap();
+        fs::remove_file(src.join("logo.png")).unwrap();
+        files_changed(&site, &json!({
+            "changes": [
+                { "path": path("a.md"), "kind": "modify" },
+                { "path": path("logo.png"), "kind": "remove" },
+                { "path": path("_partials/note.md"), "kind": "create" },
+            ],
+        }));
+        assert!(fs::read_to_string(out.join("a.html")).unwrap().contains("<h1>A2</h1>"));
+        assert!(!out.join("logo.png").exists());
+        assert!(!out.join("_partials").exists());
+
+        // Layouts aren't pages, but every page using them is rebuilt
+        fs::write(src.join("_layouts/doc.html"), "<section>{{ content }}</section>").unwrap();
+        files_changed(&site, &json!({ "changes": [{ "path": path("_layouts/doc.html"), "kind": "modify" }] }));
+        assert_eq!(fs::read_to_string(out.join("b.html")).unwrap(), "<section><p>B</p>\n</section>");
+    }
+}
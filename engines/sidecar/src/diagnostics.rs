@@ -0,0 +1,202 @@
+//! Structured transform errors and warnings
+//!
+//! Errors carry enough position information for host plugins to render a
+//! code frame in their dev overlay without re-parsing the document. Warnings
+//! are non-fatal and travel alongside a successful transform result.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+
+/// Lines of context shown on each side of the offending line
+const EXCERPT_CONTEXT: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorKind {
+    /// Frontmatter block is not valid YAML
+    Frontmatter,
+    /// Markdown or MDX rendering failed
+    Render,
+    /// The request was cancelled before this document was transformed
+    Cancelled,
+    /// Rendering ran past the per-task deadline and was abandoned
+    Timeout,
+    /// The source or rendered output was over the configured size limit
+    TooLarge,
+}
+
+/// A transform failure, sent as `RpcError.data`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransformError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// 1-based line in the original source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// 1-based column, counted in characters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    /// Byte offset in the original source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// Source lines surrounding the error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excerpt: Option<Excerpt>,
+    /// Set when the failure was served from memory rather than rendered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<Box<crate::cache::Provenance>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Excerpt {
+    /// 1-based line number of the first excerpt line
+    pub start_line: usize,
+    pub lines: Vec<String>,
+}
+
+impl TransformError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        TransformError {
+            kind,
+            message: message.into(),
+            line: None,
+            column: None,
+            offset: None,
+            excerpt: None,
+            cache: None,
+        }
+    }
+
+    /// Attach the position of a 0-based line and character column in `source`
+    pub fn at(mut self, source: &str, line: usize, column: usize) -> Self {
+        let lines: Vec<&str> = source.lines().collect();
+        let line = line.min(lines.len().saturating_sub(1));
+
+        let line_start: usize = source.split_inclusive('\n').take(line).map(str::len).sum();
+        let column_bytes: usize = lines
+            .get(line)
+            .map(|text| text.chars().take(column).map(char::len_utf8).sum())
+            .unwrap_or(0);
+
+        let first = line.saturating_sub(EXCERPT_CONTEXT);
+        let last = (line + EXCERPT_CONTEXT + 1).min(lines.len());
+
+        self.line = Some(line + 1);
+        self.column = Some(column + 1);
+        self.offset = Some(line_start + column_bytes);
+        self.excerpt = Some(Excerpt {
+            start_line: first + 1,
+            lines: lines[first..last].iter().map(|l| l.to_string()).collect(),
+        });
+        self
+    }
+}
+
+/// A non-fatal problem found while transforming a document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    /// Stable identifier such as `empty-alt-text`; owned only when read
+    /// back from the disk cache
+    pub code: Cow<'static, str>,
+    pub message: String,
+    /// 1-based line in the original source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// 1-based column, counted in characters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    /// JSON pointer into the frontmatter, for schema violations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            code: Cow::Borrowed(code),
+            message: message.into(),
+            line: None,
+            column: None,
+            path: None,
+        }
+    }
+
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Attach the position of a byte offset in `source`
+    pub fn at_offset(mut self, source: &str, offset: usize) -> Self {
+        let (line, column) = position(source, offset);
+        self.line = Some(line + 1);
+        self.column = Some(column + 1);
+        self
+    }
+
+    /// Move the diagnostic down by `lines`, e.g. past stripped frontmatter
+    pub fn shifted(mut self, lines: usize) -> Self {
+        self.line = self.line.map(|line| line + lines);
+        self
+    }
+}
+
+/// 0-based line and character column of a byte offset
+pub fn position(source: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (before.matches('\n').count(), before[line_start..].chars().count())
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{} at line {} column {}", self.message, line, column),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_and_excerpt() {
+        let source = "---\ntitle: é\nbad: [\n---\n\nbody\n";
+        let err = TransformError::new(ErrorKind::Frontmatter, "unexpected end").at(source, 2, 5);
+
+        assert_eq!(err.line, Some(3));
+        assert_eq!(err.column, Some(6));
+        // "---\n" + "title: é\n" (é is two bytes) + 5 columns
+        assert_eq!(err.offset, Some(4 + 10 + 5));
+
+        let excerpt = err.excerpt.unwrap();
+        assert_eq!(excerpt.start_line, 1);
+        assert_eq!(excerpt.lines, vec!["---", "title: é", "bad: [", "---", ""]);
+    }
+
+    #[test]
+    fn test_diagnostic_position() {
+        let source = "# Title\n\n![](é.png) ![](x.png)\n";
+        let offset = source.rfind("![").unwrap();
+        let warning = Diagnostic::new("empty-alt-text", "Image has no alt text").at_offset(source, offset);
+        assert_eq!((warning.line, warning.column), (Some(3), Some(12)));
+        assert_eq!(warning.shifted(4).line, Some(7));
+    }
+
+    #[test]
+    fn test_display() {
+        let err = TransformError::new(ErrorKind::Render, "boom");
+        assert_eq!(err.to_string(), "boom");
+        assert_eq!(err.at("a\nb", 1, 0).to_string(), "boom at line 2 column 1");
+    }
+}
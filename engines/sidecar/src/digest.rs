@@ -0,0 +1,187 @@
+//! File set digests for `computeDigest` and content hashes for `hashFile`
+//!
+//! The digest is the SHA-256 of one `path|size|mtime` line per file, sorted
+//! by path. Sorting and line rendering are sharded across the worker pool;
+//! the shards are fed to a single hasher in order, so the result is
+//! identical to hashing the lines one by one.
+//!
+//! A salt, when given, is hashed first so that outputs cached under a digest
+//! are invalidated when options or the sidecar binary change.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::parallel::ThreadPool;
+use crate::scan::FileStat;
+use crate::transform::TransformOptions;
+
+/// Files rendered per shard
+const SHARD_SIZE: usize = 4096;
+/// Bytes read at a time when hashing file contents
+const READ_CHUNK: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+/// Content hash of a file
+#[derive(Debug, Clone, Serialize)]
+pub struct FileHash {
+    pub hash: String,
+    pub algorithm: HashAlgorithm,
+    /// Bytes hashed
+    pub size: u64,
+}
+
+/// Hash a file in fixed-size chunks so memory use doesn't grow with its size
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<FileHash> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; READ_CHUNK];
+    let mut size = 0;
+    let mut sha256 = Sha256::new();
+    let mut blake3 = blake3::Hasher::new();
+
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        match algorithm {
+            HashAlgorithm::Sha256 => sha256.update(&buffer[..read]),
+            HashAlgorithm::Blake3 => {
+                blake3.update(&buffer[..read]);
+            }
+        }
+        size += read as u64;
+    }
+
+    let hash = match algorithm {
+        HashAlgorithm::Sha256 => format!("{:x}", sha256.finalize()),
+        HashAlgorithm::Blake3 => blake3.finalize().to_hex().to_string(),
+    };
+    Ok(FileHash { hash, algorithm, size })
+}
+
+/// Salt covering everything besides the files that affects transform output
+pub fn salt(options: &TransformOptions, engine: Option<&str>, extra: Option<&str>) -> String {
+    json!({
+        "sidecar": env!("CARGO_PKG_VERSION"),
+        "renderer": "pulldown-cmark",
+        "engine": engine,
+        "options": options,
+        "salt": extra,
+    })
+    .to_string()
+}
+
+/// Digest of a file set, optionally salted; sorts `files` by path. Shards
+/// are rendered on `pool`'s threads, or on the caller's without one.
+pub fn digest_files(files: &mut [FileStat], salt: Option<&str>, pool: Option<&ThreadPool>) -> String {
+    let shards = match pool {
+        Some(pool) => pool.install(|| {
+            files.par_sort_by(|a, b| a.path.cmp(&b.path));
+            files.par_chunks(SHARD_SIZE).map(render_shard).collect()
+        }),
+        None => {
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            files.chunks(SHARD_SIZE).map(render_shard).collect::<Vec<_>>()
+        }
+    };
+
+    let mut hasher = Sha256::new();
+    if let Some(salt) = salt {
+        hasher.update(format!("salt|{}\n", salt).as_bytes());
+    }
+    for shard in &shards {
+        hasher.update(shard);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn render_shard(shard: &[FileStat]) -> Vec<u8> {
+    let mut lines = Vec::with_capacity(shard.len() * 64);
+    for file in shard {
+        // Writing to a Vec cannot fail
+        let _ = writeln!(lines, "{}|{}|{}", file.path, file.size, file.mtime);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parallel::ThreadPoolBuilder;
+
+    fn file(i: usize) -> FileStat {
+        FileStat {
+            path: format!("content/{:05}.md", i),
+            size: i as u64,
+            mtime: 1_700_000_000_000 + i as u64,
+            hash: None,
+        }
+    }
+
+    #[test]
+    fn test_matches_serial_digest() {
+        let mut files: Vec<FileStat> = (0..SHARD_SIZE * 3 + 17).rev().map(file).collect();
+
+        let mut sorted = files.clone();
+        sorted.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut hasher = Sha256::new();
+        for f in &sorted {
+            hasher.update(format!("{}|{}|{}\n", f.path, f.size, f.mtime).as_bytes());
+        }
+        let serial = format!("{:x}", hasher.finalize());
+
+        let pool = ThreadPoolBuilder::new().workers(4).build();
+        assert_eq!(digest_files(&mut files, None, Some(&pool)), serial);
+        assert_eq!(files[0].path, "content/00000.md");
+        files.reverse();
+        assert_eq!(digest_files(&mut files, None, None), serial);
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_salt_changes_digest() {
+        let mut files: Vec<FileStat> = (0..3).map(file).collect();
+        let plain = digest_files(&mut files, None, None);
+
+        let defaults = salt(&TransformOptions::default(), Some("sidecar"), None);
+        let minified = salt(
+            &TransformOptions { minify: Some(true), ..Default::default() },
+            Some("sidecar"),
+            None,
+        );
+        let salted = digest_files(&mut files, Some(&defaults), None);
+        assert_ne!(salted, plain);
+        assert_ne!(digest_files(&mut files, Some(&minified), None), salted);
+        assert_eq!(digest_files(&mut files, Some(&defaults), None), salted);
+    }
+
+    #[test]
+    fn test_hash_file_across_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("asset.bin");
+        let contents: Vec<u8> = (0..READ_CHUNK * 2 + 5).map(|i| i as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let sha = hash_file(&path, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(sha.hash, format!("{:x}", Sha256::digest(&contents)));
+        assert_eq!(sha.size, contents.len() as u64);
+
+        let blake = hash_file(&path, HashAlgorithm::Blake3).unwrap();
+        assert_eq!(blake.hash, blake3::hash(&contents).to_hex().to_string());
+    }
+}
@@ -0,0 +1,239 @@
+//! Message framing on stdin/stdout
+//!
+//! The default is newline-delimited JSON. `content-length` uses LSP-style
+//! `Content-Length: N\r\n\r\n` headers so payloads never depend on line
+//! boundaries, and `auto` picks whichever the host sends first.
+//!
+//! Messages over [`max_body_bytes`] are skipped without being buffered and
+//! reported as [`Incoming::TooLarge`], so the connection can refuse them
+//! and carry on with the next one.
+
+use std::io::{self, BufRead, Read, Write};
+use std::str::FromStr;
+
+use crate::limits;
+
+const CONTENT_LENGTH: &str = "content-length";
+/// Room a message needs beyond the documents it carries, for the request
+/// envelope and options
+const MESSAGE_HEADROOM: u64 = 1024 * 1024;
+
+/// What reading the next message found
+#[derive(Debug, PartialEq, Eq)]
+pub enum Incoming {
+    Message(Vec<u8>),
+    /// A message of `length` bytes, over the `limit`, that was skipped
+    TooLarge { length: u64, limit: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Ndjson,
+    ContentLength,
+    /// Detect from the first bytes the host sends
+    Auto,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(Framing::Ndjson),
+            "content-length" => Ok(Framing::ContentLength),
+            "auto" => Ok(Framing::Auto),
+            other => Err(format!("unknown framing '{}' (expected ndjson, content-length or auto)", other)),
+        }
+    }
+}
+
+impl Framing {
+    /// Resolve `Auto` by peeking at the input without consuming it
+    pub fn detect<R: BufRead>(self, reader: &mut R) -> io::Result<Framing> {
+        if self != Framing::Auto {
+            return Ok(self);
+        }
+        let buffered = reader.fill_buf()?;
+        let prefix = &buffered[..buffered.len().min(CONTENT_LENGTH.len())];
+        if prefix.eq_ignore_ascii_case(CONTENT_LENGTH.as_bytes()) {
+            Ok(Framing::ContentLength)
+        } else {
+            Ok(Framing::Ndjson)
+        }
+    }
+
+    /// Read the next message body; `None` at end of input. An error leaves
+    /// the stream at an unknown position.
+    pub fn read<R: BufRead>(self, reader: &mut R) -> io::Result<Option<Incoming>> {
+        let max = max_body_bytes();
+        match self {
+            Framing::ContentLength => read_content_length(reader, max),
+            Framing::Ndjson | Framing::Auto => read_line(reader, max),
+        }
+    }
+
+    /// Write a message without flushing, so several can share one write
+    pub fn write<W: Write>(self, writer: &mut W, body: &[u8]) -> io::Result<()> {
+        match self {
+            Framing::ContentLength => {
+                write!(writer, "Content-Length: {}\r\n\r\n", body.len())?;
+                writer.write_all(body)?;
+            }
+            Framing::Ndjson | Framing::Auto => {
+                writer.write_all(body)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R, max: u64) -> io::Result<Option<Incoming>> {
+    loop {
+        let mut line = Vec::new();
+        let mut length = 0;
+        let mut oversized = false;
+        loop {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if available.is_empty() {
+                break;
+            }
+            let newline = available.iter().position(|&b| b == b'\n');
+            let chunk = &available[..newline.map_or(available.len(), |i| i + 1)];
+            length += chunk.len() as u64;
+            // Past the limit the rest of the line is only counted
+            if !oversized {
+                line.extend_from_slice(chunk);
+                if line.trim_ascii_end().len() as u64 > max {
+                    oversized = true;
+                    line = Vec::new();
+                }
+            }
+            let consumed = chunk.len();
+            reader.consume(consumed);
+            if newline.is_some() {
+                break;
+            }
+        }
+        if length == 0 {
+            return Ok(None);
+        }
+        if oversized {
+            return Ok(Some(Incoming::TooLarge { length, limit: max }));
+        }
+        let line = line.trim_ascii_end();
+        if !line.trim_ascii_start().is_empty() {
+            return Ok(Some(Incoming::Message(line.to_vec())));
+        }
+    }
+}
+
+fn read_content_length<R: BufRead>(reader: &mut R, max: u64) -> io::Result<Option<Incoming>> {
+    let mut length = None;
+    let mut saw_header = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return if saw_header {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input ended inside message headers"))
+            } else {
+                Ok(None)
+            };
+        }
+        let header = header.trim_end_matches(['\n', '\r']);
+        if header.is_empty() {
+            // Tolerate stray blank lines between messages
+            if !saw_header {
+                continue;
+            }
+            break;
+        }
+        saw_header = true;
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case(CONTENT_LENGTH) {
+                let parsed = value.trim().parse::<u64>().map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("invalid Content-Length: {}", e))
+                })?;
+                length = Some(parsed);
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header"))?;
+    // Skipped before allocating, so a huge length can't exhaust memory;
+    // the next message starts right after it
+    if length > max {
+        let skipped = io::copy(&mut reader.take(length), &mut io::sink())?;
+        if skipped < length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input ended inside a message body"));
+        }
+        return Ok(Some(Incoming::TooLarge { length, limit: max }));
+    }
+    let mut body = vec![0; length as usize];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Incoming::Message(body)))
+}
+
+/// Largest message body accepted: a document at the input limit, with
+/// room for JSON escaping to double it and for the envelope
+fn max_body_bytes() -> u64 {
+    limits::limits().max_input_bytes.saturating_mul(2).saturating_add(MESSAGE_HEADROOM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn message(body: &[u8]) -> Option<Incoming> {
+        Some(Incoming::Message(body.to_vec()))
+    }
+
+    #[test]
+    fn test_content_length_roundtrip() {
+        let mut out = Vec::new();
+        Framing::ContentLength.write(&mut out, b"{\"a\":\"x\\ny\"}").unwrap();
+        Framing::ContentLength.write(&mut out, "{\"b\":\"é\"}".as_bytes()).unwrap();
+
+        let mut reader = Cursor::new(out);
+        assert_eq!(Framing::Auto.detect(&mut reader).unwrap(), Framing::ContentLength);
+        assert_eq!(Framing::ContentLength.read(&mut reader).unwrap(), message(b"{\"a\":\"x\\ny\"}"));
+        assert_eq!(Framing::ContentLength.read(&mut reader).unwrap(), message("{\"b\":\"é\"}".as_bytes()));
+        assert_eq!(Framing::ContentLength.read(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_ndjson_skips_blank_lines() {
+        let mut reader = Cursor::new("{\"a\":1}\r\n\n{\"b\":2}\n");
+        assert_eq!(Framing::Auto.detect(&mut reader).unwrap(), Framing::Ndjson);
+        assert_eq!(Framing::Ndjson.read(&mut reader).unwrap(), message(b"{\"a\":1}"));
+        assert_eq!(Framing::Ndjson.read(&mut reader).unwrap(), message(b"{\"b\":2}"));
+        assert_eq!(Framing::Ndjson.read(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncated_headers() {
+        let mut reader = Cursor::new("Content-Length: 10\r\n");
+        assert!(Framing::ContentLength.read(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_oversized_body_is_skipped() {
+        let mut reader = Cursor::new("Content-Length: 12\r\n\r\n{\"a\":\"long\"}Content-Length: 2\r\n\r\n{}");
+        assert_eq!(read_content_length(&mut reader, 8).unwrap(), Some(Incoming::TooLarge { length: 12, limit: 8 }));
+        assert_eq!(read_content_length(&mut reader, 8).unwrap(), message(b"{}"));
+
+        // A length the input doesn't have isn't waited on forever
+        let mut reader = Cursor::new(format!("Content-Length: {}\r\n\r\n{{}}", u64::MAX));
+        assert_eq!(read_content_length(&mut reader, 8).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut reader = Cursor::new("{\"a\":\"long\"}\r\n{}\n");
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Some(Incoming::TooLarge { length: 14, limit: 8 }));
+        assert_eq!(read_line(&mut reader, 8).unwrap(), message(b"{}"));
+        assert_eq!(read_line(&mut reader, 8).unwrap(), None);
+    }
+}
@@ -0,0 +1,211 @@
+//! Crash-safe file writes
+//!
+//! Files are written to a temporary sibling and renamed into place, so a
+//! reader (or the next incremental build) only ever sees the old or the new
+//! contents, never a torn write. How hard we try to survive power loss is
+//! controlled by [`Durability`].
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::Duration;
+
+use crate::watchdog;
+
+/// How much effort to spend making writes survive a crash or power loss
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Atomic rename only; data may be lost on power failure but never torn
+    #[default]
+    None,
+    /// fsync the file before renaming it into place
+    FsyncOnClose,
+    /// Additionally fsync the parent directory so the rename itself is durable
+    FsyncDir,
+}
+
+impl FromStr for Durability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Durability::None),
+            "fsync-on-close" => Ok(Durability::FsyncOnClose),
+            "fsync-dir" => Ok(Durability::FsyncDir),
+            other => Err(format!(
+                "unknown durability policy '{}' (expected none, fsync-on-close or fsync-dir)",
+                other
+            )),
+        }
+    }
+}
+
+/// Temporary files untouched for this long are abandoned whoever wrote them
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+static POLICY: AtomicU8 = AtomicU8::new(0);
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Set the process-wide durability policy used by [`write_atomic`]
+pub fn set_durability(durability: Durability) {
+    POLICY.store(durability as u8, Ordering::Relaxed);
+}
+
+pub fn durability() -> Durability {
+    match POLICY.load(Ordering::Relaxed) {
+        1 => Durability::FsyncOnClose,
+        2 => Durability::FsyncDir,
+        _ => Durability::None,
+    }
+}
+
+/// Atomically replace `path` with `contents` using the process-wide policy
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_atomic_with(path, contents, durability())
+}
+
+pub fn write_atomic_with(path: &Path, contents: &[u8], durability: Durability) -> io::Result<()> {
+    write_atomic_streamed(path, durability, |file| file.write_all(contents))
+}
+
+/// Atomically replace `path` with whatever `write` writes, for contents too
+/// large to assemble in memory first
+pub fn write_atomic_streamed(
+    path: &Path,
+    durability: Durability,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    
+    let temp = temp_path(path);
+    let result = (|| {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        write(&mut file)?;
+        if durability != Durability::None {
+            file.sync_all()?;
+        }
+        drop(file);
+        fs::rename(&temp, path)?;
+        if durability == Durability::FsyncDir {
+            sync_dir(dir)?;
+        }
+        Ok(())
+    })();
+    
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Temporary files live next to their target (renames must not cross
+/// filesystems) and carry a `.tmp` suffix so leftovers are recognizable.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let unique = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), unique))
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    // Directories cannot be opened for syncing on this platform
+    Ok(())
+}
+
+/// Remove temporary files left behind by writes interrupted by a crash,
+/// in `dir` and its subdirectories. Files another live process is still
+/// writing are kept unless they've gone untouched for [`STALE_TEMP_AGE`].
+pub fn remove_stale_temp_files(dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            removed += remove_stale_temp_files(&entry.path())?;
+        } else if name.starts_with('.') && name.ends_with(".tmp") && file_type.is_file() && is_stale(&name, &entry)? {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn is_stale(name: &str, entry: &fs::DirEntry) -> io::Result<bool> {
+    let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+    if age > STALE_TEMP_AGE {
+        return Ok(true);
+    }
+    Ok(temp_owner(name).is_some_and(|pid| !watchdog::process_alive(pid)))
+}
+
+/// Pid of the process that named a temporary file, from the
+/// `.{name}.{pid}.{counter}.tmp` pattern of [`temp_path`]
+fn temp_owner(name: &str) -> Option<u32> {
+    let mut parts = name.strip_suffix(".tmp")?.rsplitn(3, '.');
+    parts.next()?.parse::<u64>().ok()?;
+    parts.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic_replaces_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("out.json");
+        
+        write_atomic_with(&path, b"first", Durability::FsyncDir).unwrap();
+        write_atomic_with(&path, b"second", Durability::None).unwrap();
+        
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_remove_stale_temp_files() {
+        // Spawn and reap a child so its pid is known to be gone
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        let live = std::process::id();
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(format!(".out.json.{}.0.tmp", dead)), b"partial").unwrap();
+        fs::write(dir.path().join(format!(".out.json.{}.1.tmp", live)), b"writing").unwrap();
+        fs::write(dir.path().join(".unnamed.tmp"), b"unknown").unwrap();
+        fs::write(dir.path().join("out.json"), b"ok").unwrap();
+        fs::create_dir_all(dir.path().join("transforms/ab")).unwrap();
+        fs::write(dir.path().join(format!("transforms/ab/.abc.json.{}.2.tmp", dead)), b"partial").unwrap();
+        
+        assert_eq!(remove_stale_temp_files(dir.path()).unwrap(), 2);
+        assert!(dir.path().join("out.json").exists());
+        // Another sidecar's write in progress survives
+        assert!(dir.path().join(format!(".out.json.{}.1.tmp", live)).exists());
+        assert!(dir.path().join(".unnamed.tmp").exists());
+    }
+
+    #[test]
+    fn test_temp_owner() {
+        assert_eq!(temp_owner(".out.json.123.4.tmp"), Some(123));
+        assert_eq!(temp_owner(".out.json.tmp"), None);
+    }
+
+    #[test]
+    fn test_parse_durability() {
+        assert_eq!("fsync-dir".parse::<Durability>(), Ok(Durability::FsyncDir));
+        assert!("always".parse::<Durability>().is_err());
+    }
+}
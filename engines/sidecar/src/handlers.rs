@@ -1,25 +1,297 @@
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use sha2::{Sha256, Digest};
-use tracing::debug;
-use pulldown_cmark::{Parser, Options, html};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use fastmd_text::{NormalizeOptions, Transformation};
+use tracing::{debug, warn};
 
-use crate::protocol::{RpcId, RpcResponse, create_response, create_error_response, INVALID_PARAMS, TRANSFORM_ERROR};
+use crate::blocks;
+use crate::cache::{self, GcPolicy};
+use crate::cancel;
+use crate::canonical::canonical_path;
+use crate::cascade::{self, Cascaded};
+use crate::clientlog::{self, ForwardLevel};
+use crate::depgraph::{self, Fingerprint};
+use crate::digest::{self, HashAlgorithm};
+use crate::diagnostics::{Diagnostic, ErrorKind, TransformError};
+use crate::fsutil;
+use crate::hmr::{self, ChangeKind, HmrInfo};
+use crate::limits;
+use crate::linkcheck;
+use crate::metadata::{self, MetadataQuery};
+use crate::metrics;
+use crate::ordering::{link_entries, OrderSpec, OrderedEntry};
+use crate::outbox::Outbox;
+use crate::parallel::process::ChildRenderer;
+use crate::parallel::{self, Chunking, FailureKind, SubmitError, TaskBatch, TaskResult, ThreadPool, TransformTask};
+use crate::prime;
+use crate::protocol::{RpcError, RpcId, RpcResponse, create_response, create_error_response, CACHE_ERROR, INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST, IO_ERROR, PATH_OUTSIDE_ROOT, POOL_BUSY, PROTOCOL_VERSION, TRANSFORM_ERROR, TRANSFORM_TIMEOUT, TRANSFORM_TOO_LARGE};
+use crate::publish::{SkipPolicy, SkipReason};
+use crate::sandbox;
+use crate::scan::{self, FileStat, ScanOptions};
+use crate::search::{SearchIndexBuilder, SearchIndexOptions};
+use crate::schema::FrontmatterSchema;
+use crate::session::{self, RuleLevel};
+use crate::source;
+use crate::store;
+use crate::transform::{extract_frontmatter, frontmatter_warnings, parse_frontmatter, parse_frontmatter_lenient, mdx_has_named_exports, mdx_import_specifiers, output_hash, TransformOptions};
+use crate::transform;
+use crate::utils::{is_document, normalize_path};
+use crate::watch;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InitializeRequest {
+    client_name: Option<String>,
+    protocol_version: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InitializeResponse {
+    name: &'static str,
+    version: &'static str,
+    protocol_version: &'static str,
+    /// Whether the client's protocol version can talk to this sidecar
+    compatible: bool,
+    methods: Vec<&'static str>,
+    engines: Vec<&'static str>,
+    features: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct CancelRequest {
+    /// Id of the request to cancel
+    id: RpcId,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigureRequest {
+    /// Replaces the session's default transform options; the project
+    /// config fills in any left unset
+    defaults: Option<TransformOptions>,
+    /// Replaces the session's warning rules, on top of the project config's
+    rules: Option<BTreeMap<String, RuleLevel>>,
+    /// Shorthand for `defaults.baseUrl`
+    base_url: Option<String>,
+    /// JSON Schema that frontmatter is validated against
+    frontmatter_schema: Option<Value>,
+    /// Also send this connection warnings or errors as `log` notifications
+    log_notifications: Option<ForwardLevel>,
+    /// Clear all session configuration before applying this request
+    #[serde(default)]
+    reset: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigureResponse {
+    defaults: TransformOptions,
+    rules: BTreeMap<String, RuleLevel>,
+    frontmatter_schema: Option<Value>,
+    log_notifications: ForwardLevel,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValidateFrontmatterRequest {
+    /// Document whose frontmatter block is validated
+    content: Option<String>,
+    /// Already parsed frontmatter, validated as is
+    frontmatter: Option<Value>,
+    /// Overrides the configured schema for this call
+    schema: Option<Value>,
+}
 
 #[derive(Debug, Deserialize)]
 struct TransformRequest {
     file: String,
     content: String,
-    #[allow(dead_code)]
-    options: Option<TransformOptions>,
+    #[serde(default)]
+    options: TransformOptions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransformPathRequest {
+    path: String,
+    #[serde(default)]
+    options: TransformOptions,
+    /// Refuse the file above this size; capped at `--max-input-mb`
+    max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransformBatchRequest {
+    items: Vec<BatchItem>,
+    /// Frontmatter-driven rules for leaving documents out
+    #[serde(default)]
+    skip: SkipPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchItem {
+    /// Caller-chosen identifier echoed back in the matching result
+    id: Value,
+    file: String,
+    content: String,
+    #[serde(default)]
+    options: TransformOptions,
+}
+
+#[derive(Debug, Serialize)]
+struct TransformBatchResponse {
+    results: Vec<BatchItemResult>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum BatchItemResult {
+    Success {
+        id: Value,
+        #[serde(flatten)]
+        response: TransformResponse,
+    },
+    Failure {
+        id: Value,
+        error: RpcError,
+    },
+    Skipped {
+        id: Value,
+        skipped: SkipReason,
+    },
+}
+
+/// What became of one document in a batch
+enum DocumentOutcome {
+    Transformed(TransformResponse),
+    Skipped(SkipReason),
+    Failed(TransformError),
+}
+
+impl From<Result<TransformResponse, TransformError>> for DocumentOutcome {
+    fn from(result: Result<TransformResponse, TransformError>) -> Self {
+        match result {
+            Ok(response) => DocumentOutcome::Transformed(response),
+            Err(e) => DocumentOutcome::Failed(e),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransformGlobRequest {
+    patterns: Vec<String>,
+    /// Directory patterns are relative to (default: working directory)
+    root: Option<String>,
+    #[serde(default)]
+    options: TransformOptions,
+    #[serde(default)]
+    output: GlobOutput,
+    #[serde(default = "default_true")]
+    respect_ignore: bool,
+    #[serde(default)]
+    hidden: bool,
+    /// Frontmatter-driven rules for leaving documents out
+    #[serde(default)]
+    skip: SkipPolicy,
+    /// Build a search index from the transformed documents
+    search_index: Option<SearchIndexOptions>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WatchRequest {
+    patterns: Vec<String>,
+    /// Directory patterns are relative to (default: working directory)
+    root: Option<String>,
+    /// Quiet period before buffered changes are reported
+    #[serde(default = "default_debounce_ms")]
+    debounce_ms: u64,
+    /// Re-transform changed documents in the background so the disk cache
+    /// is warm for the next request (default: true when a cache is configured)
+    #[serde(default = "default_true")]
+    revalidate: bool,
+    /// Options revalidated documents are transformed with
+    #[serde(default)]
+    options: TransformOptions,
+}
+
+fn default_debounce_ms() -> u64 {
+    watch::DEFAULT_DEBOUNCE_MS
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UnwatchRequest {
+    watch_id: u64,
 }
 
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-struct TransformOptions {
-    mode: Option<String>,
-    sourcemap: Option<bool>,
-    framework: Option<String>,
+struct GlobOutput {
+    #[serde(default)]
+    mode: OutputMode,
+    /// Output directory for `write` mode; the source tree layout is mirrored
+    dir: Option<String>,
+    #[serde(default = "default_output_extension")]
+    extension: String,
+}
+
+impl Default for GlobOutput {
+    fn default() -> Self {
+        GlobOutput {
+            mode: OutputMode::default(),
+            dir: None,
+            extension: default_output_extension(),
+        }
+    }
+}
+
+fn default_output_extension() -> String {
+    "js".to_string()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum OutputMode {
+    /// Only report success or failure per file
+    None,
+    /// Include the transform result in each notification
+    #[default]
+    Inline,
+    /// Write generated modules to `output.dir`
+    Write,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TransformGlobResponse {
+    files: usize,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    /// The search index, or where it was written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search_index: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyOutputRequest {
+    #[serde(flatten)]
+    transform: TransformRequest,
+    expected_hash: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyOutputResponse {
+    hash: String,
+    stable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matches: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
@@ -28,15 +300,24 @@ struct TransformResponse {
     map: Option<Value>,
     metadata: Option<Value>,
     dependencies: Option<Vec<String>>,
+    warnings: Vec<Diagnostic>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct NormalizeRequest {
     content: String,
     #[serde(default)]
     remove_bom: bool,
+    /// Convert both `\r\n` and lone `\r` to `\n`
     #[serde(default = "default_true")]
     normalize_lf: bool,
+    /// Compose to Unicode normalization form C
+    #[serde(default)]
+    nfc: bool,
+    /// End non-empty content with exactly one newline
+    #[serde(default)]
+    final_newline: bool,
 }
 
 fn default_true() -> bool {
@@ -47,255 +328,1778 @@ fn default_true() -> bool {
 struct NormalizeResponse {
     content: String,
     changed: bool,
+    /// Transformations that changed the content, in the order applied
+    applied: Vec<Transformation>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct ComputeDigestRequest {
-    files: Vec<FileInfo>,
+    #[serde(default)]
+    files: Vec<FileStat>,
+    /// Globs the sidecar expands and stats itself, in addition to `files`
+    #[serde(default)]
+    patterns: Vec<String>,
+    /// Directory patterns are relative to (default: working directory)
+    root: Option<String>,
+    #[serde(default = "default_true")]
+    respect_ignore: bool,
+    #[serde(default)]
+    hidden: bool,
+    /// Mix configuration into the digest so caches keyed by it invalidate
+    /// when options or the sidecar change
+    context: Option<DigestContext>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DigestContext {
+    /// Transform options outputs are built with; session defaults apply
+    #[serde(default)]
+    options: TransformOptions,
+    /// Name of the host-side engine, e.g. `sidecar` or `wasm`
+    engine: Option<String>,
+    /// Free-form salt such as the host plugin version
+    salt: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScanFilesRequest {
+    patterns: Vec<String>,
+    /// Directory patterns are relative to (default: working directory)
+    root: Option<String>,
+    #[serde(default = "default_true")]
+    respect_ignore: bool,
+    #[serde(default)]
+    hidden: bool,
+    /// Also return a SHA-256 of each file's contents
+    #[serde(default)]
+    hash: bool,
 }
 
 #[derive(Debug, Deserialize)]
-struct FileInfo {
+struct HashFileRequest {
     path: String,
-    size: u64,
-    mtime: u64,
+    #[serde(default)]
+    algorithm: HashAlgorithm,
 }
 
 #[derive(Debug, Serialize)]
 struct ComputeDigestResponse {
     digest: String,
+    /// Number of files that went into the digest
+    files: usize,
 }
 
-pub fn handle_ping(id: RpcId) -> RpcResponse {
-    create_response(id, json!({ "pong": true }))
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrderDocumentsRequest {
+    documents: Vec<OrderDocument>,
+    #[serde(default)]
+    order: OrderSpec,
+    page_size: Option<usize>,
 }
 
-pub fn handle_transform(id: RpcId, params: Option<Value>) -> RpcResponse {
+#[derive(Debug, Deserialize)]
+struct OrderDocument {
+    path: String,
+    frontmatter: Option<Value>,
+    /// Raw source; frontmatter is extracted from it when not given explicitly
+    content: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct OrderDocumentsResponse {
+    documents: Vec<OrderedEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AffectedOutputsRequest {
+    #[serde(default)]
+    changed_paths: Vec<String>,
+    /// Current fingerprints; files that differ from the last call count as changed
+    #[serde(default)]
+    files: Vec<FileStat>,
+}
+
+#[derive(Debug, Serialize)]
+struct AffectedOutputsResponse {
+    changed: Vec<String>,
+    affected: Vec<String>,
+}
+
+/// Handshake: report what this binary supports so the host can detect a
+/// mismatched sidecar up front instead of hitting method-not-found later
+pub fn handle_initialize(id: RpcId, params: Option<Value>, methods: &[&'static str]) -> RpcResponse {
+    let req: InitializeRequest = match params {
+        Some(p) => match serde_json::from_value(p) {
+            Ok(r) => r,
+            Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+        },
+        None => InitializeRequest::default(),
+    };
+    
+    if let Some(client) = &req.client_name {
+        debug!("Initialize from {} (protocol {:?})", client, req.protocol_version);
+    }
+    
+    let response = InitializeResponse {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        protocol_version: PROTOCOL_VERSION,
+        compatible: req.protocol_version.as_deref().map(protocol_compatible).unwrap_or(true),
+        methods: methods.to_vec(),
+        engines: vec!["pulldown-cmark"],
+        features: json!({
+            "sourcemap": true,
+            "minify": true,
+            "deterministic": true,
+            "abbreviations": true,
+            "warnings": true,
+            "parallel": parallel::global_pool().is_some(),
+        }),
+    };
+    
+    create_response(id, serde_json::to_value(response).unwrap())
+}
+
+/// Protocol versions are compatible when their major components match
+fn protocol_compatible(client_version: &str) -> bool {
+    let major = |v: &str| v.split('.').next().map(|m| m.trim().to_string());
+    major(client_version) == major(PROTOCOL_VERSION)
+}
+
+/// Cancel an in-flight request. Tasks not yet handed to the pool are dropped
+/// and the original caller receives a "request cancelled" error.
+pub fn handle_cancel(id: RpcId, params: Option<Value>) -> RpcResponse {
     let params = match params {
         Some(p) => p,
         None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
     };
     
-    let req: TransformRequest = match serde_json::from_value(params) {
+    let req: CancelRequest = match serde_json::from_value(params) {
         Ok(r) => r,
         Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
     };
     
-    debug!("Transform request for file: {}", req.file);
+    let cancelled = cancel::cancel(&req.id);
+    debug!("Cancel {:?}: {}", req.id, if cancelled { "signalled" } else { "not in flight" });
+    create_response(id, json!({ "cancelled": cancelled }))
+}
+
+/// Store session-wide defaults applied to every subsequent transform
+pub fn handle_configure(id: RpcId, params: Option<Value>, outbox: &Outbox) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
     
-    // Simple frontmatter extraction
-    let (frontmatter, content) = extract_frontmatter(&req.content);
+    let req: ConfigureRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
     
-    let mut metadata = json!({
-        "file": req.file.clone(),
-    });
+    let schema = match req.frontmatter_schema.map(FrontmatterSchema::compile).transpose() {
+        Ok(s) => s,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, e, None),
+    };
     
-    // Add frontmatter to metadata if present
-    if let Some(fm) = frontmatter {
-        metadata["frontmatter"] = fm;
+    let connection_id = session::connection_id();
+    let shared = session::current();
+    let mut session = shared.write();
+    if req.reset {
+        *session = Default::default();
+        clientlog::stop_connection(connection_id);
+    }
+    if let Some(defaults) = req.defaults {
+        session.defaults = defaults;
+    }
+    if let Some(rules) = req.rules {
+        session.rules = rules;
+    }
+    if req.base_url.is_some() {
+        session.defaults.base_url = req.base_url;
+    }
+    if let Some(schema) = schema {
+        session.frontmatter_schema = Some(Arc::new(schema));
+    }
+    if let Some(level) = req.log_notifications {
+        clientlog::set(connection_id, level, outbox);
     }
     
-    // Determine file type
-    let is_mdx = req.file.ends_with(".mdx");
-    
-    let transformed_code = if is_mdx {
-        // For MDX, we do minimal preprocessing for now
-        // Just extract imports/exports and pass through
-        transform_mdx(&content, &req.file)
-    } else {
-        // For regular markdown, convert to HTML
-        transform_markdown(&content, &req.file)
-    };
-    
-    let response = match transformed_code {
-        Ok(code) => TransformResponse {
-            code,
-            map: None,
-            metadata: Some(metadata),
-            dependencies: None,
-        },
-        Err(e) => {
-            return create_error_response(id, TRANSFORM_ERROR, format!("Transform failed: {}", e), None);
-        }
+    let response = ConfigureResponse {
+        defaults: session.effective_defaults(),
+        rules: session.effective_rules(),
+        frontmatter_schema: session.frontmatter_schema.as_ref().map(|s| s.schema().clone()),
+        log_notifications: clientlog::level(connection_id),
     };
-    
     create_response(id, serde_json::to_value(response).unwrap())
 }
 
-fn transform_markdown(content: &str, file_path: &str) -> Result<String, String> {
-    // Set up options for pulldown-cmark
-    let mut options = Options::empty();
-    options.insert(Options::ENABLE_TABLES);
-    options.insert(Options::ENABLE_FOOTNOTES);
-    options.insert(Options::ENABLE_STRIKETHROUGH);
-    options.insert(Options::ENABLE_TASKLISTS);
-    options.insert(Options::ENABLE_SMART_PUNCTUATION);
+/// Validate frontmatter against the configured (or a supplied) JSON Schema
+pub fn handle_validate_frontmatter(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
     
-    // Parse markdown
-    let parser = Parser::new_ext(content, options);
+    let req: ValidateFrontmatterRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
     
-    // Convert to HTML
-    let mut html_output = String::new();
-    html::push_html(&mut html_output, parser);
+    let schema = match req.schema {
+        Some(schema) => match FrontmatterSchema::compile(schema) {
+            Ok(s) => Arc::new(s),
+            Err(e) => return create_error_response(id, INVALID_PARAMS, e, None),
+        },
+        None => match session::current().read().frontmatter_schema.clone() {
+            Some(s) => s,
+            None => return create_error_response(id, INVALID_PARAMS, "No frontmatter schema configured".to_string(), None),
+        },
+    };
     
-    // Wrap in ES module export
-    let escaped_html = html_output
-        .replace('\\', "\\\\")
-        .replace('`', "\\`")
-        .replace("${", "\\${");
+    let source = req.content.unwrap_or_default();
+    let frontmatter = match req.frontmatter {
+        Some(fm) => Some(fm),
+        None => match parse_frontmatter(&source) {
+            Ok((fm, _)) => fm,
+            Err(e) => return transform_error_response(id, e),
+        },
+    };
     
-    Ok(format!(
-        r#"// Generated from: {}
-export default `{}`;
-"#,
-        file_path,
-        escaped_html
-    ))
+    let diagnostics = schema.validate(frontmatter.as_ref(), &source);
+    create_response(id, json!({
+        "valid": diagnostics.is_empty(),
+        "diagnostics": diagnostics,
+    }))
 }
 
-fn extract_frontmatter(content: &str) -> (Option<Value>, String) {
-    let lines: Vec<&str> = content.lines().collect();
-    
-    // Check if content starts with frontmatter delimiter
-    if lines.is_empty() || lines[0].trim() != "---" {
-        return (None, content.to_string());
+/// Pool, per-method and cache metrics for build summaries and saturation checks
+pub fn handle_stats(id: RpcId) -> RpcResponse {
+    let metrics = metrics::global();
+    create_response(id, json!({
+        "uptimeMs": metrics.uptime().as_millis() as u64,
+        "pool": parallel::global_pool().map(|pool| pool.stats()),
+        "methods": metrics.methods(),
+        "cache": cache::global().map(|cache| cache.stats()),
+        "sharedCache": store::global().map(|store| store.stats()),
+    }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PoolConfigureRequest {
+    /// Most workers running at once
+    workers: Option<usize>,
+    /// Workers kept running while the pool is idle
+    min_workers: Option<usize>,
+    /// Most tasks waiting for a worker; 0 lifts the bound
+    queue_size: Option<usize>,
+}
+
+/// Resize the worker pool while it runs, e.g. to shrink it while the
+/// machine is on battery; answers with the pool's stats after the change
+pub fn handle_pool_configure(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let req: PoolConfigureRequest = match params.map(serde_json::from_value).transpose() {
+        Ok(r) => r.unwrap_or_default(),
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    if req.workers == Some(0) {
+        return create_error_response(id, INVALID_PARAMS, "workers must be at least 1".to_string(), None);
     }
+    let Some(pool) = parallel::global_pool() else {
+        return create_error_response(id, INVALID_REQUEST, "Parallel processing is disabled".to_string(), None);
+    };
     
-    // Find the closing delimiter
-    let mut end_index = None;
-    for (i, line) in lines.iter().enumerate().skip(1) {
-        if line.trim() == "---" {
-            end_index = Some(i);
-            break;
-        }
+    if req.workers.is_some() || req.min_workers.is_some() {
+        let workers = req.workers.unwrap_or_else(|| pool.num_workers());
+        let min_workers = req.min_workers.unwrap_or_else(|| pool.min_workers());
+        pool.resize(workers, min_workers);
     }
-    
-    if let Some(end) = end_index {
-        // Extract YAML content
-        let yaml_content = lines[1..end].join("\n");
-        
-        // Parse YAML to JSON
-        let frontmatter = if let Ok(yaml_value) = serde_yaml::from_str::<serde_json::Value>(&yaml_content) {
-            Some(yaml_value)
-        } else {
-            None
-        };
-        
-        // Return frontmatter and content after the closing delimiter
-        let remaining_content = lines[(end + 1)..].join("\n");
-        (frontmatter, remaining_content)
-    } else {
-        // No closing delimiter found, treat all as content
-        (None, content.to_string())
+    if let Some(size) = req.queue_size {
+        pool.set_queue_size(Some(size).filter(|&size| size > 0));
     }
+    create_response(id, serde_json::to_value(pool.stats()).unwrap())
 }
 
-fn transform_mdx(content: &str, file_path: &str) -> Result<String, String> {
-    // For MDX, we need more complex processing
-    // For now, just do basic preprocessing
-    
-    let mut imports = Vec::new();
-    let mut exports = Vec::new();
-    let mut body_lines = Vec::new();
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheGcRequest {
+    /// Size budget in bytes (default: `--cache-max-mb`)
+    max_bytes: Option<u64>,
+    /// Remove entries unused for this many seconds (default: `--cache-max-age-days`)
+    max_age_secs: Option<u64>,
+}
+
+/// Run cache garbage collection now, with the configured limits unless
+/// the request overrides them
+pub fn handle_cache_gc(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let req: CacheGcRequest = match params.map(serde_json::from_value).transpose() {
+        Ok(r) => r.unwrap_or_default(),
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    let Some(disk_cache) = cache::global() else {
+        return create_error_response(id, CACHE_ERROR, "No cache directory configured".to_string(), None);
+    };
     
-    for line in content.lines() {
-        if line.trim_start().starts_with("import ") {
-            imports.push(line.to_string());
-        } else if line.trim_start().starts_with("export ") && !line.contains("export default") {
-            exports.push(line.to_string());
-        } else {
-            body_lines.push(line);
+    let configured = disk_cache.policy();
+    let policy = GcPolicy {
+        max_bytes: req.max_bytes.or(configured.max_bytes),
+        max_age: req.max_age_secs.map(Duration::from_secs).or(configured.max_age),
+    };
+    match disk_cache.collect(policy) {
+        Ok(report) => create_response(id, serde_json::to_value(report).unwrap()),
+        Err(e) => create_error_response(id, CACHE_ERROR, format!("Cache GC failed: {}", e), None),
+    }
+}
+
+/// Entry count, size and hit rate of the disk cache
+pub fn handle_cache_stats(id: RpcId) -> RpcResponse {
+    let Some(disk_cache) = cache::global() else {
+        return create_error_response(id, CACHE_ERROR, "No cache directory configured".to_string(), None);
+    };
+    match disk_cache.usage() {
+        Ok(usage) => {
+            let mut stats = serde_json::to_value(usage).unwrap();
+            stats["session"] = serde_json::to_value(disk_cache.stats()).unwrap();
+            create_response(id, stats)
         }
+        Err(e) => create_error_response(id, CACHE_ERROR, format!("Failed to read cache: {}", e), None),
     }
-    
-    let body = body_lines.join("\n");
-    
-    // For now, just pass through with minimal structure
-    // In production, this would integrate with MDX compiler
-    let mut result = String::new();
-    
-    result.push_str(&format!("// Generated from: {}\n", file_path));
-    
-    for import in imports {
-        result.push_str(&import);
-        result.push('\n');
+}
+
+/// Documents selected by path and glob, for `cacheClear` and `invalidate`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PathSelection {
+    /// Exact document paths
+    paths: Vec<String>,
+    /// Globs over document paths, relative to `root` when given
+    patterns: Vec<String>,
+    root: Option<String>,
+}
+
+impl PathSelection {
+    fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.patterns.is_empty()
     }
     
-    if !exports.is_empty() {
-        result.push('\n');
-        for export in exports {
-            result.push_str(&export);
-            result.push('\n');
-        }
+    fn matcher(&self) -> Result<impl Fn(&str) -> bool + '_, String> {
+        let patterns = scan::Patterns::new(&self.patterns)?;
+        Ok(move |file: &str| {
+            let path = Path::new(file);
+            let relative = self.root.as_deref().and_then(|root| path.strip_prefix(root).ok()).unwrap_or(path);
+            self.paths.iter().any(|p| p == file) || patterns.is_match(relative)
+        })
     }
+}
+
+/// Remove cache entries, all of them or those of matching documents
+pub fn handle_cache_clear(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let req: PathSelection = match params.map(serde_json::from_value).transpose() {
+        Ok(r) => r.unwrap_or_default(),
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    let Some(disk_cache) = cache::global() else {
+        return create_error_response(id, CACHE_ERROR, "No cache directory configured".to_string(), None);
+    };
+    let matches = match req.matcher() {
+        Ok(m) => m,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid pattern: {}", e), None),
+    };
     
-    // For now, wrap content as template literal
-    // Real MDX would compile JSX here
-    result.push_str("\nexport default `");
-    result.push_str(&body.replace('\\', "\\\\").replace('`', "\\`").replace("${", "\\${"));
-    result.push_str("`;\n");
-    
-    Ok(result)
+    let filtered = !req.is_empty();
+    match disk_cache.clear(if filtered { Some(&matches) } else { None }) {
+        Ok(removed) => create_response(id, json!({ "removed": removed })),
+        Err(e) => create_error_response(id, CACHE_ERROR, format!("Failed to clear cache: {}", e), None),
+    }
 }
 
-pub fn handle_normalize(id: RpcId, params: Option<Value>) -> RpcResponse {
+/// Evict cache entries and everything derived from the selected documents:
+/// dependency graph edges, HMR fingerprints, render fragments, remembered
+/// failures and stored metadata. For hosts that run their own file watchers.
+pub fn handle_invalidate(id: RpcId, params: Option<Value>) -> RpcResponse {
     let params = match params {
         Some(p) => p,
         None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
     };
     
-    let req: NormalizeRequest = match serde_json::from_value(params) {
+    let req: PathSelection = match serde_json::from_value(params) {
         Ok(r) => r,
         Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
     };
-    
-    let mut content = req.content;
-    let mut changed = false;
-    
-    // Remove BOM if requested
-    if req.remove_bom && content.starts_with('\u{FEFF}') {
-        content = content[3..].to_string();
-        changed = true;
-    }
-    
-    // Normalize line endings if requested
-    if req.normalize_lf && content.contains("\r\n") {
-        content = content.replace("\r\n", "\n");
-        changed = true;
+    if req.is_empty() {
+        return create_error_response(id, INVALID_PARAMS, "No paths or patterns given".to_string(), None);
     }
-    
-    let response = NormalizeResponse {
-        content,
-        changed,
+    let matches = match req.matcher() {
+        Ok(m) => m,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid pattern: {}", e), None),
     };
     
-    create_response(id, serde_json::to_value(response).unwrap())
+    let cache_entries = match cache::global().map(|disk_cache| disk_cache.clear(Some(&matches))) {
+        Some(Ok(removed)) => removed,
+        Some(Err(e)) => return create_error_response(id, CACHE_ERROR, format!("Failed to clear cache: {}", e), None),
+        None => 0,
+    };
+    create_response(id, json!({
+        "cacheEntries": cache_entries,
+        "documents": depgraph::graph().write().forget(&matches),
+        "hmr": hmr::tracker().forget(&matches),
+        "fragments": blocks::forget(&matches),
+        "failures": cache::forget_failures(&matches),
+        "metadata": metadata::index().write().forget(&matches),
+    }))
 }
 
-pub fn handle_compute_digest(id: RpcId, params: Option<Value>) -> RpcResponse {
+#[derive(Debug, Default, Deserialize)]
+pub struct LinkGraphRequest {
+    /// Only links from documents in this directory
+    pub directory: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BacklinksRequest {
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckLinksRequest {
+    #[serde(default)]
+    files: Vec<String>,
+    /// Globs expanded under `root`, in addition to `files`
+    #[serde(default)]
+    patterns: Vec<String>,
+    /// Directory patterns are relative to (default: working directory)
+    root: Option<String>,
+    #[serde(default = "default_true")]
+    respect_ignore: bool,
+    #[serde(default)]
+    hidden: bool,
+}
+
+/// Resolve the relative links and anchors of a file set and report the
+/// broken ones with their positions
+pub fn handle_check_links(id: RpcId, params: Option<Value>) -> RpcResponse {
     let params = match params {
         Some(p) => p,
         None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
     };
     
-    let req: ComputeDigestRequest = match serde_json::from_value(params) {
+    let req: CheckLinksRequest = match serde_json::from_value(params) {
         Ok(r) => r,
         Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
     };
     
-    // Sort files by path for stable digest
-    let mut files = req.files;
-    files.sort_by(|a, b| a.path.cmp(&b.path));
-    
-    // Create digest string
-    let mut hasher = Sha256::new();
-    for file in files {
-        hasher.update(format!("{}|{}|{}\n", file.path, file.size, file.mtime).as_bytes());
+    let mut files: Vec<PathBuf> = req.files.iter().map(PathBuf::from).collect();
+    if !req.patterns.is_empty() {
+        let root = req.root.map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+        if let Err(e) = sandbox::check(&root) {
+            return outside_root(id, e);
+        }
+        let scan_options = ScanOptions {
+            respect_ignore: req.respect_ignore,
+            hidden: req.hidden,
+        };
+        match scan::expand(&req.patterns, &root, &scan_options) {
+            Ok(matched) => files.extend(matched),
+            Err(e) => return create_error_response(id, INVALID_PARAMS, e, None),
+        }
     }
     
-    let digest = format!("{:x}", hasher.finalize());
-    
-    let response = ComputeDigestResponse { digest };
-    
-    create_response(id, serde_json::to_value(response).unwrap())
-}
\ No newline at end of file
+    create_response(id, json!(linkcheck::check(&files)))
+}
+
+/// Links between the documents transformed so far
+pub fn handle_link_graph(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let req: LinkGraphRequest = match params.map(serde_json::from_value).transpose() {
+        Ok(req) => req.unwrap_or_default(),
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    let index = metadata::index().read();
+    let edges = index.link_graph(req.directory.as_deref());
+    let nodes: std::collections::BTreeSet<&str> = edges.iter().flat_map(|(from, to)| [*from, *to]).collect();
+    let edges: Vec<Value> = edges.iter().map(|(from, to)| json!({ "from": from, "to": to })).collect();
+    create_response(id, json!({ "nodes": nodes, "edges": edges }))
+}
+
+/// Documents linking to a path, for "linked from" sections
+pub fn handle_backlinks(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let req: BacklinksRequest = match params {
+        Some(p) => match serde_json::from_value(p) {
+            Ok(r) => r,
+            Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+        },
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    let path = normalize_path(&req.path);
+    let index = metadata::index().read();
+    let backlinks: Vec<Value> = index
+        .backlinks(&path)
+        .map(|document| json!({ "file": document.file, "title": document.title() }))
+        .collect();
+    create_response(id, json!({ "path": path, "backlinks": backlinks }))
+}
+
+/// Documents from the metadata store matching tag, directory and date filters
+pub fn handle_query_metadata(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let query: MetadataQuery = match params.map(serde_json::from_value).transpose() {
+        Ok(q) => q.unwrap_or_default(),
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    let index = metadata::index().read();
+    match index.query(&query) {
+        Ok((documents, total)) => create_response(id, json!({ "total": total, "documents": documents })),
+        Err(e) => create_error_response(id, INVALID_PARAMS, e, None),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CacheExportRequest {
+    /// Archive to write
+    path: PathBuf,
+}
+
+/// Pack the disk cache into one archive, e.g. for CI to persist
+pub fn handle_cache_export(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: CacheExportRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    if let Err(e) = sandbox::check(&req.path) {
+        return outside_root(id, e);
+    }
+    let Some(disk_cache) = cache::global() else {
+        return create_error_response(id, CACHE_ERROR, "No cache directory configured".to_string(), None);
+    };
+    match disk_cache.export(&req.path) {
+        Ok(report) => create_response(id, serde_json::to_value(report).unwrap()),
+        Err(e) => create_error_response(id, CACHE_ERROR, format!("Failed to export cache: {}", e), None),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CacheImportRequest {
+    /// Archive written by `cacheExport`
+    path: PathBuf,
+    /// Replace entries that already exist
+    #[serde(default)]
+    overwrite: bool,
+}
+
+/// Restore entries from a `cacheExport` archive
+pub fn handle_cache_import(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: CacheImportRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    if let Err(e) = sandbox::check(&req.path) {
+        return outside_root(id, e);
+    }
+    let Some(disk_cache) = cache::global() else {
+        return create_error_response(id, CACHE_ERROR, "No cache directory configured".to_string(), None);
+    };
+    match disk_cache.import(&req.path, req.overwrite) {
+        Ok(report) => create_response(id, serde_json::to_value(report).unwrap()),
+        Err(e) => create_error_response(id, CACHE_ERROR, format!("Failed to import cache: {}", e), None),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachePrimeRequest {
+    #[serde(default)]
+    files: Vec<String>,
+    /// Globs expanded under `root`, in addition to `files`
+    #[serde(default)]
+    patterns: Vec<String>,
+    /// Directory patterns are relative to (default: working directory)
+    root: Option<String>,
+    #[serde(default)]
+    options: TransformOptions,
+    #[serde(default = "default_true")]
+    respect_ignore: bool,
+    #[serde(default)]
+    hidden: bool,
+}
+
+/// Fill the disk cache in the background; `cachePrime/done` reports when
+/// every file has been transformed
+pub fn handle_cache_prime(id: RpcId, params: Option<Value>, outbox: &Outbox) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: CachePrimeRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    if cache::global().is_none() {
+        return create_error_response(id, CACHE_ERROR, "No cache directory configured".to_string(), None);
+    }
+    
+    let mut files: Vec<PathBuf> = req.files.iter().map(PathBuf::from).collect();
+    if !req.patterns.is_empty() {
+        let root = req.root.map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+        if let Err(e) = sandbox::check(&root) {
+            return outside_root(id, e);
+        }
+        let scan_options = ScanOptions {
+            respect_ignore: req.respect_ignore,
+            hidden: req.hidden,
+        };
+        match scan::expand(&req.patterns, &root, &scan_options) {
+            Ok(matched) => files.extend(matched),
+            Err(e) => return create_error_response(id, INVALID_PARAMS, e, None),
+        }
+    }
+    
+    let queued = files.len();
+    let options = req.options;
+    let prime_id = prime::spawn(files, Some(outbox.clone()), move |path| warm_cache(path, &options));
+    create_response(id, json!({ "primeId": prime_id, "queued": queued }))
+}
+
+/// Transform a file from disk for the cache alone. HMR change tracking is
+/// left alone so the host's next request still sees what changed.
+fn warm_cache(path: &Path, options: &TransformOptions) -> Result<(), String> {
+    let content = source::read_source(path, limits::limits().max_input_bytes).map_err(|e| e.to_string())?;
+    let transform = TransformRequest {
+        file: path.to_string_lossy().into_owned(),
+        content,
+        options: options.clone(),
+    };
+    transform_document(&transform, false, None).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// A document transformed by [`build_document`]
+pub struct BuiltDocument {
+    pub code: String,
+    /// Resolved frontmatter
+    pub frontmatter: Option<Value>,
+    /// Whether the output came from the disk or shared cache
+    pub cached: bool,
+}
+
+/// Transform a document for a static build: through the disk cache like
+/// any request, outside HMR change tracking
+pub fn build_document(file: &str, content: String, options: &TransformOptions) -> Result<BuiltDocument, TransformError> {
+    let transform = TransformRequest {
+        file: file.to_string(),
+        content,
+        options: options.clone(),
+    };
+    let response = transform_document(&transform, false, None)?;
+    let mut metadata = response.metadata.unwrap_or_default();
+    Ok(BuiltDocument {
+        code: response.code,
+        frontmatter: metadata.get_mut("frontmatter").map(Value::take),
+        cached: metadata["cache"]["hit"] == true,
+    })
+}
+
+/// Transform one document outside any request, as `fastmd-sidecar
+/// transform` does; returns what a `transform` request would get
+pub fn transform_file(file: &str, content: String, options: &TransformOptions) -> Result<Value, TransformError> {
+    let transform = TransformRequest {
+        file: file.to_string(),
+        content,
+        options: options.clone(),
+    };
+    let response = transform_document(&transform, false, None)?;
+    Ok(serde_json::to_value(response).unwrap())
+}
+
+/// Clear method and pool metrics, e.g. between benchmark configurations
+pub fn handle_reset_stats(id: RpcId) -> RpcResponse {
+    metrics::global().reset();
+    if let Some(pool) = parallel::global_pool() {
+        pool.reset_stats();
+    }
+    create_response(id, json!({ "reset": true }))
+}
+
+pub fn handle_ping(id: RpcId) -> RpcResponse {
+    create_response(id, json!({ "pong": true }))
+}
+
+pub fn handle_transform(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: TransformRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    debug!("Transform request for file: {}", req.file);
+    
+    match run_transform(&req) {
+        Ok(response) => create_response(id, serde_json::to_value(response).unwrap()),
+        Err(e) => transform_error_response(id, e),
+    }
+}
+
+/// Error response carrying the structured error as `data`
+fn transform_error_response(id: RpcId, error: TransformError) -> RpcResponse {
+    let rpc_error = transform_rpc_error(error);
+    create_error_response(id, rpc_error.code, rpc_error.message, rpc_error.data)
+}
+
+fn transform_rpc_error(error: TransformError) -> RpcError {
+    RpcError {
+        code: match error.kind {
+            ErrorKind::Timeout => TRANSFORM_TIMEOUT,
+            ErrorKind::TooLarge => TRANSFORM_TOO_LARGE,
+            _ => TRANSFORM_ERROR,
+        },
+        message: format!("Transform failed: {}", error),
+        data: Some(serde_json::to_value(error).unwrap()),
+    }
+}
+
+fn run_transform(req: &TransformRequest) -> Result<TransformResponse, TransformError> {
+    transform_document(req, true, parallel::global_pool())
+}
+
+/// Transform one document, rendering a markdown body on `pool` when given
+fn transform_document(req: &TransformRequest, track_changes: bool, pool: Option<&ThreadPool>) -> Result<TransformResponse, TransformError> {
+    let _span = tracing::trace_span!("transform", file = %req.file).entered();
+    let prepared = prepare_document(req)?;
+    let metadata = record_document(req, &prepared, track_changes);
+    let rendered = match cached_document(&prepared) {
+        Some(hit) => Ok(hit),
+        None => render_shared(req, &prepared, pool).map(|rendered| cache_rendered(req, &prepared, rendered)),
+    };
+    finish_document(req, prepared, metadata, rendered)
+}
+
+/// A document with its options, frontmatter and defaults resolved: all
+/// rendering its body depends on
+struct Prepared {
+    session: session::Session,
+    options: TransformOptions,
+    source: String,
+    /// The body below the frontmatter
+    content: String,
+    frontmatter: Option<Value>,
+    warnings: Vec<Diagnostic>,
+    /// Defaults files merged into the frontmatter
+    defaults_files: Vec<String>,
+    /// Set when the disk cache is enabled or failures are remembered
+    cache_key: Option<cache::CacheKey>,
+}
+
+/// A rendered body and, when a cache was consulted, where it came from
+type Rendered = (cache::Entry, Option<cache::Provenance>);
+
+/// Resolve a document's options and frontmatter; fails on documents over
+/// the input limit, or that failed to render recently
+fn prepare_document(req: &TransformRequest) -> Result<Prepared, TransformError> {
+    limits::check_input(req.content.len())?;
+    let session = session::current().read().clone();
+    let options = session.resolve(&req.options);
+    
+    // Line endings depend on the checkout, not the document
+    let source = if options.deterministic() {
+        req.content.replace("\r\n", "\n")
+    } else {
+        req.content.clone()
+    };
+    
+    let _span = tracing::trace_span!("frontmatter").entered();
+    // Invalid frontmatter is reported, and the body rendered without it
+    let (frontmatter, content, invalid) = parse_frontmatter_lenient(&source);
+    let mut warnings = options.warnings();
+    warnings.extend(invalid);
+    warnings.extend(frontmatter_warnings(&source, frontmatter.as_ref()));
+    let cascaded = cascade_defaults(&req.file, frontmatter, &options);
+    let frontmatter = cascaded.frontmatter;
+    warnings.extend(cascaded.warnings);
+    warnings.extend(session.schema_warnings(&options, frontmatter.as_ref(), &source));
+    
+    let cache_key = (cache::global().is_some() || cache::has_failures())
+        .then(|| cache::key(&req.file, &source, &options, frontmatter.as_ref()));
+    // A document that failed recently fails the same way until it changes
+    if let Some(error) = cache_key.as_ref().and_then(cache::failure) {
+        return Err(error);
+    }
+    Ok(Prepared {
+        session,
+        options,
+        source,
+        content,
+        frontmatter,
+        warnings,
+        defaults_files: cascaded.files,
+        cache_key,
+    })
+}
+
+/// Record a prepared document's metadata and, when `track_changes`, its
+/// change for HMR; returns the response metadata so far
+fn record_document(req: &TransformRequest, prepared: &Prepared, track_changes: bool) -> Value {
+    let content = &prepared.content;
+    metadata::record(&req.file, &prepared.source, prepared.frontmatter.as_ref(), content);
+    
+    // Determine file type
+    let is_mdx = req.file.ends_with(".mdx");
+    
+    let change = if track_changes {
+        hmr::tracker().record(
+            &req.file,
+            &prepared.frontmatter.as_ref().map(|fm| fm.to_string()).unwrap_or_default(),
+            content,
+        )
+    } else {
+        ChangeKind::Unchanged
+    };
+    let hmr_info = HmrInfo {
+        // Markdown modules only export a string; MDX named exports may be read by importers
+        accepts_self: !is_mdx || !mdx_has_named_exports(content),
+        dependencies: if is_mdx { mdx_import_specifiers(content) } else { Vec::new() },
+        change,
+        frontmatter_only: change == ChangeKind::Frontmatter,
+    };
+    
+    let mut metadata = json!({
+        "file": req.file.clone(),
+        "hmr": hmr_info,
+    });
+    
+    // Add frontmatter to metadata if present
+    if let Some(fm) = &prepared.frontmatter {
+        metadata["frontmatter"] = fm.clone();
+    }
+    metadata
+}
+
+/// A prepared document's body from the disk cache, if it is there
+fn cached_document(prepared: &Prepared) -> Option<Rendered> {
+    let (disk_cache, key) = cache::global().zip(prepared.cache_key.as_ref())?;
+    let hit = tracing::trace_span!("cache").in_scope(|| disk_cache.get(key))?;
+    let provenance = cache::Provenance::hit(key, cache::CacheSource::Disk, &hit);
+    Some((hit.entry, Some(provenance)))
+}
+
+/// Store a body missing from the disk cache in it
+fn cache_rendered(req: &TransformRequest, prepared: &Prepared, (entry, shared): Rendered) -> Rendered {
+    let Some((disk_cache, key)) = cache::global().zip(prepared.cache_key.as_ref()) else {
+        return (entry, shared);
+    };
+    if let Err(e) = disk_cache.put(key, &req.file, &entry) {
+        warn!("Failed to write cache entry for {}: {}", req.file, e);
+    }
+    // A shared hit is still worth reporting; a shared miss is a
+    // miss of the project cache first
+    let provenance = shared.filter(|shared| shared.hit).unwrap_or_else(|| cache::Provenance::miss(key));
+    (entry, Some(provenance))
+}
+
+/// The response for a prepared document once its body is rendered. A
+/// failure is remembered unless the render was cancelled or abandoned.
+fn finish_document(
+    req: &TransformRequest,
+    prepared: Prepared,
+    mut metadata: Value,
+    rendered: Result<Rendered, TransformError>,
+) -> Result<TransformResponse, TransformError> {
+    let rendered = rendered.and_then(|(entry, provenance)| {
+        limits::check_output(entry.code.len())?;
+        Ok((entry, provenance))
+    });
+    let (rendered, provenance) = rendered.inspect_err(|error| {
+        if matches!(error.kind, ErrorKind::Cancelled | ErrorKind::Timeout) {
+            return;
+        }
+        let key = prepared.cache_key.clone()
+            .unwrap_or_else(|| cache::key(&req.file, &prepared.source, &prepared.options, prepared.frontmatter.as_ref()));
+        cache::record_failure(&key, &req.file, error);
+    })?;
+    if let Some(provenance) = provenance {
+        metadata["cache"] = json!(provenance);
+    }
+    let code = rendered.code;
+    let map = rendered.map;
+    let mut warnings = prepared.warnings;
+    warnings.extend(rendered.warnings);
+    
+    let mut dependencies = rendered.dependencies;
+    dependencies.extend(prepared.defaults_files);
+    depgraph::graph().write().record(&req.file, dependencies.iter().cloned());
+    
+    if prepared.options.deterministic() {
+        metadata["outputHash"] = json!(output_hash(&code));
+    }
+    
+    Ok(TransformResponse {
+        code,
+        map,
+        metadata: Some(metadata),
+        dependencies: Some(dependencies),
+        warnings: prepared.session.filter_warnings(warnings),
+    })
+}
+
+/// Render a prepared document's body through the shared store, when one
+/// is enabled and the document's output can be relocated; the provenance
+/// is `None` when the store wasn't consulted
+fn render_shared(req: &TransformRequest, prepared: &Prepared, pool: Option<&ThreadPool>) -> Result<Rendered, TransformError> {
+    if let Some(hit) = shared_document(req, prepared) {
+        return Ok(hit);
+    }
+    let entry = match pool {
+        // The pool only renders markdown
+        Some(pool) if !req.file.ends_with(".mdx") => render_on_pool(pool, req, prepared)?,
+        _ => render_inline(req, prepared)?,
+    };
+    Ok(share_rendered(req, prepared, entry))
+}
+
+fn render_inline(req: &TransformRequest, prepared: &Prepared) -> Result<cache::Entry, TransformError> {
+    transform::render_document(
+        &req.file,
+        &prepared.source,
+        &prepared.content,
+        &prepared.options,
+        prepared.frontmatter.as_ref(),
+    )
+}
+
+/// Render a prepared markdown body on the worker pool, under its task
+/// deadline. A pool with no room renders the body here instead, since a
+/// single document shouldn't fail for want of a worker.
+fn render_on_pool(pool: &ThreadPool, req: &TransformRequest, prepared: &Prepared) -> Result<cache::Entry, TransformError> {
+    let mut task = TransformTask::new(req.file.clone(), req.file.clone().into(), prepared.content.clone())
+        .with_options(prepared.options.clone())
+        .with_document(prepared.source.clone(), prepared.frontmatter.clone());
+    if let Some(token) = cancel::current() {
+        task = task.with_cancel(token);
+    }
+    match parallel::runtime().block_on(pool.submit(task)) {
+        Ok(TaskResult::Success { entry, .. }) => Ok(entry),
+        Ok(TaskResult::Failure { error, kind, .. }) => Err(task_error(error, kind)),
+        Err(e) => {
+            debug!("Rendering {} inline: {}", req.file, e);
+            render_inline(req, prepared)
+        }
+    }
+}
+
+/// A prepared document's body from the shared store, if it is there
+fn shared_document(req: &TransformRequest, prepared: &Prepared) -> Option<Rendered> {
+    let (shared, dir) = store::global().zip(store::document_dir(&req.file))?;
+    let key = cache::content_key(&req.file, &prepared.source, &prepared.options, prepared.frontmatter.as_ref());
+    let hit = shared.get(&key)?;
+    let provenance = cache::Provenance::hit(&key, cache::CacheSource::Shared, &hit);
+    Some((store::attach(&hit.entry, dir), Some(provenance)))
+}
+
+/// Store a body rendered after missing the shared store in it
+fn share_rendered(req: &TransformRequest, prepared: &Prepared, entry: cache::Entry) -> Rendered {
+    let Some((shared, dir)) = store::global().zip(store::document_dir(&req.file)) else {
+        return (entry, None);
+    };
+    let key = cache::content_key(&req.file, &prepared.source, &prepared.options, prepared.frontmatter.as_ref());
+    if let Some(object) = store::detach(&entry, dir, &prepared.source, prepared.frontmatter.as_ref()) {
+        if let Err(e) = shared.put(&key, &req.file, &object) {
+            warn!("Failed to write shared cache object for {}: {}", req.file, e);
+        }
+    }
+    (entry, Some(cache::Provenance::miss(&key)))
+}
+
+/// Transform a file the sidecar reads from disk itself
+pub fn handle_transform_path(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: TransformPathRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    debug!("Transform path request for file: {}", req.path);
+    
+    let max_bytes = limits::limits().input_limit(req.max_bytes);
+    let content = match source::read_source(Path::new(&req.path), max_bytes) {
+        Ok(c) => c,
+        Err(e) => {
+            let code = match e {
+                source::ReadError::TooLarge { .. } => TRANSFORM_TOO_LARGE,
+                source::ReadError::OutsideRoot(_) => PATH_OUTSIDE_ROOT,
+                _ => IO_ERROR,
+            };
+            return create_error_response(
+                id,
+                code,
+                format!("Failed to read {}: {}", req.path, e),
+                Some(json!({ "path": req.path })),
+            )
+        }
+    };
+    
+    let transform = TransformRequest {
+        file: req.path,
+        content,
+        options: req.options,
+    };
+    
+    match run_transform(&transform) {
+        Ok(response) => create_response(id, serde_json::to_value(response).unwrap()),
+        Err(e) => transform_error_response(id, e),
+    }
+}
+
+/// Transform many documents in one call, fanning markdown out over the worker pool
+pub fn handle_transform_batch(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: TransformBatchRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    debug!("Transform batch of {} items", req.items.len());
+    
+    let now = match req.skip.now() {
+        Ok(n) => n,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, e, None),
+    };
+    
+    let (ids, documents): (Vec<Value>, Vec<TransformRequest>) = req
+        .items
+        .into_iter()
+        .map(|item| {
+            (item.id, TransformRequest {
+                file: item.file,
+                content: item.content,
+                options: item.options,
+            })
+        })
+        .unzip();
+    
+    let outcomes = match transform_documents(&documents, &req.skip, now) {
+        Ok(outcomes) => outcomes,
+        Err(e) => return create_error_response(id, POOL_BUSY, e.to_string(), None),
+    };
+    let results = ids
+        .into_iter()
+        .zip(outcomes)
+        .map(|(id, outcome)| batch_item_result(id, outcome))
+        .collect();
+    
+    create_response(id, serde_json::to_value(TransformBatchResponse { results }).unwrap())
+}
+
+/// Transform documents as [`transform_document`] would, rendering markdown
+/// bodies on the worker pool, and leaving out those `skip` excludes as of
+/// `now` (Unix seconds). Results are returned in input order. Fails as a
+/// whole when the pool has no room for the work.
+fn transform_documents(documents: &[TransformRequest], skip: &SkipPolicy, now: i64) -> Result<Vec<DocumentOutcome>, SubmitError> {
+    let mut results: Vec<Option<DocumentOutcome>> = (0..documents.len()).map(|_| None).collect();
+    // Documents waiting on the pool, with their metadata so far
+    let mut pending: Vec<Option<(Prepared, Value)>> = (0..documents.len()).map(|_| None).collect();
+    let mut tasks = Vec::new();
+    
+    for (index, document) in documents.iter().enumerate() {
+        let _span = tracing::trace_span!("transform", file = %document.file).entered();
+        let prepared = match prepare_document(document) {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                results[index] = Some(DocumentOutcome::Failed(e));
+                continue;
+            }
+        };
+        if let Some(reason) = skip.skip_reason(prepared.frontmatter.as_ref(), now) {
+            results[index] = Some(DocumentOutcome::Skipped(reason));
+            continue;
+        }
+        
+        let metadata = record_document(document, &prepared, true);
+        let cached = cached_document(&prepared)
+            .or_else(|| shared_document(document, &prepared).map(|shared| cache_rendered(document, &prepared, shared)));
+        if let Some(rendered) = cached {
+            results[index] = Some(finish_document(document, prepared, metadata, Ok(rendered)).into());
+            continue;
+        }
+        if document.file.ends_with(".mdx") {
+            // The pool only renders markdown; MDX passthrough is cheap enough inline
+            let rendered = render_shared(document, &prepared, None).map(|rendered| cache_rendered(document, &prepared, rendered));
+            results[index] = Some(finish_document(document, prepared, metadata, rendered).into());
+            continue;
+        }
+        
+        // Task ids are input positions so results can be matched back
+        let task = TransformTask::new(index.to_string(), document.file.clone().into(), prepared.content.clone())
+            .with_options(prepared.options.clone())
+            .with_document(prepared.source.clone(), prepared.frontmatter.clone());
+        tasks.push(task);
+        pending[index] = Some((prepared, metadata));
+    }
+    
+    // Submit in pool-sized chunks so a cancelled request stops queueing work
+    let mut task_results = Vec::with_capacity(tasks.len());
+    for chunk in pool_chunking().chunk(tasks) {
+        if cancel::is_cancelled() {
+            break;
+        }
+        match parallel::global_pool() {
+            Some(pool) => {
+                let mut batch = TaskBatch::new("batch".to_string(), chunk);
+                if let Some(token) = cancel::current() {
+                    batch = batch.with_cancel(token);
+                }
+                task_results.extend(pool.process_batch(batch)?)
+            }
+            None => task_results.extend(chunk.into_iter().map(process_task_inline)),
+        }
+    }
+    
+    for result in task_results {
+        let Some(index) = result.id().parse::<usize>().ok().filter(|i| *i < results.len()) else {
+            continue;
+        };
+        let Some((prepared, mut metadata)) = pending[index].take() else {
+            continue;
+        };
+        let document = &documents[index];
+        let rendered = match result {
+            TaskResult::Success { entry, duration_ms, worker_id, .. } => {
+                metadata["durationMs"] = json!(duration_ms);
+                metadata["workerId"] = json!(worker_id);
+                let rendered = share_rendered(document, &prepared, entry);
+                Ok(cache_rendered(document, &prepared, rendered))
+            }
+            TaskResult::Failure { error, kind, .. } => Err(task_error(error, kind)),
+        };
+        results[index] = Some(finish_document(document, prepared, metadata, rendered).into());
+    }
+    
+    Ok(results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| {
+                DocumentOutcome::Failed(if cancel::is_cancelled() {
+                    TransformError::new(ErrorKind::Cancelled, "Request cancelled")
+                } else {
+                    TransformError::new(ErrorKind::Render, "Task result was lost")
+                })
+            })
+        })
+        .collect())
+}
+
+/// Apply directory frontmatter defaults when the options ask for them
+fn cascade_defaults(file: &str, frontmatter: Option<Value>, options: &TransformOptions) -> Cascaded {
+    if options.cascade_defaults() {
+        cascade::apply(Path::new(file), frontmatter)
+    } else {
+        Cascaded { frontmatter, ..Default::default() }
+    }
+}
+
+/// How much work is submitted to the pool at once: a chunk per worker
+fn pool_chunking() -> Chunking {
+    let config = parallel::ParallelConfig::from_env();
+    config.chunking.times(parallel::global_pool().map(|p| p.stats().num_workers).unwrap_or(1))
+}
+
+/// The error a document gets for a failed pool task
+fn task_error(error: String, kind: FailureKind) -> TransformError {
+    let kind = match kind {
+        FailureKind::Error => ErrorKind::Render,
+        FailureKind::Timeout => ErrorKind::Timeout,
+        FailureKind::Cancelled => ErrorKind::Cancelled,
+        FailureKind::TooLarge => ErrorKind::TooLarge,
+    };
+    TransformError::new(kind, error)
+}
+
+/// Serial fallback used when the parallel subsystem is disabled
+fn process_task_inline(task: TransformTask) -> TaskResult {
+    let start = std::time::Instant::now();
+    let failure = |error: String, kind: FailureKind| TaskResult::Failure {
+        id: task.id.clone(),
+        error,
+        kind,
+        worker_id: None,
+    };
+    let entry = match task.render() {
+        Ok(entry) => entry,
+        Err(error) if error.kind == ErrorKind::Cancelled => return failure(error.message, FailureKind::Cancelled),
+        Err(error) => return failure(error.message, FailureKind::Error),
+    };
+    if let Err(error) = limits::limits().check_output(entry.code.len()) {
+        return failure(error, FailureKind::TooLarge);
+    }
+    TaskResult::Success {
+        id: task.id,
+        entry,
+        duration_ms: start.elapsed().as_millis() as u64,
+        worker_id: None,
+    }
+}
+
+fn batch_item_result(id: Value, outcome: DocumentOutcome) -> BatchItemResult {
+    match outcome {
+        DocumentOutcome::Transformed(response) => BatchItemResult::Success { id, response },
+        DocumentOutcome::Skipped(reason) => BatchItemResult::Skipped { id, skipped: reason },
+        DocumentOutcome::Failed(e) => BatchItemResult::Failure {
+            id,
+            error: transform_rpc_error(e),
+        },
+    }
+}
+
+/// Transform every file matching the given globs, streaming a
+/// `transformGlob/result` notification per file and answering with a summary
+pub fn handle_transform_glob(id: RpcId, params: Option<Value>, outbox: &Outbox) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: TransformGlobRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    if req.output.mode == OutputMode::Write && req.output.dir.is_none() {
+        return create_error_response(id, INVALID_PARAMS, "output.dir is required for write mode".to_string(), None);
+    }
+    let now = match req.skip.now() {
+        Ok(n) => n,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, e, None),
+    };
+    
+    let root = req.root.map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+    if let Err(e) = sandbox::check(&root) {
+        return outside_root(id, e);
+    }
+    if let Some(Err(e)) = req.output.dir.as_deref().map(|dir| sandbox::check(Path::new(dir))) {
+        return outside_root(id, e);
+    }
+    let index_path = req.search_index.as_ref().and_then(|options| options.path.as_deref());
+    if let Some(Err(e)) = index_path.map(|path| sandbox::check(Path::new(path))) {
+        return outside_root(id, e);
+    }
+    let scan_options = ScanOptions {
+        respect_ignore: req.respect_ignore,
+        hidden: req.hidden,
+    };
+    let files = match scan::expand(&req.patterns, &root, &scan_options) {
+        Ok(f) => f,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, e, None),
+    };
+    
+    debug!("Transform glob matched {} files", files.len());
+    
+    let mut search_index = req.search_index.map(|options| SearchIndexBuilder::new(options, &root));
+    let mut summary = TransformGlobResponse {
+        files: files.len(),
+        ..Default::default()
+    };
+    
+    // Files are read a chunk at a time, sized by what's on disk
+    let chunks = pool_chunking().chunk_by(files, |path| std::fs::metadata(path).map_or(0, |meta| meta.len() as usize));
+    for chunk in &chunks {
+        if cancel::is_cancelled() {
+            break;
+        }
+        let mut documents = Vec::with_capacity(chunk.len());
+        let mut paths = Vec::with_capacity(chunk.len());
+        for path in chunk {
+            let file = path.to_string_lossy().into_owned();
+            match source::read_source(path, limits::limits().max_input_bytes) {
+                Ok(content) => {
+                    paths.push(path);
+                    documents.push(TransformRequest {
+                        file,
+                        content,
+                        options: req.options.clone(),
+                    });
+                }
+                Err(e) => {
+                    summary.failed += 1;
+                    outbox.notify("transformGlob/result", json!({
+                        "requestId": id,
+                        "path": file,
+                        "error": format!("Failed to read {}: {}", file, e),
+                    }));
+                }
+            }
+        }
+        
+        let outcomes = match transform_documents(&documents, &req.skip, now) {
+            Ok(outcomes) => outcomes,
+            // Results sent so far stand; the summary says how far the glob got
+            Err(e) => return create_error_response(id, POOL_BUSY, e.to_string(), Some(json!(summary))),
+        };
+        for ((path, document), outcome) in paths.into_iter().zip(&documents).zip(outcomes) {
+            let mut notification = json!({ "requestId": id, "path": document.file });
+            let emitted = match outcome {
+                DocumentOutcome::Transformed(response) => {
+                    if let Some(search_index) = &mut search_index {
+                        search_index.add(path, &document.content);
+                    }
+                    emit_output(path, &root, &req.output, response, &mut notification)
+                }
+                DocumentOutcome::Skipped(reason) => {
+                    summary.skipped += 1;
+                    notification["skipped"] = json!(reason);
+                    outbox.notify("transformGlob/result", notification);
+                    continue;
+                }
+                DocumentOutcome::Failed(e) => {
+                    notification["details"] = serde_json::to_value(&e).unwrap();
+                    Err(format!("Transform failed: {}", e))
+                }
+            };
+            match emitted {
+                Ok(()) => summary.succeeded += 1,
+                Err(e) => {
+                    summary.failed += 1;
+                    notification["error"] = json!(e);
+                }
+            }
+            outbox.notify("transformGlob/result", notification);
+        }
+    }
+    
+    if let Some(search_index) = search_index {
+        let documents = search_index.len();
+        let target = search_index.path().map(PathBuf::from);
+        let index = search_index.finish();
+        summary.search_index = Some(match target {
+            Some(target) => {
+                // Checked again at write time, as emitted outputs are
+                if let Err(e) = sandbox::check(&target) {
+                    return outside_root(id, e);
+                }
+                let bytes = match serde_json::to_vec(&index) {
+                    Ok(bytes) => bytes,
+                    Err(e) => return create_error_response(id, INTERNAL_ERROR, format!("Failed to serialize search index: {}", e), None),
+                };
+                if let Err(e) = fsutil::write_atomic(&target, &bytes) {
+                    return create_error_response(id, IO_ERROR, format!("Failed to write search index {}: {}", target.display(), e), None);
+                }
+                json!({ "path": target.to_string_lossy(), "documents": documents })
+            }
+            None => index,
+        });
+    }
+    
+    create_response(id, serde_json::to_value(summary).unwrap())
+}
+
+/// Apply the output policy to one transformed file
+fn emit_output(
+    path: &Path,
+    root: &Path,
+    output: &GlobOutput,
+    response: TransformResponse,
+    notification: &mut Value,
+) -> Result<(), String> {
+    match output.mode {
+        OutputMode::None => {}
+        OutputMode::Inline => {
+            notification["result"] = serde_json::to_value(response).unwrap();
+        }
+        OutputMode::Write => {
+            let dir = Path::new(output.dir.as_deref().unwrap_or("."));
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            let target = dir.join(relative).with_extension(output.extension.trim_start_matches('.'));
+            // A symlink under the output directory may lead out of the sandbox
+            sandbox::check(&target).map_err(|e| e.to_string())?;
+            fsutil::write_atomic(&target, response.code.as_bytes())
+                .map_err(|e| format!("Failed to write {}: {}", target.display(), e))?;
+            notification["output"] = json!(target.to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+/// Watch files matching the given globs and report changes to them as
+/// debounced `filesChanged` notifications until `unwatch` is called
+pub fn handle_watch(id: RpcId, params: Option<Value>, outbox: &Outbox) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: WatchRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    let root = req.root.map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+    if let Err(e) = sandbox::check(&root) {
+        return outside_root(id, e);
+    }
+    let debounce = Duration::from_millis(req.debounce_ms);
+    let revalidate = (req.revalidate && cache::global().is_some()).then(|| {
+        // The debounce thread has no connection; transforms resolve options
+        // against the session of the one that started the watch
+        let context = session::current_context();
+        let options = req.options;
+        Box::new(move |files: Vec<PathBuf>| {
+            let documents: Vec<PathBuf> = files.into_iter().filter(|path| is_document(path)).collect();
+            if documents.is_empty() {
+                return;
+            }
+            let options = options.clone();
+            let spawn = || prime::spawn(documents, None, move |path| warm_cache(path, &options));
+            match &context {
+                Some(context) => context.enter(spawn),
+                None => spawn(),
+            };
+        }) as watch::OnChange
+    });
+    match watch::watch(&root, &req.patterns, debounce, session::connection_id(), outbox.clone(), revalidate) {
+        Ok(watch_id) => {
+            debug!("Watching {} as {}", root.display(), watch_id);
+            create_response(id, json!({ "watchId": watch_id }))
+        }
+        Err(e) => create_error_response(id, INVALID_PARAMS, e, None),
+    }
+}
+
+pub fn handle_unwatch(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: UnwatchRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    let unwatched = watch::unwatch(req.watch_id, session::connection_id());
+    create_response(id, json!({ "unwatched": unwatched }))
+}
+
+/// Render a document in deterministic mode in a worker process and report
+/// whether it matches this sidecar's output for it: the disk cache's entry
+/// when there is one, else a render in this process. A separate process
+/// has its own hash seeds and allocations, which a second render here
+/// would share. Optionally compares against a previously recorded hash.
+/// HMR change tracking and document metadata are left alone.
+pub fn handle_verify_output(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: VerifyOutputRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    let mut transform = req.transform;
+    transform.options.deterministic = Some(true);
+    
+    let prepared = match prepare_document(&transform) {
+        Ok(p) => p,
+        Err(e) => return transform_error_response(id, e),
+    };
+    let reference = match cached_document(&prepared) {
+        Some((entry, _)) => Ok(entry),
+        None => transform::render_document(
+            &transform.file,
+            &prepared.source,
+            &prepared.content,
+            &prepared.options,
+            prepared.frontmatter.as_ref(),
+        ),
+    };
+    let task = TransformTask::new("verify".to_string(), transform.file.clone().into(), prepared.content.clone())
+        .with_options(prepared.options.clone())
+        .with_document(prepared.source.clone(), prepared.frontmatter.clone());
+    let fresh = ChildRenderer::sidecar()
+        .render(&task)
+        .map_err(|e| TransformError::new(ErrorKind::Render, e));
+    let (reference, fresh) = match reference.and_then(|reference| Ok((reference, fresh?))) {
+        Ok(outputs) => outputs,
+        Err(e) => return transform_error_response(id, e),
+    };
+    
+    let hash = output_hash(&reference.code);
+    let response = VerifyOutputResponse {
+        stable: output_hash(&fresh.code) == hash,
+        matches: req.expected_hash.map(|expected| expected == hash),
+        hash,
+    };
+    
+    create_response(id, serde_json::to_value(response).unwrap())
+}
+
+pub fn handle_normalize(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: NormalizeRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    let options = NormalizeOptions {
+        remove_bom: req.remove_bom,
+        normalize_crlf: req.normalize_lf,
+        normalize_cr: req.normalize_lf,
+        nfc: req.nfc,
+        final_newline: req.final_newline,
+    };
+    let normalized = fastmd_text::normalize(&req.content, &options);
+    
+    let response = NormalizeResponse {
+        changed: !normalized.applied.is_empty(),
+        content: normalized.content,
+        applied: normalized.applied,
+    };
+    
+    create_response(id, serde_json::to_value(response).unwrap())
+}
+
+pub fn handle_compute_digest(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: ComputeDigestRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    let mut files = req.files;
+    if !req.patterns.is_empty() {
+        let root = req.root.map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+        if let Err(e) = sandbox::check(&root) {
+            return outside_root(id, e);
+        }
+        let scan_options = ScanOptions {
+            respect_ignore: req.respect_ignore,
+            hidden: req.hidden,
+        };
+        match scan::stat_matching(&req.patterns, &root, &scan_options, false) {
+            Ok(matched) => files.extend(matched.into_iter().map(|mut file| {
+                file.path = root.join(&file.path).to_string_lossy().into_owned();
+                file
+            })),
+            Err(e) => return create_error_response(id, INVALID_PARAMS, e, None),
+        }
+    }
+    // The same files give the same digest however the host spelled them
+    for file in &mut files {
+        file.path = canonical_path(&file.path);
+    }
+    
+    let salt = req.context.map(|context| {
+        let options = session::current().read().resolve(&context.options);
+        digest::salt(&options, context.engine.as_deref(), context.salt.as_deref())
+    });
+    let digest = digest::digest_files(&mut files, salt.as_deref(), parallel::global_pool());
+    
+    let response = ComputeDigestResponse { digest, files: files.len() };
+    
+    create_response(id, serde_json::to_value(response).unwrap())
+}
+
+/// Error for a request naming a path outside the project root
+fn outside_root(id: RpcId, e: sandbox::OutsideRoot) -> RpcResponse {
+    let path = e.path.to_string_lossy().into_owned();
+    create_error_response(id, PATH_OUTSIDE_ROOT, e.to_string(), Some(json!({ "path": path })))
+}
+
+/// Hash a file's contents without loading it into memory
+pub fn handle_hash_file(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: HashFileRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    if let Err(e) = sandbox::check(Path::new(&req.path)) {
+        return outside_root(id, e);
+    }
+    match digest::hash_file(Path::new(&req.path), req.algorithm) {
+        Ok(hash) => create_response(id, serde_json::to_value(hash).unwrap()),
+        Err(e) => create_error_response(
+            id,
+            IO_ERROR,
+            format!("Failed to read {}: {}", req.path, e),
+            Some(json!({ "path": req.path })),
+        ),
+    }
+}
+
+/// List files matching the given globs with their size and mtime, in the
+/// shape `computeDigest` accepts
+pub fn handle_scan_files(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: ScanFilesRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    let root = req.root.map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+    if let Err(e) = sandbox::check(&root) {
+        return outside_root(id, e);
+    }
+    let scan_options = ScanOptions {
+        respect_ignore: req.respect_ignore,
+        hidden: req.hidden,
+    };
+    match scan::stat_matching(&req.patterns, &root, &scan_options, req.hash) {
+        Ok(files) => create_response(id, json!({ "files": files })),
+        Err(e) => create_error_response(id, INVALID_PARAMS, e, None),
+    }
+}
+
+pub fn handle_affected_outputs(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: AffectedOutputsRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    let mut graph = depgraph::graph().write();
+    
+    // Explicit paths plus whatever the fingerprint delta reports
+    let mut changed: Vec<String> = req.changed_paths.iter().map(canonical_path).collect();
+    changed.extend(graph.diff_fingerprints(
+        req.files
+            .into_iter()
+            .map(|f| (f.path, Fingerprint { size: f.size, mtime: f.mtime })),
+    ));
+    changed.sort();
+    changed.dedup();
+    
+    let affected = graph.affected(&changed);
+    drop(graph);
+    depgraph::persist();
+    
+    let response = AffectedOutputsResponse { changed, affected };
+    
+    create_response(id, serde_json::to_value(response).unwrap())
+}
+
+pub fn handle_order_documents(id: RpcId, params: Option<Value>) -> RpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return create_error_response(id, INVALID_PARAMS, "Missing params".to_string(), None),
+    };
+    
+    let req: OrderDocumentsRequest = match serde_json::from_value(params) {
+        Ok(r) => r,
+        Err(e) => return create_error_response(id, INVALID_PARAMS, format!("Invalid params: {}", e), None),
+    };
+    
+    let frontmatters: Vec<Option<Value>> = req
+        .documents
+        .iter()
+        .map(|doc| match (&doc.frontmatter, &doc.content) {
+            (Some(fm), _) => Some(fm.clone()),
+            (None, Some(content)) => extract_frontmatter(content).0,
+            (None, None) => None,
+        })
+        .collect();
+    
+    let order = req.order.order(
+        req.documents
+            .iter()
+            .zip(&frontmatters)
+            .map(|(doc, fm)| (doc.path.as_str(), fm.as_ref())),
+    );
+    let ordered_paths: Vec<String> = order.into_iter().map(|i| req.documents[i].path.clone()).collect();
+    
+    let response = OrderDocumentsResponse {
+        documents: link_entries(&ordered_paths, req.page_size),
+    };
+    
+    create_response(id, serde_json::to_value(response).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_compute_digest_respect_ignore() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("docs/generated")).unwrap();
+        fs::write(dir.path().join("docs/keep.md"), "x").unwrap();
+        fs::write(dir.path().join("docs/generated/skip.md"), "x").unwrap();
+        fs::write(dir.path().join(".gitignore"), "generated/\n").unwrap();
+
+        let digest = |params: Value| handle_compute_digest(RpcId::Number(1), Some(params)).result.unwrap();
+        let root = dir.path().to_string_lossy();
+        let ignoring = digest(json!({ "patterns": ["**/*.md"], "root": root }));
+        let all = digest(json!({ "patterns": ["**/*.md"], "root": root, "respectIgnore": false }));
+        assert_eq!(ignoring["files"], 1);
+        assert_eq!(all["files"], 2);
+        assert_ne!(all["digest"], ignoring["digest"]);
+    }
+
+    #[test]
+    fn test_transform_renders_on_pool() {
+        let request = |file: &str, content: String| TransformRequest {
+            file: file.to_string(),
+            content,
+            options: TransformOptions::default(),
+        };
+        let pool = parallel::ThreadPoolBuilder::new().workers(1).build();
+        let document = request("pooled.md", "# Pooled\n\nSome *text*.\n".to_string());
+        let pooled = transform_document(&document, false, Some(&pool)).unwrap();
+        assert_eq!(pooled.code, transform_document(&document, false, None).unwrap().code);
+        assert_eq!(pool.stats().total_tasks, 1);
+        pool.shutdown();
+        
+        // A pool past its deadline fails the document as timed out
+        let pool = parallel::ThreadPoolBuilder::new().workers(1).task_timeout(Duration::ZERO).build();
+        let slow = request("pooled-slow.md", "*a* ".repeat(200_000));
+        assert_eq!(transform_document(&slow, false, Some(&pool)).unwrap_err().kind, ErrorKind::Timeout);
+        pool.shutdown();
+        
+        // and one that is shut down leaves the document to render inline
+        assert!(transform_document(&document, false, Some(&pool)).is_ok());
+    }
+}
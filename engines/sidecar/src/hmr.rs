@@ -0,0 +1,101 @@
+//! Hot module replacement bookkeeping
+//!
+//! Remembers the frontmatter and body hashes of the last transform of each
+//! file so the host plugin can tell prose-only and frontmatter-only edits
+//! apart and generate precise `import.meta.hot` handling.
+
+use std::sync::OnceLock;
+
+use dashmap::DashMap;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// What changed in a document since its previous transform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    /// First transform of this file in the session
+    Initial,
+    /// Content is identical to the previous transform
+    Unchanged,
+    /// Only the frontmatter block changed
+    Frontmatter,
+    /// Only the document body changed
+    Body,
+    /// Both frontmatter and body changed
+    Both,
+}
+
+/// HMR metadata attached to transform results
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HmrInfo {
+    /// Whether the emitted module can hot-swap itself without notifying importers
+    pub accepts_self: bool,
+    /// Module specifiers the emitted module imports
+    pub dependencies: Vec<String>,
+    pub change: ChangeKind,
+    pub frontmatter_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fingerprint {
+    frontmatter: [u8; 32],
+    body: [u8; 32],
+}
+
+/// Tracks the last seen fingerprint per file
+#[derive(Debug, Default)]
+pub struct HmrTracker {
+    files: DashMap<String, Fingerprint>,
+}
+
+impl HmrTracker {
+    /// Record a new version of `file` and classify the change
+    pub fn record(&self, file: &str, frontmatter: &str, body: &str) -> ChangeKind {
+        let next = Fingerprint {
+            frontmatter: Sha256::digest(frontmatter.as_bytes()).into(),
+            body: Sha256::digest(body.as_bytes()).into(),
+        };
+        
+        match self.files.insert(file.to_string(), next) {
+            None => ChangeKind::Initial,
+            Some(prev) => match (prev.frontmatter != next.frontmatter, prev.body != next.body) {
+                (false, false) => ChangeKind::Unchanged,
+                (true, false) => ChangeKind::Frontmatter,
+                (false, true) => ChangeKind::Body,
+                (true, true) => ChangeKind::Both,
+            },
+        }
+    }
+    
+    /// Forget the files `matches` accepts, so their next transform counts
+    /// as the first; returns how many were forgotten
+    pub fn forget(&self, matches: impl Fn(&str) -> bool) -> usize {
+        let before = self.files.len();
+        self.files.retain(|file, _| !matches(file));
+        before - self.files.len()
+    }
+}
+
+static TRACKER: OnceLock<HmrTracker> = OnceLock::new();
+
+/// Process-wide tracker shared by all transform entry points
+pub fn tracker() -> &'static HmrTracker {
+    TRACKER.get_or_init(HmrTracker::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_classification() {
+        let tracker = HmrTracker::default();
+        assert_eq!(tracker.record("a.md", "title: A", "Body"), ChangeKind::Initial);
+        assert_eq!(tracker.record("a.md", "title: A", "Body"), ChangeKind::Unchanged);
+        assert_eq!(tracker.record("a.md", "title: B", "Body"), ChangeKind::Frontmatter);
+        assert_eq!(tracker.record("a.md", "title: B", "Edited"), ChangeKind::Body);
+        assert_eq!(tracker.record("a.md", "title: C", "Again"), ChangeKind::Both);
+    }
+}
@@ -0,0 +1,149 @@
+//! HTTP server mode
+//!
+//! `--http <port>` exposes a small REST surface over the same handlers the
+//! RPC protocol uses, for consumers that don't speak JSON-RPC (CI scripts,
+//! other languages). Request bodies are the RPC `params`; successful
+//! responses are the RPC `result`. Bodies over [`MAX_BODY_BYTES`] get a
+//! 413, and a handler that panics a 500, as it would an internal error.
+
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info};
+
+use crate::codec::Codec;
+use crate::outbox::Outbox;
+use crate::parallel;
+use crate::protocol::{self, RpcId, RpcRequest, RpcResponse};
+
+/// Largest request body accepted, in bytes
+const MAX_BODY_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Map an endpoint to the RPC method that serves it
+fn route(method: &Method, path: &str) -> Option<&'static str> {
+    match (method, path) {
+        (Method::Post, "/transform") => Some("transform"),
+        (Method::Post, "/digest") => Some("computeDigest"),
+        (Method::Post, "/normalize") => Some("normalize"),
+        (Method::Get, "/stats") => Some("stats"),
+        _ => None,
+    }
+}
+
+/// Serve HTTP on localhost until the process exits
+pub fn serve(port: u16) -> anyhow::Result<()> {
+    // Only bind loopback; the sidecar reads arbitrary paths on request
+    let server = Server::http(("127.0.0.1", port)).map_err(|e| anyhow::anyhow!("Failed to bind port {}: {}", port, e))?;
+    info!("HTTP server listening on http://127.0.0.1:{}", port);
+
+    for request in server.incoming_requests() {
+        thread::spawn(move || {
+            if let Err(e) = handle(request) {
+                error!("Failed to answer HTTP request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request) -> io::Result<()> {
+    // Ignore any query string when routing
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    let Some(method) = route(request.method(), &path) else {
+        return request.respond(json_response(404, &json!({ "error": "Not found" })));
+    };
+
+    let params = if *request.method() == Method::Post {
+        // A declared length over the limit is refused without reading
+        let declared = request.body_length().map_or(0, |length| length as u64);
+        let body = if declared <= MAX_BODY_BYTES { read_body(request.as_reader(), MAX_BODY_BYTES)? } else { None };
+        let Some(body) = body else {
+            let error = json!({ "error": format!("Request body exceeds the {} byte limit", MAX_BODY_BYTES) });
+            return request.respond(json_response(413, &error));
+        };
+        match serde_json::from_slice::<Value>(&body) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                let error = json!({ "error": format!("Invalid JSON body: {}", e) });
+                return request.respond(json_response(400, &error));
+            }
+        }
+    } else {
+        None
+    };
+
+    let rpc = RpcRequest {
+        jsonrpc: "2.0".to_string(),
+        id: RpcId::Null,
+        method: method.to_string(),
+        params,
+    };
+    // Notifications have nowhere to go over plain HTTP
+    let (outbox, _) = Outbox::new(Codec::Json);
+    let response = panic::catch_unwind(AssertUnwindSafe(|| crate::handle_request(rpc, &outbox))).unwrap_or_else(|payload| {
+        let message = parallel::panic_message(payload.as_ref());
+        error!("HTTP {} panicked: {}", path, message);
+        protocol::create_error_response(RpcId::Null, protocol::INTERNAL_ERROR, format!("Internal error: {}", message), None)
+    });
+    let (status, body) = to_http(response);
+    request.respond(json_response(status, &body))
+}
+
+/// The body, or `None` if it is longer than `limit`
+fn read_body(reader: impl Read, limit: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut body = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut body)?;
+    Ok((body.len() as u64 <= limit).then_some(body))
+}
+
+/// Translate an RPC response into a status code and JSON body
+fn to_http(response: RpcResponse) -> (u16, Value) {
+    match response.error {
+        None => (200, response.result.unwrap_or(Value::Null)),
+        Some(error) => {
+            let status = match error.code {
+                protocol::INVALID_PARAMS => 400,
+                protocol::TRANSFORM_ERROR => 422,
+                _ => 500,
+            };
+            (status, json!({ "error": error.message, "code": error.code, "data": error.data }))
+        }
+    }
+}
+
+fn json_response(status: u16, body: &Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    let header = Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+    Response::from_data(serde_json::to_vec(body).unwrap_or_default())
+        .with_status_code(status)
+        .with_header(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes() {
+        assert_eq!(route(&Method::Post, "/transform"), Some("transform"));
+        assert_eq!(route(&Method::Get, "/stats"), Some("stats"));
+        assert_eq!(route(&Method::Get, "/transform"), None);
+        assert_eq!(route(&Method::Post, "/missing"), None);
+    }
+
+    #[test]
+    fn test_error_status() {
+        let response = protocol::create_error_response(RpcId::Null, protocol::INVALID_PARAMS, "Missing params".to_string(), None);
+        let (status, body) = to_http(response);
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "Missing params");
+    }
+
+    #[test]
+    fn test_body_limit() {
+        assert_eq!(read_body(&b"{}"[..], 2).unwrap(), Some(b"{}".to_vec()));
+        assert_eq!(read_body(&b"{ }"[..], 2).unwrap(), None);
+    }
+}
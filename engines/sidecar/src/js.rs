@@ -0,0 +1,203 @@
+//! JavaScript in MDX documents, parsed with `swc_ecma_parser`
+//!
+//! As in MDX, a top-level paragraph starting with `import` or `export` is
+//! ESM: it runs to the next blank line, or on past it while the code so far
+//! ends too early to parse, and may hold only imports and exports. Those
+//! blocks are lifted out of the body; a default export among them is the
+//! layout the content renders in, kept as `MDXLayout` so it doesn't clash
+//! with the module's own default export. `{expressions}` in the body are
+//! checked here too, so neither ends up in a module that doesn't parse.
+
+use swc_common::{BytePos, Spanned};
+use swc_ecma_ast::{Expr, Module, ModuleDecl, ModuleItem, Stmt};
+use swc_ecma_parser::{EsSyntax, Parser, StringInput, Syntax};
+
+use crate::jsx::SyntaxError;
+
+/// Name a document's default export is given
+pub const LAYOUT: &str = "MDXLayout";
+
+/// The imports and exports of an MDX document, and its body without them
+#[derive(Debug, Default)]
+pub struct Esm {
+    /// ESM lines as they are emitted, with the 0-based line each came from
+    pub lines: Vec<(usize, String)>,
+    /// The remaining lines, with the 0-based line each came from
+    pub body_lines: Vec<(usize, String)>,
+    /// Modules imported or re-exported from, in document order
+    pub specifiers: Vec<String>,
+    /// Whether anything but the layout is exported
+    pub named_exports: bool,
+    /// Whether the document exports a layout as its default
+    pub layout: bool,
+}
+
+impl Esm {
+    /// The body, lines joined as they were
+    pub fn body(&self) -> String {
+        self.body_lines.iter().map(|(_, line)| line.as_str()).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// Split the ESM blocks of `content` from its body. Fails at the first
+/// block that isn't valid ESM, with its offset in `content`.
+pub fn split(content: &str) -> Result<Esm, SyntaxError> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut esm = Esm::default();
+    let mut fence: Option<&str> = None;
+    let mut previous_blank = true;
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+        } else if indent < 4 && (trimmed.starts_with("```") || trimmed.starts_with("~~~")) {
+            fence = Some(&trimmed[..3]);
+        } else if previous_blank && starts_esm(line) {
+            let end = parse_block(content, &lines, index, &mut esm)?;
+            previous_blank = false;
+            index = end;
+            continue;
+        }
+        previous_blank = line.trim().is_empty();
+        esm.body_lines.push((index, line.to_string()));
+        index += 1;
+    }
+    Ok(esm)
+}
+
+/// Whether a line opens an ESM block
+fn starts_esm(line: &str) -> bool {
+    ["import", "export"].iter().any(|keyword| {
+        line.strip_prefix(keyword)
+            .is_some_and(|rest| rest.starts_with([' ', '\t', '{', '*']))
+    })
+}
+
+/// Parse the block starting at line `start`, adding it to `esm`; returns
+/// the line after it
+fn parse_block(content: &str, lines: &[&str], start: usize, esm: &mut Esm) -> Result<usize, SyntaxError> {
+    let block_start = line_offset(lines, start);
+    let mut end = next_blank(lines, start);
+    let (module, code) = loop {
+        let code = lines[start..end].join("\n");
+        match parse_module(&code) {
+            Ok(module) => break (module, code),
+            // Ended too early: the code may go on past a blank line
+            Err((_, offset)) if offset >= code.trim_end().len() && end < lines.len() => {
+                end = next_blank(lines, end + 1);
+            }
+            Err((message, offset)) => {
+                return Err(SyntaxError { message, offset: (block_start + offset).min(content.len()) });
+            }
+        }
+    };
+
+    // Where `export default` is, to make it the layout
+    let mut layout_at = None;
+    for item in &module.body {
+        let (decl, lo) = match item {
+            ModuleItem::ModuleDecl(decl) => (decl, start_of(decl)),
+            ModuleItem::Stmt(stmt) => {
+                let offset = block_start + start_of(stmt);
+                return Err(SyntaxError { message: "Expected only `import` and `export` statements".to_string(), offset });
+            }
+        };
+        match decl {
+            ModuleDecl::Import(import) => esm.specifiers.push(import.src.value.to_string_lossy().into_owned()),
+            ModuleDecl::ExportAll(export) => {
+                esm.specifiers.push(export.src.value.to_string_lossy().into_owned());
+                esm.named_exports = true;
+            }
+            ModuleDecl::ExportNamed(export) => {
+                if let Some(src) = &export.src {
+                    esm.specifiers.push(src.value.to_string_lossy().into_owned());
+                }
+                esm.named_exports = true;
+            }
+            ModuleDecl::ExportDefaultDecl(_) | ModuleDecl::ExportDefaultExpr(_) => {
+                if esm.layout || layout_at.is_some() {
+                    return Err(SyntaxError { message: "Expected one default export".to_string(), offset: block_start + lo });
+                }
+                layout_at = Some(lo);
+            }
+            _ => esm.named_exports = true,
+        }
+    }
+
+    let code = match layout_at {
+        Some(lo) => {
+            esm.layout = true;
+            let keyword = code[lo..].find("default").map_or(0, |at| at + "default".len());
+            format!("{}const {} ={}", &code[..lo], LAYOUT, &code[lo + keyword..])
+        }
+        None => code,
+    };
+    esm.lines.extend(code.lines().enumerate().map(|(line, text)| (start + line, text.to_string())));
+    Ok(end)
+}
+
+/// Index of the first blank line from `from`, or the line count
+fn next_blank(lines: &[&str], from: usize) -> usize {
+    (from..lines.len()).find(|&index| lines[index].trim().is_empty()).unwrap_or(lines.len())
+}
+
+/// Byte offset of line `index`, for content split with `str::lines`
+fn line_offset(lines: &[&str], index: usize) -> usize {
+    // `lines` drops `\r\n` too, but offsets only need to be close there
+    lines[..index].iter().map(|line| line.len() + 1).sum()
+}
+
+/// Syntax accepted in MDX: modern JavaScript with JSX
+fn syntax() -> Syntax {
+    Syntax::Es(EsSyntax {
+        jsx: true,
+        ..Default::default()
+    })
+}
+
+/// Parse `code` as a module, failing with the first error and its offset
+fn parse_module(code: &str) -> Result<Module, (String, usize)> {
+    // Position 0 is swc's dummy position, so `code` starts at 1
+    let input = StringInput::new(code, BytePos(1), BytePos(1 + code.len() as u32));
+    let mut parser = Parser::new(syntax(), input, None);
+    let module = parser.parse_module();
+    let error = match module {
+        Ok(module) => match parser.take_errors().into_iter().next() {
+            None => return Ok(module),
+            Some(error) => error,
+        },
+        Err(error) => error,
+    };
+    Err((error.kind().msg().into_owned(), start_of(&error)))
+}
+
+/// Check that `code` is one JavaScript expression, as `{}` in MDX must
+/// hold
+pub fn check_expression(code: &str) -> Result<(), String> {
+    // Wrapped so that the whole of `code`, not a prefix, must parse
+    let wrapped = format!("(\n{}\n);", code);
+    let module = parse_module(&wrapped).map_err(|(message, _)| message)?;
+    let whole = match module.body.as_slice() {
+        [ModuleItem::Stmt(Stmt::Expr(stmt))] => match &*stmt.expr {
+            // Positions start at 1, so the closing `)` ends at the length
+            Expr::Paren(paren) => paren.span.hi.0 as usize == wrapped.len(),
+            _ => false,
+        },
+        _ => false,
+    };
+    if whole {
+        Ok(())
+    } else {
+        Err("Expected a single expression".to_string())
+    }
+}
+
+/// Offset of a node in the code it was parsed from
+fn start_of(node: &impl Spanned) -> usize {
+    (node.span().lo.0 as usize).saturating_sub(1)
+}
@@ -0,0 +1,1095 @@
+//! Compiling MDX bodies to JSX runtime calls
+//!
+//! For `framework: "react"` or `"preact"` the MDX body becomes an
+//! `MDXContent` component built from `jsx`/`jsxs` calls against the
+//! automatic runtime, so bundlers can import the module directly instead of
+//! running it through `@mdx-js/mdx` a second time. Markdown comes from the
+//! same pulldown-cmark events the HTML path uses; component tags are read
+//! by a small scanner that understands string, expression and spread
+//! attributes, which is what MDX documents put there. As in MDX, a line
+//! holding only tags, or lines holding only an `{expression}`, are a block
+//! of their own and never part of a paragraph.
+//!
+//! `{expressions}` are scanned for brackets, strings and comments to find
+//! where they end, and JSX elements where an operand can start
+//! (`{items.map(i => <li>{i}</li>)}`, `{open && <Panel />}`) are compiled
+//! to runtime calls like tags in the body. The rest of the code is parsed
+//! with swc ([`js::check_expression`]) and emitted verbatim. Code that
+//! isn't one expression, and closing tags that don't match the open
+//! element, fail the compile rather than produce a module that doesn't
+//! parse.
+
+use std::collections::BTreeSet;
+
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use serde_json::json;
+
+use crate::js;
+use crate::sourcemap;
+use crate::transform::TransformOptions;
+
+/// Frameworks whose MDX output is compiled to JSX
+const JSX_FRAMEWORKS: &[&str] = &["react", "preact"];
+/// Expressions and elements nested deeper than this fail the compile
+/// instead of the stack
+const MAX_NESTING: usize = 256;
+const TOO_DEEP: &str = "Expected fewer nested elements and expressions";
+
+/// Module the automatic runtime is imported from, when the options ask for
+/// JSX output
+pub fn import_source(options: &TransformOptions) -> Option<&str> {
+    let framework = options.framework.as_deref().filter(|f| JSX_FRAMEWORKS.contains(f))?;
+    Some(options.jsx_import_source.as_deref().unwrap_or(framework))
+}
+
+/// Import line for the automatic runtime
+pub fn runtime_import(import_source: &str) -> String {
+    format!(
+        "import {{ Fragment as _Fragment, jsx as _jsx, jsxs as _jsxs }} from {};\n",
+        json!(format!("{}/jsx-runtime", import_source))
+    )
+}
+
+/// The compiled body: `_createMdxContent` and the default-exported
+/// `MDXContent` wrapper
+pub struct Component {
+    pub code: String,
+    /// (1-based line in `code`, 0-based line in the body) for each
+    /// top-level block
+    pub line_map: Vec<(usize, usize)>,
+}
+
+/// Why a body doesn't compile, and the offset in it where that shows
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub offset: usize,
+}
+
+/// Compile `body`; with `layout` the document's own default export, kept
+/// as [`js::LAYOUT`], wraps the content instead of `components.wrapper`
+pub fn compile(body: &str, layout: bool) -> Result<Component, SyntaxError> {
+    let mut builder = Builder::new();
+    let mut markdown_start = 0;
+    let mut fence: Option<&str> = None;
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        if line_start < markdown_start {
+            // Inside an expression block
+            continue;
+        }
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if indent < 4 && (trimmed.starts_with("```") || trimmed.starts_with("~~~")) {
+            fence = Some(&trimmed[..3]);
+            continue;
+        }
+        if indent < 4 && is_tag_line(trimmed) {
+            builder.markdown(&body[markdown_start..line_start], markdown_start);
+            builder.html(line, line_start);
+            markdown_start = offset;
+        } else if let Some(len) = expression_block(&body[line_start..]).filter(|_| indent < 4) {
+            builder.markdown(&body[markdown_start..line_start], markdown_start);
+            builder.html(&body[line_start..line_start + len], line_start);
+            markdown_start = line_start + len;
+        }
+    }
+    builder.markdown(&body[markdown_start..], markdown_start);
+    let root = builder.finish()?;
+
+    let mut code = String::from("function _createMdxContent(props) {\n");
+    let defaults = root
+        .intrinsics
+        .iter()
+        .map(|name| format!("{}: {}", prop_key(name), json!(name)))
+        .chain(std::iter::once("...props.components".to_string()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    code.push_str(&format!("  const _components = {{ {} }};\n", defaults));
+    code.push_str("  return _jsxs(_Fragment, {\n    children: [\n");
+
+    let mut line_map = Vec::new();
+    let children = root.element.children.len();
+    for (index, (child, offset)) in root.element.children.iter().zip(&root.offsets).enumerate() {
+        if let Some(offset) = offset {
+            line_map.push((code.lines().count() + 1, sourcemap::line_of(body, *offset)));
+        }
+        code.push_str("      ");
+        print(child, &mut code);
+        code.push_str(if index + 1 < children { ",\n" } else { "\n" });
+    }
+    code.push_str("    ]\n  });\n}\n");
+    code.push_str("export default function MDXContent(props = {}) {\n");
+    if layout {
+        code.push_str(&format!("  return _jsx({}, {{ ...props, children: _jsx(_createMdxContent, {{ ...props }}) }});\n}}\n", js::LAYOUT));
+    } else {
+        code.push_str(
+            "  const { wrapper: MDXLayout } = props.components || {};\n  \
+             return MDXLayout ? _jsx(MDXLayout, { ...props, children: _jsx(_createMdxContent, { ...props }) }) : _createMdxContent(props);\n}\n",
+        );
+    }
+
+    Ok(Component { code, line_map })
+}
+
+/// Whether a line starts with a tag and so is JSX rather than markdown
+fn is_tag_line(line: &str) -> bool {
+    // A tag with an invalid expression is still a tag, reported when built
+    line.starts_with('<') && (line.starts_with("<!--") || parse_tag(line, 0).is_some())
+}
+
+/// Length of the lines from the start of `input` that hold only an
+/// `{expression}`, which may span several of them, if they do
+fn expression_block(input: &str) -> Option<usize> {
+    let indent = input.len() - input.trim_start_matches([' ', '\t']).len();
+    let (_, len) = group(&input[indent..], 0, false).ok()?;
+    let end = indent + len;
+    let line_end = input[end..].find('\n').map_or(input.len(), |newline| end + newline + 1);
+    input[end..line_end].trim().is_empty().then_some(line_end)
+}
+
+fn parser_options() -> Options {
+    // No smart punctuation: quotes inside `{expressions}` must stay ASCII
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Expr(Js),
+    Element(Element),
+}
+
+/// A JavaScript expression: code emitted verbatim, with the JSX elements in
+/// it compiled to calls
+#[derive(Debug, Clone, PartialEq)]
+struct Js(Vec<JsPart>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum JsPart {
+    Code(String),
+    Element(Element),
+}
+
+impl Js {
+    fn code(code: impl Into<String>) -> Self {
+        Js(vec![JsPart::Code(code.into())])
+    }
+
+    /// The argument of a `...spread`, if this is one
+    fn spread(mut self) -> Option<Js> {
+        let Some(JsPart::Code(code)) = self.0.first_mut() else {
+            return None;
+        };
+        *code = code.strip_prefix("...")?.trim_start().to_string();
+        Some(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Name {
+    /// HTML element, overridable through `props.components`
+    Intrinsic(String),
+    /// Component in scope (imported or exported by the document)
+    Component(String),
+    Fragment,
+}
+
+impl Name {
+    /// As written in the tag
+    fn as_str(&self) -> &str {
+        match self {
+            Name::Intrinsic(name) | Name::Component(name) => name,
+            Name::Fragment => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Prop {
+    /// Key and JavaScript value
+    Value(String, Js),
+    Spread(Js),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Element {
+    name: Name,
+    props: Vec<Prop>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn new(name: Name) -> Self {
+        Element {
+            name,
+            props: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn intrinsic(name: &str) -> Self {
+        Element::new(Name::Intrinsic(name.to_string()))
+    }
+
+    fn prop(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.props.push(Prop::Value(key.to_string(), Js::code(json!(value.into()).to_string())));
+        self
+    }
+}
+
+/// What closes an open element
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Closer {
+    /// The matching markdown end event
+    Markdown,
+    /// A `</name>` tag, or the enclosing markdown element ending
+    Html,
+    /// Closed together with the enclosing markdown element
+    Implicit,
+}
+
+struct Frame {
+    element: Element,
+    closer: Closer,
+    /// Source offset the element starts at
+    offset: usize,
+}
+
+struct Root {
+    element: Element,
+    /// Source offset of each top-level child, when known
+    offsets: Vec<Option<usize>>,
+    intrinsics: BTreeSet<String>,
+}
+
+struct Builder {
+    stack: Vec<Frame>,
+    offsets: Vec<Option<usize>>,
+    in_table_head: bool,
+    in_code: bool,
+    /// The first syntax error found; building carries on, ignoring later ones
+    error: Option<SyntaxError>,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Builder {
+            stack: vec![Frame {
+                element: Element::new(Name::Fragment),
+                closer: Closer::Markdown,
+                offset: 0,
+            }],
+            offsets: Vec::new(),
+            in_table_head: false,
+            in_code: false,
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, message: String, offset: usize) {
+        self.error.get_or_insert(SyntaxError { message, offset });
+    }
+
+    /// Markdown between tag lines; `base` is its offset in the body
+    fn markdown(&mut self, markdown: &str, base: usize) {
+        if markdown.trim().is_empty() {
+            return;
+        }
+        let mut events = Parser::new_ext(markdown, parser_options()).into_offset_iter().peekable();
+        while let Some((mut event, range)) = events.next() {
+            // Text arrives in pieces, and JSX inside an `{expression}` as
+            // inline HTML across lines; the pieces are scanned for tags as
+            // one text
+            if let Event::Text(text) | Event::InlineHtml(text) = &event {
+                let mut text = text.to_string();
+                while let Some((more, _)) = events.next_if(|(event, _)| {
+                    matches!(event, Event::Text(_) | Event::InlineHtml(_) | Event::SoftBreak)
+                }) {
+                    match more {
+                        Event::Text(more) | Event::InlineHtml(more) => text.push_str(&more),
+                        _ => text.push('\n'),
+                    }
+                }
+                event = Event::Text(text.into());
+            }
+            self.event(event, base + range.start);
+        }
+    }
+
+    fn event(&mut self, event: Event, offset: usize) {
+        match event {
+            Event::Start(tag) => self.start(tag, offset),
+            Event::End(TagEnd::HtmlBlock) | Event::End(TagEnd::MetadataBlock(_)) => {}
+            Event::End(tag) => {
+                match tag {
+                    TagEnd::TableHead => self.in_table_head = false,
+                    TagEnd::CodeBlock => self.in_code = false,
+                    _ => {}
+                }
+                self.end_markdown();
+            }
+            Event::Text(text) if self.in_code => self.push(Node::Text(text.to_string()), Some(offset)),
+            // Tags with expression attributes aren't HTML to CommonMark
+            Event::Text(text) => self.inline(&text, offset, true),
+            Event::Code(code) => {
+                let mut element = Element::intrinsic("code");
+                element.children.push(Node::Text(code.to_string()));
+                self.push(Node::Element(element), Some(offset));
+            }
+            Event::Html(html) | Event::InlineHtml(html) => self.html(&html, offset),
+            Event::FootnoteReference(label) => {
+                let mut link = Element::intrinsic("a").prop("href", format!("#{}", label));
+                link.children.push(Node::Text(label.to_string()));
+                let mut sup = Element::intrinsic("sup").prop("className", "footnote-reference");
+                sup.children.push(Node::Element(link));
+                self.push(Node::Element(sup), Some(offset));
+            }
+            Event::SoftBreak => self.push(Node::Text("\n".to_string()), None),
+            Event::HardBreak => self.push(Node::Element(Element::intrinsic("br")), None),
+            Event::Rule => self.push(Node::Element(Element::intrinsic("hr")), Some(offset)),
+            Event::TaskListMarker(checked) => {
+                let mut input = Element::intrinsic("input").prop("type", "checkbox");
+                input.props.push(Prop::Value("checked".to_string(), Js::code(checked.to_string())));
+                input.props.push(Prop::Value("disabled".to_string(), Js::code("true")));
+                self.push(Node::Element(input), None);
+            }
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag, offset: usize) {
+        let element = match tag {
+            // Component tags inside these are handled by the Html events
+            Tag::HtmlBlock | Tag::MetadataBlock(_) => return,
+            Tag::Paragraph => Element::intrinsic("p"),
+            Tag::Heading { level, id, .. } => {
+                let element = Element::intrinsic(&level.to_string());
+                match id {
+                    Some(id) => element.prop("id", id.to_string()),
+                    None => element,
+                }
+            }
+            Tag::BlockQuote(_) => Element::intrinsic("blockquote"),
+            Tag::CodeBlock(kind) => {
+                self.in_code = true;
+                self.open(Element::intrinsic("pre"), Closer::Markdown, offset);
+                let code = Element::intrinsic("code");
+                let code = match kind {
+                    CodeBlockKind::Fenced(info) => match info.split_whitespace().next() {
+                        Some(lang) => code.prop("className", format!("language-{}", lang)),
+                        None => code,
+                    },
+                    CodeBlockKind::Indented => code,
+                };
+                self.open(code, Closer::Implicit, offset);
+                return;
+            }
+            Tag::List(Some(start)) if start != 1 => {
+                let mut list = Element::intrinsic("ol");
+                list.props.push(Prop::Value("start".to_string(), Js::code(start.to_string())));
+                list
+            }
+            Tag::List(Some(_)) => Element::intrinsic("ol"),
+            Tag::List(None) => Element::intrinsic("ul"),
+            Tag::Item => Element::intrinsic("li"),
+            Tag::FootnoteDefinition(label) => Element::intrinsic("div")
+                .prop("className", "footnote-definition")
+                .prop("id", label.to_string()),
+            Tag::Table(_) => Element::intrinsic("table"),
+            Tag::TableHead => {
+                self.in_table_head = true;
+                self.open(Element::intrinsic("thead"), Closer::Markdown, offset);
+                self.open(Element::intrinsic("tr"), Closer::Implicit, offset);
+                return;
+            }
+            Tag::TableRow => {
+                let in_body = self.stack.last().is_some_and(|frame| frame.element.name == Name::Intrinsic("tbody".to_string()));
+                if !in_body {
+                    self.open(Element::intrinsic("tbody"), Closer::Implicit, offset);
+                }
+                Element::intrinsic("tr")
+            }
+            Tag::TableCell => Element::intrinsic(if self.in_table_head { "th" } else { "td" }),
+            Tag::Emphasis => Element::intrinsic("em"),
+            Tag::Strong => Element::intrinsic("strong"),
+            Tag::Strikethrough => Element::intrinsic("del"),
+            Tag::Link { dest_url, title, .. } => {
+                let link = Element::intrinsic("a").prop("href", dest_url.to_string());
+                if title.is_empty() { link } else { link.prop("title", title.to_string()) }
+            }
+            Tag::Image { dest_url, title, .. } => {
+                let image = Element::intrinsic("img").prop("src", dest_url.to_string());
+                if title.is_empty() { image } else { image.prop("title", title.to_string()) }
+            }
+        };
+        self.open(element, Closer::Markdown, offset);
+    }
+
+    fn open(&mut self, element: Element, closer: Closer, offset: usize) {
+        self.stack.push(Frame { element, closer, offset });
+    }
+
+    /// Close everything up to and including the innermost markdown element
+    fn end_markdown(&mut self) {
+        while self.stack.len() > 1 {
+            let closer = self.stack.last().map(|frame| frame.closer);
+            self.close();
+            if closer == Some(Closer::Markdown) {
+                break;
+            }
+        }
+    }
+
+    /// Close `</name>`, which must match the innermost element opened by
+    /// a tag in the current markdown element
+    fn end_html(&mut self, name: &Name, offset: usize) {
+        let open = self.stack[1..]
+            .iter()
+            .rev()
+            .take_while(|frame| frame.closer != Closer::Markdown)
+            .position(|frame| frame.closer == Closer::Html);
+        let Some(depth) = open else {
+            return self.fail(format!("Unexpected closing tag `</{}>`", name.as_str()), offset);
+        };
+        let innermost = &self.stack[self.stack.len() - 1 - depth].element.name;
+        if innermost != name {
+            let message = format!("Expected a closing tag for `<{}>` before `</{}>`", innermost.as_str(), name.as_str());
+            return self.fail(message, offset);
+        }
+        for _ in 0..=depth {
+            self.close();
+        }
+    }
+
+    fn close(&mut self) {
+        let Some(frame) = self.stack.pop() else { return };
+        let mut element = frame.element;
+        if element.name == Name::Intrinsic("img".to_string()) {
+            // Image descriptions become the alt text
+            let alt = plain_text(&element.children);
+            element.children.clear();
+            element = element.prop("alt", alt);
+        }
+        self.push(Node::Element(element), Some(frame.offset));
+    }
+
+    fn push(&mut self, node: Node, offset: Option<usize>) {
+        if self.stack.len() == 1 {
+            self.offsets.push(offset);
+        }
+        if let Some(frame) = self.stack.last_mut() {
+            frame.element.children.push(node);
+        }
+    }
+
+    /// Raw HTML or JSX: open, close and self-closing tags with text between
+    fn html(&mut self, html: &str, offset: usize) {
+        self.inline(html, offset, false);
+    }
+
+    /// Tags, `{expressions}` and the text around them; unless
+    /// `keep_whitespace`, text that is only whitespace is layout and dropped
+    fn inline(&mut self, html: &str, offset: usize, keep_whitespace: bool) {
+        let mut rest = html;
+        while !rest.is_empty() {
+            let Some(open) = rest.find(['<', '{']) else {
+                self.html_text(rest, offset, keep_whitespace);
+                break;
+            };
+            self.html_text(&rest[..open], offset, keep_whitespace);
+            rest = &rest[open..];
+            if rest.starts_with('{') {
+                let (expr, len) = match group(rest, 0, false) {
+                    Ok(group) => group,
+                    Err(message) => return self.fail(message, offset),
+                };
+                match expr {
+                    Ok(Some(expr)) => self.push(Node::Expr(expr), Some(offset)),
+                    // Empty, or only a comment
+                    Ok(None) => {}
+                    Err(message) => self.fail(message, offset),
+                }
+                rest = &rest[len..];
+                continue;
+            }
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+                continue;
+            }
+            match parse_tag(rest, 0) {
+                Some((tag, None, len)) => {
+                    self.tag(tag, offset);
+                    rest = &rest[len..];
+                }
+                Some((_, Some(message), len)) => {
+                    self.fail(message, offset);
+                    rest = &rest[len..];
+                }
+                None => {
+                    self.html_text(&rest[..1], offset, true);
+                    rest = &rest[1..];
+                }
+            }
+        }
+    }
+
+    fn html_text(&mut self, text: &str, offset: usize, keep_whitespace: bool) {
+        if !text.is_empty() && (keep_whitespace || !text.trim().is_empty()) {
+            self.push(Node::Text(text.to_string()), Some(offset));
+        }
+    }
+
+    fn tag(&mut self, tag: ParsedTag, offset: usize) {
+        match tag {
+            ParsedTag::Close(name) => self.end_html(&name, offset),
+            ParsedTag::Open { name, props, self_closing } => {
+                let element = Element { name, props, children: Vec::new() };
+                self.open(element, Closer::Html, offset);
+                if self_closing {
+                    self.close();
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Root, SyntaxError> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        while self.stack.len() > 1 {
+            self.close();
+        }
+        let frame = self.stack.pop().expect("root frame");
+        let mut intrinsics = BTreeSet::new();
+        collect_intrinsics(&frame.element.children, &mut intrinsics);
+        Ok(Root {
+            element: frame.element,
+            offsets: self.offsets,
+            intrinsics,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ParsedTag {
+    Open { name: Name, props: Vec<Prop>, self_closing: bool },
+    Close(Name),
+}
+
+/// Parse the tag `input` starts with, returning it, why its attribute
+/// expressions are invalid if they are, and its length. `depth` is how
+/// deeply the tag is nested in expressions.
+fn parse_tag(input: &str, depth: usize) -> Option<(ParsedTag, Option<String>, usize)> {
+    let mut invalid = None;
+    let mut pos = 1;
+    let closing = input[pos..].starts_with('/');
+    if closing {
+        pos += 1;
+    }
+    let name_len = input[pos..]
+        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '$')))
+        .unwrap_or(input.len() - pos);
+    let name = element_name(&input[pos..pos + name_len]);
+    if name == Name::Fragment && name_len > 0 {
+        return None;
+    }
+    pos += name_len;
+
+    let mut props = Vec::new();
+    loop {
+        pos += input[pos..].len() - input[pos..].trim_start().len();
+        let rest = &input[pos..];
+        if rest.starts_with('>') {
+            let tag = if closing { ParsedTag::Close(name) } else { ParsedTag::Open { name, props, self_closing: false } };
+            return Some((tag, invalid, pos + 1));
+        }
+        if rest.starts_with("/>") && !closing {
+            return Some((ParsedTag::Open { name, props, self_closing: true }, invalid, pos + 2));
+        }
+        if closing {
+            return None;
+        }
+        if let Some(inner) = rest.strip_prefix('{') {
+            if !inner.trim_start().starts_with("...") {
+                return None;
+            }
+            let (spread, len) = group(rest, depth + 1, true).ok()?;
+            match spread.map(|spread| spread.and_then(Js::spread)) {
+                Ok(Some(spread)) => props.push(Prop::Spread(spread)),
+                Ok(None) => invalid = invalid.or(Some("Expected an expression after `...`".to_string())),
+                Err(message) => invalid = invalid.or(Some(message)),
+            }
+            pos += len;
+            continue;
+        }
+
+        let key_len = rest.find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/')).filter(|len| *len > 0)?;
+        let key = &rest[..key_len];
+        pos += key_len;
+        if !input[pos..].starts_with('=') {
+            props.push(Prop::Value(key.to_string(), Js::code("true")));
+            continue;
+        }
+        pos += 1;
+        let value = &input[pos..];
+        let (js, len) = match value.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let end = value[1..].find(quote)? + 1;
+                (Js::code(json!(&value[1..end]).to_string()), end + 1)
+            }
+            '{' => {
+                let (expr, len) = group(value, depth + 1, false).ok()?;
+                match expr {
+                    Ok(Some(expr)) => (expr, len),
+                    Ok(None) => {
+                        invalid = invalid.or(Some(format!("Attribute `{}` has an empty expression", key)));
+                        (Js::code("undefined"), len)
+                    }
+                    Err(message) => {
+                        invalid = invalid.or(Some(message));
+                        (Js::code("undefined"), len)
+                    }
+                }
+            }
+            _ => return None,
+        };
+        props.push(Prop::Value(key.to_string(), js));
+        pos += len;
+    }
+}
+
+/// Parse the JSX element `input` starts with inside an expression,
+/// returning it, or the first reason it is invalid, and its length. Fails
+/// outright if there is no element there or it never closes.
+fn parse_element(input: &str, depth: usize) -> Result<(Result<Element, String>, usize), String> {
+    if depth > MAX_NESTING {
+        return Err(TOO_DEEP.to_string());
+    }
+    let Some((ParsedTag::Open { name, props, self_closing }, mut invalid, mut pos)) = parse_tag(input, depth) else {
+        return Err("Expected a JSX element in the expression".to_string());
+    };
+    let mut element = Element { name, props, children: Vec::new() };
+    if self_closing {
+        return Ok((invalid.map_or(Ok(element), Err), pos));
+    }
+    loop {
+        let rest = &input[pos..];
+        let unclosed = || format!("Expected a closing tag for `<{}>`", element.name.as_str());
+        let open = rest.find(['<', '{']).ok_or_else(unclosed)?;
+        let text = jsx_text(&rest[..open]);
+        if !text.is_empty() {
+            element.children.push(Node::Text(text));
+        }
+        pos += open;
+        let rest = &input[pos..];
+        if rest.starts_with("</") {
+            let (tag, _, len) = parse_tag(rest, depth).ok_or_else(unclosed)?;
+            match tag {
+                ParsedTag::Close(name) if name != element.name => {
+                    let message = format!("Expected a closing tag for `<{}>` before `</{}>`", element.name.as_str(), name.as_str());
+                    invalid = invalid.or(Some(message));
+                }
+                _ => {}
+            }
+            pos += len;
+            break;
+        }
+        let (child, len) = if rest.starts_with('{') {
+            let (expr, len) = group(rest, depth + 1, false)?;
+            (expr.map(|expr| expr.map(Node::Expr)), len)
+        } else {
+            let (child, len) = parse_element(rest, depth + 1)?;
+            (child.map(|child| Some(Node::Element(child))), len)
+        };
+        match child {
+            Ok(Some(child)) => element.children.push(child),
+            // Empty, or only a comment
+            Ok(None) => {}
+            Err(message) => invalid = invalid.or(Some(message)),
+        }
+        pos += len;
+    }
+    Ok((invalid.map_or(Ok(element), Err), pos))
+}
+
+/// Text between JSX tags as JSX reads it: lines are trimmed where they
+/// meet other lines, blank ones dropped, and the rest joined by spaces
+fn jsx_text(text: &str) -> String {
+    if !text.contains('\n') {
+        return text.to_string();
+    }
+    let lines: Vec<&str> = text.split('\n').collect();
+    let last = lines.len() - 1;
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            let line = if index > 0 { line.trim_start() } else { line };
+            if index < last { line.trim_end() } else { line }
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn element_name(name: &str) -> Name {
+    match name.chars().next() {
+        None => Name::Fragment,
+        Some(first) if first.is_uppercase() || name.contains('.') => Name::Component(name.to_string()),
+        Some(_) => Name::Intrinsic(name.to_string()),
+    }
+}
+
+/// Characters after which an operand, and so a JSX element, can start
+const OPERAND_FOLLOWS: &[char] = &[
+    '(', '[', '{', ',', '?', ':', '=', '>', '&', '|', '!', '+', '-', '*', '/', '%', '^', '~',
+];
+
+/// Parse the `{...}` group `input` starts with, returning the JavaScript
+/// inside it with comments removed, or `None` if nothing else is there,
+/// and its length. As in MDX the group ends at the first `}` without an
+/// open `{`, even inside a line comment. What doesn't parse as one
+/// expression, or `...` and one with `spread`, is invalid; a group that
+/// never ends fails outright.
+fn group(input: &str, depth: usize, spread: bool) -> Result<(Result<Option<Js>, String>, usize), String> {
+    if depth > MAX_NESTING {
+        return Err(TOO_DEEP.to_string());
+    }
+    let mut parts = Vec::new();
+    let mut code = String::new();
+    // The code with each element standing in as `_`, for the checks
+    let mut shape = String::new();
+    let mut brackets = Vec::new();
+    let mut invalid = None;
+    let mut pos = 1;
+    let end = loop {
+        let rest = &input[pos..];
+        let c = rest.chars().next().ok_or("Expected a closing `}` for the expression")?;
+        let closes_group = !brackets.contains(&'}');
+        match c {
+            '"' | '\'' | '`' => {
+                let len = string_len(rest).ok_or("Expected the string in the expression to end")?;
+                code.push_str(&rest[..len]);
+                shape.push_str(&rest[..len]);
+                pos += len;
+                continue;
+            }
+            '/' if rest.starts_with("//") => {
+                pos += rest.find(|c| c == '\n' || (closes_group && c == '}')).unwrap_or(rest.len());
+                continue;
+            }
+            '/' if rest.starts_with("/*") => {
+                match rest[2..].find("*/") {
+                    Some(end) => pos += end + 4,
+                    None => {
+                        invalid = invalid.or(Some("Expected `*/` to close the comment".to_string()));
+                        pos += 2;
+                    }
+                }
+                code.push(' ');
+                shape.push(' ');
+                continue;
+            }
+            '<' if shape.trim_end().chars().last().is_none_or(|prev| OPERAND_FOLLOWS.contains(&prev))
+                && rest[1..].starts_with(|c: char| c.is_alphabetic() || c == '>') =>
+            {
+                let (element, len) = parse_element(rest, depth + 1)?;
+                match element {
+                    Ok(element) => {
+                        parts.push(JsPart::Code(std::mem::take(&mut code)));
+                        parts.push(JsPart::Element(element));
+                    }
+                    Err(message) => invalid = invalid.or(Some(message)),
+                }
+                shape.push('_');
+                pos += len;
+                continue;
+            }
+            '}' if closes_group => {
+                if let Some(close) = brackets.last() {
+                    invalid = invalid.or(Some(format!("Expected a closing `{}` in expression", close)));
+                }
+                break pos + 1;
+            }
+            '(' => brackets.push(')'),
+            '[' => brackets.push(']'),
+            '{' => brackets.push('}'),
+            ')' | ']' | '}' => {
+                if brackets.last() == Some(&c) {
+                    brackets.pop();
+                } else {
+                    invalid = invalid.or(Some(format!("Unexpected `{}` in expression", c)));
+                    if c == '}' {
+                        while brackets.pop().is_some_and(|close| close != '}') {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        code.push(c);
+        shape.push(c);
+        pos += c.len_utf8();
+    };
+    parts.push(JsPart::Code(code));
+    if let Some(message) = invalid {
+        return Ok((Err(message), end));
+    }
+
+    let shape = shape.trim();
+    if shape.is_empty() {
+        return Ok((Ok(None), end));
+    }
+    let expression = if spread { shape.strip_prefix("...").unwrap_or(shape) } else { shape };
+    if let Err(message) = js::check_expression(expression) {
+        return Ok((Err(format!("Expected an expression: {}", message)), end));
+    }
+
+    parts.retain(|part| !matches!(part, JsPart::Code(code) if code.is_empty()));
+    if let Some(JsPart::Code(code)) = parts.first_mut() {
+        *code = code.trim_start().to_string();
+    }
+    if let Some(JsPart::Code(code)) = parts.last_mut() {
+        *code = code.trim_end().to_string();
+    }
+    Ok((Ok(Some(Js(parts))), end))
+}
+
+/// Length of the string literal `input` starts with, or `None` if it
+/// doesn't end
+fn string_len(input: &str) -> Option<usize> {
+    let quote = input.chars().next()?;
+    let mut escaped = false;
+    for (index, c) in input.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return Some(index + 1);
+        }
+    }
+    None
+}
+
+fn collect_intrinsics(nodes: &[Node], names: &mut BTreeSet<String>) {
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Expr(expr) => collect_expr_intrinsics(expr, names),
+            Node::Element(element) => collect_element_intrinsics(element, names),
+        }
+    }
+}
+
+fn collect_element_intrinsics(element: &Element, names: &mut BTreeSet<String>) {
+    if let Name::Intrinsic(name) = &element.name {
+        names.insert(name.clone());
+    }
+    for prop in &element.props {
+        match prop {
+            Prop::Value(_, expr) | Prop::Spread(expr) => collect_expr_intrinsics(expr, names),
+        }
+    }
+    collect_intrinsics(&element.children, names);
+}
+
+fn collect_expr_intrinsics(expr: &Js, names: &mut BTreeSet<String>) {
+    for part in &expr.0 {
+        if let JsPart::Element(element) = part {
+            collect_element_intrinsics(element, names);
+        }
+    }
+}
+
+fn plain_text(nodes: &[Node]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Text(text) => text.clone(),
+            Node::Expr(_) => String::new(),
+            Node::Element(element) => plain_text(&element.children),
+        })
+        .collect()
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+fn prop_key(key: &str) -> String {
+    if is_identifier(key) { key.to_string() } else { json!(key).to_string() }
+}
+
+fn print(node: &Node, out: &mut String) {
+    match node {
+        Node::Text(text) => out.push_str(&json!(text).to_string()),
+        Node::Expr(expr) => print_expr(expr, out),
+        Node::Element(element) => print_element(element, out),
+    }
+}
+
+fn print_expr(expr: &Js, out: &mut String) {
+    for part in &expr.0 {
+        match part {
+            JsPart::Code(code) => out.push_str(code),
+            JsPart::Element(element) => print_element(element, out),
+        }
+    }
+}
+
+fn print_element(element: &Element, out: &mut String) {
+    out.push_str(if element.children.len() > 1 { "_jsxs(" } else { "_jsx(" });
+    match &element.name {
+        Name::Intrinsic(name) if is_identifier(name) => out.push_str(&format!("_components.{}", name)),
+        Name::Intrinsic(name) => out.push_str(&format!("_components[{}]", json!(name))),
+        Name::Component(name) => out.push_str(name),
+        Name::Fragment => out.push_str("_Fragment"),
+    }
+    out.push_str(", {");
+    let mut first = true;
+    let mut separator = |out: &mut String| {
+        out.push_str(if first { " " } else { ", " });
+        first = false;
+    };
+    for prop in &element.props {
+        separator(out);
+        match prop {
+            Prop::Value(key, value) => {
+                out.push_str(&format!("{}: ", prop_key(key)));
+                print_expr(value, out);
+            }
+            Prop::Spread(expr) => {
+                out.push_str("...");
+                print_expr(expr, out);
+            }
+        }
+    }
+    match element.children.as_slice() {
+        [] => {}
+        [child] => {
+            separator(out);
+            out.push_str("children: ");
+            print(child, out);
+        }
+        children => {
+            separator(out);
+            out.push_str("children: [");
+            for (index, child) in children.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                print(child, out);
+            }
+            out.push(']');
+        }
+    }
+    out.push_str(if first { "})" } else { " })" });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_to_jsx_calls() {
+        let component = compile("# Hello *world*\n\n- [x] done\n\n```js\nlet a;\n```\n", false).unwrap();
+        assert!(component.code.contains(r#"const _components = { code: "code", em: "em", h1: "h1", input: "input", li: "li", pre: "pre", ul: "ul", ...props.components };"#));
+        assert!(component.code.contains(r#"      _jsxs(_components.h1, { children: ["Hello ", _jsx(_components.em, { children: "world" })] }),"#));
+        assert!(component.code.contains(r#"_jsx(_components.input, { type: "checkbox", checked: true, disabled: true })"#));
+        assert!(component.code.contains(r#"_jsx(_components.pre, { children: _jsx(_components.code, { className: "language-js", children: "let a;\n" }) })"#));
+        assert_eq!(component.line_map, vec![(5, 0), (6, 2), (7, 4)]);
+    }
+
+    #[test]
+    fn test_component_tags() {
+        let body = "<Card title=\"Hi\" count={1 + 1} {...rest} open>\n\nSome **text** and {props.name}\n\n</Card>\n\n<Badge label='new' />\n";
+        let code = compile(body, false).unwrap().code;
+        assert!(code.contains(
+            r#"      _jsx(Card, { title: "Hi", count: 1 + 1, ...rest, open: true, children: _jsxs(_components.p, { children: ["Some ", _jsx(_components.strong, { children: "text" }), " and ", props.name] }) }),"#
+        ));
+        assert!(code.contains(r#"      _jsx(Badge, { label: "new" })"#));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let error = |body: &str| compile(body, false).err().map(|error| (error.message, error.offset));
+        assert_eq!(error("Text\n\n</div>\n"), Some(("Unexpected closing tag `</div>`".to_string(), 6)));
+        assert_eq!(
+            error("<A>\n<B>\n</A>\n").map(|(message, _)| message),
+            Some("Expected a closing tag for `<B>` before `</A>`".to_string())
+        );
+        assert!(error("Sum: {a +}\n").is_some());
+        assert!(error("{let a = 1}\n").is_some());
+        assert!(error("{a; b}\n").is_some());
+        assert!(error("Open {a\n").is_some());
+        assert!(error("{f(a}\n").is_some());
+        assert!(error("<Card title={} />\n").is_some());
+        assert!(error("{open && <Badge>}\n").is_some());
+        // Parsed, not just scanned
+        assert!(error("{a ? b}\n").is_some());
+        assert!(error("{...rest}\n").is_some());
+        assert!(error("<Card {...a b} />\n").is_some());
+        assert!(compile("{new Date().getFullYear()} and {async () => { await a; }}\n", false).is_ok());
+        assert_eq!(
+            error("{<A><B></A></B>}\n").map(|(message, _)| message),
+            Some("Expected a closing tag for `<B>` before `</A>`".to_string())
+        );
+        let deep = format!("{{{}}}\n", "<a>".repeat(MAX_NESTING + 1));
+        assert_eq!(error(&deep).map(|(message, _)| message), Some(TOO_DEEP.to_string()));
+
+        // Comments are dropped rather than emitted
+        let code = compile("{/* note */}\n\nCount: {i++ // up}\n", false).unwrap().code;
+        assert!(!code.contains("note") && !code.contains("up"), "{}", code);
+        assert!(code.contains("\"Count: \", i++"), "{}", code);
+    }
+
+    #[test]
+    fn test_jsx_in_expressions() {
+        let code = compile("<ul>\n{items.map(i => <li key={i}>{i}</li>)}\n</ul>\n", false).unwrap().code;
+        assert!(code.contains(r#"const _components = { li: "li", ul: "ul", ...props.components };"#), "{}", code);
+        assert!(code.contains(
+            r#"      _jsx(_components.ul, { children: items.map(i => _jsx(_components.li, { key: i, children: i })) })"#
+        ));
+
+        let code = compile("Shown {open && <Badge label=\"new\" />}\n\n{open ? <p>Don't\n  stop</p> : <>\n  <b>no</b>\n</>}\n", false).unwrap().code;
+        assert!(code.contains(r#"_jsxs(_components.p, { children: ["Shown ", open && _jsx(Badge, { label: "new" })] }),"#), "{}", code);
+        assert!(code.contains(
+            r#"      open ? _jsx(_components.p, { children: "Don't stop" }) : _jsx(_Fragment, { children: _jsx(_components.b, { children: "no" }) })"#
+        ));
+        // Comparisons aren't elements
+        assert!(compile("{a < b}\n", false).unwrap().code.contains("      a < b\n"));
+    }
+
+    #[test]
+    fn test_import_source() {
+        let mut options = TransformOptions {
+            framework: Some("preact".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(import_source(&options), Some("preact"));
+        options.jsx_import_source = Some("@emotion/react".to_string());
+        assert_eq!(import_source(&options), Some("@emotion/react"));
+        options.framework = Some("astro".to_string());
+        assert_eq!(import_source(&options), None);
+        assert_eq!(
+            runtime_import("react"),
+            "import { Fragment as _Fragment, jsx as _jsx, jsxs as _jsxs } from \"react/jsx-runtime\";\n"
+        );
+    }
+}
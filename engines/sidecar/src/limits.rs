@@ -0,0 +1,103 @@
+//! Size limits on transform input and output
+//!
+//! A huge document committed by accident shouldn't take the sidecar down
+//! with it. Sources over the input limit are refused before any parsing,
+//! and rendered output over the output limit is dropped instead of being
+//! cached or sent, both with a `tooLarge` error naming the limit.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::diagnostics::{ErrorKind, TransformError};
+use crate::source;
+
+/// Default upper bound for a document's source
+pub const DEFAULT_MAX_INPUT_BYTES: u64 = source::DEFAULT_MAX_BYTES;
+/// Default upper bound for a document's rendered output
+pub const DEFAULT_MAX_OUTPUT_BYTES: u64 = 64 * 1024 * 1024;
+
+static MAX_INPUT: AtomicU64 = AtomicU64::new(DEFAULT_MAX_INPUT_BYTES);
+static MAX_OUTPUT: AtomicU64 = AtomicU64::new(DEFAULT_MAX_OUTPUT_BYTES);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_input_bytes: u64,
+    pub max_output_bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_input_bytes: DEFAULT_MAX_INPUT_BYTES,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        }
+    }
+}
+
+impl Limits {
+    /// Refuse a source of `len` bytes if it's over the input limit
+    pub fn check_input(&self, len: usize) -> Result<(), String> {
+        check("input", len, self.max_input_bytes)
+    }
+
+    /// The input limit for a request asking for `requested`, which can
+    /// tighten it but not lift it
+    pub fn input_limit(&self, requested: Option<u64>) -> u64 {
+        requested.map_or(self.max_input_bytes, |requested| requested.min(self.max_input_bytes))
+    }
+
+    /// Refuse rendered output of `len` bytes if it's over the output limit
+    pub fn check_output(&self, len: usize) -> Result<(), String> {
+        check("output", len, self.max_output_bytes)
+    }
+}
+
+fn check(what: &str, len: usize, limit: u64) -> Result<(), String> {
+    if len as u64 > limit {
+        return Err(format!("{} is {} bytes, exceeding the {} byte limit", what, len, limit));
+    }
+    Ok(())
+}
+
+/// Set the process-wide limits returned by [`limits`]
+pub fn set_limits(limits: Limits) {
+    MAX_INPUT.store(limits.max_input_bytes, Ordering::Relaxed);
+    MAX_OUTPUT.store(limits.max_output_bytes, Ordering::Relaxed);
+}
+
+pub fn limits() -> Limits {
+    Limits {
+        max_input_bytes: MAX_INPUT.load(Ordering::Relaxed),
+        max_output_bytes: MAX_OUTPUT.load(Ordering::Relaxed),
+    }
+}
+
+/// [`Limits::check_input`] against the process-wide limits, as a transform error
+pub fn check_input(len: usize) -> Result<(), TransformError> {
+    limits().check_input(len).map_err(|e| TransformError::new(ErrorKind::TooLarge, e))
+}
+
+/// [`Limits::check_output`] against the process-wide limits, as a transform error
+pub fn check_output(len: usize) -> Result<(), TransformError> {
+    limits().check_output(len).map_err(|e| TransformError::new(ErrorKind::TooLarge, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks() {
+        let limits = Limits {
+            max_input_bytes: 10,
+            max_output_bytes: 20,
+        };
+        assert!(limits.check_input(10).is_ok());
+        assert_eq!(limits.check_input(11).unwrap_err(), "input is 11 bytes, exceeding the 10 byte limit");
+        assert!(limits.check_output(20).is_ok());
+        assert!(limits.check_output(21).is_err());
+
+        assert_eq!(limits.input_limit(None), 10);
+        assert_eq!(limits.input_limit(Some(4)), 4);
+        assert_eq!(limits.input_limit(Some(u64::MAX)), 10);
+    }
+}
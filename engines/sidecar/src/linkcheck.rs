@@ -0,0 +1,247 @@
+//! Dead internal link detection for the `checkLinks` RPC
+//!
+//! Relative links and images are resolved against the file system, and
+//! fragments against the heading ids (and HTML `id`/`name` attributes) of
+//! the document they point into, using the same slugs transforms assign.
+//! External URLs and root-relative routes belong to the host's router and
+//! are not checked. Target documents outside the checked set are read as
+//! needed, once per check.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use dashmap::DashMap;
+use pulldown_cmark::{Event, Parser, Tag};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::deps::resolve_local;
+use crate::diagnostics::Diagnostic;
+use crate::sandbox;
+use crate::source;
+use crate::transform::{assign_heading_ids, extract_frontmatter, parser_options};
+use crate::utils::is_document;
+
+/// A broken link and the document it was found in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BrokenLink {
+    pub file: String,
+    #[serde(flatten)]
+    pub diagnostic: Diagnostic,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkReport {
+    /// Documents checked
+    pub files: usize,
+    /// Internal links and images checked
+    pub links: usize,
+    pub broken: Vec<BrokenLink>,
+    /// Documents that could not be read
+    pub errors: Vec<Unreadable>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Unreadable {
+    pub file: String,
+    pub error: String,
+}
+
+/// A link in a document body, before resolution
+struct Link {
+    url: String,
+    offset: usize,
+    image: bool,
+}
+
+/// A parsed document: its links and the anchors it defines
+struct Document {
+    links: Vec<Link>,
+    anchors: HashSet<String>,
+    /// Lines of frontmatter stripped before parsing
+    line_offset: usize,
+    source: String,
+}
+
+impl Document {
+    fn parse(source: String) -> Self {
+        let (_, content) = extract_frontmatter(&source);
+        let line_offset = source.lines().count().saturating_sub(content.lines().count());
+        let mut events = Vec::new();
+        let mut links = Vec::new();
+        for (event, range) in Parser::new_ext(&content, parser_options(true)).into_offset_iter() {
+            match &event {
+                Event::Start(Tag::Link { dest_url, .. }) | Event::Start(Tag::Image { dest_url, .. }) => links.push(Link {
+                    url: dest_url.to_string(),
+                    offset: range.start,
+                    image: matches!(event, Event::Start(Tag::Image { .. })),
+                }),
+                _ => {}
+            }
+            events.push(event);
+        }
+
+        let mut anchors = HashSet::new();
+        for event in assign_heading_ids(events) {
+            match event {
+                Event::Start(Tag::Heading { id: Some(id), .. }) => {
+                    anchors.insert(id.to_string());
+                }
+                Event::Html(html) | Event::InlineHtml(html) => anchors.extend(html_anchors(&html)),
+                _ => {}
+            }
+        }
+        Document { links, anchors, line_offset, source: content }
+    }
+}
+
+/// Values of `id` and `name` attributes in a fragment of raw HTML
+fn html_anchors(html: &str) -> Vec<String> {
+    let mut anchors = Vec::new();
+    for attribute in [" id=", " name="] {
+        let mut rest = html;
+        while let Some(start) = rest.find(attribute) {
+            rest = &rest[start + attribute.len()..];
+            let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+                continue;
+            };
+            if let Some(end) = rest[1..].find(quote) {
+                anchors.push(rest[1..end + 1].to_string());
+            }
+        }
+    }
+    anchors
+}
+
+/// Check the internal links of `files`
+pub fn check(files: &[PathBuf]) -> LinkReport {
+    // Anchors of link targets, or `None` for targets that aren't readable documents
+    let targets: DashMap<String, Option<HashSet<String>>> = DashMap::new();
+    let anchors_of = |path: &str| -> Option<HashSet<String>> {
+        if let Some(anchors) = targets.get(path) {
+            return anchors.clone();
+        }
+        let anchors = source::read_source(Path::new(path), source::DEFAULT_MAX_BYTES)
+            .ok()
+            .map(|source| Document::parse(source).anchors);
+        targets.insert(path.to_string(), anchors.clone());
+        anchors
+    };
+
+    let checked: Vec<Result<(usize, Vec<BrokenLink>), Unreadable>> = files
+        .par_iter()
+        .map(|path| {
+            let file = path.to_string_lossy().into_owned();
+            let source = source::read_source(path, source::DEFAULT_MAX_BYTES).map_err(|e| Unreadable {
+                file: file.clone(),
+                error: e.to_string(),
+            })?;
+            let document = Document::parse(source);
+            targets.insert(file.clone(), Some(document.anchors.clone()));
+            let mut checked = 0;
+            let mut broken = Vec::new();
+            for link in &document.links {
+                let problem = if let Some(anchor) = link.url.strip_prefix('#') {
+                    checked += 1;
+                    (!anchor.is_empty() && !document.anchors.contains(anchor))
+                        .then(|| ("missing-anchor", format!("No heading or element with id '{}' in this document", anchor)))
+                } else if let Some(target) = resolve_local(&file, &link.url) {
+                    checked += 1;
+                    check_target(&link.url, &target, link.image, &anchors_of)
+                } else {
+                    None
+                };
+                if let Some((code, message)) = problem {
+                    let diagnostic = Diagnostic::new(code, message)
+                        .at_offset(&document.source, link.offset)
+                        .shifted(document.line_offset);
+                    broken.push(BrokenLink { file: file.clone(), diagnostic });
+                }
+            }
+            Ok((checked, broken))
+        })
+        .collect();
+
+    let mut report = LinkReport {
+        files: files.len(),
+        ..Default::default()
+    };
+    for result in checked {
+        match result {
+            Ok((links, broken)) => {
+                report.links += links;
+                report.broken.extend(broken);
+            }
+            Err(error) => report.errors.push(error),
+        }
+    }
+    report
+}
+
+/// Code and message for a link to `target` that doesn't resolve
+fn check_target(
+    url: &str,
+    target: &str,
+    image: bool,
+    anchors_of: &impl Fn(&str) -> Option<HashSet<String>>,
+) -> Option<(&'static str, String)> {
+    // Outside the sandbox is broken without a look, so reports can't tell
+    // what exists elsewhere on the host
+    if sandbox::check(Path::new(target)).is_err() || !Path::new(target).exists() {
+        let kind = if image { "Image" } else { "Link target" };
+        return Some(("broken-link", format!("{} '{}' does not exist", kind, url)));
+    }
+    let anchor = url.split_once('#').map(|(_, anchor)| anchor).filter(|anchor| !anchor.is_empty())?;
+    if !is_document(Path::new(target)) {
+        return None;
+    }
+    match anchors_of(target) {
+        Some(anchors) if !anchors.contains(anchor) => {
+            Some(("missing-anchor", format!("No heading or element with id '{}' in {}", anchor, target)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_anchors() {
+        assert_eq!(html_anchors("<a id=\"top\"></a><span name='x'>"), vec!["top", "x"]);
+        assert!(html_anchors("<div id=top>").is_empty());
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+        write("guide.md", "# Getting Started\n\n<a id=\"legacy\"></a>\n");
+        write("logo.png", "");
+        let index = write(
+            "index.md",
+            "---\ntitle: Home\n---\n# Home\n\n[ok](guide.md#getting-started) [html](./guide.md#legacy) ![](logo.png)\n\n\
+             [gone](missing.md) ![](missing.png)\n[bad anchor](guide.md#nope) [self](#home) [self bad](#away)\n\n\
+             [external](https://example.com) [route](/docs/)\n",
+        );
+
+        let report = check(&[index, dir.path().join("absent.md")]);
+        assert_eq!((report.files, report.links), (2, 8));
+        assert_eq!(report.errors.len(), 1);
+        let broken: Vec<(&str, usize, usize)> = report
+            .broken
+            .iter()
+            .map(|link| (link.diagnostic.code.as_ref(), link.diagnostic.line.unwrap(), link.diagnostic.column.unwrap()))
+            .collect();
+        assert_eq!(
+            broken,
+            vec![("broken-link", 8, 1), ("broken-link", 8, 20), ("missing-anchor", 9, 1), ("missing-anchor", 9, 43)]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! Socket server mode
+//!
+//! With `--listen <path>` the sidecar accepts any number of clients on a
+//! Unix domain socket, so several host processes (dev server, test runner)
+//! share one warm sidecar, its worker pool and its caches.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use tracing::info;
+
+use crate::codec::Codec;
+use crate::framing::Framing;
+
+#[cfg(unix)]
+pub fn serve_socket(path: &Path, framing: Framing, codec: Codec) -> anyhow::Result<()> {
+    use std::io::BufReader;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use tracing::{debug, error};
+
+    use crate::connection::ShutdownScope;
+
+    // A socket left behind by a crashed sidecar would make bind fail
+    if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let _ = SOCKET_PATH.set(path.to_path_buf());
+    info!("Listening on {}", path.display());
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            debug!("Client connected");
+            let result = stream
+                .try_clone()
+                .and_then(|reader| {
+                    // Other clients share the sidecar; `shutdown` only ends this one
+                    crate::connection::serve(BufReader::new(reader), stream, framing, codec, ShutdownScope::Connection)
+                });
+            match result {
+                Ok(()) => debug!("Client disconnected"),
+                Err(e) => error!("Client connection failed: {}", e),
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve_socket(path: &Path, _framing: Framing, _codec: Codec) -> anyhow::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("--listen {} requires Unix domain sockets, which this platform lacks", path.display()),
+    )
+    .into())
+}
+
+static SOCKET_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Remove the socket file, if serving on one, before the process exits
+pub fn cleanup() {
+    if let Some(path) = SOCKET_PATH.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,241 @@
+//! Log output: human-readable or JSON lines, to stderr or a rotated file
+//!
+//! A sidecar running for days under CI or as a daemon shouldn't grow one
+//! log file without bound, so `--log-file` rotates by size: when the next
+//! line would take the file past `--log-max-mb`, `sidecar.log` becomes
+//! `sidecar.log.1`, `sidecar.log.1` becomes `sidecar.log.2`, and so on,
+//! keeping `--log-keep` old files. `--log-format json` writes one object
+//! per line with `timestamp`, `level`, `target` and the event's fields.
+//!
+//! `--trace-out` also records spans (requests, pool tasks, transform stages)
+//! in Chrome trace format, to open in Perfetto or `chrome://tracing`. Task
+//! and stage spans are at `trace` level, so they stay out of the logs and
+//! cost nothing unless a trace is being recorded. The same spans can go to an
+//! OpenTelemetry collector, see [`otlp`](crate::otlp).
+//!
+//! Each request runs in an `info` span carrying its JSON-RPC id (a generated
+//! `trace` id for notifications), pool tasks included, so every log line it
+//! causes is prefixed with `rpc{id=7 method=transformBatch}`, or has a
+//! `span` field in JSON, and interleaved batches can be told apart. Clients
+//! can also have warnings sent to them, see [`clientlog`](crate::clientlog).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::filter::{filter_fn, EnvFilter};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+
+use crate::clientlog;
+use crate::otlp::{self, OtlpConfig};
+
+pub const DEFAULT_MAX_MB: u64 = 10;
+pub const DEFAULT_KEEP: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// One human-readable line per event
+    #[default]
+    Text,
+    /// One JSON object per event
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format '{}' (expected text or json)", other)),
+        }
+    }
+}
+
+/// Where and how to log
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// `EnvFilter` directives, e.g. `info` or `fastmd_sidecar::cache=debug`
+    pub filter: String,
+    pub format: LogFormat,
+    /// stderr when unset
+    pub file: Option<PathBuf>,
+    pub max_bytes: u64,
+    /// Rotated files kept besides the current one
+    pub keep: usize,
+    /// Chrome trace file to record spans to
+    pub trace_out: Option<PathBuf>,
+    /// Collector to export spans and metrics to
+    pub otlp: Option<OtlpConfig>,
+}
+
+/// Writes the trace file; dropping it completes the file
+static TRACE: Mutex<Option<FlushGuard>> = Mutex::new(None);
+
+/// Install the global subscriber
+pub fn init(config: &LogConfig) -> io::Result<()> {
+    let writer = match &config.file {
+        Some(path) => BoxMakeWriter::new(Mutex::new(RotatingFile::open(path, config.max_bytes, config.keep)?)),
+        None => BoxMakeWriter::new(io::stderr),
+    };
+    let mut fmt = tracing_subscriber::fmt::layer().with_writer(writer);
+    if config.file.is_some() {
+        fmt = fmt.with_ansi(false);
+    }
+    let fmt = match config.format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt.json().flatten_event(true).boxed(),
+    };
+    let filter = EnvFilter::try_new(&config.filter).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let trace = match &config.trace_out {
+        Some(path) => {
+            let (layer, guard) = ChromeLayerBuilder::new()
+                .writer(BufWriter::new(File::create(path)?))
+                .include_args(true)
+                .build();
+            *TRACE.lock().unwrap_or_else(|e| e.into_inner()) = Some(guard);
+            // Spans only; events are in the log
+            Some(layer.with_filter(filter_fn(|metadata| metadata.is_span())))
+        }
+        None => None,
+    };
+    let otlp = config.otlp.clone().map(|config| {
+        tracing::debug!("Exporting telemetry to {}", config.endpoint);
+        otlp::layer(config).with_filter(filter_fn(|metadata| metadata.is_span()))
+    });
+    tracing_subscriber::registry()
+        .with(fmt.with_filter(filter))
+        .with(trace)
+        .with(otlp)
+        .with(clientlog::layer())
+        .init();
+    Ok(())
+}
+
+/// Write out the spans recorded so far. The trace file stays open, and is
+/// readable as it is: trace viewers don't need the closing `]`.
+pub fn flush_trace() {
+    if let Some(guard) = TRACE.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        guard.flush();
+    }
+}
+
+/// Complete the trace file; later spans aren't recorded
+pub fn finish_trace() {
+    let guard = TRACE.lock().unwrap_or_else(|e| e.into_inner()).take();
+    drop(guard);
+}
+
+/// A log file that moves aside when it reaches its size limit
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    max_bytes: u64,
+    keep: usize,
+    /// Whether the last rotation failed, so it is only reported once
+    rotate_failed: bool,
+}
+
+impl RotatingFile {
+    /// Append to `path`, creating it and its directory if needed
+    pub fn open(path: &Path, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            len,
+            max_bytes,
+            keep,
+            rotate_failed: false,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Events are written whole, so a line is never split across files
+        if self.len > 0 && self.len + buf.len() as u64 > self.max_bytes {
+            // Logging must go on even if the old file can't be moved
+            match self.rotate() {
+                Ok(()) => self.rotate_failed = false,
+                Err(e) if !std::mem::replace(&mut self.rotate_failed, true) => {
+                    // Logged from elsewhere: the event is written through
+                    // the lock this write holds
+                    let path = self.path.clone();
+                    std::thread::spawn(move || tracing::warn!("Failed to rotate {}: {}", path.display(), e));
+                }
+                Err(_) => {}
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs/sidecar.log");
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "four\nfive\n");
+        assert_eq!(read(file.rotated(1)), "three\n");
+        assert_eq!(read(file.rotated(2)), "one\ntwo\n");
+
+        // Reopening appends, counting what's already there
+        drop(file);
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        file.write_all(b"six\n").unwrap();
+        assert_eq!(read(path.clone()), "six\n");
+        assert_eq!(read(file.rotated(1)), "four\nfive\n");
+        // Only `keep` old files survive
+        assert_eq!(read(file.rotated(2)), "three\n");
+        assert!(!file.rotated(3).exists());
+    }
+}
@@ -1,13 +1,68 @@
 use anyhow::Result;
 use clap::Parser;
-use std::io::{self, BufRead, BufReader, Write};
-use tracing::{debug, error, info};
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 
+mod abbr;
+mod bench;
+mod blocks;
+mod build;
+mod cache;
+mod canonical;
+mod cancel;
+mod cascade;
+mod clientlog;
+mod codec;
+mod config;
+mod connection;
+mod depgraph;
+mod dev;
+mod deps;
+mod diagnostics;
+mod digest;
+mod framing;
+mod fsutil;
 mod handlers;
+mod hmr;
+mod http;
+mod js;
+mod jsx;
+mod linkcheck;
+mod limits;
+mod listen;
+mod logging;
+mod metadata;
+mod metrics;
+mod minify;
+mod ordering;
+mod oneshot;
+mod otlp;
+mod output;
+mod outbox;
+mod parallel;
+mod prime;
 mod protocol;
+mod publish;
+mod sandbox;
+mod scan;
+mod search;
+mod schema;
+mod session;
+mod source;
+mod sourcemap;
+mod store;
+mod transform;
 mod utils;
+mod watch;
+mod watchdog;
+mod ws;
 
-use protocol::{RpcMessage, RpcRequest, RpcResponse};
+use codec::Codec;
+use connection::ShutdownScope;
+use outbox::Outbox;
+use protocol::{RpcRequest, RpcResponse};
 
 #[derive(Parser, Debug)]
 #[command(name = "fastmd-sidecar")]
@@ -16,92 +71,368 @@ struct Args {
     #[arg(long, default_value = "info")]
     log_level: String,
     
+    /// Log line format: text or json (one object per line)
+    #[arg(long, default_value = "text")]
+    log_format: logging::LogFormat,
+    
+    /// Log to this file instead of stderr, rotating it by size
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+    
+    /// Rotate the log file when it would grow past this many megabytes
+    #[arg(long, default_value_t = logging::DEFAULT_MAX_MB)]
+    log_max_mb: u64,
+    
+    /// Rotated log files to keep
+    #[arg(long, default_value_t = logging::DEFAULT_KEEP)]
+    log_keep: usize,
+    
+    /// Record requests, pool tasks and transform stages to this file in
+    /// Chrome trace format, for Perfetto or chrome://tracing
+    #[arg(long)]
+    trace_out: Option<PathBuf>,
+    
+    /// Export the same spans, and cache counters, to this OpenTelemetry
+    /// collector over OTLP/HTTP (default: OTEL_EXPORTER_OTLP_ENDPOINT)
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+    
+    /// Project config file (default: fastmd.toml or fastmd.json in the
+    /// project root or working directory)
+    #[arg(long)]
+    config: Option<PathBuf>,
+    
     #[arg(long)]
     cache_dir: Option<String>,
+    
+    /// Evict least recently used cache entries beyond this many megabytes
+    #[arg(long)]
+    cache_max_mb: Option<u64>,
+    
+    /// Evict cache entries unused for this many days
+    #[arg(long)]
+    cache_max_age_days: Option<u64>,
+    
+    /// zstd level for cached entries; 0 stores them uncompressed [default: 3]
+    #[arg(long)]
+    cache_compression_level: Option<i32>,
+    
+    /// Share transform results with other projects through a
+    /// content-addressed store under the user cache directory
+    #[arg(long)]
+    shared_cache: bool,
+    
+    /// Root of the shared store; implies --shared-cache
+    #[arg(long)]
+    shared_cache_dir: Option<PathBuf>,
+    
+    /// Durability of cache and output writes: none, fsync-on-close, fsync-dir
+    #[arg(long, default_value = "none")]
+    durability: fsutil::Durability,
+    
+    /// Exit when this process dies (default: the process that started us)
+    #[arg(long)]
+    parent_pid: Option<u32>,
+    
+    /// Don't watch the parent process; rely on stdin EOF only
+    #[arg(long)]
+    no_parent_watch: bool,
+    
+    /// Exit if no message arrives for this many seconds
+    #[arg(long)]
+    heartbeat_timeout: Option<u64>,
+    
+    /// Message framing: ndjson, content-length, or auto (detect from input)
+    #[arg(long, default_value = "ndjson")]
+    framing: framing::Framing,
+    
+    /// Message body encoding: json or msgpack (implies content-length framing)
+    #[arg(long, default_value = "json")]
+    codec: Codec,
+    
+    /// Refuse documents larger than this many megabytes
+    #[arg(long, default_value_t = limits::DEFAULT_MAX_INPUT_BYTES / (1024 * 1024))]
+    max_input_mb: u64,
+    
+    /// Fail transforms whose output is larger than this many megabytes
+    #[arg(long, default_value_t = limits::DEFAULT_MAX_OUTPUT_BYTES / (1024 * 1024))]
+    max_output_mb: u64,
+    
+    /// Refuse to read files outside this directory
+    #[arg(long)]
+    project_root: Option<PathBuf>,
+    
+    /// Serve clients on this Unix domain socket instead of stdio
+    #[arg(long)]
+    listen: Option<PathBuf>,
+    
+    /// Serve a REST API on this localhost port instead of stdio
+    #[arg(long, conflicts_with = "listen")]
+    http: Option<u16>,
+    
+    /// Serve JSON-RPC over WebSocket on this localhost port instead of stdio
+    #[arg(long, conflicts_with_all = ["listen", "http"])]
+    ws: Option<u16>,
+    
+    /// Also accept WebSocket clients from pages of this origin (e.g.
+    /// https://docs.example.com); loopback origins are always accepted
+    #[arg(long, requires = "ws")]
+    ws_allow_origin: Vec<String>,
+    
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Render a content directory to static files and exit
+    Build(build::BuildArgs),
+    /// Render one document, print the result and exit
+    Transform(oneshot::TransformArgs),
+    /// Build a content directory, then rebuild what changes until interrupted
+    Watch(dev::WatchArgs),
+    /// Compare markdown engines' speed and output on a corpus of documents
+    Bench(bench::BenchArgs),
+    /// Render documents for a parent sidecar over stdio (see FASTMD_ISOLATION)
+    #[command(hide = true)]
+    Worker,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
     
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(args.log_level)
-        .with_writer(io::stderr)
-        .init();
+    logging::init(&logging::LogConfig {
+        filter: args.log_level.clone(),
+        format: args.log_format,
+        file: args.log_file.clone(),
+        max_bytes: args.log_max_mb * 1024 * 1024,
+        keep: args.log_keep,
+        trace_out: args.trace_out.clone(),
+        otlp: otlp::OtlpConfig::from_env(args.otlp_endpoint.clone()),
+    })
+    .map_err(|e| anyhow::anyhow!("Cannot set up logging: {}", e))?;
+    
+    if let Some(Command::Worker) = &args.command {
+        return Ok(parallel::process::serve(io::stdin().lock(), io::stdout().lock())?);
+    }
     
     info!("FastMD sidecar starting");
     
-    // Setup stdin/stdout for NDJSON communication
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-    let reader = BufReader::new(stdin.lock());
-    
-    // Process messages
-    for line in reader.lines() {
-        let line = match line {
-            Ok(l) => l,
-            Err(e) => {
-                error!("Failed to read line: {}", e);
-                continue;
-            }
-        };
-        
-        if line.trim().is_empty() {
-            continue;
-        }
-        
-        debug!("Received: {}", line);
-        
-        // Parse message
-        let message: RpcMessage = match serde_json::from_str(&line) {
-            Ok(m) => m,
-            Err(e) => {
-                error!("Failed to parse message: {}", e);
-                let error_response = protocol::create_parse_error();
-                writeln!(stdout, "{}", serde_json::to_string(&error_response)?)?;
-                stdout.flush()?;
-                continue;
-            }
-        };
-        
-        // Handle message
-        match message {
-            RpcMessage::Request(req) => {
-                let response = handle_request(req);
-                writeln!(stdout, "{}", serde_json::to_string(&response)?)?;
-                stdout.flush()?;
-            }
-            RpcMessage::Notification(notif) => {
-                handle_notification(notif);
-            }
+    fsutil::set_durability(args.durability);
+    limits::set_limits(limits::Limits {
+        max_input_bytes: args.max_input_mb * 1024 * 1024,
+        max_output_bytes: args.max_output_mb * 1024 * 1024,
+    });
+    if let Some(root) = &args.project_root {
+        sandbox::set_root(root).map_err(|e| anyhow::anyhow!("Invalid project root {}: {}", root.display(), e))?;
+        info!("Confining reads to {}", root.display());
+    }
+    
+    // Flags take precedence over the config file
+    let config_dir = sandbox::root().map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+    let config = config::init(args.config.as_deref(), &config_dir).map_err(anyhow::Error::msg)?;
+    let mut pool_config = parallel::ParallelConfig::default();
+    pool_config.num_workers = config.pool.workers;
+    pool_config.min_workers = config.pool.min_workers.unwrap_or(pool_config.min_workers);
+    parallel::set_default_config(pool_config);
+    let cache_dir = args.cache_dir.as_deref().map(PathBuf::from).or(config.cache.dir);
+    let compression_level = args
+        .cache_compression_level
+        .or(config.cache.compression_level)
+        .unwrap_or(cache::DEFAULT_COMPRESSION_LEVEL);
+    
+    if let Some(cache_dir) = &cache_dir {
+        // Writes interrupted by a crash leave only temporary files behind
+        match fsutil::remove_stale_temp_files(cache_dir) {
+            Ok(0) | Err(_) => {}
+            Ok(n) => info!("Removed {} stale temporary files from cache", n),
         }
     }
+    depgraph::init(cache_dir.as_deref());
+    metadata::init(cache_dir.as_deref());
+    let gc_policy = cache::GcPolicy {
+        max_bytes: args.cache_max_mb.or(config.cache.max_mb).map(|mb| mb * 1024 * 1024),
+        max_age: args
+            .cache_max_age_days
+            .or(config.cache.max_age_days)
+            .map(|days| Duration::from_secs(days * 86_400)),
+    };
+    cache::init(cache_dir.as_deref(), gc_policy, compression_level);
+    let shared_root = args
+        .shared_cache_dir
+        .clone()
+        .or_else(|| args.shared_cache.then(store::default_root).flatten());
+    if args.shared_cache && shared_root.is_none() {
+        warn!("No user cache directory found; the shared cache is disabled");
+    }
+    store::init(shared_root.as_deref(), gc_policy, compression_level);
+    
+    if let Some(Command::Build(build_args)) = &args.command {
+        let result = build::run(build_args);
+        persist_state();
+        return result;
+    }
+    if let Some(Command::Transform(transform_args)) = &args.command {
+        let result = oneshot::run(transform_args);
+        persist_state();
+        return result;
+    }
+    if let Some(Command::Watch(watch_args)) = &args.command {
+        return dev::run(watch_args, checkpoint_state);
+    }
+    if let Some(Command::Bench(bench_args)) = &args.command {
+        return bench::run(bench_args);
+    }
+    
+    let watchdog_config = watchdog::WatchdogConfig {
+        parent_pid: if args.no_parent_watch { None } else { args.parent_pid.or_else(watchdog::default_parent_pid) },
+        heartbeat_timeout: args.heartbeat_timeout.map(Duration::from_secs),
+    };
+    watchdog::spawn(watchdog_config, persist_state);
+    
+    if let Some(path) = &args.listen {
+        return listen::serve_socket(path, args.framing, args.codec);
+    }
+    if let Some(port) = args.http {
+        return http::serve(port);
+    }
+    if let Some(port) = args.ws {
+        return ws::serve(port, args.codec, args.ws_allow_origin.clone());
+    }
+    
+    connection::serve(BufReader::new(io::stdin().lock()), io::stdout(), args.framing, args.codec, ShutdownScope::Process)?;
     
+    persist_state();
+    parallel::shutdown_global_pool();
     info!("FastMD sidecar shutting down");
     Ok(())
 }
 
-fn handle_request(req: RpcRequest) -> RpcResponse {
+/// Methods advertised by `initialize`; keep in sync with `handle_request`
+const METHODS: &[&str] = &[
+    "initialize",
+    "configure",
+    "validateFrontmatter",
+    "cancel",
+    "ping",
+    "shutdown",
+    "transform",
+    "transformPath",
+    "transformBatch",
+    "transformGlob",
+    "watch",
+    "unwatch",
+    "verifyOutput",
+    "normalize",
+    "computeDigest",
+    "scanFiles",
+    "hashFile",
+    "affectedOutputs",
+    "orderDocuments",
+    "stats",
+    "resetStats",
+    "poolConfigure",
+    "cacheGc",
+    "cacheStats",
+    "cacheClear",
+    "cachePrime",
+    "cacheExport",
+    "cacheImport",
+    "invalidate",
+    "queryMetadata",
+    "linkGraph",
+    "backlinks",
+    "checkLinks",
+];
+
+fn handle_request(req: RpcRequest, outbox: &Outbox) -> RpcResponse {
+    let method = req.method.clone();
+    let started = Instant::now();
+    // Log lines and spans while handling the request carry its id, also
+    // on pool workers, so concurrent requests can be told apart
+    let span = tracing::info_span!("rpc", id = %req.id, method = %method);
+    let response = span.in_scope(|| dispatch(req, outbox));
+    
+    // Unknown method names are client bugs, not workload
+    let not_found = response.error.as_ref().is_some_and(|e| e.code == protocol::METHOD_NOT_FOUND);
+    if !not_found {
+        metrics::global().record(&method, started.elapsed(), response.error.is_none());
+    }
+    response
+}
+
+fn dispatch(req: RpcRequest, outbox: &Outbox) -> RpcResponse {
     match req.method.as_str() {
+        "initialize" => handlers::handle_initialize(req.id, req.params, METHODS),
+        "configure" => handlers::handle_configure(req.id, req.params, outbox),
+        "validateFrontmatter" => handlers::handle_validate_frontmatter(req.id, req.params),
+        "cancel" => handlers::handle_cancel(req.id, req.params),
         "ping" => handlers::handle_ping(req.id),
         "shutdown" => {
             info!("Shutdown requested");
+            persist_state();
+            listen::cleanup();
+            parallel::shutdown_global_pool();
             std::process::exit(0);
         }
         "transform" => handlers::handle_transform(req.id, req.params),
+        "transformPath" => handlers::handle_transform_path(req.id, req.params),
+        "transformBatch" => handlers::handle_transform_batch(req.id, req.params),
+        "transformGlob" => handlers::handle_transform_glob(req.id, req.params, outbox),
+        "watch" => handlers::handle_watch(req.id, req.params, outbox),
+        "unwatch" => handlers::handle_unwatch(req.id, req.params),
+        "verifyOutput" => handlers::handle_verify_output(req.id, req.params),
         "normalize" => handlers::handle_normalize(req.id, req.params),
         "computeDigest" => handlers::handle_compute_digest(req.id, req.params),
+        "scanFiles" => handlers::handle_scan_files(req.id, req.params),
+        "hashFile" => handlers::handle_hash_file(req.id, req.params),
+        "affectedOutputs" => handlers::handle_affected_outputs(req.id, req.params),
+        "orderDocuments" => handlers::handle_order_documents(req.id, req.params),
+        "stats" => handlers::handle_stats(req.id),
+        "resetStats" => handlers::handle_reset_stats(req.id),
+        "poolConfigure" => handlers::handle_pool_configure(req.id, req.params),
+        "cacheGc" => handlers::handle_cache_gc(req.id, req.params),
+        "cacheStats" => handlers::handle_cache_stats(req.id),
+        "cacheClear" => handlers::handle_cache_clear(req.id, req.params),
+        "cachePrime" => handlers::handle_cache_prime(req.id, req.params, outbox),
+        "cacheExport" => handlers::handle_cache_export(req.id, req.params),
+        "cacheImport" => handlers::handle_cache_import(req.id, req.params),
+        "invalidate" => handlers::handle_invalidate(req.id, req.params),
+        "queryMetadata" => handlers::handle_query_metadata(req.id, req.params),
+        "linkGraph" => handlers::handle_link_graph(req.id, req.params),
+        "backlinks" => handlers::handle_backlinks(req.id, req.params),
+        "checkLinks" => handlers::handle_check_links(req.id, req.params),
         _ => protocol::create_method_not_found(req.id),
     }
 }
 
+/// Flush state that should survive a restart, before exiting
+fn persist_state() {
+    checkpoint_state();
+    logging::finish_trace();
+    otlp::flush();
+}
+
+/// [`persist_state`] for a process that keeps running
+fn checkpoint_state() {
+    depgraph::persist();
+    metadata::persist();
+    logging::flush_trace();
+}
+
 fn handle_notification(notif: protocol::RpcNotification) {
+    // Notifications have no id to correlate by, so they get one
+    static NEXT_TRACE: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+    let trace = format!("n{}", NEXT_TRACE.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+    let _span = tracing::info_span!("notification", trace = %trace, method = %notif.method).entered();
     match notif.method.as_str() {
         "log" => {
             if let Some(params) = notif.params {
                 info!("Client log: {:?}", params);
             }
         }
+        // Keeps the watchdog's heartbeat timer from expiring while idle
+        "heartbeat" => {}
         _ => {
             debug!("Unknown notification: {}", notif.method);
         }
@@ -0,0 +1,402 @@
+//! Queryable per-document metadata for the `queryMetadata` RPC
+//!
+//! Every transform records what a static site usually needs to build
+//! listings and archives: the resolved frontmatter, headings, outgoing
+//! links and a word count. Documents are kept ordered by path, so a
+//! directory is a range scan, and frontmatter tags are indexed. With
+//! `--cache-dir` the store is saved next to the dependency graph and
+//! survives restarts, turning the sidecar into a small content database.
+//!
+//! Links between documents are resolved as they are indexed, which gives
+//! the `linkGraph` and `backlinks` RPCs a site-wide link graph without a
+//! separate indexing pass.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use parking_lot::RwLock;
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::deps::resolve_local;
+use crate::fsutil;
+use crate::ordering::{lookup, parse_date};
+use crate::output::Heading;
+use crate::search::plain_text;
+use crate::transform::{assign_heading_ids, collect_headings, parser_options};
+use crate::utils::is_document;
+
+const METADATA_FILE: &str = "metadata.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentMetadata {
+    pub file: String,
+    pub frontmatter: Option<Value>,
+    pub headings: Vec<Heading>,
+    /// Link and image destinations, in document order
+    pub links: Vec<String>,
+    /// Other documents this one links to, resolved to paths
+    #[serde(default)]
+    pub references: BTreeSet<String>,
+    /// Words of prose, not counting code blocks
+    pub word_count: usize,
+    /// Hash of the source and resolved frontmatter this was extracted from
+    pub source_hash: String,
+}
+
+impl DocumentMetadata {
+    /// The frontmatter `title`, or else the text of the first heading
+    pub fn title(&self) -> Option<&str> {
+        self.frontmatter
+            .as_ref()
+            .and_then(|frontmatter| frontmatter.get("title"))
+            .and_then(Value::as_str)
+            .or_else(|| self.headings.first().map(|heading| heading.text.as_str()))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MetadataQuery {
+    /// Documents tagged with every one of these
+    pub tags: Vec<String>,
+    /// Directory documents must be in, at any depth
+    pub directory: Option<String>,
+    /// Inclusive date range, in any format frontmatter dates accept
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Frontmatter key holding the date (dot-separated for nesting; default: `date`)
+    pub date_key: Option<String>,
+    /// Return at most this many documents, after `offset`
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MetadataIndex {
+    documents: BTreeMap<String, DocumentMetadata>,
+    /// Tag -> documents carrying it; rebuilt on load
+    #[serde(skip)]
+    tags: HashMap<String, BTreeSet<String>>,
+    /// Linked document -> documents linking to it; rebuilt on load
+    #[serde(skip)]
+    backlinks: HashMap<String, BTreeSet<String>>,
+}
+
+impl MetadataIndex {
+    /// Whether `file` is indexed from the source with this hash
+    pub fn is_current(&self, file: &str, source_hash: &str) -> bool {
+        self.documents.get(file).is_some_and(|document| document.source_hash == source_hash)
+    }
+
+    pub fn insert(&mut self, document: DocumentMetadata) {
+        self.remove(&document.file);
+        for tag in tags_of(document.frontmatter.as_ref()) {
+            self.tags.entry(tag).or_default().insert(document.file.clone());
+        }
+        for target in &document.references {
+            self.backlinks.entry(target.clone()).or_default().insert(document.file.clone());
+        }
+        self.documents.insert(document.file.clone(), document);
+    }
+
+    fn remove(&mut self, file: &str) {
+        let Some(previous) = self.documents.remove(file) else {
+            return;
+        };
+        for tag in tags_of(previous.frontmatter.as_ref()) {
+            unlink(&mut self.tags, &tag, file);
+        }
+        for target in &previous.references {
+            unlink(&mut self.backlinks, target, file);
+        }
+    }
+
+    /// Drop the documents `matches` accepts; returns how many
+    pub fn forget(&mut self, matches: impl Fn(&str) -> bool) -> usize {
+        let files: Vec<String> = self.documents.keys().filter(|file| matches(file)).cloned().collect();
+        for file in &files {
+            self.remove(file);
+        }
+        files.len()
+    }
+
+    /// Documents matching `query` in path order, and how many matched
+    /// before `limit` and `offset` were applied
+    pub fn query(&self, query: &MetadataQuery) -> Result<(Vec<&DocumentMetadata>, usize), String> {
+        let bound = |date: &Option<String>| {
+            date.as_deref()
+                .map(|date| parse_date(date).ok_or_else(|| format!("Invalid date '{}'", date)))
+                .transpose()
+        };
+        let (from, to) = (bound(&query.from)?, bound(&query.to)?);
+        let date_key = query.date_key.as_deref().unwrap_or("date");
+
+        let candidates = self.in_directory(query.directory.as_deref());
+        let tagged = |document: &DocumentMetadata| {
+            query
+                .tags
+                .iter()
+                .all(|tag| self.tags.get(tag).is_some_and(|files| files.contains(&document.file)))
+        };
+        let dated = |document: &DocumentMetadata| {
+            if from.is_none() && to.is_none() {
+                return true;
+            }
+            let date = document
+                .frontmatter
+                .as_ref()
+                .and_then(|frontmatter| lookup(frontmatter, date_key))
+                .and_then(Value::as_str)
+                .and_then(parse_date);
+            date.is_some_and(|date| from.is_none_or(|from| date >= from) && to.is_none_or(|to| date <= to))
+        };
+
+        let matched: Vec<&DocumentMetadata> = candidates.filter(|document| tagged(document) && dated(document)).collect();
+        let total = matched.len();
+        let page = matched
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect();
+        Ok((page, total))
+    }
+
+    /// Documents linking to `file`, in path order
+    pub fn backlinks(&self, file: &str) -> impl Iterator<Item = &DocumentMetadata> {
+        self.backlinks
+            .get(file)
+            .into_iter()
+            .flatten()
+            .filter_map(|source| self.documents.get(source))
+    }
+
+    /// Links between documents, from the documents in `directory` (or all)
+    pub fn link_graph(&self, directory: Option<&str>) -> Vec<(&str, &str)> {
+        self.in_directory(directory)
+            .flat_map(|document| document.references.iter().map(|target| (document.file.as_str(), target.as_str())))
+            .collect()
+    }
+
+    /// Indexed documents in `directory` at any depth, or all of them
+    pub fn in_directory<'a>(&'a self, directory: Option<&str>) -> Box<dyn Iterator<Item = &'a DocumentMetadata> + 'a> {
+        match directory {
+            Some(directory) => {
+                let prefix = format!("{}/", directory.trim_end_matches('/'));
+                Box::new(
+                    self.documents
+                        .range(prefix.clone()..)
+                        .take_while(move |(file, _)| file.starts_with(&prefix))
+                        .map(|(_, document)| document),
+                )
+            }
+            None => Box::new(self.documents.values()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Self {
+        let mut index: Self = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring corrupt metadata store {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        for document in std::mem::take(&mut index.documents).into_values() {
+            index.insert(document);
+        }
+        index
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let bytes = serde_json::to_vec(self).map_err(std::io::Error::other)?;
+        fsutil::write_atomic(path, &bytes)
+    }
+}
+
+/// Remove `file` from the set under `key`, dropping the set once empty
+fn unlink(index: &mut HashMap<String, BTreeSet<String>>, key: &str, file: &str) {
+    if let Some(files) = index.get_mut(key) {
+        files.remove(file);
+        if files.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+/// `tags` from frontmatter, as a list or a single string
+fn tags_of(frontmatter: Option<&Value>) -> Vec<String> {
+    match frontmatter.and_then(|frontmatter| frontmatter.get("tags")) {
+        Some(Value::Array(tags)) => tags.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+        Some(Value::String(tag)) => vec![tag.clone()],
+        _ => Vec::new(),
+    }
+}
+
+/// Extract the metadata of a document body
+pub fn extract(file: &str, content: &str, frontmatter: Option<&Value>, source_hash: String) -> DocumentMetadata {
+    let events: Vec<Event> = Parser::new_ext(content, parser_options(true)).collect();
+    let events = assign_heading_ids(events);
+    let mut links = Vec::new();
+    let mut references = BTreeSet::new();
+    for event in &events {
+        if let Event::Start(Tag::Link { dest_url, .. }) | Event::Start(Tag::Image { dest_url, .. }) = event {
+            links.push(dest_url.to_string());
+            references.extend(
+                resolve_local(file, dest_url).filter(|target| target != file && is_document(Path::new(target))),
+            );
+        }
+    }
+    let word_count = plain_text(&events)
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count();
+    DocumentMetadata {
+        file: file.to_string(),
+        frontmatter: frontmatter.cloned(),
+        headings: collect_headings(&events),
+        links,
+        references,
+        word_count,
+        source_hash,
+    }
+}
+
+struct MetadataStore {
+    index: RwLock<MetadataIndex>,
+    path: Option<PathBuf>,
+}
+
+static STORE: OnceLock<MetadataStore> = OnceLock::new();
+
+/// Load the persisted store from the cache directory, if one is configured.
+/// Must be called before the first use of [`index`] to take effect.
+pub fn init(cache_dir: Option<&Path>) {
+    STORE.get_or_init(|| {
+        let path = cache_dir.map(|dir| dir.join(METADATA_FILE));
+        let index = path.as_deref().map(MetadataIndex::load).unwrap_or_default();
+        MetadataStore {
+            index: RwLock::new(index),
+            path,
+        }
+    });
+}
+
+/// Process-wide metadata index
+pub fn index() -> &'static RwLock<MetadataIndex> {
+    init(None);
+    &STORE.get().expect("metadata store initialized").index
+}
+
+/// Index a transformed document, unless it is indexed from this source already
+pub fn record(file: &str, source: &str, frontmatter: Option<&Value>, content: &str) {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(source.as_bytes());
+    hasher.update(frontmatter.map(Value::to_string).unwrap_or_default().as_bytes());
+    let source_hash = hasher.finalize().to_hex().to_string();
+    if index().read().is_current(file, &source_hash) {
+        return;
+    }
+    let document = extract(file, content, frontmatter, source_hash);
+    index().write().insert(document);
+}
+
+/// Write the store back to the cache directory
+pub fn persist() {
+    let Some(store) = STORE.get() else { return };
+    let Some(path) = &store.path else { return };
+    if let Err(e) = store.index.read().save(path) {
+        tracing::error!("Failed to persist metadata store: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document(file: &str, frontmatter: Value) -> DocumentMetadata {
+        extract(file, "# Title\n\nSome *em*phasized words [here](./a.md) .\n\n```\nnot counted\n```\n", Some(&frontmatter), String::new())
+    }
+
+    #[test]
+    fn test_extract() {
+        let document = document("/site/blog/a.md", json!({}));
+        assert_eq!(document.headings[0].slug, "title");
+        assert_eq!(document.links, vec!["./a.md"]);
+        assert_eq!(document.word_count, 5);
+    }
+
+    #[test]
+    fn test_query() {
+        let mut index = MetadataIndex::default();
+        index.insert(document("/site/blog/a.md", json!({ "tags": ["rust", "web"], "date": "2024-01-10" })));
+        index.insert(document("/site/blog/b.md", json!({ "tags": "rust", "date": "2024-03-01" })));
+        index.insert(document("/site/docs/c.md", json!({ "tags": ["web"] })));
+        index.insert(document("/site/blog-old/d.md", json!({ "tags": ["rust"] })));
+
+        let files = |index: &MetadataIndex, query: MetadataQuery| -> Vec<String> {
+            index.query(&query).unwrap().0.into_iter().map(|document| document.file.clone()).collect()
+        };
+        let rust = || MetadataQuery { tags: vec!["rust".to_string()], ..Default::default() };
+        assert_eq!(files(&index, rust()), vec!["/site/blog-old/d.md", "/site/blog/a.md", "/site/blog/b.md"]);
+        assert_eq!(
+            files(&index, MetadataQuery { directory: Some("/site/blog/".to_string()), ..rust() }),
+            vec!["/site/blog/a.md", "/site/blog/b.md"]
+        );
+        assert_eq!(
+            files(&index, MetadataQuery { from: Some("2024-02-01".to_string()), ..rust() }),
+            vec!["/site/blog/b.md"]
+        );
+        let (page, total) = index.query(&MetadataQuery { limit: Some(1), offset: 1, ..rust() }).unwrap();
+        assert_eq!((page[0].file.as_str(), total), ("/site/blog/a.md", 3));
+        assert!(index.query(&MetadataQuery { to: Some("soon".to_string()), ..Default::default() }).is_err());
+
+        // Re-indexing a document moves it between tags
+        index.insert(document("/site/blog/a.md", json!({ "tags": ["web"] })));
+        assert_eq!(files(&index, rust()), vec!["/site/blog-old/d.md", "/site/blog/b.md"]);
+        assert_eq!(index.forget(|file| file.starts_with("/site/blog/")), 2);
+        assert_eq!(files(&index, MetadataQuery::default()), vec!["/site/blog-old/d.md", "/site/docs/c.md"]);
+    }
+
+    #[test]
+    fn test_link_graph() {
+        let mut index = MetadataIndex::default();
+        let linking = |file: &str, body: &str| extract(file, body, None, String::new());
+        index.insert(linking("/site/a.md", "# A\n\n[b](./b.md#intro) [c](notes/c.mdx) ![img](./x.png) [ext](https://example.com)\n"));
+        index.insert(linking("/site/notes/c.mdx", "[back](../a.md) [b](../b.md)\n"));
+
+        assert_eq!(
+            index.link_graph(None),
+            vec![("/site/a.md", "/site/b.md"), ("/site/a.md", "/site/notes/c.mdx"), ("/site/notes/c.mdx", "/site/a.md"), ("/site/notes/c.mdx", "/site/b.md")]
+        );
+        assert_eq!(index.link_graph(Some("/site/notes")).len(), 2);
+        let backlinks: Vec<&str> = index.backlinks("/site/b.md").map(|document| document.file.as_str()).collect();
+        assert_eq!(backlinks, vec!["/site/a.md", "/site/notes/c.mdx"]);
+        assert_eq!(index.backlinks("/site/notes/c.mdx").next().and_then(DocumentMetadata::title), Some("A"));
+
+        // Edits and removals update the backlinks
+        index.insert(linking("/site/a.md", "No links left\n"));
+        assert_eq!(index.backlinks("/site/b.md").count(), 1);
+        index.forget(|file| file.ends_with(".mdx"));
+        assert_eq!(index.backlinks("/site/b.md").count(), 0);
+    }
+
+    #[test]
+    fn test_persistence_rebuilds_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(METADATA_FILE);
+        let mut index = MetadataIndex::default();
+        index.insert(document("/a.md", json!({ "tags": ["rust"] })));
+        index.insert(extract("/b.md", "[a](a.md)\n", None, String::new()));
+        index.save(&path).unwrap();
+
+        let loaded = MetadataIndex::load(&path);
+        let query = MetadataQuery { tags: vec!["rust".to_string()], ..Default::default() };
+        assert_eq!(loaded.query(&query).unwrap().1, 1);
+        assert_eq!(loaded.backlinks("/a.md").count(), 1);
+    }
+}
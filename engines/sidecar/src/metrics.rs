@@ -0,0 +1,202 @@
+//! Per-method request metrics reported by the `stats` RPC
+//!
+//! Latencies go into log-scaled histograms so percentiles stay cheap to
+//! record and bounded in memory regardless of how long the session runs.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use parking_lot::RwLock;
+use serde::Serialize;
+
+/// Smallest latency bucket bound, in milliseconds
+const HISTOGRAM_MIN_MS: f64 = 0.01;
+/// Each bucket's upper bound is this factor above the previous one (~10% error)
+const HISTOGRAM_GROWTH: f64 = 1.2;
+/// Enough buckets to cover 0.01ms .. ~2h
+const HISTOGRAM_BUCKETS: usize = 112;
+
+/// Latency histogram with geometrically growing buckets
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: vec![0; HISTOGRAM_BUCKETS],
+            total: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, ms: f64) {
+        let bucket = if ms <= HISTOGRAM_MIN_MS {
+            0
+        } else {
+            ((ms / HISTOGRAM_MIN_MS).ln() / HISTOGRAM_GROWTH.ln()).ceil() as usize
+        };
+        self.counts[bucket.min(HISTOGRAM_BUCKETS - 1)] += 1;
+        self.total += 1;
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile (0.0..=1.0)
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        let rank = ((q * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return HISTOGRAM_MIN_MS * HISTOGRAM_GROWTH.powi(bucket as i32);
+            }
+        }
+        HISTOGRAM_MIN_MS * HISTOGRAM_GROWTH.powi(HISTOGRAM_BUCKETS as i32 - 1)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MethodStats {
+    pub calls: u64,
+    pub errors: u64,
+    pub total_ms: f64,
+    pub max_ms: f64,
+    pub histogram: Histogram,
+}
+
+/// Serializable view of [`MethodStats`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodSummary {
+    pub calls: u64,
+    pub errors: u64,
+    pub total_ms: f64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl MethodStats {
+    pub fn record(&mut self, elapsed: Duration, ok: bool) {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        self.calls += 1;
+        if !ok {
+            self.errors += 1;
+        }
+        self.total_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+        self.histogram.record(ms);
+    }
+
+    pub fn mean_ms(&self) -> f64 {
+        if self.calls > 0 {
+            self.total_ms / self.calls as f64
+        } else {
+            0.0
+        }
+    }
+
+    pub fn summary(&self) -> MethodSummary {
+        // Bucket bounds overshoot; never report a percentile above the max seen
+        let quantile = |q| self.histogram.quantile(q).min(self.max_ms);
+        MethodSummary {
+            calls: self.calls,
+            errors: self.errors,
+            total_ms: self.total_ms,
+            mean_ms: self.mean_ms(),
+            max_ms: self.max_ms,
+            p50_ms: quantile(0.50),
+            p95_ms: quantile(0.95),
+            p99_ms: quantile(0.99),
+        }
+    }
+}
+
+pub struct Metrics {
+    started: RwLock<Instant>,
+    methods: DashMap<String, MethodStats>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            started: RwLock::new(Instant::now()),
+            methods: DashMap::new(),
+        }
+    }
+
+    /// Record one handled request
+    pub fn record(&self, method: &str, elapsed: Duration, ok: bool) {
+        self.methods.entry(method.to_string()).or_default().record(elapsed, ok);
+    }
+
+    /// Snapshot of every method seen so far, sorted by name
+    pub fn methods(&self) -> BTreeMap<String, MethodSummary> {
+        self.methods
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().summary()))
+            .collect()
+    }
+
+    /// Time since startup or the last [`Metrics::reset`]
+    pub fn uptime(&self) -> Duration {
+        self.started.read().elapsed()
+    }
+
+    /// Forget everything recorded so far
+    pub fn reset(&self) {
+        self.methods.clear();
+        *self.started.write() = Instant::now();
+    }
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn global() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let metrics = Metrics::new();
+        metrics.record("transform", Duration::from_millis(4), true);
+        metrics.record("transform", Duration::from_millis(2), false);
+
+        let methods = metrics.methods();
+        let transform = &methods["transform"];
+        assert_eq!(transform.calls, 2);
+        assert_eq!(transform.errors, 1);
+        assert!((transform.mean_ms - 3.0).abs() < 0.01);
+        assert!((transform.max_ms - 4.0).abs() < 0.01);
+
+        metrics.reset();
+        assert!(metrics.methods().is_empty());
+    }
+
+    #[test]
+    fn test_histogram_quantiles() {
+        let mut histogram = Histogram::default();
+        for ms in 1..=100 {
+            histogram.record(ms as f64);
+        }
+        // Within one bucket (20%) of the exact value
+        let p50 = histogram.quantile(0.50);
+        let p99 = histogram.quantile(0.99);
+        assert!((50.0..=60.0).contains(&p50), "p50 = {}", p50);
+        assert!((99.0..=119.0).contains(&p99), "p99 = {}", p99);
+        assert_eq!(Histogram::default().quantile(0.5), 0.0);
+    }
+}
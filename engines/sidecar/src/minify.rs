@@ -0,0 +1,217 @@
+//! Whitespace and comment minification for generated HTML.
+//!
+//! The minifier is deliberately conservative: it never rewrites tags or
+//! attributes, it only drops HTML comments and collapses whitespace in text.
+//! Content of whitespace-sensitive elements (`pre`, `code`, `textarea`,
+//! `script`, `style`) is emitted verbatim.
+
+/// Elements whose contents must be preserved byte-for-byte
+const PRESERVE_TAGS: &[&str] = &["pre", "code", "textarea", "script", "style"];
+
+/// Block-level elements around which whitespace is insignificant
+const BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "body", "dd", "details", "div", "dl", "dt",
+    "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6",
+    "head", "header", "hr", "html", "li", "main", "nav", "ol", "p", "pre", "section", "summary",
+    "table", "tbody", "td", "tfoot", "th", "thead", "tr", "ul",
+];
+
+#[derive(Debug)]
+enum Token<'a> {
+    Tag { raw: &'a str, name: String, closing: bool },
+    Comment,
+    Text(&'a str),
+}
+
+/// Minify an HTML fragment
+pub fn minify_html(html: &str) -> String {
+    let tokens = tokenize(html);
+    let mut output = String::with_capacity(html.len());
+    let mut preserve_depth = 0usize;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Tag { raw, name, closing } => {
+                if PRESERVE_TAGS.contains(&name.as_str()) {
+                    if *closing {
+                        preserve_depth = preserve_depth.saturating_sub(1);
+                    } else if !raw.ends_with("/>") {
+                        preserve_depth += 1;
+                    }
+                }
+                output.push_str(raw);
+            }
+            Token::Comment => {}
+            Token::Text(text) => {
+                if preserve_depth > 0 {
+                    output.push_str(text);
+                    continue;
+                }
+
+                let after_block = is_block_boundary(tokens[..i].iter().rev());
+                let before_block = is_block_boundary(tokens[i + 1..].iter());
+                let mut collapsed = collapse_whitespace(text);
+                if before_block {
+                    collapsed.truncate(collapsed.trim_end().len());
+                }
+                if after_block || output.ends_with(' ') {
+                    collapsed = collapsed.trim_start().to_string();
+                }
+                output.push_str(&collapsed);
+            }
+        }
+    }
+
+    output
+}
+
+/// Whether the nearest tag in the given direction is a block-level tag.
+/// Comments are skipped since they are removed from the output.
+fn is_block_boundary<'a, 'b: 'a>(mut tokens: impl Iterator<Item = &'a Token<'b>>) -> bool {
+    match tokens.find(|t| !matches!(t, Token::Comment)) {
+        Some(Token::Tag { name, .. }) => BLOCK_TAGS.contains(&name.as_str()),
+        Some(_) => false,
+        None => true,
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !in_whitespace {
+                result.push(' ');
+                in_whitespace = true;
+            }
+        } else {
+            result.push(c);
+            in_whitespace = false;
+        }
+    }
+    result
+}
+
+fn tokenize(html: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+    let mut raw_text_end: Option<String> = None;
+
+    while !rest.is_empty() {
+        // Inside script/style everything up to the closing tag is text
+        if let Some(end_tag) = raw_text_end.take() {
+            let end = find_ascii_case_insensitive(rest, &end_tag).unwrap_or(rest.len());
+            if end > 0 {
+                tokens.push(Token::Text(&rest[..end]));
+            }
+            rest = &rest[end..];
+            continue;
+        }
+
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map(|i| i + 3).unwrap_or(rest.len());
+            tokens.push(Token::Comment);
+            rest = &rest[end..];
+        } else if rest.starts_with('<') && is_tag_start(rest) {
+            let end = find_tag_end(rest);
+            let raw = &rest[..end];
+            let closing = raw.starts_with("</");
+            let name: String = raw[if closing { 2 } else { 1 }..]
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+                .collect::<String>()
+                .to_ascii_lowercase();
+            if !closing && (name == "script" || name == "style") && !raw.ends_with("/>") {
+                raw_text_end = Some(format!("</{}", name));
+            }
+            tokens.push(Token::Tag { raw, name, closing });
+            rest = &rest[end..];
+        } else {
+            // Text may start with a stray '<' or with a multi-byte character
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let end = rest[first..].find('<').map(|i| i + first).unwrap_or(rest.len());
+            tokens.push(Token::Text(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+
+    tokens
+}
+
+fn is_tag_start(s: &str) -> bool {
+    let mut chars = s.chars().skip(1);
+    match chars.next() {
+        Some('/') => chars.next().is_some_and(|c| c.is_ascii_alphabetic()),
+        Some('!') => true,
+        Some(c) => c.is_ascii_alphabetic(),
+        None => false,
+    }
+}
+
+/// Find the end of a tag, honoring quoted attribute values
+fn find_tag_end(s: &str) -> usize {
+    let mut quote: Option<char> = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+fn find_ascii_case_insensitive(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .to_ascii_lowercase()
+        .find(&needle.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collapses_block_whitespace() {
+        let html = "<h1>Title</h1>\n<p>Some   text\nacross lines</p>\n<ul>\n<li>One</li>\n</ul>\n";
+        assert_eq!(
+            minify_html(html),
+            "<h1>Title</h1><p>Some text across lines</p><ul><li>One</li></ul>"
+        );
+    }
+
+    #[test]
+    fn test_keeps_inline_spacing() {
+        let html = "<p><em>a</em> <strong>b</strong></p>";
+        assert_eq!(minify_html(html), html);
+    }
+
+    #[test]
+    fn test_strips_comments() {
+        let html = "<p>a<!-- hidden --> b</p>\n<!-- block -->\n<p>c</p>";
+        assert_eq!(minify_html(html), "<p>a b</p><p>c</p>");
+    }
+
+    #[test]
+    fn test_preserves_pre_and_code() {
+        let html = "<pre><code class=\"language-rust\">fn main() {\n    let  x = 1;\n}\n</code></pre>\n<p>x  <code>a  b</code></p>";
+        assert_eq!(
+            minify_html(html),
+            "<pre><code class=\"language-rust\">fn main() {\n    let  x = 1;\n}\n</code></pre><p>x <code>a  b</code></p>"
+        );
+    }
+
+    #[test]
+    fn test_quoted_attributes_with_angle_brackets() {
+        let html = "<p title=\"a > b\">x   y</p>";
+        assert_eq!(minify_html(html), "<p title=\"a > b\">x y</p>");
+    }
+
+    #[test]
+    fn test_non_ascii_text() {
+        let html = "<p>日本語   text</p>\n<p>é < ü</p>";
+        assert_eq!(minify_html(html), "<p>日本語 text</p><p>é < ü</p>");
+    }
+}
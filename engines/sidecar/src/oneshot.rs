@@ -0,0 +1,98 @@
+//! `fastmd-sidecar transform <file>`: render one document and print it
+//!
+//! Runs the same transform a `transformPath` request would, with the
+//! project config's defaults and the disk cache, but without starting the
+//! RPC loop. Handy for diffing output between options or versions and for
+//! shell pipelines.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use tracing::warn;
+
+use crate::fsutil;
+use crate::handlers::transform_file;
+use crate::limits;
+use crate::source;
+use crate::transform::TransformOptions;
+
+#[derive(clap::Args, Debug)]
+pub struct TransformArgs {
+    /// Document to render
+    pub file: PathBuf,
+    /// Write the result to this file instead of stdout
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+    /// Transform options as JSON, as in a `transform` request
+    #[arg(long)]
+    pub options: Option<String>,
+    /// Print the whole response (code, map, metadata, warnings) as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+pub fn run(args: &TransformArgs) -> Result<()> {
+    let options: TransformOptions = match &args.options {
+        Some(options) => serde_json::from_str(options).context("Invalid --options")?,
+        None => TransformOptions::default(),
+    };
+    let file = args.file.to_string_lossy();
+    let content = source::read_source(&args.file, limits::limits().max_input_bytes)
+        .map_err(|e| anyhow!("Failed to read {}: {}", file, e))?;
+    let response = transform_file(&file, content, &options).map_err(|e| anyhow!("{}: {}", file, e))?;
+
+    for warning in response["warnings"].as_array().into_iter().flatten() {
+        let message = warning["message"].as_str().unwrap_or_default();
+        match warning["line"].as_u64() {
+            Some(line) => warn!("{}:{}: {}", file, line, message),
+            None => warn!("{}: {}", file, message),
+        }
+    }
+    let output = if args.json {
+        let mut json = serde_json::to_string_pretty(&response)?;
+        json.push('\n');
+        json
+    } else {
+        response["code"].as_str().map(str::to_string).unwrap_or_default()
+    };
+    match &args.out {
+        Some(out) => fsutil::write_atomic(out, output.as_bytes()).with_context(|| format!("Failed to write {}", out.display())),
+        None => Ok(io::stdout().lock().write_all(output.as_bytes())?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::fs;
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("page.md");
+        fs::write(&file, "# Hi\n\n[home](/)\n").unwrap();
+        let out = dir.path().join("out/page.js");
+        let mut args = TransformArgs {
+            file: file.clone(),
+            out: Some(out.clone()),
+            options: Some(r#"{"baseUrl": "/docs"}"#.to_string()),
+            json: false,
+        };
+        run(&args).unwrap();
+        let code = fs::read_to_string(&out).unwrap();
+        assert!(code.contains("<h1>Hi</h1>") && code.contains("href=\"/docs/\""), "{}", code);
+
+        args.json = true;
+        run(&args).unwrap();
+        let response: Value = serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(response["code"].as_str(), Some(code.as_str()));
+
+        args.options = Some("{".to_string());
+        assert!(run(&args).is_err());
+        args.options = None;
+        args.file = dir.path().join("missing.md");
+        assert!(run(&args).unwrap_err().to_string().starts_with("Failed to read"));
+    }
+}
@@ -0,0 +1,219 @@
+//! Batch-level document ordering
+//!
+//! Every structure derived from a set of documents (prev/next links,
+//! pagination, feeds, merged output) must agree on one order. Ordering is by
+//! a primary key with a fixed tie-breaking chain:
+//!
+//! 1. the primary key (frontmatter value, date, or path), honoring `direction`;
+//!    documents without a value always sort last, regardless of direction
+//! 2. the document path, always ascending
+//! 3. the original input position
+
+use std::cmp::Ordering;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OrderBy {
+    #[default]
+    Path,
+    /// The frontmatter `date` field (or `key`, if given), parsed as a date
+    Date,
+    /// An arbitrary frontmatter key
+    Frontmatter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OrderSpec {
+    #[serde(default)]
+    pub by: OrderBy,
+    /// Frontmatter key for `frontmatter` and `date` ordering (dot-separated for nesting)
+    pub key: Option<String>,
+    #[serde(default)]
+    pub direction: Direction,
+}
+
+/// Position of a document within the ordered set
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderedEntry {
+    pub path: String,
+    pub position: usize,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+    /// 1-based page number when a page size is given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, PartialOrd)]
+enum SortKey {
+    Number(f64),
+    Date(i64),
+    Text(String),
+}
+
+impl OrderSpec {
+    fn sort_key(&self, path: &str, frontmatter: Option<&Value>) -> Option<SortKey> {
+        match self.by {
+            OrderBy::Path => Some(SortKey::Text(path.to_string())),
+            OrderBy::Date => {
+                let value = lookup(frontmatter?, self.key.as_deref().unwrap_or("date"))?;
+                parse_date(value.as_str()?).map(SortKey::Date)
+            }
+            OrderBy::Frontmatter => {
+                let value = lookup(frontmatter?, self.key.as_deref()?)?;
+                match value {
+                    Value::Number(n) => n.as_f64().map(SortKey::Number),
+                    Value::String(s) => Some(SortKey::Text(s.clone())),
+                    Value::Bool(b) => Some(SortKey::Number(*b as u8 as f64)),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Compute the permutation that orders the given documents.
+    /// Returns indices into the input slice.
+    pub fn order<'a>(&self, documents: impl IntoIterator<Item = (&'a str, Option<&'a Value>)>) -> Vec<usize> {
+        let keyed: Vec<(usize, &str, Option<SortKey>)> = documents
+            .into_iter()
+            .enumerate()
+            .map(|(i, (path, frontmatter))| (i, path, self.sort_key(path, frontmatter)))
+            .collect();
+
+        let mut indices: Vec<usize> = (0..keyed.len()).collect();
+        indices.sort_by(|&a, &b| {
+            let (ia, pa, ka) = &keyed[a];
+            let (ib, pb, kb) = &keyed[b];
+            let primary = match (ka, kb) {
+                (Some(x), Some(y)) => {
+                    let ord = compare_keys(x, y);
+                    if self.direction == Direction::Desc { ord.reverse() } else { ord }
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            primary.then_with(|| pa.cmp(pb)).then_with(|| ia.cmp(ib))
+        });
+        indices
+    }
+}
+
+fn compare_keys(a: &SortKey, b: &SortKey) -> Ordering {
+    match (a, b) {
+        (SortKey::Number(x), SortKey::Number(y)) => x.total_cmp(y),
+        (SortKey::Date(x), SortKey::Date(y)) => x.cmp(y),
+        (SortKey::Text(x), SortKey::Text(y)) => x.cmp(y),
+        // Mixed types: numbers before dates before text
+        _ => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// Build prev/next/page information for paths that are already ordered
+pub fn link_entries(ordered_paths: &[String], page_size: Option<usize>) -> Vec<OrderedEntry> {
+    ordered_paths
+        .iter()
+        .enumerate()
+        .map(|(position, path)| OrderedEntry {
+            path: path.clone(),
+            position,
+            prev: position.checked_sub(1).map(|i| ordered_paths[i].clone()),
+            next: ordered_paths.get(position + 1).cloned(),
+            page: page_size.filter(|size| *size > 0).map(|size| position / size + 1),
+        })
+        .collect()
+}
+
+/// Look up a dot-separated key in a frontmatter object
+pub fn lookup<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').try_fold(value, |v, part| v.get(part))
+}
+
+/// Parse a frontmatter date (RFC 3339, `YYYY-MM-DD HH:MM[:SS]` or `YYYY-MM-DD`)
+/// into Unix seconds, treating dates without an offset as UTC
+pub fn parse_date(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.timestamp());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(dt.and_utc().timestamp());
+        }
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|dt| dt.and_utc().timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn order_paths(spec: &OrderSpec, docs: &[(&str, Value)]) -> Vec<String> {
+        spec.order(docs.iter().map(|(p, fm)| (*p, Some(fm))))
+            .into_iter()
+            .map(|i| docs[i].0.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_order_by_date_desc_with_ties() {
+        let docs = vec![
+            ("b.md", json!({ "date": "2024-01-02" })),
+            ("a.md", json!({ "date": "2024-01-02T00:00:00Z" })),
+            ("c.md", json!({ "date": "2024-03-01" })),
+            ("d.md", json!({})),
+        ];
+        let spec = OrderSpec { by: OrderBy::Date, key: None, direction: Direction::Desc };
+        // Equal dates fall back to ascending path; undated documents last
+        assert_eq!(order_paths(&spec, &docs), vec!["c.md", "a.md", "b.md", "d.md"]);
+    }
+
+    #[test]
+    fn test_order_by_frontmatter_key() {
+        let docs = vec![
+            ("x.md", json!({ "nav": { "order": 2 } })),
+            ("y.md", json!({ "nav": { "order": 1 } })),
+            ("z.md", json!({ "nav": { "order": 2 } })),
+        ];
+        let spec = OrderSpec {
+            by: OrderBy::Frontmatter,
+            key: Some("nav.order".to_string()),
+            direction: Direction::Asc,
+        };
+        assert_eq!(order_paths(&spec, &docs), vec!["y.md", "x.md", "z.md"]);
+    }
+
+    #[test]
+    fn test_link_entries() {
+        let paths = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let entries = link_entries(&paths, Some(2));
+        assert_eq!(entries[0].prev, None);
+        assert_eq!(entries[1].prev.as_deref(), Some("a"));
+        assert_eq!(entries[1].next.as_deref(), Some("c"));
+        assert_eq!(entries[2].page, Some(2));
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-02"), Some(86400));
+        assert_eq!(parse_date("1970-01-01T01:00:00+01:00"), Some(0));
+        assert_eq!(parse_date("not a date"), None);
+    }
+}
@@ -0,0 +1,475 @@
+//! OpenTelemetry export over OTLP/HTTP
+//!
+//! With `--otlp-endpoint` (or `OTEL_EXPORTER_OTLP_ENDPOINT`) set, the
+//! spans also recorded by `--trace-out` (requests, pool tasks, transform
+//! stages) are sent to a collector as OTLP JSON, along with the disk and
+//! shared cache counters, so the sidecar shows up in whatever tracing
+//! backend the rest of the build infrastructure reports to. Spans are
+//! batched and sent from a background thread every few seconds; a
+//! collector that's down costs dropped telemetry, never a slower
+//! transform. `OTEL_SERVICE_NAME` and `OTEL_EXPORTER_OTLP_HEADERS` are
+//! honoured as in other OpenTelemetry SDKs.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use serde_json::{json, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::cache;
+use crate::store;
+
+/// How often batched spans and metrics are sent
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Spans sent early once this many are waiting
+const MAX_BATCH: usize = 512;
+/// Finished spans waiting for the exporter; more are dropped
+const QUEUE_CAPACITY: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    /// Collector base URL, e.g. `http://localhost:4318`
+    pub endpoint: String,
+    pub service_name: String,
+    /// Sent with every export, e.g. for authentication
+    pub headers: Vec<(String, String)>,
+}
+
+impl OtlpConfig {
+    /// `endpoint`, or the one in `OTEL_EXPORTER_OTLP_ENDPOINT`; `None` if
+    /// neither is set
+    pub fn from_env(endpoint: Option<String>) -> Option<Self> {
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let endpoint = endpoint.or_else(|| env("OTEL_EXPORTER_OTLP_ENDPOINT"))?;
+        Some(OtlpConfig {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            service_name: env("OTEL_SERVICE_NAME").unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string()),
+            headers: env("OTEL_EXPORTER_OTLP_HEADERS").map(|headers| parse_headers(&headers)).unwrap_or_default(),
+        })
+    }
+}
+
+/// `key=value,key=value`, as in `OTEL_EXPORTER_OTLP_HEADERS`
+fn parse_headers(headers: &str) -> Vec<(String, String)> {
+    headers
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+enum Message {
+    Span(FinishedSpan),
+    /// Send everything now, then reply
+    Flush(Sender<()>),
+}
+
+static EXPORTER: OnceLock<Sender<Message>> = OnceLock::new();
+
+/// Start the exporter thread and return the layer feeding it
+pub fn layer(config: OtlpConfig) -> OtlpLayer {
+    let (sender, receiver) = bounded(QUEUE_CAPACITY);
+    let exporter = Exporter::new(config);
+    thread::Builder::new()
+        .name("fastmd-otlp".to_string())
+        .spawn(move || exporter.run(receiver))
+        .expect("failed to spawn OTLP exporter thread");
+    let _ = EXPORTER.set(sender.clone());
+    OtlpLayer { sender }
+}
+
+/// Send whatever is pending and wait (briefly) for it to go out; called
+/// before exiting
+pub fn flush() {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let (done, finished) = bounded(1);
+    if exporter.send(Message::Flush(done)).is_ok() {
+        let _ = finished.recv_timeout(REQUEST_TIMEOUT * 2);
+    }
+}
+
+/// A span as it's exported
+#[derive(Debug, Clone)]
+struct FinishedSpan {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    name: &'static str,
+    target: &'static str,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(String, Value)>,
+}
+
+/// Stored in each open span's extensions
+struct OpenSpan {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    start: SystemTime,
+    attributes: Vec<(String, Value)>,
+}
+
+pub struct OtlpLayer {
+    sender: Sender<Message>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<OpenSpan>().map(|open| (open.trace_id, open.span_id)));
+        let mut attributes = FieldVisitor::default();
+        attrs.record(&mut attributes);
+        span.extensions_mut().insert(OpenSpan {
+            trace_id: parent.map(|(trace_id, _)| trace_id).unwrap_or_else(new_id),
+            span_id: new_id(),
+            parent_span_id: parent.map(|(_, span_id)| span_id),
+            start: SystemTime::now(),
+            attributes: attributes.0,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(open) = span.extensions_mut().get_mut::<OpenSpan>() {
+                let mut attributes = FieldVisitor(std::mem::take(&mut open.attributes));
+                values.record(&mut attributes);
+                open.attributes = attributes.0;
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<OpenSpan>() else {
+            return;
+        };
+        let finished = FinishedSpan {
+            trace_id: open.trace_id,
+            span_id: open.span_id,
+            parent_span_id: open.parent_span_id,
+            name: span.metadata().name(),
+            target: span.metadata().target(),
+            start: open.start,
+            end: SystemTime::now(),
+            attributes: open.attributes,
+        };
+        // A full queue means the collector can't keep up; drop rather than block
+        let _ = self.sender.try_send(Message::Span(finished));
+    }
+}
+
+/// Span fields as OTLP attributes
+#[derive(Default)]
+struct FieldVisitor(Vec<(String, Value)>);
+
+impl FieldVisitor {
+    fn set(&mut self, field: &Field, value: Value) {
+        self.0.retain(|(key, _)| key != field.name());
+        self.0.push((field.name().to_string(), value));
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.set(field, json!({ "doubleValue": value }));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.set(field, json!({ "boolValue": value }));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, json!({ "stringValue": value }));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.set(field, json!({ "stringValue": format!("{:?}", value) }));
+    }
+}
+
+/// Random enough ids without a random number generator: a hash of the
+/// process, the time and a counter
+fn new_id<const N: usize>() -> [u8; N] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&std::process::id().to_le_bytes());
+    hasher.update(&unix_nanos(SystemTime::now()).to_le_bytes());
+    hasher.update(&COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    let mut id = [0; N];
+    id.copy_from_slice(&hasher.finalize().as_bytes()[..N]);
+    id
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_nanos())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+struct Exporter {
+    config: OtlpConfig,
+    agent: ureq::Agent,
+    started: SystemTime,
+    /// Whether the last export failed, so failures are logged once
+    failing: bool,
+}
+
+impl Exporter {
+    fn new(config: OtlpConfig) -> Self {
+        Exporter {
+            config,
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            started: SystemTime::now(),
+            failing: false,
+        }
+    }
+
+    fn run(mut self, messages: Receiver<Message>) {
+        let mut batch = Vec::new();
+        let mut next_export = Instant::now() + EXPORT_INTERVAL;
+        loop {
+            let timeout = next_export.saturating_duration_since(Instant::now());
+            match messages.recv_timeout(timeout) {
+                Ok(Message::Span(span)) => {
+                    batch.push(span);
+                    if batch.len() >= MAX_BATCH {
+                        self.export_spans(&mut batch);
+                    }
+                }
+                Ok(Message::Flush(done)) => {
+                    self.export_spans(&mut batch);
+                    self.export_metrics();
+                    let _ = done.send(());
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.export_spans(&mut batch);
+                    self.export_metrics();
+                    next_export = Instant::now() + EXPORT_INTERVAL;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.export_spans(&mut batch);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn export_spans(&mut self, batch: &mut Vec<FinishedSpan>) {
+        if batch.is_empty() {
+            return;
+        }
+        let body = traces_body(&self.config.service_name, batch);
+        batch.clear();
+        self.post("/v1/traces", &body);
+    }
+
+    fn export_metrics(&mut self) {
+        let caches = [("project", cache::global()), ("shared", store::global())];
+        let stats: Vec<(&str, cache::CacheStats)> = caches
+            .into_iter()
+            .filter_map(|(name, cache)| cache.map(|cache| (name, cache.stats())))
+            .collect();
+        if stats.is_empty() {
+            return;
+        }
+        let body = metrics_body(&self.config.service_name, &stats, self.started, SystemTime::now());
+        self.post("/v1/metrics", &body);
+    }
+
+    fn post(&mut self, path: &str, body: &Value) {
+        let url = format!("{}{}", self.config.endpoint, path);
+        let mut request = self.agent.post(&url).set("Content-Type", "application/json");
+        for (key, value) in &self.config.headers {
+            request = request.set(key, value);
+        }
+        match request.send_string(&body.to_string()) {
+            Ok(_) => {
+                if std::mem::take(&mut self.failing) {
+                    tracing::info!("OTLP export to {} recovered", self.config.endpoint);
+                }
+            }
+            Err(e) => {
+                if !std::mem::replace(&mut self.failing, true) {
+                    tracing::warn!("OTLP export to {} failed: {}", url, e);
+                }
+            }
+        }
+    }
+}
+
+fn resource(service_name: &str) -> Value {
+    json!({
+        "attributes": [
+            { "key": "service.name", "value": { "stringValue": service_name } },
+            { "key": "service.version", "value": { "stringValue": env!("CARGO_PKG_VERSION") } },
+        ],
+    })
+}
+
+fn scope() -> Value {
+    json!({ "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") })
+}
+
+fn traces_body(service_name: &str, spans: &[FinishedSpan]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let mut attributes: Vec<Value> = span
+                .attributes
+                .iter()
+                .map(|(key, value)| json!({ "key": key, "value": value }))
+                .collect();
+            attributes.push(json!({ "key": "code.namespace", "value": { "stringValue": span.target } }));
+            json!({
+                "traceId": hex(&span.trace_id),
+                "spanId": hex(&span.span_id),
+                "parentSpanId": span.parent_span_id.map(|id| hex(&id)).unwrap_or_default(),
+                "name": span.name,
+                // Internal
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(span.start).to_string(),
+                "endTimeUnixNano": unix_nanos(span.end).to_string(),
+                "attributes": attributes,
+            })
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": resource(service_name),
+            "scopeSpans": [{ "scope": scope(), "spans": spans }],
+        }],
+    })
+}
+
+fn metrics_body(service_name: &str, stats: &[(&str, cache::CacheStats)], started: SystemTime, now: SystemTime) -> Value {
+    let point = |cache: &str, value: Value| {
+        let mut point = json!({
+            "attributes": [{ "key": "cache", "value": { "stringValue": cache } }],
+            "startTimeUnixNano": unix_nanos(started).to_string(),
+            "timeUnixNano": unix_nanos(now).to_string(),
+        });
+        point.as_object_mut().unwrap().extend(value.as_object().unwrap().clone());
+        point
+    };
+    let counter = |name: &str, description: &str, value: fn(&cache::CacheStats) -> u64| {
+        let points: Vec<Value> = stats
+            .iter()
+            .map(|(cache, stats)| point(cache, json!({ "asInt": value(stats).to_string() })))
+            .collect();
+        json!({
+            "name": name,
+            "description": description,
+            "unit": "1",
+            // Cumulative since the sidecar started
+            "sum": { "aggregationTemporality": 2, "isMonotonic": true, "dataPoints": points },
+        })
+    };
+    let hit_rate: Vec<Value> = stats
+        .iter()
+        .map(|(cache, stats)| point(cache, json!({ "asDouble": stats.hit_rate })))
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(service_name),
+            "scopeMetrics": [{
+                "scope": scope(),
+                "metrics": [
+                    counter("fastmd.cache.hits", "Lookups served from the cache", |stats| stats.hits),
+                    counter("fastmd.cache.misses", "Lookups the cache couldn't serve", |stats| stats.misses),
+                    counter("fastmd.cache.writes", "Entries written to the cache", |stats| stats.writes),
+                    {
+                        "name": "fastmd.cache.hit_rate",
+                        "description": "Hits per lookup",
+                        "unit": "1",
+                        "gauge": { "dataPoints": hit_rate },
+                    },
+                ],
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_parse_headers() {
+        assert_eq!(
+            parse_headers("api-key=secret, x-team = docs,broken,=empty"),
+            vec![("api-key".to_string(), "secret".to_string()), ("x-team".to_string(), "docs".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_export_spans() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let subscriber = tracing_subscriber::registry().with(layer(OtlpConfig {
+            endpoint,
+            service_name: "test".to_string(),
+            headers: vec![("x-team".to_string(), "docs".to_string())],
+        }));
+        tracing::subscriber::with_default(subscriber, || {
+            let _rpc = tracing::trace_span!("rpc", method = "transform").entered();
+            tracing::trace_span!("render", file = "a.md", bytes = 42u64).in_scope(|| {});
+        });
+        let exporter = thread::spawn(flush);
+
+        let mut request = server.recv_timeout(Duration::from_secs(10)).unwrap().unwrap();
+        assert_eq!(request.url(), "/v1/traces");
+        assert!(request.headers().iter().any(|header| header.field.equiv("x-team") && header.value == "docs"));
+        let mut body = String::new();
+        request.as_reader().read_to_string(&mut body).unwrap();
+        request.respond(tiny_http::Response::empty(200)).unwrap();
+        exporter.join().unwrap();
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(resource["resource"]["attributes"][0]["value"]["stringValue"], "test");
+        let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+        // Children close first
+        let (render, rpc) = (&spans[0], &spans[1]);
+        assert_eq!((render["name"].as_str(), rpc["name"].as_str()), (Some("render"), Some("rpc")));
+        assert_eq!(render["traceId"], rpc["traceId"]);
+        assert_eq!(render["parentSpanId"], rpc["spanId"]);
+        assert_eq!(rpc["parentSpanId"], "");
+        assert_eq!(rpc["traceId"].as_str().unwrap().len(), 32);
+        assert!(render["attributes"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "key": "bytes", "value": { "intValue": "42" } })));
+    }
+}
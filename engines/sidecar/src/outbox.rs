@@ -0,0 +1,76 @@
+//! Outgoing message queue
+//!
+//! Responses and server-initiated notifications are funneled through a
+//! single channel drained by a writer thread, so handlers running anywhere
+//! can emit messages without interleaving partial lines on stdout. The
+//! writer sends whatever has queued up by the time it gets to write in one
+//! go, so a burst of batch results doesn't cost a syscall each.
+
+use std::io::{BufWriter, Write};
+use std::thread;
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::codec::Codec;
+use crate::framing::Framing;
+use crate::protocol::{create_notification, RpcResponse};
+
+/// Bytes of queued messages gathered into one write
+const WRITE_BUFFER: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    sender: Sender<Vec<u8>>,
+    codec: Codec,
+}
+
+impl Outbox {
+    pub fn new(codec: Codec) -> (Outbox, Receiver<Vec<u8>>) {
+        let (sender, receiver) = unbounded();
+        (Outbox { sender, codec }, receiver)
+    }
+
+    pub fn send_response(&self, response: &RpcResponse) {
+        self.send(response);
+    }
+
+    /// Reply to a batch request with one array
+    pub fn send_batch(&self, responses: &[RpcResponse]) {
+        self.send(&responses);
+    }
+
+    /// Emit a JSON-RPC notification
+    pub fn notify(&self, method: &str, params: Value) {
+        self.send(&create_notification(method, Some(params)));
+    }
+
+    fn send<T: Serialize>(&self, message: &T) {
+        match self.codec.encode(message) {
+            Ok(body) => {
+                // The writer only goes away during shutdown
+                let _ = self.sender.send(body);
+            }
+            Err(e) => tracing::error!("Failed to serialize outgoing message: {}", e),
+        }
+    }
+}
+
+/// Drain the outbox into `writer` using `framing` until every [`Outbox`]
+/// clone has been dropped
+pub fn spawn_writer<W: Write + Send + 'static>(receiver: Receiver<Vec<u8>>, writer: W, framing: Framing) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut writer = BufWriter::with_capacity(WRITE_BUFFER, writer);
+        while let Ok(body) = receiver.recv() {
+            let written = std::iter::once(body)
+                .chain(receiver.try_iter())
+                .try_for_each(|body| framing.write(&mut writer, &body))
+                .and_then(|()| writer.flush());
+            if let Err(e) = written {
+                tracing::error!("Failed to write message: {}", e);
+                break;
+            }
+        }
+    })
+}
@@ -0,0 +1,252 @@
+//! Shapes of the generated module
+//!
+//! The default is an ES module whose default export is the HTML as a
+//! template literal. Bundler-less consumers can ask for CommonJS, the bare
+//! HTML string, or a JSON payload instead, and any module shape can also
+//! expose `html`, `frontmatter` and `headings` as named exports.
+//!
+//! With `framework: "astro"` the module matches what Astro's own markdown
+//! pipeline emits (`frontmatter`, `file`, `getHeadings()`, `Content`, ...),
+//! so the sidecar can stand in for Astro's markdown processor.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::transform::{escape_template_literal, TransformOptions};
+
+const ASTRO_RUNTIME: &str = "astro/runtime/server/index.js";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OutputFormat {
+    /// ES module
+    #[default]
+    Esm,
+    /// CommonJS module
+    Cjs,
+    /// The rendered HTML, without a module wrapper
+    Html,
+    /// `{ html, frontmatter, headings }` as JSON
+    Json,
+}
+
+/// A document heading, in the shape Astro's `getHeadings()` returns
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heading {
+    pub depth: u8,
+    pub slug: String,
+    pub text: String,
+}
+
+/// The module shape selected by transform options
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Shape {
+    pub format: OutputFormat,
+    pub named_exports: bool,
+    /// Astro content module; takes precedence over `format`
+    pub astro: bool,
+}
+
+impl Shape {
+    pub fn of(options: &TransformOptions) -> Self {
+        Shape {
+            format: options.format(),
+            named_exports: options.named_exports(),
+            astro: options.framework.as_deref() == Some("astro"),
+        }
+    }
+
+    /// Whether the output includes the document's headings
+    pub fn needs_headings(&self) -> bool {
+        self.named_exports || self.astro || self.format == OutputFormat::Json
+    }
+}
+
+/// Everything a generated module can expose
+pub struct ModuleParts<'a> {
+    /// Path shown in the header comment
+    pub file: &'a str,
+    pub html: &'a str,
+    pub frontmatter: Option<&'a Value>,
+    pub headings: &'a [Heading],
+    /// Markdown body, for Astro's `rawContent()`
+    pub raw: &'a str,
+}
+
+/// Wrap rendered HTML in a module of the selected shape, by default an ES
+/// module exporting it as the default export. Returns the code and the
+/// 1-based line the HTML starts on, or `None` when output lines don't
+/// correspond to HTML lines.
+pub fn render(parts: &ModuleParts, shape: Shape) -> (String, Option<usize>) {
+    if shape.astro {
+        return (render_astro(parts), Some(3));
+    }
+    let html = escape_template_literal(parts.html);
+    let frontmatter = parts.frontmatter.cloned().unwrap_or_else(|| json!({}));
+    let headings = serde_json::to_string(parts.headings).unwrap_or_else(|_| "[]".to_string());
+    let header = format!("// Generated from: {}\n", parts.file);
+
+    match (shape.format, shape.named_exports) {
+        (OutputFormat::Esm, false) => (format!("{}export default `{}`;\n", header, html), Some(2)),
+        (OutputFormat::Esm, true) => (
+            format!(
+                "{}export const frontmatter = {};\nexport const headings = {};\nexport const html = `{}`;\nexport default html;\n",
+                header, frontmatter, headings, html
+            ),
+            Some(4),
+        ),
+        (OutputFormat::Cjs, false) => (format!("{}module.exports = `{}`;\n", header, html), Some(2)),
+        (OutputFormat::Cjs, true) => (
+            format!(
+                "{}const frontmatter = {};\nconst headings = {};\nconst html = `{}`;\nmodule.exports = {{ default: html, html, frontmatter, headings }};\n",
+                header, frontmatter, headings, html
+            ),
+            Some(4),
+        ),
+        (OutputFormat::Html, _) => (parts.html.to_string(), Some(1)),
+        (OutputFormat::Json, _) => {
+            let payload = json!({
+                "html": parts.html,
+                "frontmatter": frontmatter,
+                "headings": parts.headings,
+            });
+            (payload.to_string(), None)
+        }
+    }
+}
+
+/// Module in the shape Astro's markdown pipeline produces. A `layout` in
+/// the frontmatter is imported and wraps the content, which it gets with
+/// the rest of the frontmatter as props.
+fn render_astro(parts: &ModuleParts) -> String {
+    let frontmatter = parts.frontmatter.cloned().unwrap_or_else(|| json!({}));
+    let headings = serde_json::to_string(parts.headings).unwrap_or_else(|_| "[]".to_string());
+    // Imports are hoisted, so the layout's doesn't move the HTML's line
+    let (layout_import, content) = match frontmatter.get("layout").and_then(Value::as_str) {
+        Some(layout) => (
+            format!("import Layout from {};\n", json!(layout)),
+            r#"  const { layout, ...content } = frontmatter;
+  content.file = file;
+  content.url = url;
+  return render`${renderComponent(result, "Layout", Layout, {
+    file,
+    url,
+    content,
+    frontmatter: content,
+    headings: getHeadings(),
+    rawContent,
+    compiledContent,
+    "server:root": true,
+  }, {
+    default: () => render`${unescapeHTML(html)}`,
+  })}`;"#,
+        ),
+        None => (String::new(), "  return render`${maybeRenderHead(result)}${unescapeHTML(html)}`;"),
+    };
+    format!(
+        r#"// Generated from: {file}
+import {{ createComponent, maybeRenderHead, render, renderComponent, unescapeHTML }} from "{runtime}";
+const html = `{html}`;
+
+{layout_import}export const frontmatter = {frontmatter};
+export const file = {file_json};
+export const url = undefined;
+export function rawContent() {{
+  return {raw};
+}}
+export function compiledContent() {{
+  return html;
+}}
+export function getHeadings() {{
+  return {headings};
+}}
+export const Content = createComponent((result, _props, slots) => {{
+{content}
+}});
+export default Content;
+"#,
+        file = parts.file,
+        runtime = ASTRO_RUNTIME,
+        html = escape_template_literal(parts.html),
+        frontmatter = frontmatter,
+        file_json = json!(parts.file),
+        raw = json!(parts.raw),
+        headings = headings,
+        layout_import = layout_import,
+        content = content,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(format: OutputFormat, named_exports: bool) -> Shape {
+        Shape {
+            format,
+            named_exports,
+            astro: false,
+        }
+    }
+
+    fn parts<'a>(frontmatter: Option<&'a Value>, headings: &'a [Heading]) -> ModuleParts<'a> {
+        ModuleParts {
+            file: "a.md",
+            html: "<h1 id=\"hi\">Hi `x`</h1>\n",
+            frontmatter,
+            headings,
+            raw: "# Hi `x`\n",
+        }
+    }
+
+    #[test]
+    fn test_shapes() {
+        let frontmatter = json!({ "title": "Hi" });
+        let headings = vec![Heading { depth: 1, slug: "hi".to_string(), text: "Hi x".to_string() }];
+        let parts = parts(Some(&frontmatter), &headings);
+
+        let (esm, line) = render(&parts, shape(OutputFormat::Esm, false));
+        assert_eq!(esm, "// Generated from: a.md\nexport default `<h1 id=\"hi\">Hi \\`x\\`</h1>\n`;\n");
+        assert_eq!(line, Some(2));
+
+        let (named, line) = render(&parts, shape(OutputFormat::Esm, true));
+        assert!(named.contains("export const frontmatter = {\"title\":\"Hi\"};\n"));
+        assert!(named.contains("export const headings = [{\"depth\":1,\"slug\":\"hi\",\"text\":\"Hi x\"}];\n"));
+        assert_eq!(named.lines().nth(3).unwrap(), "export const html = `<h1 id=\"hi\">Hi \\`x\\`</h1>");
+        assert_eq!(line, Some(4));
+
+        let (cjs, _) = render(&parts, shape(OutputFormat::Cjs, true));
+        assert!(cjs.ends_with("module.exports = { default: html, html, frontmatter, headings };\n"));
+
+        assert_eq!(render(&parts, shape(OutputFormat::Html, true)).0, parts.html);
+
+        let (json, line) = render(&parts, shape(OutputFormat::Json, false));
+        let payload: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(payload["frontmatter"]["title"], "Hi");
+        assert_eq!(payload["headings"][0]["slug"], "hi");
+        assert_eq!(line, None);
+    }
+
+    #[test]
+    fn test_astro_module() {
+        let frontmatter = json!({ "title": "Hi", "layout": "../Layout.astro" });
+        let headings = vec![Heading { depth: 1, slug: "hi".to_string(), text: "Hi x".to_string() }];
+        let astro = Shape { astro: true, ..Default::default() };
+        let (code, line) = render(&parts(Some(&frontmatter), &headings), astro);
+
+        assert_eq!(code.lines().nth(2).unwrap(), "const html = `<h1 id=\"hi\">Hi \\`x\\`</h1>");
+        assert_eq!(line, Some(3));
+        assert!(code.contains("export const file = \"a.md\";"));
+        assert!(code.contains("return \"# Hi `x`\\n\";"));
+        assert!(code.contains(r#"return [{"depth":1,"slug":"hi","text":"Hi x"}];"#));
+        assert!(code.contains("import Layout from \"../Layout.astro\";\n"));
+        assert!(code.contains("renderComponent(result, \"Layout\", Layout, {"));
+        assert!(code.contains("default: () => render`${unescapeHTML(html)}`,"));
+        assert!(code.ends_with("export default Content;\n"));
+
+        let (code, line) = render(&parts(None, &headings), astro);
+        assert_eq!(line, Some(3));
+        assert!(!code.contains("Layout") && !code.contains("const { layout"));
+        assert!(code.contains("  return render`${maybeRenderHead(result)}${unescapeHTML(html)}`;\n});"));
+    }
+}
@@ -0,0 +1,70 @@
+//! Pinning worker threads to CPU cores
+//!
+//! On hybrid CPUs the OS moves threads between performance and efficiency
+//! cores as load shifts, so a large batch can run at uneven speed. With
+//! pinning enabled (`FASTMD_PIN_WORKERS`), worker `n` stays on the `n`th
+//! physical core, wrapping around when there are more workers than cores.
+//! SMT siblings are skipped where the topology is known (Linux), so two
+//! workers don't share a core while others sit idle.
+
+use std::fs;
+use std::sync::OnceLock;
+
+use core_affinity::CoreId;
+
+static CORES: OnceLock<Vec<CoreId>> = OnceLock::new();
+
+/// Pin the calling thread to the core of worker `worker_id`; false if the
+/// cores are unknown or the OS refused
+pub fn pin(worker_id: usize) -> bool {
+    let cores = CORES.get_or_init(physical_cores);
+    if cores.is_empty() {
+        return false;
+    }
+    core_affinity::set_for_current(cores[worker_id % cores.len()])
+}
+
+/// One logical CPU per physical core
+fn physical_cores() -> Vec<CoreId> {
+    core_affinity::get_core_ids()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|core| is_first_sibling(core.id))
+        .collect()
+}
+
+/// Whether `cpu` is the lowest-numbered hardware thread of its core; true
+/// when the topology can't be read
+fn is_first_sibling(cpu: usize) -> bool {
+    let path = format!("/sys/devices/system/cpu/cpu{}/topology/thread_siblings_list", cpu);
+    match fs::read_to_string(path) {
+        Ok(siblings) => first_cpu(&siblings).is_none_or(|first| first == cpu),
+        Err(_) => true,
+    }
+}
+
+/// First CPU of a sorted kernel CPU list such as `0,8` or `2-3`
+fn first_cpu(list: &str) -> Option<usize> {
+    list.trim().split([',', '-']).next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_cpu() {
+        assert_eq!(first_cpu("0,8\n"), Some(0));
+        assert_eq!(first_cpu("2-3"), Some(2));
+        assert_eq!(first_cpu("5"), Some(5));
+        assert_eq!(first_cpu(""), None);
+    }
+
+    #[test]
+    fn test_physical_cores() {
+        let logical = core_affinity::get_core_ids().unwrap_or_default().len();
+        let physical = physical_cores().len();
+        assert!(physical <= logical);
+        assert_eq!(physical == 0, logical == 0);
+    }
+}
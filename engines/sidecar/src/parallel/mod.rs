@@ -7,12 +7,18 @@
 pub mod task;
 pub mod worker;
 pub mod pool;
+pub mod queue;
+pub mod affinity;
+pub mod process;
 
-pub use task::{TransformTask, TaskResult, TaskBatch, TaskOptions};
-pub use worker::{Worker, WorkerMessage, WorkerStats};
-pub use pool::{ThreadPool, ThreadPoolBuilder, PoolStats};
+pub use task::{Chunking, TransformTask, TaskResult, TaskBatch, FailureKind};
+#[allow(unused_imports)]
+pub use worker::{panic_message, Isolation, Worker, WorkerStats, DEFAULT_STACK_SIZE};
+#[allow(unused_imports)]
+pub use pool::{Backend, ThreadPool, ThreadPoolBuilder, PoolStats, SubmitError, DEFAULT_IDLE_TIMEOUT, DEFAULT_TASK_TIMEOUT};
 
-use std::sync::Once;
+use std::sync::{Once, OnceLock};
+use std::time::Duration;
 
 static INIT: Once = Once::new();
 
@@ -47,8 +53,23 @@ pub fn recommended_workers() -> usize {
 pub struct ParallelConfig {
     pub enabled: bool,
     pub num_workers: Option<usize>,
-    pub batch_size: usize,
+    /// Workers kept running while idle; more start as work queues up
+    pub min_workers: usize,
+    /// How long a worker above the minimum stays idle before it stops
+    pub idle_timeout: Duration,
+    /// How batches are cut into chunks, per worker
+    pub chunking: Chunking,
     pub queue_size: usize,
+    /// How long one document may take before it is abandoned
+    pub task_timeout: Duration,
+    /// Keep each worker on one physical core
+    pub pin_workers: bool,
+    /// Worker thread stack size in bytes
+    pub stack_size: usize,
+    /// Where workers render documents
+    pub isolation: Isolation,
+    /// What runs batches
+    pub backend: Backend,
 }
 
 impl Default for ParallelConfig {
@@ -56,16 +77,32 @@ impl Default for ParallelConfig {
         ParallelConfig {
             enabled: true,
             num_workers: None, // Auto-detect
-            batch_size: 10,
+            min_workers: 0,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            chunking: Chunking::default(),
             queue_size: 1000,
+            task_timeout: DEFAULT_TASK_TIMEOUT,
+            pin_workers: false,
+            stack_size: DEFAULT_STACK_SIZE,
+            isolation: Isolation::default(),
+            backend: Backend::default(),
         }
     }
 }
 
+static DEFAULT_CONFIG: OnceLock<ParallelConfig> = OnceLock::new();
+
+/// Replace the built-in defaults the global pool's configuration starts
+/// from (the project config file's pool settings). Only the first call
+/// counts, and only before the pool is first used.
+pub fn set_default_config(config: ParallelConfig) {
+    let _ = DEFAULT_CONFIG.set(config);
+}
+
 impl ParallelConfig {
-    /// Create config from environment variables
+    /// Create config from environment variables, over the defaults
     pub fn from_env() -> Self {
-        let mut config = Self::default();
+        let mut config = DEFAULT_CONFIG.get().cloned().unwrap_or_default();
         
         if let Ok(val) = std::env::var("FASTMD_PARALLEL") {
             config.enabled = val.to_lowercase() != "false";
@@ -77,9 +114,59 @@ impl ParallelConfig {
             }
         }
         
+        if let Ok(val) = std::env::var("FASTMD_MIN_WORKERS") {
+            if let Ok(num) = val.parse::<usize>() {
+                config.min_workers = num;
+            }
+        }
+        
         if let Ok(val) = std::env::var("FASTMD_BATCH_SIZE") {
             if let Ok(size) = val.parse::<usize>() {
-                config.batch_size = size;
+                config.chunking = Chunking::Count(size);
+            }
+        }
+        
+        if let Ok(val) = std::env::var("FASTMD_BATCH_BYTES") {
+            if let Ok(bytes) = val.parse::<usize>() {
+                config.chunking = Chunking::Bytes(bytes);
+            }
+        }
+        
+        if let Ok(val) = std::env::var("FASTMD_PIN_WORKERS") {
+            config.pin_workers = matches!(val.to_lowercase().as_str(), "1" | "true");
+        }
+        
+        if let Ok(val) = std::env::var("FASTMD_WORKER_STACK_MB") {
+            if let Ok(mb) = val.parse::<usize>() {
+                config.stack_size = mb * 1024 * 1024;
+            }
+        }
+        
+        if let Ok(val) = std::env::var("FASTMD_ISOLATION") {
+            match val.to_lowercase().as_str() {
+                "process" => config.isolation = Isolation::Process,
+                "thread" => config.isolation = Isolation::Thread,
+                _ => tracing::warn!("Unknown FASTMD_ISOLATION '{}', using the default", val),
+            }
+        }
+        
+        if let Ok(val) = std::env::var("FASTMD_BACKEND") {
+            match val.to_lowercase().as_str() {
+                "rayon" => config.backend = Backend::Rayon,
+                "workers" | "custom" => config.backend = Backend::Custom,
+                _ => tracing::warn!("Unknown FASTMD_BACKEND '{}', using the default", val),
+            }
+        }
+        
+        if let Ok(val) = std::env::var("FASTMD_TASK_TIMEOUT_MS") {
+            if let Ok(ms) = val.parse::<u64>() {
+                config.task_timeout = Duration::from_millis(ms);
+            }
+        }
+        
+        if let Ok(val) = std::env::var("FASTMD_IDLE_TIMEOUT_MS") {
+            if let Ok(ms) = val.parse::<u64>() {
+                config.idle_timeout = Duration::from_millis(ms);
             }
         }
         
@@ -87,34 +174,54 @@ impl ParallelConfig {
     }
 }
 
-/// Global thread pool instance (optional singleton pattern)
-static mut GLOBAL_POOL: Option<ThreadPool> = None;
-static POOL_INIT: Once = Once::new();
+/// Global thread pool instance, `None` when parallel processing is disabled
+static GLOBAL_POOL: OnceLock<Option<ThreadPool>> = OnceLock::new();
 
 /// Get or create the global thread pool
 pub fn global_pool() -> Option<&'static ThreadPool> {
-    unsafe {
-        POOL_INIT.call_once(|| {
+    GLOBAL_POOL
+        .get_or_init(|| {
             let config = ParallelConfig::from_env();
-            if config.enabled {
+            config.enabled.then(|| {
                 initialize();
-                let pool = ThreadPoolBuilder::new()
+                ThreadPoolBuilder::new()
                     .workers(config.num_workers.unwrap_or_else(recommended_workers))
+                    .min_workers(config.min_workers)
+                    .idle_timeout(config.idle_timeout)
                     .queue_size(config.queue_size)
-                    .build();
-                GLOBAL_POOL = Some(pool);
-            }
-        });
-        GLOBAL_POOL.as_ref()
-    }
+                    .task_timeout(config.task_timeout)
+                    .pin_workers(config.pin_workers)
+                    .stack_size(config.stack_size)
+                    .limits(crate::limits::limits())
+                    .chunking(config.chunking)
+                    .isolation(config.isolation)
+                    .backend(config.backend)
+                    .build()
+            })
+        })
+        .as_ref()
+}
+
+/// Runtime request threads await [`ThreadPool::submit`] on. Its one
+/// thread only drives timers; queueing into a full pool waits on its
+/// blocking threads.
+pub fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("fastmd-submit")
+            .enable_time()
+            .build()
+            .expect("Failed to start the submission runtime")
+    })
 }
 
-/// Shutdown the global thread pool
+/// Shutdown the global thread pool, if it was ever created. Safe to call
+/// more than once; later submissions fail with [`SubmitError::Closed`].
 pub fn shutdown_global_pool() {
-    unsafe {
-        if let Some(pool) = GLOBAL_POOL.take() {
-            pool.shutdown();
-        }
+    if let Some(Some(pool)) = GLOBAL_POOL.get() {
+        pool.shutdown();
     }
 }
 
@@ -133,7 +240,8 @@ mod tests {
     fn test_parallel_config_default() {
         let config = ParallelConfig::default();
         assert!(config.enabled);
-        assert_eq!(config.batch_size, 10);
+        assert_eq!(config.min_workers, 0);
+        assert_eq!(config.chunking, Chunking::Bytes(task::DEFAULT_CHUNK_BYTES));
         assert_eq!(config.queue_size, 1000);
     }
 
@@ -1,135 +1,418 @@
-use std::sync::Arc;
-use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
-use parking_lot::Mutex;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use crossbeam_deque::Worker as Deque;
 use dashmap::DashMap;
 use num_cpus;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use serde::Serialize;
+use tokio::sync::oneshot;
 
 use crate::parallel::{
-    task::{TransformTask, TaskResult, TaskBatch},
-    worker::{Worker, WorkerMessage, WorkerStats},
+    queue::{Job, Queues, Reply, Results},
+    task::{Chunking, FailureKind, TransformTask, TaskResult, TaskBatch},
+    worker::{Engine, Isolation, Worker, WorkerConfig, WorkerStats},
 };
+use crate::limits::Limits;
+use crate::metrics::MethodSummary;
+
+/// How long a submission waits for room in a full queue by default
+pub const DEFAULT_SUBMIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a task may run before it is abandoned by default
+pub const DEFAULT_TASK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a worker above the minimum stays idle before stopping by default
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often callers waiting on results look for tasks past their deadline
+const DEADLINE_CHECK: Duration = Duration::from_millis(20);
+
+/// Why a task could not be queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitError {
+    /// The queue stayed full for the whole submit timeout
+    Busy,
+    /// The workers are gone
+    Closed,
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Busy => write!(f, "Worker pool is busy; the task queue is full"),
+            SubmitError::Closed => write!(f, "Worker pool is shut down"),
+        }
+    }
+}
+
+/// What runs batches; single tasks always go to the pool's own workers,
+/// which answer them with the least latency.
+///
+/// `bench_backends` (release build, 8 workers on one available core,
+/// mean of 5 batches) measured:
+///
+/// | batch                  | workers  | rayon    |
+/// |------------------------|----------|----------|
+/// | 2000 × 10 paragraphs   | 74.5 ms  | 67.7 ms  |
+/// | 200 × 500 paragraphs   | 1.04 s   | 0.96 s   |
+/// | 20 × 5000 paragraphs   | 6.98 s   | 6.81 s   |
+///
+/// Rayon finishes 2–9% sooner; that isn't worth giving up per-task
+/// deadlines and queue backpressure for, so the workers stay the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The pool's workers, with its queue bound and per-task deadlines
+    #[default]
+    Custom,
+    /// A rayon pool with as many threads as the pool has workers. Tasks
+    /// can still be cancelled, but aren't abandoned at their deadline.
+    Rayon,
+}
 
 /// Thread pool for parallel Markdown transformation
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    task_sender: Sender<WorkerMessage>,
-    task_receiver: Arc<Mutex<Receiver<WorkerMessage>>>,
-    result_sender: Sender<TaskResult>,
-    result_receiver: Receiver<TaskResult>,
+    /// Started workers, taken on shutdown
+    workers: Arc<Mutex<Vec<Worker>>>,
+    worker_config: Arc<WorkerConfig>,
+    queues: Arc<Queues>,
     stats: Arc<DashMap<usize, WorkerStats>>,
-    num_workers: usize,
+    /// How long a submission waits for room in a full queue
+    submit_timeout: Duration,
+    /// How long a task may run before its caller stops waiting for it
+    task_timeout: Duration,
+    backend: Backend,
+    /// How batches are cut into chunks; one chunk per worker is queued at a time
+    chunking: Chunking,
+    /// Runs batches for [`Backend::Rayon`] and [`ThreadPool::install`],
+    /// started on first use
+    rayon: OnceLock<rayon::ThreadPool>,
+}
+
+/// A queued task its caller is waiting on
+struct Pending {
+    id: String,
+    started: Arc<OnceLock<(usize, Instant)>>,
+    /// Chunk of its batch the task was queued in
+    chunk: usize,
 }
 
 impl ThreadPool {
-    /// Create a new thread pool with the specified number of workers
-    pub fn new(num_workers: Option<usize>) -> Self {
-        let num_workers = num_workers.unwrap_or_else(|| num_cpus::get());
-        tracing::info!("Creating thread pool with {} workers", num_workers);
+    fn with_config(config: ThreadPoolBuilder) -> Self {
+        let num_workers = config.num_workers.unwrap_or_else(num_cpus::get);
+        let min_workers = config.min_workers.unwrap_or(num_workers).min(num_workers);
+        tracing::info!("Creating thread pool with {} to {} workers", min_workers, num_workers);
 
-        // Create channels for task distribution and result collection
-        let (task_sender, task_receiver) = unbounded();
-        let (result_sender, result_receiver) = unbounded();
-        let task_receiver = Arc::new(Mutex::new(task_receiver));
+        // Each worker owns a deque the others can steal from; results go
+        // back on a channel each caller brings along with its tasks
+        let mut deques: Vec<(usize, Deque<Job>)> = (0..num_workers).map(|id| (id, Deque::new_fifo())).collect();
+        let stealers = deques.iter().map(|(_, local)| local.stealer()).collect();
+        // Workers beyond the minimum start once the backlog calls for them,
+        // lowest ids first
+        let mut parked = deques.split_off(min_workers);
+        parked.reverse();
+        let queues = Queues::new(stealers, config.queue_size)
+            .with_scaling(min_workers, config.idle_timeout, parked);
+        let queues = Arc::new(queues);
         
         let stats = Arc::new(DashMap::new());
-        let mut workers = Vec::with_capacity(num_workers);
-
-        // Spawn worker threads
         for id in 0..num_workers {
-            let worker = Worker::spawn(
-                id,
-                Arc::clone(&task_receiver),
-                result_sender.clone(),
-            );
             stats.insert(id, WorkerStats::default());
-            workers.push(worker);
         }
 
+        // Spawn worker threads
+        let workers = deques
+            .into_iter()
+            .map(|(id, local)| Worker::spawn(id, local, Arc::clone(&queues), &config.worker))
+            .collect();
+
         ThreadPool {
-            workers,
-            task_sender,
-            task_receiver,
-            result_sender,
-            result_receiver,
+            workers: Arc::new(Mutex::new(workers)),
+            worker_config: Arc::new(config.worker),
+            queues,
             stats,
-            num_workers,
+            submit_timeout: config.submit_timeout,
+            task_timeout: config.task_timeout,
+            backend: config.backend,
+            chunking: config.chunking,
+            rayon: OnceLock::new(),
         }
     }
 
-    /// Process a single task
-    pub fn process(&self, task: TransformTask) -> Result<TaskResult, String> {
-        // Send task to worker pool
-        self.task_sender
-            .send(WorkerMessage::Task(task))
-            .map_err(|e| format!("Failed to send task: {}", e))?;
+    /// Most workers the pool runs at once
+    pub fn num_workers(&self) -> usize {
+        self.queues.max_workers()
+    }
 
-        // Wait for result
-        self.result_receiver
-            .recv()
-            .map_err(|e| format!("Failed to receive result: {}", e))
+    /// Workers kept running however idle the pool is
+    pub fn min_workers(&self) -> usize {
+        self.queues.min_workers()
     }
 
-    /// Process a batch of tasks in parallel
-    pub fn process_batch(&self, batch: TaskBatch) -> Vec<TaskResult> {
-        let task_count = batch.tasks.len();
-        let mut results = Vec::with_capacity(task_count);
+    /// Resize the running pool to at most `workers` workers, keeping
+    /// `min_workers` of them running. Workers past the new size stop once
+    /// their current task is done, and their queued tasks go to the rest;
+    /// new ones start as the minimum or the backlog calls for them. The
+    /// rayon backend keeps the size it started with.
+    pub fn resize(&self, workers: usize, min_workers: usize) {
+        let workers = workers.max(1);
+        for id in self.num_workers()..workers {
+            self.stats.entry(id).or_default();
+        }
+        self.queues.resize(workers, min_workers);
+        tracing::info!("Resized thread pool to {} to {} workers", self.queues.min_workers(), workers);
+        while self.queues.active() < self.queues.min_workers() {
+            let Some((id, local)) = self.queues.unpark() else {
+                break;
+            };
+            start(id, local, &self.queues, &self.workers, &self.worker_config);
+        }
+        scale_up(&self.queues, &self.workers, &self.worker_config);
+    }
 
-        // Split batch for optimal distribution
-        let chunks = batch.split(self.num_workers);
-        
-        // Send all tasks
-        for chunk in chunks {
-            for task in chunk {
-                if let Err(e) = self.task_sender.send(WorkerMessage::Task(task)) {
-                    tracing::error!("Failed to send task: {}", e);
+    /// Bound the queue of tasks waiting for a worker, or lift the bound
+    /// with `None`
+    pub fn set_queue_size(&self, size: Option<usize>) {
+        self.queues.set_capacity(size);
+    }
+
+    /// Queue a task of chunk `chunk` in `lane` whose result goes to
+    /// `reply`, waiting for room if the queue is full
+    fn enqueue(&self, task: TransformTask, chunk: usize, lane: u64, reply: Sender<Results>) -> Result<Pending, SubmitError> {
+        let id = task.id.clone();
+        let job = Job::new(task, Reply::Batch(reply)).in_lane(lane);
+        let started = Arc::clone(&job.started);
+        self.queues.push(job, self.submit_timeout)?;
+        scale_up(&self.queues, &self.workers, &self.worker_config);
+        Ok(Pending { id, started, chunk })
+    }
+
+    /// Submit a task without blocking the caller's thread: the returned
+    /// future queues the task when first polled and resolves to its result
+    /// once a worker has processed it. Waiting for room in a full queue
+    /// happens on tokio's blocking pool, so it must run inside a tokio
+    /// runtime; spawn it to queue the task right away.
+    pub fn submit(&self, task: TransformTask) -> impl Future<Output = Result<TaskResult, SubmitError>> + Send + 'static {
+        let queues = Arc::clone(&self.queues);
+        let workers = Arc::clone(&self.workers);
+        let worker_config = Arc::clone(&self.worker_config);
+        let stats = Arc::clone(&self.stats);
+        let (submit_timeout, task_timeout) = (self.submit_timeout, self.task_timeout);
+        async move {
+            let id = task.id.clone();
+            let (reply, mut result) = oneshot::channel();
+            let job = Job::new(task, Reply::Async(reply));
+            let started = Arc::clone(&job.started);
+            tokio::task::spawn_blocking(move || {
+                queues.push(job, submit_timeout)?;
+                scale_up(&queues, &workers, &worker_config);
+                Ok(())
+            })
+            .await
+            .map_err(|_| SubmitError::Closed)??;
+
+            loop {
+                match tokio::time::timeout(DEADLINE_CHECK, &mut result).await {
+                    Ok(Ok(result)) => {
+                        record(&stats, &result);
+                        return Ok(result);
+                    }
+                    // The worker dropped the job without answering
+                    Ok(Err(_)) => return Err(SubmitError::Closed),
+                    Err(_) => {
+                        if let Some(&(worker_id, started)) = started.get() {
+                            if started.elapsed() >= task_timeout {
+                                let result = timed_out(id, worker_id, task_timeout);
+                                record(&stats, &result);
+                                return Ok(result);
+                            }
+                        }
+                    }
                 }
             }
         }
+    }
 
-        // Collect all results
-        for _ in 0..task_count {
-            match self.result_receiver.recv() {
-                Ok(result) => {
-                    // Update stats
-                    if result.is_success() {
-                        if let TaskResult::Success { duration_ms, .. } = &result {
-                            // In real implementation, track which worker processed this
-                            self.stats.iter().next().map(|entry| {
-                                entry.value().record_success(*duration_ms);
-                            });
+    /// Wait for the results of `pending`, indexed by task index, adding
+    /// them to `collected`
+    fn collect(&self, results: &Receiver<Results>, pending: &mut [Option<Pending>], collected: &mut Vec<(usize, TaskResult)>) {
+        let mut remaining = pending.iter().flatten().count();
+        if remaining > 0 {
+            self.collect_until(results, pending, collected, |_| {
+                remaining -= 1;
+                remaining == 0
+            });
+        }
+    }
+
+    /// Wait for results of `pending` until `finished`, called with each
+    /// task whose result is in, returns true. Tasks that run past the
+    /// deadline are reported as timed out; the worker running one can't be
+    /// interrupted, so its result is dropped when it arrives.
+    fn collect_until(
+        &self,
+        results: &Receiver<Results>,
+        pending: &mut [Option<Pending>],
+        collected: &mut Vec<(usize, TaskResult)>,
+        mut finished: impl FnMut(&Pending) -> bool,
+    ) {
+        loop {
+            match results.recv_timeout(DEADLINE_CHECK) {
+                Ok(group) => {
+                    let mut done = false;
+                    for (index, result) in group {
+                        // Results of abandoned tasks were already reported
+                        if let Some(task) = pending.get_mut(index).and_then(Option::take) {
+                            record(&self.stats, &result);
+                            collected.push((index, result));
+                            done |= finished(&task);
                         }
-                    } else {
-                        self.stats.iter().next().map(|entry| {
-                            entry.value().record_failure();
-                        });
                     }
-                    results.push(result);
+                    if done {
+                        return;
+                    }
                 }
-                Err(e) => {
-                    tracing::error!("Failed to receive result: {}", e);
+                Err(RecvTimeoutError::Timeout) => {
+                    for (index, slot) in pending.iter_mut().enumerate() {
+                        let Some(&(worker_id, started)) = slot.as_ref().and_then(|task| task.started.get()) else {
+                            continue;
+                        };
+                        if started.elapsed() < self.task_timeout {
+                            continue;
+                        }
+                        let Some(task) = slot.take() else {
+                            continue;
+                        };
+                        let result = timed_out(task.id.clone(), worker_id, self.task_timeout);
+                        record(&self.stats, &result);
+                        collected.push((index, result));
+                        if finished(&task) {
+                            return;
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    let remaining = pending.iter().flatten().count();
+                    tracing::error!("Failed to receive {} results: the workers are gone", remaining);
+                    return;
                 }
             }
         }
+    }
 
-        results
+    /// Process a batch of tasks in parallel. The batch is cut into chunks
+    /// by the pool's [`Chunking`], and a chunk is queued once fewer chunks
+    /// than workers are unfinished, so a run of large documents is spread
+    /// over the workers instead of queued behind one. The batch gets a lane
+    /// of its own, so concurrent batches take turns on the workers, each
+    /// weighted by the highest priority among its tasks. Results come back
+    /// in task order unless the batch asks for completion order. Fails with
+    /// [`SubmitError::Busy`] if the queue stays full, cancelling the tasks
+    /// queued by then but not the rest of the batch's request.
+    pub fn process_batch(&self, mut batch: TaskBatch) -> Result<Vec<TaskResult>, SubmitError> {
+        let task_count = batch.tasks.len();
+        tracing::debug!("Processing batch {} of {} tasks", batch.id, task_count);
+        let preserve_order = batch.preserve_order;
+        let cancel = batch.cancel.child();
+        for task in &mut batch.tasks {
+            task.cancel = Some(cancel.clone());
+        }
+        if self.backend == Backend::Rayon {
+            return Ok(self.process_batch_rayon(batch));
+        }
+        let mut pending: Vec<Option<Pending>> = Vec::new();
+        pending.resize_with(task_count, || None);
+        // Results of this batch only, however many callers share the pool
+        let (reply, result_receiver) = bounded(task_count);
+
+        let mut collected = Vec::with_capacity(task_count);
+
+        let lane = self.queues.new_lane();
+        let chunks = self.chunking.chunk(batch.tasks);
+        // Tasks of each chunk without a result yet
+        let mut unfinished: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        let (mut open, window) = (0, self.num_workers().max(1));
+        for (chunk_index, chunk) in chunks.into_iter().enumerate() {
+            if open >= window {
+                self.collect_until(&result_receiver, &mut pending, &mut collected, |task| {
+                    unfinished[task.chunk] -= 1;
+                    if unfinished[task.chunk] == 0 {
+                        open -= 1;
+                    }
+                    open < window
+                });
+            }
+            open += 1;
+            for task in chunk {
+                let index = task.index;
+                match self.enqueue(task, chunk_index, lane, reply.clone()) {
+                    Ok(task) => {
+                        if let Some(slot) = pending.get_mut(index) {
+                            *slot = Some(task);
+                        }
+                    }
+                    Err(SubmitError::Busy) => {
+                        // Queued tasks are skipped rather than run for nobody
+                        cancel.cancel();
+                        return Err(SubmitError::Busy);
+                    }
+                    Err(e) => tracing::error!("Failed to send task: {}", e),
+                }
+            }
+        }
+        drop(reply);
+
+        // Collect the remaining results
+        self.collect(&result_receiver, &mut pending, &mut collected);
+        if preserve_order {
+            collected.sort_by_key(|(index, _)| *index);
+        }
+        Ok(collected.into_iter().map(|(_, result)| result).collect())
     }
 
-    /// Process multiple files concurrently
-    pub async fn process_files(&self, files: Vec<(String, String)>) -> Vec<TaskResult> {
-        let tasks: Vec<TransformTask> = files
-            .into_iter()
-            .enumerate()
-            .map(|(i, (path, content))| {
-                TransformTask::new(
-                    format!("file-{}", i),
-                    path.into(),
-                    content,
-                )
-            })
-            .collect();
+    /// Run `op` on threads sized and named like the pool's workers, so
+    /// rayon iterators inside it don't spill onto rayon's global pool
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        self.rayon().install(op)
+    }
 
-        let batch = TaskBatch::new("batch".to_string(), tasks);
-        self.process_batch(batch)
+    /// The rayon pool behind [`Backend::Rayon`] and [`ThreadPool::install`]
+    fn rayon(&self) -> &rayon::ThreadPool {
+        self.rayon.get_or_init(|| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(self.num_workers().max(1))
+                .thread_name({
+                    let prefix = self.worker_config.name_prefix.clone();
+                    move |i| format!("{}-batch-{}", prefix, i)
+                })
+                .stack_size(self.worker_config.stack_size)
+                .build()
+                .expect("failed to start rayon pool")
+        })
+    }
+
+    /// Run a batch on the rayon pool; results are always in task order
+    fn process_batch_rayon(&self, batch: TaskBatch) -> Vec<TaskResult> {
+        let rayon = self.rayon();
+        // Tasks are queued from the start of the batch until a thread takes them
+        let queued = Instant::now();
+        let results: Vec<TaskResult> = rayon.install(|| {
+            batch
+                .tasks
+                .into_par_iter()
+                .map(|task| {
+                    let worker_id = rayon::current_thread_index().unwrap_or(0);
+                    let start = Instant::now();
+                    let result = Worker::execute(worker_id, &mut Engine::Thread, task, &self.worker_config);
+                    self.queues.record_task(start.saturating_duration_since(queued), start.elapsed(), result.is_success());
+                    result
+                })
+                .collect()
+        });
+        for result in &results {
+            record(&self.stats, result);
+        }
+        results
     }
 
     /// Get pool statistics
@@ -137,16 +420,29 @@ impl ThreadPool {
         let mut total_tasks = 0;
         let mut total_duration = 0;
         let mut total_errors = 0;
+        let num_workers = self.num_workers();
+        let mut workers = vec![WorkerStats::default(); num_workers];
 
         for entry in self.stats.iter() {
             let stats = entry.value();
             total_tasks += stats.tasks_processed;
             total_duration += stats.total_duration_ms;
             total_errors += stats.errors;
+            if let Some(worker) = workers.get_mut(*entry.key()) {
+                *worker = stats.clone();
+            }
         }
 
+        let timings = self.queues.timings();
         PoolStats {
-            num_workers: self.num_workers,
+            num_workers,
+            min_workers: self.min_workers(),
+            active_workers: self.queues.active(),
+            queue_size: self.queues.capacity(),
+            queue_depth: self.queues.pending(),
+            max_queue_depth: self.queues.max_pending(),
+            queue_time: timings.queued.summary(),
+            run_time: timings.running.summary(),
             total_tasks,
             total_duration_ms: total_duration,
             total_errors,
@@ -155,20 +451,32 @@ impl ThreadPool {
             } else {
                 0.0
             },
+            workers,
+        }
+    }
+
+    /// Zero every worker's counters and the task timings
+    pub fn reset_stats(&self) {
+        for mut entry in self.stats.iter_mut() {
+            *entry.value_mut() = WorkerStats::default();
         }
+        self.queues.reset_timings();
     }
 
-    /// Shutdown the thread pool gracefully
-    pub fn shutdown(self) {
+    /// Shutdown the thread pool gracefully, waiting for queued tasks to
+    /// finish; does nothing if the pool is already shut down
+    pub fn shutdown(&self) {
+        if self.queues.is_shut_down() {
+            return;
+        }
         tracing::info!("Shutting down thread pool");
         
-        // Send shutdown message to all workers
-        for _ in 0..self.num_workers {
-            let _ = self.task_sender.send(WorkerMessage::Shutdown);
-        }
+        // Workers finish queued tasks, then exit
+        self.queues.shut_down();
 
         // Wait for all workers to finish
-        for worker in self.workers {
+        let workers = std::mem::take(&mut *self.workers.lock());
+        for worker in workers {
             if let Err(e) = worker.join() {
                 tracing::error!("Worker failed to join: {:?}", e);
             }
@@ -178,60 +486,182 @@ impl ThreadPool {
     }
 }
 
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Let workers of a pool that wasn't shut down exit on their own
+        self.queues.shut_down();
+    }
+}
+
+/// Start parked workers while the backlog calls for them
+fn scale_up(queues: &Arc<Queues>, workers: &Mutex<Vec<Worker>>, config: &WorkerConfig) {
+    while queues.wants_worker() {
+        let Some((id, local)) = queues.unpark() else {
+            break;
+        };
+        start(id, local, queues, workers, config);
+    }
+}
+
+/// Start worker `id` on an unparked deque
+fn start(id: usize, local: Deque<Job>, queues: &Arc<Queues>, workers: &Mutex<Vec<Worker>>, config: &WorkerConfig) {
+    tracing::debug!("Starting worker {} ({} running)", id, queues.active());
+    let mut workers = workers.lock();
+    workers.retain(|worker| !worker.is_finished());
+    workers.push(Worker::spawn(id, local, Arc::clone(queues), config));
+}
+
+/// Count a result against the worker that produced it
+fn record(stats: &DashMap<usize, WorkerStats>, result: &TaskResult) {
+    let Some(mut stats) = result.worker_id().and_then(|worker| stats.get_mut(&worker)) else {
+        return;
+    };
+    match result {
+        TaskResult::Success { duration_ms, .. } => stats.record_success(*duration_ms),
+        TaskResult::Failure { .. } => stats.record_failure(),
+    }
+}
+
+/// Failure of task `id`, abandoned on `worker_id` after `timeout`
+fn timed_out(id: String, worker_id: usize, timeout: Duration) -> TaskResult {
+    tracing::warn!("Task {} on worker {} timed out", id, worker_id);
+    TaskResult::Failure {
+        id,
+        error: format!("Task exceeded its {} ms deadline", timeout.as_millis()),
+        kind: FailureKind::Timeout,
+        worker_id: Some(worker_id),
+    }
+}
+
 /// Statistics for the entire thread pool
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct PoolStats {
     pub num_workers: usize,
+    /// Workers kept running however idle the pool is
+    pub min_workers: usize,
+    /// Workers running; the rest start when the queue backs up
+    pub active_workers: usize,
+    /// Most tasks that may wait for a worker at once, if bounded
+    pub queue_size: Option<usize>,
+    /// Tasks sent to the pool but not yet picked up by a worker
+    pub queue_depth: usize,
+    /// Deepest the queue has been since the stats were last reset
+    pub max_queue_depth: usize,
+    /// Time tasks waited for a worker; a growing share of the total is
+    /// the sign of a saturated pool
+    pub queue_time: MethodSummary,
+    /// Time tasks spent on a worker; failed tasks count as errors
+    pub run_time: MethodSummary,
     pub total_tasks: usize,
     pub total_duration_ms: u64,
     pub total_errors: usize,
     pub average_duration_ms: f64,
-}
-
-impl PoolStats {
-    pub fn throughput(&self) -> f64 {
-        if self.total_duration_ms > 0 {
-            (self.total_tasks as f64 * 1000.0) / self.total_duration_ms as f64
-        } else {
-            0.0
-        }
-    }
-
-    pub fn error_rate(&self) -> f64 {
-        if self.total_tasks > 0 {
-            self.total_errors as f64 / self.total_tasks as f64
-        } else {
-            0.0
-        }
-    }
+    /// Counters of each worker, by worker id
+    pub workers: Vec<WorkerStats>,
 }
 
 /// Builder for ThreadPool with configuration options
 pub struct ThreadPoolBuilder {
     num_workers: Option<usize>,
+    min_workers: Option<usize>,
+    idle_timeout: Duration,
     queue_size: Option<usize>,
+    submit_timeout: Duration,
+    task_timeout: Duration,
+    worker: WorkerConfig,
+    backend: Backend,
+    chunking: Chunking,
 }
 
 impl ThreadPoolBuilder {
     pub fn new() -> Self {
         ThreadPoolBuilder {
             num_workers: None,
+            min_workers: None,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
             queue_size: None,
+            submit_timeout: DEFAULT_SUBMIT_TIMEOUT,
+            task_timeout: DEFAULT_TASK_TIMEOUT,
+            worker: WorkerConfig::default(),
+            backend: Backend::default(),
+            chunking: Chunking::default(),
         }
     }
 
+    /// Most workers the pool runs at once
     pub fn workers(mut self, num: usize) -> Self {
         self.num_workers = Some(num);
         self
     }
 
+    /// Start with `num` workers, adding more up to [`workers`](Self::workers)
+    /// while tasks back up and stopping them again once idle. With none,
+    /// an idle pool holds no threads and starts them on the next task.
+    /// Without this the pool runs all its workers.
+    pub fn min_workers(mut self, num: usize) -> Self {
+        self.min_workers = Some(num);
+        self
+    }
+
+    /// How long a worker above the minimum stays idle before it stops
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Bound the queue of tasks waiting for a worker
     pub fn queue_size(mut self, size: usize) -> Self {
         self.queue_size = Some(size);
         self
     }
 
+    /// How long a task may run before it is abandoned and reported as
+    /// timed out
+    pub fn task_timeout(mut self, timeout: Duration) -> Self {
+        self.task_timeout = timeout;
+        self
+    }
+
+    /// Keep each worker on its own physical core
+    pub fn pin_workers(mut self, pin: bool) -> Self {
+        self.worker.pin = pin;
+        self
+    }
+
+    /// Stack size of worker threads, in bytes
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.worker.stack_size = bytes;
+        self
+    }
+
+    /// Fail tasks whose input or rendered output is over `limits`
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.worker.limits = limits;
+        self
+    }
+
+    /// Where workers render documents. Batches run by [`Backend::Rayon`]
+    /// always render in-thread.
+    pub fn isolation(mut self, isolation: Isolation) -> Self {
+        self.worker.isolation = isolation;
+        self
+    }
+
+    /// How batches are cut into chunks; see [`ThreadPool::process_batch`]
+    pub fn chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// What runs batches
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
     pub fn build(self) -> ThreadPool {
-        ThreadPool::new(self.num_workers)
+        ThreadPool::with_config(self)
     }
 }
 
@@ -240,16 +670,24 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
 
+    /// Submit `task` and wait for its result, as a request thread does
+    fn process(pool: &ThreadPool, task: TransformTask) -> Result<TaskResult, SubmitError> {
+        super::super::runtime().block_on(pool.submit(task))
+    }
+
     #[test]
     fn test_thread_pool_creation() {
-        let pool = ThreadPool::new(Some(4));
-        assert_eq!(pool.num_workers, 4);
+        let pool = ThreadPoolBuilder::new().workers(4).build();
+        assert_eq!(pool.num_workers(), 4);
+        pool.shutdown();
         pool.shutdown();
+        let task = TransformTask::new("late".to_string(), PathBuf::from("test.md"), "# Late".to_string());
+        assert_eq!(process(&pool, task).unwrap_err(), SubmitError::Closed);
     }
 
     #[test]
     fn test_single_task_processing() {
-        let pool = ThreadPool::new(Some(2));
+        let pool = ThreadPoolBuilder::new().workers(2).build();
         
         let task = TransformTask::new(
             "test-1".to_string(),
@@ -257,7 +695,7 @@ mod tests {
             "# Hello World".to_string(),
         );
         
-        let result = pool.process(task).unwrap();
+        let result = process(&pool, task).unwrap();
         assert!(result.is_success());
         assert_eq!(result.id(), "test-1");
         
@@ -266,7 +704,7 @@ mod tests {
 
     #[test]
     fn test_batch_processing() {
-        let pool = ThreadPool::new(Some(4));
+        let pool = ThreadPoolBuilder::new().workers(4).build();
         
         let tasks: Vec<TransformTask> = (0..10)
             .map(|i| {
@@ -279,19 +717,88 @@ mod tests {
             .collect();
         
         let batch = TaskBatch::new("test-batch".to_string(), tasks);
-        let results = pool.process_batch(batch);
+        let results = pool.process_batch(batch).unwrap();
         
         assert_eq!(results.len(), 10);
-        for result in results {
+        for (i, result) in results.iter().enumerate() {
             assert!(result.is_success());
+            assert_eq!(result.id(), format!("task-{}", i));
         }
         
+        // Sizes vary so tasks finish out of order
+        let tasks = (0..10)
+            .map(|i| TransformTask::new(format!("task-{}", i), PathBuf::from("test.md"), "x ".repeat(1000 * (i % 3))))
+            .collect();
+        let mut results = pool.process_batch(TaskBatch::new("unordered".to_string(), tasks).in_completion_order()).unwrap();
+        results.sort_by_key(|result| result.id()[5..].parse::<usize>().unwrap());
+        assert!(results.iter().enumerate().all(|(i, result)| result.id() == format!("task-{}", i)));
+        
         pool.shutdown();
     }
 
+    #[test]
+    fn test_batches_are_queued_a_chunk_per_worker() {
+        // Two chunks of one task run at a time, the rest queued as they finish
+        let pool = ThreadPoolBuilder::new().workers(2).chunking(Chunking::Bytes(1)).build();
+        let tasks = (0..12)
+            .map(|i| TransformTask::new(format!("task-{}", i), PathBuf::from("test.md"), "x ".repeat(100 * (i % 4))))
+            .collect();
+        let results = pool.process_batch(TaskBatch::new("windowed".to_string(), tasks)).unwrap();
+        assert_eq!(results.len(), 12);
+        assert!(results.iter().enumerate().all(|(i, result)| result.is_success() && result.id() == format!("task-{}", i)));
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_concurrent_callers_get_their_own_results() {
+        let pool = ThreadPoolBuilder::new().workers(2).build();
+        
+        std::thread::scope(|scope| {
+            for caller in 0..4 {
+                let pool = &pool;
+                scope.spawn(move || {
+                    for i in 0..20 {
+                        let id = format!("caller-{}-{}", caller, i);
+                        let task = TransformTask::new(id.clone(), PathBuf::from("test.md"), "# Test".to_string());
+                        assert_eq!(process(pool, task).unwrap().id(), id);
+                    }
+                    let tasks = (0..5)
+                        .map(|i| TransformTask::new(format!("batch-{}-{}", caller, i), PathBuf::from("test.md"), "# Test".to_string()))
+                        .collect();
+                    let results = pool.process_batch(TaskBatch::new(format!("batch-{}", caller), tasks)).unwrap();
+                    assert_eq!(results.len(), 5);
+                    assert!(results.iter().all(|result| result.id().starts_with(&format!("batch-{}-", caller))));
+                });
+            }
+        });
+        
+        pool.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_async_submission() {
+        let pool = ThreadPoolBuilder::new().workers(2).build();
+        let task = |i: usize| TransformTask::new(format!("task-{}", i), PathBuf::from("test.md"), format!("# Document {}", i));
+        
+        // Futures don't borrow the pool
+        let pending: Vec<_> = (0..5).map(|i| pool.submit(task(i))).collect();
+        for (i, result) in pending.into_iter().enumerate() {
+            let result = result.await.unwrap();
+            assert!(result.is_success());
+            assert_eq!(result.id(), format!("task-{}", i));
+        }
+        
+        // Spawned submissions are queued before any result is awaited
+        let spawned: Vec<_> = (5..7).map(|i| tokio::spawn(pool.submit(task(i)))).collect();
+        for (i, handle) in (5..7).zip(spawned) {
+            assert_eq!(handle.await.unwrap().unwrap().id(), format!("task-{}", i));
+        }
+        assert_eq!(pool.stats().total_tasks, 7);
+    }
+
     #[test]
     fn test_pool_stats() {
-        let pool = ThreadPool::new(Some(2));
+        let pool = ThreadPoolBuilder::new().workers(2).build();
         
         // Process some tasks
         for i in 0..5 {
@@ -300,16 +807,206 @@ mod tests {
                 PathBuf::from("test.md"),
                 "# Test".to_string(),
             );
-            let _ = pool.process(task);
+            let _ = process(&pool, task);
         }
         
         let stats = pool.stats();
         assert_eq!(stats.num_workers, 2);
-        // Stats might not be perfectly accurate in tests due to async nature
+        assert_eq!(stats.total_tasks, 5);
+        assert_eq!(stats.workers.len(), 2);
+        assert_eq!(stats.workers.iter().map(|worker| worker.tasks_processed).sum::<usize>(), 5);
+        
+        let tasks = (0..20)
+            .map(|i| TransformTask::new(format!("batch-{}", i), PathBuf::from("test.md"), "# Test".to_string()))
+            .collect();
+        let results = pool.process_batch(TaskBatch::new("batch".to_string(), tasks)).unwrap();
+        assert!(results.iter().all(|result| result.worker_id().is_some_and(|worker| worker < 2)));
+        let stats = pool.stats();
+        assert_eq!(stats.total_tasks, 25);
+        // Workers record timings before answering
+        assert_eq!((stats.queue_time.calls, stats.run_time.calls), (25, 25));
+        assert!(stats.max_queue_depth >= 1);
+        assert!(stats.run_time.max_ms >= stats.run_time.p50_ms);
+        
+        pool.reset_stats();
+        let stats = pool.stats();
+        assert_eq!((stats.total_tasks, stats.run_time.calls, stats.max_queue_depth), (0, 0, 0));
+        
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_full_queue_reports_busy() {
+        // Without workers nothing drains the queue
+        let mut builder = ThreadPoolBuilder::new().workers(0).queue_size(2);
+        builder.submit_timeout = Duration::from_millis(10);
+        let pool = builder.build();
+        let task = |i: usize| TransformTask::new(format!("task-{}", i), PathBuf::from("test.md"), "# Test".to_string());
+        
+        // As a request submits it: the request's own token, which would
+        // answer it as cancelled instead of busy once set
+        let id = crate::protocol::RpcId::String("flood".to_string());
+        let token = crate::cancel::register(&id);
+        let batch = TaskBatch::new("flood".to_string(), (0..3).map(task).collect()).with_cancel(crate::cancel::current().unwrap());
+        assert_eq!(pool.process_batch(batch).unwrap_err(), SubmitError::Busy);
+        crate::cancel::unregister(&id, &token);
+        assert!(!token.is_cancelled());
+        assert_eq!(pool.stats().queue_depth, 2);
+        assert_eq!(process(&pool, task(3)).unwrap_err(), SubmitError::Busy);
+        // The queued tasks were given up on
+        let queued = pool.queues.next_job(&Deque::new_fifo()).unwrap();
+        assert!(queued.task.is_cancelled());
+    }
+
+    #[test]
+    fn test_slow_task_times_out() {
+        let pool = ThreadPoolBuilder::new()
+            .workers(1)
+            .task_timeout(Duration::ZERO)
+            .build();
+        // Takes far longer to render than one deadline check
+        let slow = TransformTask::new("slow".to_string(), PathBuf::from("slow.md"), "*a* ".repeat(200_000));
+        
+        let result = process(&pool, slow).unwrap();
+        assert!(matches!(result, TaskResult::Failure { kind: FailureKind::Timeout, worker_id: Some(0), .. }));
+        assert_eq!(result.id(), "slow");
+        assert_eq!(pool.stats().total_errors, 1);
+    }
+
+    #[test]
+    fn test_workers_scale_with_backlog() {
+        let pool = ThreadPoolBuilder::new()
+            .workers(4)
+            .min_workers(1)
+            .idle_timeout(Duration::from_millis(50))
+            .build();
+        assert_eq!(pool.stats().active_workers, 1);
+        
+        let tasks = (0..64)
+            .map(|i| TransformTask::new(format!("task-{}", i), PathBuf::from("test.md"), "Some *text*\n\n".repeat(500)))
+            .collect();
+        let results = pool.process_batch(TaskBatch::new("cold".to_string(), tasks)).unwrap();
+        let mut workers: Vec<usize> = results.iter().filter_map(TaskResult::worker_id).collect();
+        workers.sort();
+        workers.dedup();
+        assert!(workers.len() > 1);
+        
+        // Idle workers stop, down to the minimum
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().active_workers > 1 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.stats().active_workers, 1);
+        
+        // and start again when needed
+        let task = TransformTask::new("warm".to_string(), PathBuf::from("test.md"), "# Warm".to_string());
+        assert!(process(&pool, task).unwrap().is_success());
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_idle_pool_stops_every_worker() {
+        let pool = ThreadPoolBuilder::new()
+            .workers(2)
+            .min_workers(0)
+            .idle_timeout(Duration::from_millis(20))
+            .build();
+        assert_eq!(pool.stats().active_workers, 0);
         
+        for round in 0..3 {
+            let task = TransformTask::new(format!("round-{}", round), PathBuf::from("test.md"), "# Again".to_string());
+            assert!(process(&pool, task).unwrap().is_success());
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while pool.stats().active_workers > 0 && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            assert_eq!(pool.stats().active_workers, 0);
+        }
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_resize() {
+        let pool = ThreadPoolBuilder::new().workers(4).queue_size(8).build();
+        let wait_for = |active: usize| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while pool.stats().active_workers != active && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            assert_eq!(pool.stats().active_workers, active);
+        };
+        let batch = |name: &str| {
+            let tasks = (0..16)
+                .map(|i| TransformTask::new(format!("{}-{}", name, i), PathBuf::from("test.md"), "# Task".to_string()))
+                .collect();
+            pool.process_batch(TaskBatch::new(name.to_string(), tasks)).unwrap()
+        };
+        
+        // Workers past the new size stop; the rest keep serving tasks
+        pool.resize(2, 2);
+        wait_for(2);
+        let results = batch("small");
+        assert!(results.iter().all(|result| result.is_success() && result.worker_id() < Some(2)));
+        
+        // Growing past the original size starts workers on new deques
+        pool.resize(6, 6);
+        wait_for(6);
+        let stats = pool.stats();
+        assert_eq!((stats.num_workers, stats.min_workers, stats.workers.len()), (6, 6, 6));
+        assert!(batch("large").iter().all(TaskResult::is_success));
+        
+        pool.set_queue_size(None);
+        assert_eq!(pool.stats().queue_size, None);
         pool.shutdown();
     }
 
+    #[test]
+    fn test_rayon_backend() {
+        let pool = ThreadPoolBuilder::new().workers(2).backend(Backend::Rayon).build();
+        let tasks = (0..10)
+            .map(|i| TransformTask::new(format!("task-{}", i), PathBuf::from("test.md"), format!("# Document {}", i)))
+            .collect();
+        let results = pool.process_batch(TaskBatch::new("rayon".to_string(), tasks).in_completion_order()).unwrap();
+        assert!(results.iter().enumerate().all(|(i, result)| result.is_success() && result.id() == format!("task-{}", i)));
+        assert!(results.iter().all(|result| result.worker_id().is_some_and(|worker| worker < 2)));
+        assert_eq!(pool.stats().total_tasks, 10);
+        
+        // Single tasks still go to the workers
+        let task = TransformTask::new("single".to_string(), PathBuf::from("test.md"), "# Single".to_string());
+        assert!(process(&pool, task).unwrap().is_success());
+        
+        let token = crate::cancel::CancelToken::default();
+        token.cancel();
+        let tasks = vec![TransformTask::new("cancelled".to_string(), PathBuf::from("test.md"), "# Gone".to_string())];
+        let results = pool.process_batch(TaskBatch::new("cancelled".to_string(), tasks).with_cancel(token)).unwrap();
+        assert!(matches!(results[0], TaskResult::Failure { kind: FailureKind::Cancelled, .. }));
+    }
+
+    /// Compares the backends on a few batch shapes, logging the mean time
+    /// per batch; its results are cited on [`Backend`]:
+    /// `cargo test --release bench_backends -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_backends() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let document = |size: usize| "Some *emphasis*, a [link](x.md) and `code`.\n\n".repeat(size);
+        let shapes = [("2000 small", 2000, 10), ("200 medium", 200, 500), ("20 large", 20, 5000)];
+        for backend in [Backend::Custom, Backend::Rayon] {
+            let pool = ThreadPoolBuilder::new().workers(8).backend(backend).build();
+            for (name, count, size) in shapes {
+                let started = Instant::now();
+                for round in 0..5 {
+                    let tasks = (0..count)
+                        .map(|i| TransformTask::new(format!("{}-{}", round, i), PathBuf::from("bench.md"), document(size)))
+                        .collect();
+                    pool.process_batch(TaskBatch::new(name.to_string(), tasks)).unwrap();
+                }
+                tracing::info!(?backend, batch = name, per_batch = ?(started.elapsed() / 5), "bench_backends");
+            }
+            pool.shutdown();
+        }
+    }
+
     #[test]
     fn test_thread_pool_builder() {
         let pool = ThreadPoolBuilder::new()
@@ -317,7 +1014,23 @@ mod tests {
             .queue_size(1000)
             .build();
         
-        assert_eq!(pool.num_workers, 8);
+        assert_eq!(pool.num_workers(), 8);
+        pool.shutdown();
+        
+        let mut builder = ThreadPoolBuilder::new().workers(1).stack_size(16 * 1024 * 1024);
+        builder.worker.name_prefix = "md".to_string();
+        builder.worker.panic_hook = Some(Arc::new(|worker, message| eprintln!("worker {} panicked: {}", worker, message)));
+        let pool = builder.build();
+        assert_eq!(pool.worker_config.name_prefix, "md");
+        // Deep nesting is fine on a large stack
+        let task = TransformTask::new("deep".to_string(), PathBuf::from("deep.md"), format!("{}deep", "> ".repeat(5000)));
+        assert!(process(&pool, task).unwrap().is_success());
+        pool.shutdown();
+        
+        // Workers run tasks whether or not the OS let them be pinned
+        let pool = ThreadPoolBuilder::new().workers(2).pin_workers(true).build();
+        let task = TransformTask::new("pinned".to_string(), PathBuf::from("test.md"), "# Pinned".to_string());
+        assert!(process(&pool, task).unwrap().is_success());
         pool.shutdown();
     }
 }
\ No newline at end of file
@@ -0,0 +1,228 @@
+//! Workers that render in a child process
+//!
+//! With [`Isolation::Process`](crate::parallel::Isolation::Process) each
+//! worker thread hands its documents to a child process of its own (the
+//! sidecar binary, run as `fastmd-sidecar worker`) instead of rendering
+//! them itself. A segfault or an OOM kill then takes down one child and
+//! fails the task it was rendering; the worker starts a new child for its
+//! next task and the sidecar carries on.
+//!
+//! Parent and child exchange one JSON object per line: `{"file": ...,
+//! "content": ..., "options": ...}` (with the whole `source` and the
+//! `frontmatter` when the document has some) to the child, `{"entry":
+//! ...}`, the rendered document as the disk cache stores it, or `{"error":
+//! ...}` back.
+
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::cache::Entry;
+use crate::parallel::task::TransformTask;
+use crate::transform::{self, render_document, TransformOptions};
+
+/// Subcommand that runs the sidecar as a render child
+pub const WORKER_SUBCOMMAND: &str = "worker";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    #[serde(default)]
+    file: String,
+    /// The whole document, when it has frontmatter above `content`
+    #[serde(default)]
+    source: Option<String>,
+    content: String,
+    #[serde(default)]
+    options: TransformOptions,
+    #[serde(default)]
+    frontmatter: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Response {
+    Entry(Entry),
+    Error(String),
+}
+
+/// A running child and its pipes
+struct Running {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+/// Renders documents in a child process, starting it on first use and
+/// again after it dies
+pub struct ChildRenderer {
+    program: OsString,
+    args: Vec<OsString>,
+    running: Option<Running>,
+}
+
+impl ChildRenderer {
+    /// Children running `program` with `args`
+    pub fn new(program: impl Into<OsString>, args: impl IntoIterator<Item = impl Into<OsString>>) -> Self {
+        ChildRenderer {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            running: None,
+        }
+    }
+
+    /// Children running this sidecar's own binary
+    pub fn sidecar() -> Self {
+        let program = std::env::current_exe().map(OsString::from).unwrap_or_else(|_| "fastmd-sidecar".into());
+        ChildRenderer::new(program, [WORKER_SUBCOMMAND])
+    }
+
+    /// Render `task` in the child. Fails if the child reports an error or
+    /// dies; a dead child is replaced on the next call.
+    pub fn render(&mut self, task: &TransformTask) -> Result<Entry, String> {
+        let mut running = match self.running.take() {
+            Some(running) => running,
+            None => self.start().map_err(|e| format!("Failed to start worker process: {}", e))?,
+        };
+        match exchange(&mut running, task) {
+            Ok(response) => {
+                self.running = Some(running);
+                match response {
+                    Response::Entry(entry) => Ok(entry),
+                    Response::Error(error) => Err(error),
+                }
+            }
+            Err(e) => {
+                let _ = running.child.kill();
+                let status = running.child.wait().map(|status| status.to_string()).unwrap_or_else(|_| e.to_string());
+                tracing::error!("Worker process {} died ({}); starting another", running.child.id(), status);
+                Err(format!("Worker process died ({})", status))
+            }
+        }
+    }
+
+    fn start(&self) -> io::Result<Running> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let input = child.stdin.take().ok_or_else(|| io::Error::other("child has no stdin"))?;
+        let output = child.stdout.take().ok_or_else(|| io::Error::other("child has no stdout"))?;
+        tracing::debug!("Started worker process {}", child.id());
+        Ok(Running {
+            child,
+            input,
+            output: BufReader::new(output),
+        })
+    }
+}
+
+impl Drop for ChildRenderer {
+    fn drop(&mut self) {
+        if let Some(mut running) = self.running.take() {
+            // Closing stdin lets the child exit on its own
+            drop(running.input);
+            let _ = running.child.wait();
+        }
+    }
+}
+
+/// Send one document and read the child's answer
+fn exchange(running: &mut Running, task: &TransformTask) -> io::Result<Response> {
+    let request = Request {
+        file: task.file.to_string_lossy().into_owned(),
+        source: task.source.clone(),
+        content: task.content.clone(),
+        options: task.options.clone(),
+        frontmatter: task.frontmatter.clone(),
+    };
+    let mut line = serde_json::to_string(&request)?;
+    line.push('\n');
+    running.input.write_all(line.as_bytes())?;
+    running.input.flush()?;
+    let mut answer = String::new();
+    if running.output.read_line(&mut answer)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(serde_json::from_str(&answer)?)
+}
+
+/// Entry point of `fastmd-sidecar worker`: render documents read from
+/// `input` until it closes
+pub fn serve(input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    transform::warm_up();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let source = request.source.as_deref().unwrap_or(&request.content);
+                let rendered = panic::catch_unwind(AssertUnwindSafe(|| {
+                    render_document(&request.file, source, &request.content, &request.options, request.frontmatter.as_ref())
+                }));
+                match rendered {
+                    Ok(Ok(entry)) => Response::Entry(entry),
+                    Ok(Err(error)) => Response::Error(error.message),
+                    Err(_) => Response::Error("Transform panicked".to_string()),
+                }
+            }
+            Err(e) => Response::Error(format!("Invalid request: {}", e)),
+        };
+        serde_json::to_writer(&mut output, &response)?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serve() {
+        let input = "{\"content\":\"# Hi\"}\n\nnot json\n\
+            {\"file\":\"a.md\",\"source\":\"---\\ntitle: T\\n---\\n# Hi\\n\\ntext\",\"content\":\"# Hi\\n\\ntext\",\
+            \"options\":{\"sourcemap\":true,\"framework\":\"astro\"},\"frontmatter\":{\"title\":\"T\"}}\n";
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+        let responses: Vec<Response> = output
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert!(matches!(&responses[0], Response::Entry(entry) if entry.code.contains("<h1>Hi</h1>") && entry.map.is_none()));
+        assert!(matches!(&responses[1], Response::Error(_)));
+        // Rendered with the document's options and frontmatter, as a
+        // request thread would
+        match &responses[2] {
+            Response::Entry(entry) => {
+                assert!(entry.code.contains("<h1 id=\"hi\">Hi</h1>"), "{}", entry.code);
+                assert!(entry.code.contains("\"title\":\"T\""), "{}", entry.code);
+                assert!(entry.map.is_some());
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dead_child_is_replaced() {
+        // Answers the first document, then crashes on the second
+        let script = "read line; echo '{\"entry\":{\"code\":\"<p>ok</p>\",\"dependencies\":[],\"warnings\":[]}}'; read line; kill -SEGV $$";
+        let mut renderer = ChildRenderer::new("sh", ["-c", script]);
+        let task = |content: &str| TransformTask::new(content.to_string(), "test.md".into(), content.to_string());
+        assert_eq!(renderer.render(&task("ok")).unwrap().code, "<p>ok</p>");
+        let error = renderer.render(&task("crash")).unwrap_err();
+        assert!(error.starts_with("Worker process died"), "{}", error);
+        // A new child answers the next document
+        assert_eq!(renderer.render(&task("again")).unwrap().code, "<p>ok</p>");
+    }
+}
@@ -0,0 +1,673 @@
+//! Work-stealing task queues shared by the pool and its workers
+//!
+//! Submitted jobs go into lanes, one per batch plus one shared by tasks
+//! submitted on their own. Workers serve the lanes round-robin, taking as
+//! many jobs in a row from a lane as its priority weighs, so a batch
+//! submitted behind a long one starts right away instead of after it.
+//! Each worker takes jobs from its own deque, refilling it from the lanes
+//! a few at a time and stealing from other workers when both run dry. Idle
+//! workers sleep until a submission wakes one of them.
+//!
+//! The queues also track how many workers run. Deques of workers that
+//! aren't running are parked; the pool starts a worker on one when the
+//! backlog grows, and workers idle for long enough stop and park theirs
+//! again, down to a minimum. The pool can be resized while it runs:
+//! growing adds parked deques, and shrinking makes workers past the new
+//! size stop between tasks, their deques staying open to thieves.
+//!
+//! How long tasks wait here and then take to run is recorded for the
+//! pool's stats, so hosts can tell a saturated pool from a slow one.
+//!
+//! While tasks are quick and more are queued, workers hold back batch
+//! results and send a few at a time, so a caller isn't woken per result.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use crossbeam_deque::{Steal, Stealer, Worker as Deque};
+use parking_lot::{Condvar, Mutex, RwLock};
+use tokio::sync::oneshot;
+
+use crate::metrics::MethodStats;
+use crate::parallel::pool::SubmitError;
+use crate::parallel::task::{TaskResult, TransformTask};
+
+/// Longest an idle worker sleeps before looking for work again; bounds the
+/// cost of a missed wakeup
+const IDLE_WAIT: Duration = Duration::from_millis(10);
+/// Longest a submission waits before re-checking a full queue
+const FULL_WAIT: Duration = Duration::from_millis(5);
+/// Expected wait for the backlog to clear above which another worker starts
+const GROW_WAIT: Duration = Duration::from_millis(20);
+/// Most jobs a worker moves from the lanes to its deque at once
+const REFILL: usize = 4;
+/// Lane of tasks submitted on their own
+pub const SHARED_LANE: u64 = 0;
+/// Tasks quicker than this on average have their results grouped
+const QUICK_TASK: Duration = Duration::from_millis(1);
+/// Most results a worker holds back before sending them
+const RESULT_GROUP: usize = 16;
+/// Longest a worker holds back results while it has more tasks
+const RESULT_DELAY: Duration = Duration::from_millis(2);
+
+/// Results of a batch's tasks, tagged with each task's index
+pub type Results = Vec<(usize, TaskResult)>;
+
+/// Where a job's result goes
+#[derive(Debug)]
+pub enum Reply {
+    /// A channel shared by the tasks of a batch
+    Batch(Sender<Results>),
+    /// The future awaiting this one task
+    Async(oneshot::Sender<TaskResult>),
+}
+
+impl Reply {
+    /// Hand back the result of task `index`; false if nobody is waiting
+    pub fn send(self, index: usize, result: TaskResult) -> bool {
+        match self {
+            Reply::Batch(sender) => sender.send(vec![(index, result)]).is_ok(),
+            Reply::Async(sender) => sender.send(result).is_ok(),
+        }
+    }
+}
+
+/// Batch results a worker holds back to send together; whatever is held
+/// is sent when it's dropped
+#[derive(Default)]
+pub struct Outgoing {
+    held: Option<(Sender<Results>, Results, Instant)>,
+}
+
+impl Outgoing {
+    /// Hand back the result of task `index`, holding it back with others
+    /// of its batch if `hold`; false if nobody is waiting for it as far
+    /// as is known yet
+    pub fn send(&mut self, reply: Reply, index: usize, result: TaskResult, hold: bool) -> bool {
+        let sender = match reply {
+            Reply::Batch(sender) if hold => sender,
+            reply => return reply.send(index, result),
+        };
+        if !self.held.as_ref().is_some_and(|(held, ..)| held.same_channel(&sender)) {
+            self.flush();
+        }
+        let (_, results, since) = self.held.get_or_insert_with(|| (sender, Vec::with_capacity(RESULT_GROUP), Instant::now()));
+        results.push((index, result));
+        if results.len() >= RESULT_GROUP || since.elapsed() >= RESULT_DELAY {
+            return self.flush();
+        }
+        true
+    }
+
+    /// Send the results held back before starting a task that would delay
+    /// them further: once they've waited [`RESULT_DELAY`], or when the task
+    /// replies elsewhere, so its result won't join them. False if nobody is
+    /// waiting for them.
+    pub fn before_task(&mut self, next: &Reply) -> bool {
+        let due = self.held.as_ref().is_some_and(|(held, _, since)| {
+            since.elapsed() >= RESULT_DELAY || !matches!(next, Reply::Batch(next) if next.same_channel(held))
+        });
+        if due {
+            self.flush()
+        } else {
+            true
+        }
+    }
+
+    /// Send the results held back; false if nobody is waiting for them
+    pub fn flush(&mut self) -> bool {
+        match self.held.take() {
+            Some((sender, results, _)) => sender.send(results).is_ok(),
+            None => true,
+        }
+    }
+}
+
+impl Drop for Outgoing {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// A task and where its result goes
+#[derive(Debug)]
+pub struct Job {
+    pub task: TransformTask,
+    pub reply: Reply,
+    /// Set to the worker and time the task started at, for deadlines
+    pub started: Arc<OnceLock<(usize, Instant)>>,
+    pub queued: Instant,
+    /// Lane the job waits in, [`SHARED_LANE`] unless it's part of a batch
+    pub lane: u64,
+}
+
+impl Job {
+    pub fn new(task: TransformTask, reply: Reply) -> Self {
+        Job {
+            task,
+            reply,
+            started: Arc::default(),
+            queued: Instant::now(),
+            lane: SHARED_LANE,
+        }
+    }
+
+    pub fn in_lane(mut self, lane: u64) -> Self {
+        self.lane = lane;
+        self
+    }
+}
+
+/// Jobs of one batch, in submission order
+struct Lane {
+    id: u64,
+    /// Jobs taken in a row before the next lane's turn: one more than the
+    /// highest priority among its tasks
+    weight: u32,
+    jobs: VecDeque<Job>,
+}
+
+/// Lanes with queued jobs, the one being served in front
+#[derive(Default)]
+struct Lanes {
+    lanes: VecDeque<Lane>,
+    /// Jobs taken from the front lane this turn
+    served: u32,
+}
+
+impl Lanes {
+    fn push(&mut self, job: Job) {
+        let weight = job.task.priority.saturating_add(1);
+        match self.lanes.iter_mut().find(|lane| lane.id == job.lane) {
+            Some(lane) => {
+                lane.weight = lane.weight.max(weight);
+                lane.jobs.push_back(job);
+            }
+            None => self.lanes.push_back(Lane {
+                id: job.lane,
+                weight,
+                jobs: VecDeque::from([job]),
+            }),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Job> {
+        let lane = self.lanes.front_mut()?;
+        let job = lane.jobs.pop_front();
+        self.served += 1;
+        if lane.jobs.is_empty() {
+            self.lanes.pop_front();
+            self.served = 0;
+        } else if self.served >= lane.weight {
+            self.lanes.rotate_left(1);
+            self.served = 0;
+        }
+        job
+    }
+}
+
+/// How long tasks waited for a worker and then ran
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    pub queued: MethodStats,
+    /// Failed tasks count as errors
+    pub running: MethodStats,
+}
+
+pub struct Queues {
+    lanes: Mutex<Lanes>,
+    /// Id of the next batch lane
+    next_lane: AtomicU64,
+    /// One per deque, indexed by worker id; grows with the pool
+    stealers: RwLock<Vec<Stealer<Job>>>,
+    /// Jobs submitted but not yet started by a worker
+    pending: AtomicUsize,
+    /// Most jobs pending at once since the last reset
+    max_pending: AtomicUsize,
+    /// Most jobs that may be pending at once; `usize::MAX` if unbounded
+    capacity: AtomicUsize,
+    shutdown: AtomicBool,
+    sleep: Mutex<()>,
+    work_ready: Condvar,
+    space_ready: Condvar,
+    /// Workers running, between `min_workers` and `max_workers` unless
+    /// some are still stopping after the pool shrank
+    active: AtomicUsize,
+    min_workers: AtomicUsize,
+    /// Workers with an id below this may run
+    max_workers: AtomicUsize,
+    /// How long a worker stays idle before it stops
+    idle_timeout: Duration,
+    /// Deques without a running worker, with their worker ids
+    parked: Mutex<Vec<(usize, Deque<Job>)>>,
+    /// Moving average of recent task durations in microseconds; 0 until
+    /// the first task finishes
+    recent_us: AtomicU64,
+    timings: Mutex<Timings>,
+}
+
+impl Queues {
+    /// Queues for workers owning the deques behind `stealers`, all running
+    pub fn new(stealers: Vec<Stealer<Job>>, capacity: Option<usize>) -> Self {
+        Queues {
+            lanes: Mutex::default(),
+            next_lane: AtomicU64::new(SHARED_LANE + 1),
+            active: AtomicUsize::new(stealers.len()),
+            min_workers: AtomicUsize::new(stealers.len()),
+            max_workers: AtomicUsize::new(stealers.len()),
+            stealers: RwLock::new(stealers),
+            pending: AtomicUsize::new(0),
+            max_pending: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(capacity.unwrap_or(usize::MAX)),
+            shutdown: AtomicBool::new(false),
+            sleep: Mutex::new(()),
+            work_ready: Condvar::new(),
+            space_ready: Condvar::new(),
+            idle_timeout: Duration::MAX,
+            parked: Mutex::new(Vec::new()),
+            recent_us: AtomicU64::new(0),
+            timings: Mutex::default(),
+        }
+    }
+
+    /// Start with the workers of `parked` stopped, and let idle workers stop
+    /// after `idle_timeout` while more than `min_workers` run
+    pub fn with_scaling(mut self, min_workers: usize, idle_timeout: Duration, parked: Vec<(usize, Deque<Job>)>) -> Self {
+        self.active = AtomicUsize::new(self.stealers.get_mut().len() - parked.len());
+        self.min_workers = AtomicUsize::new(min_workers);
+        self.idle_timeout = idle_timeout;
+        self.parked = Mutex::new(parked);
+        self
+    }
+
+    /// Submit a job, waiting up to `timeout` for room if the queue is full
+    pub fn push(&self, job: Job, timeout: Duration) -> Result<(), SubmitError> {
+        if self.shutdown.load(Ordering::Acquire) {
+            return Err(SubmitError::Closed);
+        }
+        let pending = if self.capacity().is_some() {
+            let deadline = Instant::now() + timeout;
+            loop {
+                // The bound may change while waiting
+                let capacity = self.capacity.load(Ordering::Acquire);
+                match self
+                    .pending
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < capacity).then_some(n + 1))
+                {
+                    Ok(previous) => break previous + 1,
+                    Err(_) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return Err(SubmitError::Busy);
+                        }
+                        let mut guard = self.sleep.lock();
+                        self.space_ready.wait_for(&mut guard, FULL_WAIT.min(deadline - now));
+                    }
+                }
+            }
+        } else {
+            self.pending.fetch_add(1, Ordering::AcqRel) + 1
+        };
+        self.max_pending.fetch_max(pending, Ordering::Relaxed);
+        self.lanes.lock().push(job);
+        self.work_ready.notify_one();
+        Ok(())
+    }
+
+    /// A lane for the jobs of a new batch
+    pub fn new_lane(&self) -> u64 {
+        self.next_lane.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The next job for the worker owning `local`, if any is queued anywhere
+    pub fn next_job(&self, local: &Deque<Job>) -> Option<Job> {
+        let job = local.pop().or_else(|| self.refill(local)).or_else(|| {
+            let stealers = self.stealers.read();
+            std::iter::repeat_with(|| stealers.iter().map(Stealer::steal).collect::<Steal<Job>>())
+                .find(|steal| !steal.is_retry())
+                .and_then(Steal::success)
+        })?;
+        self.pending.fetch_sub(1, Ordering::AcqRel);
+        if self.capacity().is_some() {
+            self.space_ready.notify_one();
+        }
+        Some(job)
+    }
+
+    /// Take the next job from the lanes, moving a few more to `local`
+    fn refill(&self, local: &Deque<Job>) -> Option<Job> {
+        let mut lanes = self.lanes.lock();
+        let job = lanes.pop()?;
+        for _ in 1..REFILL {
+            match lanes.pop() {
+                Some(next) => local.push(next),
+                None => break,
+            }
+        }
+        Some(job)
+    }
+
+    /// Jobs submitted but not yet started
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    /// Sleep until a job may be available
+    pub fn wait_for_work(&self) {
+        let mut guard = self.sleep.lock();
+        if self.pending() == 0 && !self.is_shut_down() {
+            self.work_ready.wait_for(&mut guard, IDLE_WAIT);
+        }
+    }
+
+    /// Refuse new jobs and let workers exit once the queues are drained
+    pub fn shut_down(&self) {
+        self.shutdown.store(true, Ordering::Release);
+        let _guard = self.sleep.lock();
+        self.work_ready.notify_all();
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+
+    /// Workers currently running
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    /// Most workers that may run at once
+    pub fn max_workers(&self) -> usize {
+        self.max_workers.load(Ordering::Acquire)
+    }
+
+    pub fn min_workers(&self) -> usize {
+        self.min_workers.load(Ordering::Acquire)
+    }
+
+    /// Most jobs that may be pending at once, if bounded
+    pub fn capacity(&self) -> Option<usize> {
+        Some(self.capacity.load(Ordering::Acquire)).filter(|&capacity| capacity != usize::MAX)
+    }
+
+    /// Let up to `max_workers` workers run, keeping at least `min_workers`
+    /// running once started. New deques are parked for the pool to start
+    /// workers on; workers past the new size stop after their current task.
+    pub fn resize(&self, max_workers: usize, min_workers: usize) {
+        {
+            let mut stealers = self.stealers.write();
+            let mut parked = self.parked.lock();
+            for id in stealers.len()..max_workers {
+                let local = Deque::new_fifo();
+                stealers.push(local.stealer());
+                parked.push((id, local));
+            }
+            // Lowest ids are unparked first
+            parked.sort_by_key(|(id, _)| std::cmp::Reverse(*id));
+            self.max_workers.store(max_workers, Ordering::Release);
+            self.min_workers.store(min_workers.min(max_workers), Ordering::Release);
+        }
+        let _guard = self.sleep.lock();
+        self.work_ready.notify_all();
+    }
+
+    /// Bound the jobs pending at once, or lift the bound with `None`. Jobs
+    /// already queued past a lower bound still run.
+    pub fn set_capacity(&self, capacity: Option<usize>) {
+        self.capacity.store(capacity.unwrap_or(usize::MAX), Ordering::Release);
+        let _guard = self.sleep.lock();
+        self.space_ready.notify_all();
+    }
+
+    /// Most jobs pending at once since the last [`reset_timings`](Self::reset_timings)
+    pub fn max_pending(&self) -> usize {
+        self.max_pending.load(Ordering::Relaxed)
+    }
+
+    /// Record a finished task that waited `queued` for a worker and then
+    /// ran for `running`
+    pub fn record_task(&self, queued: Duration, running: Duration, ok: bool) {
+        self.record_duration(running);
+        let mut timings = self.timings.lock();
+        timings.queued.record(queued, true);
+        timings.running.record(running, ok);
+    }
+
+    pub fn timings(&self) -> Timings {
+        self.timings.lock().clone()
+    }
+
+    /// Forget recorded timings; the queue high-water mark restarts from
+    /// the current depth
+    pub fn reset_timings(&self) {
+        *self.timings.lock() = Timings::default();
+        self.max_pending.store(self.pending(), Ordering::Relaxed);
+    }
+
+    /// Fold a finished task's duration into the recent average
+    pub fn record_duration(&self, duration: Duration) {
+        let sample = (duration.as_micros() as u64).max(1);
+        let _ = self.recent_us.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |recent| {
+            Some(if recent == 0 { sample } else { (recent * 3 + sample) / 4 })
+        });
+    }
+
+    /// Whether a worker should hold back results: tasks have been quick
+    /// lately and more are queued, so it will be back with more shortly
+    pub fn hold_results(&self) -> bool {
+        let recent = self.recent_us.load(Ordering::Relaxed);
+        recent != 0 && recent < QUICK_TASK.as_micros() as u64 && self.pending() > 0
+    }
+
+    /// Whether the backlog calls for another worker: more tasks wait than
+    /// workers run, and at recent speeds (if known yet) they would take a
+    /// while to clear
+    pub fn wants_worker(&self) -> bool {
+        if self.is_shut_down() || self.parked.lock().is_empty() {
+            return false;
+        }
+        let (pending, active) = (self.pending(), self.active());
+        if pending <= active || active >= self.max_workers() {
+            return false;
+        }
+        if active == 0 {
+            return true;
+        }
+        let recent = self.recent_us.load(Ordering::Relaxed);
+        recent == 0 || Duration::from_micros(recent.saturating_mul(pending as u64) / active as u64) >= GROW_WAIT
+    }
+
+    /// A parked deque and its worker id, counted as running from now on.
+    /// Deques of workers past the pool's size stay parked.
+    pub fn unpark(&self) -> Option<(usize, Deque<Job>)> {
+        let mut parked = self.parked.lock();
+        if parked.last().is_none_or(|(id, _)| *id >= self.max_workers()) {
+            return None;
+        }
+        self.active.fetch_add(1, Ordering::AcqRel);
+        parked.pop()
+    }
+
+    /// Let an idle worker stop, unless that would leave fewer than the
+    /// minimum running
+    pub fn try_retire(&self) -> bool {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n > self.min_workers()).then(|| n - 1))
+            .is_ok()
+    }
+
+    /// Let worker `id` stop if the pool has shrunk below it, unless it is
+    /// the last one running while jobs are queued
+    pub fn try_shed(&self, id: usize) -> bool {
+        if id < self.max_workers() {
+            return false;
+        }
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n > 1 || self.pending() == 0).then(|| n - 1))
+            .is_ok()
+    }
+
+    /// Park the deque of a worker that stopped. If every worker has
+    /// stopped but a job came in meanwhile, the deque is handed back
+    /// instead, counted as running again, as nobody else would take the job.
+    pub fn park(&self, id: usize, local: Deque<Job>) -> Option<Deque<Job>> {
+        // Submissions look for parked deques after counting their job, so
+        // one of the two sees the other
+        let mut parked = self.parked.lock();
+        if self.active() == 0 && self.pending() > 0 {
+            self.active.fetch_add(1, Ordering::AcqRel);
+            return Some(local);
+        }
+        // Kept in descending id order, so the lowest ids are unparked first
+        let at = parked.partition_point(|(other, _)| *other > id);
+        parked.insert(at, (id, local));
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn job(id: &str) -> (Job, crossbeam_channel::Receiver<Results>) {
+        let (reply, result) = crossbeam_channel::unbounded();
+        let task = TransformTask::new(id.to_string(), PathBuf::from("test.md"), String::new());
+        (Job::new(task, Reply::Batch(reply)), result)
+    }
+
+    #[test]
+    fn test_workers_steal_from_each_other() {
+        let (first, second) = (Deque::new_fifo(), Deque::new_fifo());
+        let queues = Queues::new(vec![first.stealer(), second.stealer()], None);
+        for i in 0..4 {
+            queues.push(job(&i.to_string()).0, Duration::ZERO).unwrap();
+        }
+        assert_eq!(queues.pending(), 4);
+
+        // The first worker refills its deque with a few jobs from the lanes
+        assert_eq!(queues.next_job(&first).unwrap().task.id, "0");
+        assert!(!first.is_empty());
+        // The second takes what's left, then steals from the first
+        let mut ids = Vec::new();
+        while let Some(job) = queues.next_job(&second) {
+            ids.push(job.task.id);
+        }
+        assert_eq!(ids.len(), 3);
+        assert_eq!(queues.pending(), 0);
+        assert!(queues.next_job(&first).is_none());
+    }
+
+    #[test]
+    fn test_batches_take_turns() {
+        let local = Deque::new_fifo();
+        let queues = Queues::new(vec![local.stealer()], None);
+        let (prime, build) = (queues.new_lane(), queues.new_lane());
+        for i in 0..6 {
+            queues.push(job(&format!("prime-{}", i)).0.in_lane(prime), Duration::ZERO).unwrap();
+        }
+        for i in 0..2 {
+            let (mut job, _) = job(&format!("build-{}", i));
+            job.task.priority = 1;
+            queues.push(job.in_lane(build), Duration::ZERO).unwrap();
+        }
+        queues.push(job("single").0, Duration::ZERO).unwrap();
+
+        let mut order = Vec::new();
+        while let Some(job) = queues.next_job(&local) {
+            order.push(job.task.id);
+        }
+        // The build weighs twice as much as the prime and runs alongside it
+        assert_eq!(
+            order,
+            vec!["prime-0", "build-0", "build-1", "single", "prime-1", "prime-2", "prime-3", "prime-4", "prime-5"]
+        );
+    }
+
+    #[test]
+    fn test_scaling() {
+        let (running, parked) = (Deque::new_fifo(), Deque::new_fifo());
+        let queues = Queues::new(vec![running.stealer(), parked.stealer()], None).with_scaling(
+            1,
+            Duration::ZERO,
+            vec![(1, parked)],
+        );
+        assert_eq!(queues.active(), 1);
+        queues.push(job("a").0, Duration::ZERO).unwrap();
+        assert!(!queues.wants_worker());
+        queues.push(job("b").0, Duration::ZERO).unwrap();
+        assert!(queues.wants_worker());
+
+        // Once tasks are known to be quick, a short backlog isn't worth a worker
+        queues.record_duration(Duration::from_micros(100));
+        assert!(!queues.wants_worker());
+
+        let (id, local) = queues.unpark().unwrap();
+        assert_eq!((id, queues.active()), (1, 2));
+        assert!(queues.try_retire());
+        assert!(!queues.try_retire());
+        assert!(queues.park(id, local).is_none());
+        assert_eq!(queues.active(), 1);
+    }
+
+    #[test]
+    fn test_results_are_grouped() {
+        let result = |id: &str| TaskResult::Failure {
+            id: id.to_string(),
+            error: String::new(),
+            kind: crate::parallel::task::FailureKind::Cancelled,
+            worker_id: None,
+        };
+        let (first, first_results) = crossbeam_channel::unbounded();
+        let (second, second_results) = crossbeam_channel::unbounded();
+        let mut outgoing = Outgoing::default();
+        assert!(outgoing.send(Reply::Batch(first.clone()), 0, result("a"), true));
+        assert!(outgoing.send(Reply::Batch(first.clone()), 1, result("b"), true));
+        assert!(first_results.is_empty());
+        // Another batch's result sends what was held for the first
+        assert!(outgoing.send(Reply::Batch(second), 0, result("c"), true));
+        let indexes: Vec<usize> = first_results.try_recv().unwrap().into_iter().map(|(index, _)| index).collect();
+        assert_eq!(indexes, vec![0, 1]);
+        // Results not held back go out alone, and dropping sends the rest
+        assert!(outgoing.send(Reply::Batch(first.clone()), 2, result("d"), false));
+        assert_eq!(first_results.try_recv().unwrap().len(), 1);
+        drop(outgoing);
+        assert_eq!(second_results.try_recv().unwrap()[0].1.id(), "c");
+
+        // Held results don't wait on a task of another batch, nor for long
+        let (third, third_results) = crossbeam_channel::unbounded();
+        let mut outgoing = Outgoing::default();
+        assert!(outgoing.send(Reply::Batch(first.clone()), 3, result("e"), true));
+        assert!(outgoing.before_task(&Reply::Batch(first.clone())));
+        assert!(first_results.is_empty());
+        assert!(outgoing.before_task(&Reply::Batch(third.clone())));
+        assert_eq!(first_results.try_recv().unwrap()[0].0, 3);
+        assert!(outgoing.send(Reply::Batch(third.clone()), 0, result("f"), true));
+        std::thread::sleep(RESULT_DELAY);
+        assert!(outgoing.before_task(&Reply::Batch(third)));
+        assert_eq!(third_results.try_recv().unwrap()[0].0, 0);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let local = Deque::new_fifo();
+        let queues = Queues::new(vec![local.stealer()], Some(1));
+        queues.push(job("a").0, Duration::ZERO).unwrap();
+        let started = Instant::now();
+        assert_eq!(queues.push(job("b").0, Duration::from_millis(20)), Err(SubmitError::Busy));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        queues.next_job(&local).unwrap();
+        queues.push(job("b").0, Duration::ZERO).unwrap();
+        assert_eq!(queues.max_pending(), 1);
+        queues.shut_down();
+        assert_eq!(queues.push(job("c").0, Duration::ZERO), Err(SubmitError::Closed));
+    }
+}
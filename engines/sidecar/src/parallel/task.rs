@@ -1,6 +1,14 @@
-use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::path::PathBuf;
 
+use crate::cache::Entry;
+use crate::cancel::CancelToken;
+use crate::diagnostics::TransformError;
+use crate::transform::{render_document, TransformOptions};
+
+/// Content per chunk under [`Chunking::Bytes`] by default
+pub const DEFAULT_CHUNK_BYTES: usize = 256 * 1024;
+
 /// A task to be processed by a worker thread
 #[derive(Debug, Clone)]
 pub struct TransformTask {
@@ -8,19 +16,22 @@ pub struct TransformTask {
     pub id: String,
     /// File path being processed
     pub file: PathBuf,
-    /// Content to transform
+    /// Content to transform: the document's body, below its frontmatter
     pub content: String,
-    /// Processing options
-    pub options: TaskOptions,
+    /// The whole document, when it has frontmatter above `content`
+    pub source: Option<String>,
+    /// Resolved frontmatter, for modules that export it
+    pub frontmatter: Option<Value>,
+    /// Fully resolved options, as the request thread would render with
+    pub options: TransformOptions,
     /// Priority (higher = more important)
     pub priority: u32,
-}
-
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct TaskOptions {
-    pub mode: Option<String>,
-    pub sourcemap: Option<bool>,
-    pub framework: Option<String>,
+    /// Position in its batch, handed back with the result
+    pub index: usize,
+    /// Checked before the task starts and at safe points while it runs
+    pub cancel: Option<CancelToken>,
+    /// Span of the request that created it, continued on the worker
+    pub span: tracing::Span,
 }
 
 /// Result of a transformation task
@@ -28,49 +39,75 @@ pub struct TaskOptions {
 pub enum TaskResult {
     Success {
         id: String,
-        code: String,
-        map: Option<serde_json::Value>,
-        metadata: Option<serde_json::Value>,
+        /// The rendered document, as the disk cache would store it
+        entry: Entry,
         duration_ms: u64,
+        /// Worker that produced the result; `None` outside the pool
+        worker_id: Option<usize>,
     },
     Failure {
         id: String,
         error: String,
-        recoverable: bool,
+        kind: FailureKind,
+        worker_id: Option<usize>,
     },
 }
 
+/// Why a task failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The transform itself failed or panicked
+    Error,
+    /// The task ran past its deadline and was abandoned
+    Timeout,
+    /// The task was cancelled before it started or at a safe point
+    Cancelled,
+    /// The input or rendered output was over the configured size limit
+    TooLarge,
+}
+
 impl TransformTask {
     pub fn new(id: String, file: PathBuf, content: String) -> Self {
         Self {
             id,
             file,
             content,
-            options: TaskOptions::default(),
+            source: None,
+            frontmatter: None,
+            options: TransformOptions::default(),
             priority: 0,
+            index: 0,
+            cancel: None,
+            span: tracing::Span::current(),
         }
     }
 
-    pub fn with_options(mut self, options: TaskOptions) -> Self {
+    pub fn with_options(mut self, options: TransformOptions) -> Self {
         self.options = options;
         self
     }
 
-    pub fn with_priority(mut self, priority: u32) -> Self {
-        self.priority = priority;
+    /// Render `content` as the body of `source`, below `frontmatter`
+    pub fn with_document(mut self, source: String, frontmatter: Option<Value>) -> Self {
+        self.source = Some(source);
+        self.frontmatter = frontmatter;
         self
     }
 
-    /// Estimate task size for load balancing
-    pub fn estimated_cost(&self) -> usize {
-        // Base cost on content size and complexity
-        let size_cost = self.content.len();
-        let complexity_multiplier = if self.content.contains("```") {
-            2 // Code blocks are more expensive
-        } else {
-            1
-        };
-        size_cost * complexity_multiplier
+    /// Render the task as a request thread would render its document
+    pub fn render(&self) -> Result<Entry, TransformError> {
+        let file = self.file.to_string_lossy();
+        let source = self.source.as_deref().unwrap_or(&self.content);
+        render_document(&file, source, &self.content, &self.options, self.frontmatter.as_ref())
+    }
+
+    pub fn with_cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
     }
 }
 
@@ -82,12 +119,71 @@ impl TaskResult {
         }
     }
 
+    pub fn worker_id(&self) -> Option<usize> {
+        match self {
+            TaskResult::Success { worker_id, .. } => *worker_id,
+            TaskResult::Failure { worker_id, .. } => *worker_id,
+        }
+    }
+
     pub fn is_success(&self) -> bool {
         matches!(self, TaskResult::Success { .. })
     }
+}
+
+/// How a batch is cut into chunks, the units it is queued in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunking {
+    /// At most this many tasks per chunk
+    Count(usize),
+    /// Tasks with up to this many bytes of content per chunk; a larger
+    /// task makes up a chunk of its own
+    Bytes(usize),
+}
 
-    pub fn is_failure(&self) -> bool {
-        matches!(self, TaskResult::Failure { .. })
+impl Default for Chunking {
+    fn default() -> Self {
+        Chunking::Bytes(DEFAULT_CHUNK_BYTES)
+    }
+}
+
+impl Chunking {
+    /// The same strategy with `n` times the budget per chunk
+    pub fn times(self, n: usize) -> Self {
+        match self {
+            Chunking::Count(count) => Chunking::Count(count.saturating_mul(n)),
+            Chunking::Bytes(bytes) => Chunking::Bytes(bytes.saturating_mul(n)),
+        }
+    }
+
+    /// Cut `tasks` into consecutive chunks, none of them empty
+    pub fn chunk(self, tasks: Vec<TransformTask>) -> Vec<Vec<TransformTask>> {
+        self.chunk_by(tasks, |task| task.content.len())
+    }
+
+    /// Cut `items` of `size` bytes each into consecutive chunks, none of
+    /// them empty
+    pub fn chunk_by<T>(self, items: Vec<T>, size: impl Fn(&T) -> usize) -> Vec<Vec<T>> {
+        let mut chunks = Vec::new();
+        let mut chunk: Vec<T> = Vec::new();
+        let mut bytes = 0;
+        for item in items {
+            let item_bytes = size(&item);
+            let full = match self {
+                Chunking::Count(count) => chunk.len() >= count.max(1),
+                Chunking::Bytes(budget) => bytes + item_bytes > budget,
+            };
+            if full && !chunk.is_empty() {
+                chunks.push(std::mem::take(&mut chunk));
+                bytes = 0;
+            }
+            bytes += item_bytes;
+            chunk.push(item);
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        chunks
     }
 }
 
@@ -96,32 +192,37 @@ impl TaskResult {
 pub struct TaskBatch {
     pub id: String,
     pub tasks: Vec<TransformTask>,
-    pub total_cost: usize,
+    /// Return results in task order rather than as they complete
+    pub preserve_order: bool,
+    /// Cancels every task of the batch; also fired when the batch is aborted
+    pub cancel: CancelToken,
 }
 
 impl TaskBatch {
-    pub fn new(id: String, tasks: Vec<TransformTask>) -> Self {
-        let total_cost = tasks.iter().map(|t| t.estimated_cost()).sum();
+    pub fn new(id: String, mut tasks: Vec<TransformTask>) -> Self {
+        for (index, task) in tasks.iter_mut().enumerate() {
+            task.index = index;
+        }
         Self {
             id,
             tasks,
-            total_cost,
+            preserve_order: true,
+            cancel: CancelToken::default(),
         }
     }
 
-    /// Split batch into smaller chunks for parallel processing
-    pub fn split(self, num_chunks: usize) -> Vec<Vec<TransformTask>> {
-        if num_chunks <= 1 || self.tasks.len() <= num_chunks {
-            return vec![self.tasks];
-        }
+    /// Cancel the batch's tasks along with `token`
+    pub fn with_cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
 
-        let chunk_size = (self.tasks.len() + num_chunks - 1) / num_chunks;
-        self.tasks
-            .into_iter()
-            .collect::<Vec<_>>()
-            .chunks(chunk_size)
-            .map(|chunk| chunk.to_vec())
-            .collect()
+    /// Return results as they complete, for callers that match them up
+    /// themselves
+    #[cfg(test)]
+    pub fn in_completion_order(mut self) -> Self {
+        self.preserve_order = false;
+        self
     }
 }
 
@@ -141,40 +242,25 @@ mod tests {
     }
 
     #[test]
-    fn test_task_cost_estimation() {
-        let simple = TransformTask::new(
-            "1".to_string(),
-            PathBuf::from("simple.md"),
-            "Hello world".to_string(),
-        );
-        assert_eq!(simple.estimated_cost(), 11);
+    fn test_chunking() {
+        let tasks = |sizes: &[usize]| -> Vec<TransformTask> {
+            sizes
+                .iter()
+                .enumerate()
+                .map(|(i, size)| TransformTask::new(i.to_string(), PathBuf::from("test.md"), "x".repeat(*size)))
+                .collect()
+        };
+        let ids = |chunks: Vec<Vec<TransformTask>>| -> Vec<Vec<String>> {
+            chunks.into_iter().map(|chunk| chunk.into_iter().map(|task| task.id).collect()).collect()
+        };
 
-        let complex = TransformTask::new(
-            "2".to_string(),
-            PathBuf::from("complex.md"),
-            "```rust\ncode\n```".to_string(),
+        assert_eq!(ids(Chunking::Count(2).chunk(tasks(&[1, 1, 1000, 1, 1]))), vec![vec!["0", "1"], vec!["2", "3"], vec!["4"]]);
+        // Small documents share a chunk; a large one gets one to itself
+        assert_eq!(
+            ids(Chunking::Bytes(100).chunk(tasks(&[40, 40, 40, 500, 10, 10]))),
+            vec![vec!["0", "1"], vec!["2"], vec!["3"], vec!["4", "5"]]
         );
-        assert_eq!(complex.estimated_cost(), 34); // 17 * 2
+        assert_eq!(Chunking::Bytes(100).times(4), Chunking::Bytes(400));
+        assert!(Chunking::default().chunk(Vec::new()).is_empty());
     }
-
-    #[test]
-    fn test_batch_splitting() {
-        let tasks: Vec<TransformTask> = (0..10)
-            .map(|i| {
-                TransformTask::new(
-                    format!("task-{}", i),
-                    PathBuf::from(format!("file-{}.md", i)),
-                    format!("Content {}", i),
-                )
-            })
-            .collect();
-
-        let batch = TaskBatch::new("batch-1".to_string(), tasks);
-        let chunks = batch.split(3);
-        
-        assert_eq!(chunks.len(), 3);
-        assert_eq!(chunks[0].len(), 4);
-        assert_eq!(chunks[1].len(), 3);
-        assert_eq!(chunks[2].len(), 3);
-    }
-}
\ No newline at end of file
+}
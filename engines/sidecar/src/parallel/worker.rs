@@ -1,116 +1,292 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 use std::thread;
-use crossbeam_channel::{Receiver, Sender};
-use parking_lot::Mutex;
-use crate::parallel::task::{TransformTask, TaskResult};
-use crate::transform::markdown_to_html;
+use crossbeam_deque::Worker as Deque;
+use crate::parallel::affinity;
+use crate::parallel::process::ChildRenderer;
+use crate::parallel::queue::{Job, Outgoing, Queues};
+use crate::parallel::task::{FailureKind, TransformTask, TaskResult};
+use crate::cache::Entry;
+use crate::cancel;
+use crate::diagnostics::ErrorKind;
+use crate::limits::Limits;
+use serde::Serialize;
+use crate::transform;
 use std::time::Instant;
 
-/// Message types for worker communication
-#[derive(Debug)]
-pub enum WorkerMessage {
-    Task(TransformTask),
+/// Stack size of worker threads by default; deeply nested documents
+/// recurse past the 2 MiB Rust gives spawned threads
+pub const DEFAULT_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Called with the worker id and message when a task panics
+pub type PanicHook = Arc<dyn Fn(usize, &str) + Send + Sync>;
+
+/// Where workers render documents
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Isolation {
+    /// On the worker threads themselves
+    #[default]
+    Thread,
+    /// In a child process per worker, so a crash while rendering fails
+    /// one task instead of the sidecar; see [`process`](crate::parallel::process)
+    Process,
+}
+
+/// What renders a worker's documents
+pub enum Engine {
+    Thread,
+    Process(ChildRenderer),
+}
+
+impl Engine {
+    /// An engine for `isolation`; in-thread ones warm up the thread first
+    pub fn new(isolation: Isolation) -> Self {
+        match isolation {
+            Isolation::Thread => {
+                transform::warm_up();
+                Engine::Thread
+            }
+            Isolation::Process => Engine::Process(ChildRenderer::sidecar()),
+        }
+    }
+
+    /// Render `task` as a request thread would. In-thread rendering stops
+    /// at the next safe point once the task is cancelled; a child process
+    /// is only asked after checking once.
+    fn render(&mut self, task: &TransformTask) -> Result<Entry, (String, FailureKind)> {
+        let rendered = match self {
+            Engine::Thread => cancel::scope(task.cancel.clone(), || task.render()),
+            Engine::Process(_) if task.is_cancelled() => return Err(cancelled()),
+            // The child process rendering it died or failed
+            Engine::Process(child) => return child.render(task).map_err(|error| (error, FailureKind::Error)),
+        };
+        rendered.map_err(|error| {
+            let kind = match error.kind {
+                ErrorKind::Cancelled => FailureKind::Cancelled,
+                ErrorKind::Timeout => FailureKind::Timeout,
+                ErrorKind::TooLarge => FailureKind::TooLarge,
+                ErrorKind::Frontmatter | ErrorKind::Render => FailureKind::Error,
+            };
+            (error.message, kind)
+        })
+    }
+}
+
+fn cancelled() -> (String, FailureKind) {
+    ("Task cancelled".to_string(), FailureKind::Cancelled)
+}
+
+/// How worker threads are started
+#[derive(Clone)]
+pub struct WorkerConfig {
+    /// Threads are named `{name_prefix}-{id}`, as profilers show them
+    pub name_prefix: String,
+    pub stack_size: usize,
+    /// Keep each worker on one physical core
+    pub pin: bool,
+    pub panic_hook: Option<PanicHook>,
+    /// Largest input and output a task may have
+    pub limits: Limits,
+    pub isolation: Isolation,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            name_prefix: "fastmd-worker".to_string(),
+            stack_size: DEFAULT_STACK_SIZE,
+            pin: false,
+            panic_hook: None,
+            limits: Limits::default(),
+            isolation: Isolation::default(),
+        }
+    }
+}
+
+/// Why a worker's loop ended
+enum Exit {
     Shutdown,
+    /// Idle for long enough to stop, or past the pool's size
+    Retired,
 }
 
 /// Worker thread that processes transformation tasks
 pub struct Worker {
-    id: usize,
     thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
     /// Create and start a new worker
-    pub fn spawn(
-        id: usize,
-        receiver: Arc<Mutex<Receiver<WorkerMessage>>>,
-        sender: Sender<TaskResult>,
-    ) -> Self {
-        let thread = thread::spawn(move || {
-            Worker::run(id, receiver, sender);
-        });
-
-        Worker {
-            id,
-            thread: Some(thread),
-        }
+    pub fn spawn(id: usize, local: Deque<Job>, queues: Arc<Queues>, config: &WorkerConfig) -> Self {
+        let config = config.clone();
+        let thread = thread::Builder::new()
+            .name(format!("{}-{}", config.name_prefix, id))
+            .stack_size(config.stack_size)
+            .spawn(move || {
+                let mut local = local;
+                if config.pin && !affinity::pin(id) {
+                    tracing::warn!("Worker {} could not be pinned to a core", id);
+                }
+                loop {
+                    // A panic that escapes task isolation restarts the loop,
+                    // so the pool keeps its size
+                    match panic::catch_unwind(AssertUnwindSafe(|| Worker::run(id, &local, &queues, &config))) {
+                        Ok(Exit::Shutdown) => break,
+                        Ok(Exit::Retired) => match queues.park(id, local) {
+                            // Work arrived while stopping and nobody else is running
+                            Some(reclaimed) => local = reclaimed,
+                            None => break,
+                        },
+                        Err(payload) => {
+                            let message = panic_message(&*payload);
+                            tracing::error!("Worker {} panicked: {}; restarting", id, message);
+                            if let Some(hook) = &config.panic_hook {
+                                hook(id, message);
+                            }
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn worker thread");
+
+        Worker { thread: Some(thread) }
     }
 
     /// Worker main loop
-    fn run(
-        id: usize,
-        receiver: Arc<Mutex<Receiver<WorkerMessage>>>,
-        sender: Sender<TaskResult>,
-    ) {
+    fn run(id: usize, local: &Deque<Job>, queues: &Queues, config: &WorkerConfig) -> Exit {
+        let mut engine = Engine::new(config.isolation);
         tracing::debug!("Worker {} started", id);
+        let mut idle_since = None;
+        let mut outgoing = Outgoing::default();
 
-        loop {
-            // Lock receiver only for receiving, not for processing
-            let message = {
-                let rx = receiver.lock();
-                rx.recv()
-            };
-
-            match message {
-                Ok(WorkerMessage::Task(task)) => {
+        let exit = loop {
+            // The pool shrank below this worker
+            if queues.try_shed(id) {
+                tracing::debug!("Worker {} removed, stopping", id);
+                break Exit::Retired;
+            }
+            match queues.next_job(local) {
+                Some(Job { task, reply, started, queued, .. }) => {
+                    idle_since = None;
+                    // Results held back mustn't wait on a slow task
+                    if !outgoing.before_task(&reply) {
+                        tracing::debug!("Worker {} dropped results nobody is waiting for", id);
+                    }
                     let start = Instant::now();
-                    let result = Worker::process_task(task);
-                    let duration_ms = start.elapsed().as_millis() as u64;
-
-                    // Update result with actual duration
-                    let result = match result {
-                        TaskResult::Success { id, code, map, metadata, .. } => {
-                            TaskResult::Success {
-                                id,
-                                code,
-                                map,
-                                metadata,
-                                duration_ms,
-                            }
-                        }
-                        failure => failure,
-                    };
+                    let _ = started.set((id, start));
+                    let index = task.index;
+                    let result = Worker::execute(id, &mut engine, task, config);
+                    queues.record_task(start.saturating_duration_since(queued), start.elapsed(), result.is_success());
 
-                    if let Err(e) = sender.send(result) {
-                        tracing::error!("Worker {} failed to send result: {}", id, e);
-                        break;
+                    // A caller that gave up waiting has dropped its receiver
+                    if !outgoing.send(reply, index, result, queues.hold_results()) {
+                        tracing::debug!("Worker {} dropped a result nobody is waiting for", id);
                     }
                 }
-                Ok(WorkerMessage::Shutdown) => {
+                // Queued work is finished before shutting down
+                None if queues.is_shut_down() => {
                     tracing::debug!("Worker {} shutting down", id);
-                    break;
+                    break Exit::Shutdown;
                 }
-                Err(e) => {
-                    tracing::error!("Worker {} channel error: {}", id, e);
-                    break;
+                None => {
+                    // Nothing more to add to results held back
+                    outgoing.flush();
+                    let idle = *idle_since.get_or_insert_with(Instant::now);
+                    if idle.elapsed() >= queues.idle_timeout() && queues.try_retire() {
+                        tracing::debug!("Worker {} idle, stopping", id);
+                        break Exit::Retired;
+                    }
+                    queues.wait_for_work();
                 }
             }
-        }
+        };
 
         tracing::debug!("Worker {} stopped", id);
+        exit
     }
 
-    /// Process a single transformation task
-    fn process_task(task: TransformTask) -> TaskResult {
-        match markdown_to_html(&task.content) {
-            Ok(html) => TaskResult::Success {
-                id: task.id,
-                code: html,
-                map: None,
-                metadata: None,
-                duration_ms: 0, // Will be updated by caller
-            },
-            Err(e) => TaskResult::Failure {
+    /// Process a task on behalf of worker `worker_id`, isolating panics
+    pub fn execute(worker_id: usize, engine: &mut Engine, task: TransformTask, config: &WorkerConfig) -> TaskResult {
+        let task_id = task.id.clone();
+        // Entered, not just the parent, so the request id shows in log lines
+        // even when the trace-level task span is filtered out
+        let _request = task.span.clone().entered();
+        let _span = tracing::trace_span!("task", worker = worker_id, file = %task.file.display()).entered();
+        Worker::isolate(worker_id, task_id, config.panic_hook.as_ref(), || {
+            Worker::process_task(worker_id, engine, task, &config.limits)
+        })
+    }
+
+    /// Run `process`, turning a panic into a failure of task `task_id`
+    fn isolate(
+        worker_id: usize,
+        task_id: String,
+        panic_hook: Option<&PanicHook>,
+        process: impl FnOnce() -> TaskResult,
+    ) -> TaskResult {
+        panic::catch_unwind(AssertUnwindSafe(process)).unwrap_or_else(|payload| {
+            let message = panic_message(&*payload);
+            tracing::error!("Worker {} panicked on task {}: {}", worker_id, task_id, message);
+            if let Some(hook) = panic_hook {
+                hook(worker_id, message);
+            }
+            TaskResult::Failure {
+                id: task_id,
+                error: format!("Transform panicked: {}", message),
+                kind: FailureKind::Error,
+                worker_id: Some(worker_id),
+            }
+        })
+    }
+
+    /// Process a single transformation task on worker `worker_id`. A
+    /// cancelled task is skipped, or abandoned at the next safe point if it
+    /// is already rendering; one whose input or output is over `limits`
+    /// fails without its output.
+    fn process_task(worker_id: usize, engine: &mut Engine, task: TransformTask, limits: &Limits) -> TaskResult {
+        let start = Instant::now();
+        if let Err(error) = limits.check_input(task.content.len()) {
+            return Worker::too_large(worker_id, task.id, error);
+        }
+        let rendered = if task.is_cancelled() {
+            Err(cancelled())
+        } else {
+            engine.render(&task)
+        };
+        match rendered {
+            Ok(entry) => {
+                if let Err(error) = limits.check_output(entry.code.len()) {
+                    return Worker::too_large(worker_id, task.id, error);
+                }
+                TaskResult::Success {
+                    id: task.id,
+                    entry,
+                    duration_ms: start.elapsed().as_millis() as u64,
+                    worker_id: Some(worker_id),
+                }
+            }
+            Err((error, kind)) => TaskResult::Failure {
                 id: task.id,
-                error: e.to_string(),
-                recoverable: true,
+                error,
+                kind,
+                worker_id: Some(worker_id),
             },
         }
     }
 
-    /// Get worker ID
-    pub fn id(&self) -> usize {
-        self.id
+    fn too_large(worker_id: usize, task_id: String, error: String) -> TaskResult {
+        TaskResult::Failure {
+            id: task_id,
+            error,
+            kind: FailureKind::TooLarge,
+            worker_id: Some(worker_id),
+        }
+    }
+
+    /// Whether the worker's thread has exited
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(thread::JoinHandle::is_finished)
     }
 
     /// Join worker thread
@@ -123,8 +299,18 @@ impl Worker {
     }
 }
 
+/// Message of a panic payload, when it has one
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
 /// Worker pool statistics
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct WorkerStats {
     pub tasks_processed: usize,
     pub total_duration_ms: u64,
@@ -140,29 +326,27 @@ impl WorkerStats {
     pub fn record_failure(&mut self) {
         self.errors += 1;
     }
-
-    pub fn average_duration_ms(&self) -> f64 {
-        if self.tasks_processed == 0 {
-            0.0
-        } else {
-            self.total_duration_ms as f64 / self.tasks_processed as f64
-        }
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parallel::queue::Reply;
     use std::path::PathBuf;
 
     #[test]
     fn test_worker_processes_task() {
-        let (tx, rx) = crossbeam_channel::unbounded();
         let (result_tx, result_rx) = crossbeam_channel::unbounded();
-        let rx = Arc::new(Mutex::new(rx));
+        let local = Deque::new_fifo();
+        let queues = Arc::new(Queues::new(vec![local.stealer()], None));
 
         // Start worker
-        let worker = Worker::spawn(0, rx, result_tx);
+        let config = WorkerConfig {
+            name_prefix: "test-worker".to_string(),
+            ..Default::default()
+        };
+        let worker = Worker::spawn(0, local, Arc::clone(&queues), &config);
+        assert_eq!(worker.thread.as_ref().unwrap().thread().name(), Some("test-worker-0"));
 
         // Send task
         let task = TransformTask::new(
@@ -170,15 +354,16 @@ mod tests {
             PathBuf::from("test.md"),
             "# Hello World".to_string(),
         );
-        tx.send(WorkerMessage::Task(task)).unwrap();
+        queues.push(Job::new(task, Reply::Batch(result_tx)), std::time::Duration::ZERO).unwrap();
 
         // Get result
-        let result = result_rx.recv_timeout(std::time::Duration::from_secs(1)).unwrap();
+        let (_, result) = result_rx.recv_timeout(std::time::Duration::from_secs(1)).unwrap().remove(0);
         assert!(result.is_success());
         assert_eq!(result.id(), "test-1");
+        assert_eq!(result.worker_id(), Some(0));
 
         // Shutdown
-        tx.send(WorkerMessage::Shutdown).unwrap();
+        queues.shut_down();
         worker.join().unwrap();
     }
 
@@ -193,6 +378,87 @@ mod tests {
         assert_eq!(stats.tasks_processed, 2);
         assert_eq!(stats.total_duration_ms, 30);
         assert_eq!(stats.errors, 1);
-        assert_eq!(stats.average_duration_ms(), 15.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_panics_become_failures() {
+        let panics = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let hook: PanicHook = {
+            let panics = Arc::clone(&panics);
+            Arc::new(move |worker, message: &str| panics.lock().push((worker, message.to_string())))
+        };
+        let result = Worker::isolate(3, "boom".to_string(), Some(&hook), || panic!("engine exploded"));
+        assert_eq!(*panics.lock(), vec![(3, "engine exploded".to_string())]);
+        match result {
+            TaskResult::Failure { id, error, worker_id, .. } => {
+                assert_eq!(id, "boom");
+                assert_eq!(error, "Transform panicked: engine exploded");
+                assert_eq!(worker_id, Some(3));
+            }
+            TaskResult::Success { .. } => panic!("expected a failure"),
+        }
+
+        let result = Worker::isolate(0, "fine".to_string(), None, || Worker::process_task(0, &mut Engine::Thread, TransformTask::new(
+            "fine".to_string(),
+            PathBuf::from("test.md"),
+            "# Fine".to_string(),
+        ), &Limits::default()));
+        assert!(result.is_success());
+    }
+
+    #[test]
+    fn test_task_renders_like_a_request() {
+        let source = "---\ntitle: T\n---\n# Hi\n\n<!-- note -->\ntext";
+        let options = crate::transform::TransformOptions {
+            minify: Some(true),
+            named_exports: Some(true),
+            sourcemap: Some(true),
+            ..Default::default()
+        };
+        let frontmatter = serde_json::json!({ "title": "T" });
+        let task = TransformTask::new("doc".to_string(), PathBuf::from("doc.md"), "# Hi\n\n<!-- note -->\ntext".to_string())
+            .with_options(options.clone())
+            .with_document(source.to_string(), Some(frontmatter.clone()));
+        let expected = crate::transform::render_document("doc.md", source, &task.content, &options, Some(&frontmatter)).unwrap();
+        match Worker::process_task(0, &mut Engine::Thread, task, &Limits::default()) {
+            TaskResult::Success { entry, .. } => {
+                assert!(entry.code.contains("export const frontmatter = {\"title\":\"T\"}"), "{}", entry.code);
+                assert!(!entry.code.contains("note"), "{}", entry.code);
+                assert_eq!(entry, expected);
+            }
+            TaskResult::Failure { error, .. } => panic!("{}", error),
+        }
+    }
+
+    #[test]
+    fn test_cancelled_task_is_skipped() {
+        let token = crate::cancel::CancelToken::default();
+        let task = TransformTask::new("queued".to_string(), PathBuf::from("test.md"), "# Queued".to_string())
+            .with_cancel(token.clone());
+        token.cancel();
+        let result = Worker::process_task(1, &mut Engine::Thread, task, &Limits::default());
+        assert!(matches!(result, TaskResult::Failure { kind: FailureKind::Cancelled, worker_id: Some(1), .. }));
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let limits = Limits {
+            max_input_bytes: 64,
+            max_output_bytes: 64,
+        };
+        let task = |content: &str| TransformTask::new("sized".to_string(), PathBuf::from("test.md"), content.to_string());
+        let mut engine = Engine::Thread;
+        let result = Worker::process_task(0, &mut engine, task(&"x".repeat(65)), &limits);
+        assert!(matches!(result, TaskResult::Failure { kind: FailureKind::TooLarge, .. }));
+        // Fits going in, but renders to a module of more than 64 bytes
+        let result = Worker::process_task(0, &mut engine, task("# A longer title"), &limits);
+        match result {
+            TaskResult::Failure { kind, error, .. } => {
+                assert_eq!(kind, FailureKind::TooLarge);
+                assert!(error.starts_with("output is"));
+            }
+            TaskResult::Success { .. } => panic!("expected a failure"),
+        }
+        assert!(Worker::process_task(0, &mut engine, task("hi"), &limits).is_success());
+    }
+}
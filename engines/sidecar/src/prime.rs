@@ -0,0 +1,105 @@
+//! Background cache warming for the `cachePrime` RPC
+//!
+//! After a cold start the first page loads would each pay for a transform.
+//! Priming walks the site on one background thread at reduced OS priority,
+//! so the disk cache fills up without competing with requests the host is
+//! actually waiting on. A `cachePrime/done` notification reports the result.
+//! Watches revalidating changed files use the same machinery, silently.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+use dashmap::DashMap;
+use serde_json::json;
+
+use crate::outbox::Outbox;
+use crate::session;
+
+/// A running prime and the connection that started it
+struct Prime {
+    connection_id: u64,
+    stop: Arc<AtomicBool>,
+}
+
+static PRIMES: OnceLock<DashMap<u64, Prime>> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn primes() -> &'static DashMap<u64, Prime> {
+    PRIMES.get_or_init(DashMap::new)
+}
+
+/// Transform `files` in the background with `transform`, which is expected
+/// to go through the cache. Returns the prime id used in the notification
+/// sent through `outbox`, if one is given.
+pub fn spawn<F>(files: Vec<PathBuf>, outbox: Option<Outbox>, transform: F) -> u64
+where
+    F: Fn(&PathBuf) -> Result<(), String> + Send + 'static,
+{
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let stop = Arc::new(AtomicBool::new(false));
+    let connection_id = session::connection_id();
+    primes().insert(id, Prime { connection_id, stop: stop.clone() });
+    // Transforms resolve options against the requesting connection's session
+    let context = session::current_context();
+    let span = tracing::Span::current();
+
+    thread::spawn(move || {
+        let _span = tracing::info_span!(parent: &span, "prime", prime_id = id).entered();
+        lower_priority();
+        let started = Instant::now();
+        let run = || {
+            let (mut transformed, mut failed) = (0usize, 0usize);
+            for file in &files {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                match transform(file) {
+                    Ok(()) => transformed += 1,
+                    Err(e) => {
+                        tracing::debug!("Priming {} failed: {}", file.display(), e);
+                        failed += 1;
+                    }
+                }
+            }
+            (transformed, failed)
+        };
+        let (transformed, failed) = match &context {
+            Some(context) => context.enter(run),
+            None => run(),
+        };
+        primes().remove(&id);
+        if let Some(outbox) = outbox.filter(|_| !stop.load(Ordering::Relaxed)) {
+            outbox.notify("cachePrime/done", json!({
+                "primeId": id,
+                "transformed": transformed,
+                "failed": failed,
+                "durationMs": started.elapsed().as_millis() as u64,
+            }));
+        }
+    });
+    id
+}
+
+/// Stop the primes a connection started, e.g. when it closes; they hold
+/// outbox clones that would keep its writer alive
+pub fn stop_connection(connection_id: u64) {
+    for prime in primes().iter().filter(|prime| prime.connection_id == connection_id) {
+        prime.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lower_priority() {
+    // On Linux a thread id is a valid target for setpriority
+    let tid = unsafe { libc::gettid() };
+    unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, 10) };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lower_priority() {
+    // Per-thread priorities aren't portable; priming still runs on a
+    // single thread
+}
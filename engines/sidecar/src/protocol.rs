@@ -1,3 +1,5 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -33,11 +35,35 @@ pub struct RpcResponse {
     pub error: Option<RpcError>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum RpcId {
     Number(i64),
     String(String),
+    /// Used when the request id could not be determined (parse errors,
+    /// malformed requests); serialized as JSON `null`
+    Null,
+}
+
+impl RpcId {
+    /// Best-effort id of a message that failed to parse as a request
+    pub fn from_malformed(message: &Value) -> RpcId {
+        message
+            .get("id")
+            .and_then(|id| serde_json::from_value(id.clone()).ok())
+            .unwrap_or(RpcId::Null)
+    }
+}
+
+/// As it appears in logs and spans
+impl fmt::Display for RpcId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcId::Number(n) => write!(f, "{}", n),
+            RpcId::String(s) => write!(f, "{}", s),
+            RpcId::Null => f.write_str("null"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -48,9 +74,12 @@ pub struct RpcError {
     pub data: Option<Value>,
 }
 
+/// Wire protocol version; must match `PROTOCOL_VERSION` in `@fastmd/shared`.
+/// Hosts with a different major version are incompatible.
+pub const PROTOCOL_VERSION: &str = "1.0.0";
+
 // Error codes
 pub const PARSE_ERROR: i32 = -32700;
-#[allow(dead_code)]
 pub const INVALID_REQUEST: i32 = -32600;
 pub const METHOD_NOT_FOUND: i32 = -32601;
 pub const INVALID_PARAMS: i32 = -32602;
@@ -59,10 +88,18 @@ pub const INTERNAL_ERROR: i32 = -32603;
 
 // Custom error codes
 pub const TRANSFORM_ERROR: i32 = -32001;
-#[allow(dead_code)]
 pub const CACHE_ERROR: i32 = -32002;
-#[allow(dead_code)]
 pub const IO_ERROR: i32 = -32003;
+/// The worker pool's queue stayed full; the request may be retried later
+pub const POOL_BUSY: i32 = -32004;
+/// A document took longer than the per-task deadline to transform
+pub const TRANSFORM_TIMEOUT: i32 = -32005;
+/// A document's source or output was over the configured size limit
+pub const TRANSFORM_TOO_LARGE: i32 = -32006;
+/// A requested path resolved outside the configured project root
+pub const PATH_OUTSIDE_ROOT: i32 = -32007;
+/// Same code LSP uses for requests cancelled by the client
+pub const REQUEST_CANCELLED: i32 = -32800;
 
 pub fn create_response(id: RpcId, result: Value) -> RpcResponse {
     RpcResponse {
@@ -86,10 +123,18 @@ pub fn create_error_response(id: RpcId, code: i32, message: String, data: Option
     }
 }
 
+pub fn create_notification(method: &str, params: Option<Value>) -> RpcNotification {
+    RpcNotification {
+        jsonrpc: "2.0".to_string(),
+        method: method.to_string(),
+        params,
+    }
+}
+
 pub fn create_parse_error() -> RpcResponse {
     RpcResponse {
         jsonrpc: "2.0".to_string(),
-        id: RpcId::String("null".to_string()),
+        id: RpcId::Null,
         result: None,
         error: Some(RpcError {
             code: PARSE_ERROR,
@@ -99,6 +144,46 @@ pub fn create_parse_error() -> RpcResponse {
     }
 }
 
+pub fn create_cancelled(id: RpcId) -> RpcResponse {
+    create_error_response(id, REQUEST_CANCELLED, "Request cancelled".to_string(), None)
+}
+
+/// Reply to a message that is valid JSON but not a valid request
+pub fn create_invalid_request(id: RpcId) -> RpcResponse {
+    RpcResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: None,
+        error: Some(RpcError {
+            code: INVALID_REQUEST,
+            message: "Invalid Request".to_string(),
+            data: None,
+        }),
+    }
+}
+
 pub fn create_method_not_found(id: RpcId) -> RpcResponse {
     create_error_response(id, METHOD_NOT_FOUND, "Method not found".to_string(), None)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_null_id() {
+        let response = serde_json::to_value(create_parse_error()).unwrap();
+        assert_eq!(response["id"], Value::Null);
+
+        let id: RpcId = serde_json::from_str("null").unwrap();
+        assert_eq!(id, RpcId::Null);
+    }
+
+    #[test]
+    fn test_id_from_malformed() {
+        let message = serde_json::json!({ "id": 7, "params": [] });
+        assert_eq!(RpcId::from_malformed(&message), RpcId::Number(7));
+        let message = serde_json::json!({ "id": { "nested": true } });
+        assert_eq!(RpcId::from_malformed(&message), RpcId::Null);
+    }
+}
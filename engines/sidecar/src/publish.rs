@@ -0,0 +1,89 @@
+//! Skipping unpublished documents in batch transforms
+//!
+//! Hosts building a site usually exclude drafts and posts dated in the
+//! future. Deciding that next to the frontmatter parser saves transforming
+//! documents only to throw them away, and lets skipped entries be reported
+//! distinctly so they can be left out of routing.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ordering::{lookup, parse_date};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkipPolicy {
+    /// Skip documents whose frontmatter has `draft: true`
+    #[serde(default)]
+    pub drafts: bool,
+    /// Skip documents dated after `now`
+    #[serde(default)]
+    pub future: bool,
+    /// Frontmatter key holding the publish date (dot-separated for nesting)
+    pub date_key: Option<String>,
+    /// Reference time for `future`, in any format frontmatter dates accept
+    /// (default: the current time)
+    pub now: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SkipReason {
+    Draft,
+    Future,
+}
+
+impl SkipPolicy {
+    /// The reference time as Unix seconds
+    pub fn now(&self) -> Result<i64, String> {
+        match &self.now {
+            Some(now) => parse_date(now).ok_or_else(|| format!("Invalid skip.now date '{}'", now)),
+            None => Ok(chrono::Utc::now().timestamp()),
+        }
+    }
+
+    /// Why a document with this frontmatter should be skipped, if it should
+    pub fn skip_reason(&self, frontmatter: Option<&Value>, now: i64) -> Option<SkipReason> {
+        let frontmatter = frontmatter?;
+        if self.drafts && frontmatter.get("draft").and_then(Value::as_bool) == Some(true) {
+            return Some(SkipReason::Draft);
+        }
+        if self.future {
+            let date = lookup(frontmatter, self.date_key.as_deref().unwrap_or("date"))
+                .and_then(Value::as_str)
+                .and_then(parse_date);
+            if date.is_some_and(|date| date > now) {
+                return Some(SkipReason::Future);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_skip_reason() {
+        let policy = SkipPolicy {
+            drafts: true,
+            future: true,
+            now: Some("2024-06-01".to_string()),
+            ..Default::default()
+        };
+        let now = policy.now().unwrap();
+
+        let draft = json!({ "draft": true, "date": "2024-01-01" });
+        let future = json!({ "date": "2024-06-02T09:00:00Z" });
+        let published = json!({ "date": "2024-05-31", "draft": false });
+        assert_eq!(policy.skip_reason(Some(&draft), now), Some(SkipReason::Draft));
+        assert_eq!(policy.skip_reason(Some(&future), now), Some(SkipReason::Future));
+        assert_eq!(policy.skip_reason(Some(&published), now), None);
+        assert_eq!(policy.skip_reason(None, now), None);
+
+        let lenient = SkipPolicy::default();
+        assert_eq!(lenient.skip_reason(Some(&draft), now), None);
+    }
+}
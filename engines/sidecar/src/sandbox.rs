@@ -0,0 +1,94 @@
+//! Confining file reads to a project root
+//!
+//! A sidecar driven by third-party plugin config shouldn't read whatever
+//! files that config names. With `--project-root` set, paths the sidecar
+//! reads, walks or writes itself (`transformPath` and `hashFile` paths,
+//! glob roots and the files globs and links lead to, cache archives,
+//! `transformGlob` output and cascaded defaults files) must resolve inside
+//! the root, or the request fails with
+//! [`PATH_OUTSIDE_ROOT`](crate::protocol::PATH_OUTSIDE_ROOT).
+//! Paths are resolved through symlinks, so a link inside the root pointing
+//! out of it is refused too. The root is only taken from the command line,
+//! never from a request.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::canonical::resolve;
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// A path that resolved outside the project root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutsideRoot {
+    pub path: PathBuf,
+}
+
+impl fmt::Display for OutsideRoot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is outside the project root", self.path.display())
+    }
+}
+
+/// Confine reads to `root` for the rest of the process. The root must
+/// exist; it can only be set once.
+pub fn set_root(root: &Path) -> io::Result<()> {
+    let root = root.canonicalize()?;
+    ROOT.set(root).map_err(|_| io::Error::other("project root is already set"))
+}
+
+pub fn root() -> Option<&'static Path> {
+    ROOT.get().map(PathBuf::as_path)
+}
+
+/// Refuse `path` if it resolves outside the project root; anything goes
+/// when no root is set
+pub fn check(path: &Path) -> Result<(), OutsideRoot> {
+    match root() {
+        Some(root) => check_in(root, path),
+        None => Ok(()),
+    }
+}
+
+/// Refuse `path` if it resolves outside `root`
+pub fn check_in(root: &Path, path: &Path) -> Result<(), OutsideRoot> {
+    if resolve(path).starts_with(root) {
+        Ok(())
+    } else {
+        Err(OutsideRoot { path: path.to_path_buf() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_check_in() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("site/docs")).unwrap();
+        let root = dir.path().join("site").canonicalize().unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        fs::write(root.join("docs/intro.md"), "# Intro").unwrap();
+
+        assert!(check_in(&root, &root.join("docs/intro.md")).is_ok());
+        assert!(check_in(&root, &root).is_ok());
+        // Files that don't exist yet are checked by where they would be
+        assert!(check_in(&root, &root.join("docs/new/page.md")).is_ok());
+        assert!(check_in(&root, &root.join("docs/../../secret.txt")).is_err());
+        assert!(check_in(&root, &root.join("missing/../../secret.txt")).is_err());
+        assert!(check_in(&root, &dir.path().join("secret.txt")).is_err());
+        // A sibling directory sharing the root's name as a prefix is outside
+        assert!(check_in(&root, &dir.path().join("site-other/page.md")).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("secret.txt"), root.join("docs/link.md")).unwrap();
+            let error = check_in(&root, &root.join("docs/link.md")).unwrap_err();
+            assert_eq!(error.path, root.join("docs/link.md"));
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Glob expansion over the file system
+//!
+//! Patterns are matched against paths relative to a root directory. Patterns
+//! prefixed with `!` exclude matches. Walking starts at the literal prefix of
+//! each pattern so `content/**/*.md` never visits `node_modules`.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::digest::{self, HashAlgorithm};
+use crate::sandbox;
+
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Honor `.gitignore`, `.ignore` and git excludes
+    pub respect_ignore: bool,
+    /// Include dot-files and dot-directories
+    pub hidden: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            respect_ignore: true,
+            hidden: false,
+        }
+    }
+}
+
+/// Include patterns and `!`-prefixed exclude patterns compiled for matching
+/// paths relative to a root
+#[derive(Debug, Clone)]
+pub struct Patterns {
+    includes: GlobSet,
+    excludes: GlobSet,
+    /// Literal prefixes of the include patterns, where walking starts
+    bases: BTreeSet<PathBuf>,
+}
+
+impl Patterns {
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        let mut includes = GlobSetBuilder::new();
+        let mut excludes = GlobSetBuilder::new();
+        let mut bases = BTreeSet::new();
+        
+        for pattern in patterns {
+            if let Some(negated) = pattern.strip_prefix('!') {
+                excludes.add(compile(negated)?);
+            } else {
+                includes.add(compile(pattern)?);
+                bases.insert(literal_prefix(pattern));
+            }
+        }
+        
+        Ok(Patterns {
+            includes: includes.build().map_err(|e| e.to_string())?,
+            excludes: excludes.build().map_err(|e| e.to_string())?,
+            bases,
+        })
+    }
+
+    pub fn is_match(&self, relative: &Path) -> bool {
+        self.includes.is_match(relative) && !self.excludes.is_match(relative)
+    }
+}
+
+/// Expand glob patterns relative to `root` into a sorted list of files
+pub fn expand(patterns: &[String], root: &Path, options: &ScanOptions) -> Result<Vec<PathBuf>, String> {
+    let patterns = Patterns::new(patterns)?;
+    
+    let mut files = BTreeSet::new();
+    for base in prune_nested(patterns.bases.clone()) {
+        let start = root.join(&base);
+        if start.is_file() {
+            collect_if_matching(&start, root, &patterns, &mut files);
+            continue;
+        }
+        if !start.is_dir() {
+            continue;
+        }
+        
+        let walker = WalkBuilder::new(&start)
+            .hidden(!options.hidden)
+            .git_ignore(options.respect_ignore)
+            .git_global(options.respect_ignore)
+            .git_exclude(options.respect_ignore)
+            .ignore(options.respect_ignore)
+            .parents(options.respect_ignore)
+            .require_git(false)
+            .build();
+        
+        for entry in walker {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    tracing::debug!("Skipping unreadable entry: {}", e);
+                    continue;
+                }
+            };
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                collect_if_matching(entry.path(), root, &patterns, &mut files);
+            }
+        }
+    }
+    
+    Ok(files.into_iter().collect())
+}
+
+/// Size and modification time of a file, as used for digests
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStat {
+    pub path: String,
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub mtime: u64,
+    /// SHA-256 of the contents, when requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl FileStat {
+    /// Stat `path`, recording it relative to `root` with `/` separators
+    pub fn read(path: &Path, root: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        Ok(FileStat {
+            path: relative.to_string_lossy().replace('\\', "/"),
+            size: metadata.len(),
+            mtime,
+            hash: None,
+        })
+    }
+
+    /// Stat `path` and hash its contents
+    pub fn read_hashed(path: &Path, root: &Path) -> io::Result<Self> {
+        let mut stat = Self::read(path, root)?;
+        stat.hash = Some(digest::hash_file(path, HashAlgorithm::Sha256)?.hash);
+        Ok(stat)
+    }
+}
+
+/// Expand glob patterns and stat every match in parallel, optionally hashing
+/// contents. Files that disappear between the walk and the stat are skipped.
+pub fn stat_matching(patterns: &[String], root: &Path, options: &ScanOptions, hash: bool) -> Result<Vec<FileStat>, String> {
+    let files = expand(patterns, root, options)?;
+    let read = if hash { FileStat::read_hashed } else { FileStat::read };
+    Ok(files
+        .par_iter()
+        .filter_map(|path| match read(path, root) {
+            Ok(stat) => Some(stat),
+            Err(e) => {
+                tracing::debug!("Skipping {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect())
+}
+
+fn compile(pattern: &str) -> Result<Glob, String> {
+    Glob::new(pattern.trim_start_matches("./")).map_err(|e| format!("Invalid glob '{}': {}", pattern, e))
+}
+
+fn collect_if_matching(path: &Path, root: &Path, patterns: &Patterns, files: &mut BTreeSet<PathBuf>) {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    if patterns.is_match(relative) && sandbox::check(path).is_ok() {
+        files.insert(path.to_path_buf());
+    }
+}
+
+/// The leading path components of a pattern that contain no glob syntax
+fn literal_prefix(pattern: &str) -> PathBuf {
+    let mut prefix = PathBuf::new();
+    for part in pattern.trim_start_matches("./").split('/') {
+        if part.contains(['*', '?', '[', ']', '{', '}']) {
+            break;
+        }
+        prefix.push(part);
+    }
+    prefix
+}
+
+/// Drop walk roots contained in another root so no directory is walked twice
+fn prune_nested(bases: BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let mut pruned: Vec<PathBuf> = Vec::new();
+    for base in bases {
+        if !pruned.iter().any(|p| base.starts_with(p)) {
+            pruned.push(base);
+        }
+    }
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn touch(root: &Path, rel: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "x").unwrap();
+    }
+
+    fn relative(files: Vec<PathBuf>, root: &Path) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_with_negation() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "content/a.md");
+        touch(dir.path(), "content/nested/b.mdx");
+        touch(dir.path(), "content/drafts/c.md");
+        touch(dir.path(), "other/d.md");
+        
+        let patterns = vec!["content/**/*.{md,mdx}".to_string(), "!content/drafts/**".to_string()];
+        let files = expand(&patterns, dir.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(relative(files, dir.path()), vec!["content/a.md", "content/nested/b.mdx"]);
+    }
+
+    #[test]
+    fn test_expand_respects_gitignore() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "docs/keep.md");
+        touch(dir.path(), "docs/generated/skip.md");
+        fs::write(dir.path().join(".gitignore"), "generated/\n").unwrap();
+        
+        let patterns = vec!["**/*.md".to_string()];
+        let files = expand(&patterns, dir.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(relative(files, dir.path()), vec!["docs/keep.md"]);
+        
+        let all = ScanOptions { respect_ignore: false, hidden: false };
+        assert_eq!(expand(&patterns, dir.path(), &all).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_stat_matching() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "content/a.md");
+        touch(dir.path(), "content/b.txt");
+        
+        let patterns = vec!["content/*.md".to_string()];
+        let stats = stat_matching(&patterns, dir.path(), &ScanOptions::default(), false).unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].path, "content/a.md");
+        assert_eq!(stats[0].size, 1);
+        assert!(stats[0].mtime > 0);
+        assert_eq!(stats[0].hash, None);
+        
+        let hashed = stat_matching(&patterns, dir.path(), &ScanOptions::default(), true).unwrap();
+        // sha256("x")
+        assert_eq!(
+            hashed[0].hash.as_deref(),
+            Some("2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881")
+        );
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(literal_prefix("content/blog/**/*.md"), PathBuf::from("content/blog"));
+        assert_eq!(literal_prefix("*.md"), PathBuf::new());
+        assert_eq!(literal_prefix("./docs/a.md"), PathBuf::from("docs/a.md"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Frontmatter validation against a JSON Schema
+//!
+//! The schema is supplied once through `configure` and checked either on
+//! demand (`validateFrontmatter`) or on every transform that opts in, so
+//! content collections fail before build instead of at runtime.
+
+use jsonschema::Validator;
+use serde_json::{Map, Value};
+
+use crate::diagnostics::Diagnostic;
+
+#[derive(Debug)]
+pub struct FrontmatterSchema {
+    schema: Value,
+    validator: Validator,
+}
+
+impl FrontmatterSchema {
+    pub fn compile(schema: Value) -> Result<Self, String> {
+        let validator = jsonschema::validator_for(&schema).map_err(|e| format!("Invalid frontmatter schema: {}", e))?;
+        Ok(FrontmatterSchema { schema, validator })
+    }
+
+    pub fn schema(&self) -> &Value {
+        &self.schema
+    }
+
+    /// Check a document's frontmatter; a missing block validates as `{}`.
+    /// Diagnostics point at the offending top-level key in `source`.
+    pub fn validate(&self, frontmatter: Option<&Value>, source: &str) -> Vec<Diagnostic> {
+        let empty = Value::Object(Map::new());
+        let instance = frontmatter.unwrap_or(&empty);
+        self.validator
+            .iter_errors(instance)
+            .map(|error| {
+                let path = error.instance_path.to_string();
+                let mut diagnostic = Diagnostic::new("frontmatter-schema", error.to_string()).with_path(&path);
+                diagnostic.line = Some(key_line(source, &path).unwrap_or(1));
+                diagnostic.column = Some(1);
+                diagnostic
+            })
+            .collect()
+    }
+}
+
+/// 1-based line of the top-level key a JSON pointer starts with
+fn key_line(source: &str, pointer: &str) -> Option<usize> {
+    let key = pointer.trim_start_matches('/').split('/').next().filter(|k| !k.is_empty())?;
+    let key = key.replace("~1", "/").replace("~0", "~");
+    source
+        .lines()
+        .enumerate()
+        .skip(1)
+        .take_while(|(_, line)| line.trim_end() != "---")
+        .find(|(_, line)| {
+            let unquoted = line.trim_start_matches(['"', '\'']);
+            line.len() == line.trim_start().len()
+                && unquoted.strip_prefix(key.as_str()).is_some_and(|rest| {
+                    rest.trim_start_matches(['"', '\'']).starts_with(':')
+                })
+        })
+        .map(|(index, _)| index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_reports_paths_and_lines() {
+        let schema = FrontmatterSchema::compile(json!({
+            "type": "object",
+            "required": ["title"],
+            "properties": {
+                "title": { "type": "string" },
+                "tags": { "type": "array", "items": { "type": "string" } }
+            }
+        }))
+        .unwrap();
+        let source = "---\ndraft: true\ntags:\n  - a\n  - 3\n---\n\nBody\n";
+        let frontmatter = json!({ "draft": true, "tags": ["a", 3] });
+
+        let mut diagnostics = schema.validate(Some(&frontmatter), source);
+        diagnostics.sort_by_key(|d| d.line);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].path.as_deref(), Some(""));
+        assert_eq!(diagnostics[0].line, Some(1));
+        assert_eq!(diagnostics[1].path.as_deref(), Some("/tags/1"));
+        assert_eq!(diagnostics[1].line, Some(3));
+
+        assert_eq!(schema.validate(None, "Body").len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        assert!(FrontmatterSchema::compile(json!({ "type": 12 })).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Search index generation for `transformGlob`
+//!
+//! Sites usually build their search index in a second pass over every
+//! document in JS. A glob transform can emit one instead, from the
+//! documents it transformed: title, URL, headings and plain-text body for
+//! each, in one of a few shapes:
+//!
+//! - `native`: documents plus an inverted index of lowercased terms to
+//!   the word positions they occur at in each document's body
+//! - `lunr`: documents to feed to a `lunr` index builder, with its
+//!   `ref` and field list
+//! - `pagefind`: records for Pagefind's `addCustomRecord`
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::output::Heading;
+use crate::transform::{assign_heading_ids, collect_headings, extract_frontmatter, parser_options};
+
+/// Format version of the `native` shape
+const NATIVE_VERSION: u32 = 1;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SearchFormat {
+    #[default]
+    Native,
+    Lunr,
+    Pagefind,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchIndexOptions {
+    #[serde(default)]
+    pub format: SearchFormat,
+    /// Write the index to this file instead of returning it
+    pub path: Option<String>,
+    /// Prefix of document URLs, which otherwise mirror paths under the glob root
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+}
+
+fn default_base_url() -> String {
+    "/".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchDocument {
+    pub id: usize,
+    pub title: String,
+    pub url: String,
+    pub headings: Vec<Heading>,
+    pub body: String,
+}
+
+/// Collects transformed documents into a search index
+pub struct SearchIndexBuilder {
+    options: SearchIndexOptions,
+    root: PathBuf,
+    documents: Vec<SearchDocument>,
+}
+
+impl SearchIndexBuilder {
+    pub fn new(options: SearchIndexOptions, root: &Path) -> Self {
+        SearchIndexBuilder {
+            options,
+            root: root.to_path_buf(),
+            documents: Vec::new(),
+        }
+    }
+
+    /// Add the document at `path` with source `source`
+    pub fn add(&mut self, path: &Path, source: &str) {
+        let (frontmatter, content) = extract_frontmatter(source);
+        let events = assign_heading_ids(Parser::new_ext(&content, parser_options(true)).collect());
+        let headings = collect_headings(&events);
+        let field = |key: &str| {
+            frontmatter
+                .as_ref()
+                .and_then(|frontmatter| frontmatter.get(key))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        let title = field("title")
+            .or_else(|| headings.first().map(|heading| heading.text.clone()))
+            .or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let url = field("permalink").unwrap_or_else(|| self.url_of(path));
+        self.documents.push(SearchDocument {
+            id: self.documents.len(),
+            title,
+            url,
+            headings,
+            body: plain_text(&events),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// File the index should be written to, if any
+    pub fn path(&self) -> Option<&str> {
+        self.options.path.as_deref()
+    }
+
+    /// URL of a document: its path under the root without the extension,
+    /// with `index` documents standing for their directory
+    fn url_of(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path).with_extension("");
+        let mut segments: Vec<String> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .filter(|segment| segment != "." && segment != "/")
+            .collect();
+        let directory = segments.last().is_some_and(|last| last == "index");
+        if directory {
+            segments.pop();
+        }
+        let mut url = format!("{}/{}", self.options.base_url.trim_end_matches('/'), segments.join("/"));
+        if directory && !url.ends_with('/') {
+            url.push('/');
+        }
+        url
+    }
+
+    /// The index in the requested shape
+    pub fn finish(self) -> Value {
+        match self.options.format {
+            SearchFormat::Native => json!({
+                "version": NATIVE_VERSION,
+                "documents": self.documents,
+                "terms": term_positions(&self.documents),
+            }),
+            SearchFormat::Lunr => {
+                let documents: Vec<Value> = self
+                    .documents
+                    .iter()
+                    .map(|document| {
+                        let headings: Vec<&str> = document.headings.iter().map(|heading| heading.text.as_str()).collect();
+                        json!({
+                            "url": document.url,
+                            "title": document.title,
+                            "headings": headings.join("\n"),
+                            "body": document.body,
+                        })
+                    })
+                    .collect();
+                json!({ "ref": "url", "fields": ["title", "headings", "body"], "documents": documents })
+            }
+            SearchFormat::Pagefind => {
+                let records: Vec<Value> = self
+                    .documents
+                    .iter()
+                    .map(|document| {
+                        json!({
+                            "url": document.url,
+                            "content": document.body,
+                            "language": "en",
+                            "meta": { "title": document.title },
+                        })
+                    })
+                    .collect();
+                json!(records)
+            }
+        }
+    }
+}
+
+/// Term -> document id -> word positions in its body
+fn term_positions(documents: &[SearchDocument]) -> BTreeMap<String, BTreeMap<usize, Vec<usize>>> {
+    let mut terms: BTreeMap<String, BTreeMap<usize, Vec<usize>>> = BTreeMap::new();
+    for document in documents {
+        for (position, term) in tokenize(&document.body).enumerate() {
+            terms.entry(term).or_default().entry(document.id).or_default().push(position);
+        }
+    }
+    terms
+}
+
+/// Lowercased words, split at anything that isn't alphanumeric
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Readable text of a document, without markup or code blocks. Words
+/// split by inline markup stay whole; blocks are separated by spaces.
+pub fn plain_text(events: &[Event<'_>]) -> String {
+    let mut text = String::new();
+    let mut in_code = false;
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(TagEnd::CodeBlock) => in_code = false,
+            Event::Text(t) | Event::Code(t) if !in_code => text.push_str(t),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(end) if !matches!(end, TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link | TagEnd::Image) => {
+                text.push(' ');
+            }
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder(format: SearchFormat) -> SearchIndexBuilder {
+        let options = SearchIndexOptions {
+            format,
+            path: None,
+            base_url: "/docs/".to_string(),
+        };
+        let mut builder = SearchIndexBuilder::new(options, Path::new("/site"));
+        builder.add(Path::new("/site/guide/index.md"), "# Guide\n\nRead the *guide*, then the guide again.\n");
+        builder.add(
+            Path::new("/site/guide/setup.mdx"),
+            "---\ntitle: Setup\n---\n## Install\n\nRun `npm i`.\n\n```sh\nnot indexed\n```\n",
+        );
+        builder
+    }
+
+    #[test]
+    fn test_documents() {
+        let builder = builder(SearchFormat::Native);
+        assert_eq!(builder.len(), 2);
+        let guide = &builder.documents[0];
+        assert_eq!((guide.title.as_str(), guide.url.as_str()), ("Guide", "/docs/guide/"));
+        assert_eq!(guide.body, "Guide Read the guide, then the guide again.");
+        let setup = &builder.documents[1];
+        assert_eq!((setup.title.as_str(), setup.url.as_str()), ("Setup", "/docs/guide/setup"));
+        assert_eq!(setup.headings[0].slug, "install");
+        assert_eq!(setup.body, "Install Run npm i.");
+    }
+
+    #[test]
+    fn test_formats() {
+        let native = builder(SearchFormat::Native).finish();
+        assert_eq!(native["terms"]["guide"], json!({ "0": [0, 3, 6] }));
+        assert_eq!(native["terms"]["npm"], json!({ "1": [2] }));
+
+        let lunr = builder(SearchFormat::Lunr).finish();
+        assert_eq!(lunr["ref"], "url");
+        assert_eq!(lunr["documents"][1]["headings"], "Install");
+
+        let pagefind = builder(SearchFormat::Pagefind).finish();
+        assert_eq!(pagefind[1]["meta"]["title"], "Setup");
+        assert_eq!(pagefind[0]["url"], "/docs/guide/");
+    }
+}
@@ -0,0 +1,200 @@
+//! Session-wide configuration set through the `configure` RPC
+//!
+//! Hosts configure defaults once instead of re-sending the same options blob
+//! with every transform. Per-request options always take precedence.
+//! Under every session's own configuration are the defaults and rules of
+//! the project config file, which can be swapped while sessions live.
+//!
+//! Every client connection has its own session: handlers find it through
+//! the [`Context`] entered by the connection for the current thread. Work
+//! done outside a connection (e.g. HTTP requests) uses a shared default.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use serde_json::Value;
+
+use crate::diagnostics::Diagnostic;
+use crate::schema::FrontmatterSchema;
+use crate::transform::TransformOptions;
+
+/// How a warning rule is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleLevel {
+    Off,
+    Warn,
+}
+
+/// Defaults and rules from the project config file
+#[derive(Debug, Clone, Default)]
+pub struct Project {
+    pub defaults: TransformOptions,
+    pub rules: BTreeMap<String, RuleLevel>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// Options applied to every transform unless the request overrides them
+    pub defaults: TransformOptions,
+    /// Warning code -> level; codes not listed are reported
+    pub rules: BTreeMap<String, RuleLevel>,
+    /// Schema frontmatter is validated against
+    pub frontmatter_schema: Option<Arc<FrontmatterSchema>>,
+}
+
+impl Session {
+    /// Fill in any options the request left unset from the session
+    /// defaults, then from the project's
+    pub fn resolve(&self, options: &TransformOptions) -> TransformOptions {
+        options.clone().or(&self.effective_defaults())
+    }
+
+    /// Session defaults over the project's
+    pub fn effective_defaults(&self) -> TransformOptions {
+        self.defaults.clone().or(&project().defaults)
+    }
+
+    /// Session rules over the project's
+    pub fn effective_rules(&self) -> BTreeMap<String, RuleLevel> {
+        let mut rules = project().rules.clone();
+        rules.extend(self.rules.iter().map(|(code, level)| (code.clone(), *level)));
+        rules
+    }
+
+    /// Schema violations, if the options ask for validation and a schema
+    /// is configured
+    pub fn schema_warnings(&self, options: &TransformOptions, frontmatter: Option<&Value>, source: &str) -> Vec<Diagnostic> {
+        match &self.frontmatter_schema {
+            Some(schema) if options.validate_frontmatter() => schema.validate(frontmatter, source),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Drop warnings whose rule is turned off
+    pub fn filter_warnings(&self, warnings: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        let rules = self.effective_rules();
+        warnings
+            .into_iter()
+            .filter(|warning| rules.get(warning.code.as_ref()) != Some(&RuleLevel::Off))
+            .collect()
+    }
+}
+
+pub type SharedSession = Arc<RwLock<Session>>;
+
+/// Identity and state of one client connection
+#[derive(Debug, Clone)]
+pub struct Context {
+    /// Unique per connection; scopes request ids for cancellation
+    pub connection_id: u64,
+    pub session: SharedSession,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Context {
+            connection_id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            session: SharedSession::default(),
+        }
+    }
+
+    /// Run `f` with this context as the current one on this thread
+    pub fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let result = f();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+        result
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+static DEFAULT_SESSION: OnceLock<SharedSession> = OnceLock::new();
+static PROJECT: RwLock<Option<Arc<Project>>> = RwLock::new(None);
+
+/// Replace the project configuration under every session
+pub fn set_project(project: Project) {
+    *PROJECT.write() = Some(Arc::new(project));
+}
+
+/// The project configuration under every session
+pub fn project() -> Arc<Project> {
+    PROJECT.read().clone().unwrap_or_default()
+}
+
+/// Session of the connection being served on this thread
+pub fn current() -> SharedSession {
+    CURRENT
+        .with(|current| current.borrow().as_ref().map(|context| context.session.clone()))
+        .unwrap_or_else(|| DEFAULT_SESSION.get_or_init(SharedSession::default).clone())
+}
+
+/// Context of the connection being served on this thread, for handing
+/// work to background threads
+pub fn current_context() -> Option<Context> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Connection being served on this thread; 0 outside any connection
+pub fn connection_id() -> u64 {
+    CURRENT.with(|current| current.borrow().as_ref().map_or(0, |context| context.connection_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_options_override_defaults() {
+        let session = Session {
+            defaults: TransformOptions {
+                minify: Some(true),
+                base_url: Some("/docs".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let request = TransformOptions {
+            minify: Some(false),
+            ..Default::default()
+        };
+
+        let resolved = session.resolve(&request);
+        assert_eq!(resolved.minify, Some(false));
+        assert_eq!(resolved.base_url.as_deref(), Some("/docs"));
+    }
+
+    #[test]
+    fn test_connections_are_isolated() {
+        let first = Context::new();
+        let second = Context::new();
+        first.enter(|| current().write().defaults.minify = Some(true));
+        
+        assert_eq!(first.enter(|| current().read().defaults.minify), Some(true));
+        assert_eq!(second.enter(|| current().read().defaults.minify), None);
+        assert_ne!(first.enter(connection_id), second.enter(connection_id));
+        assert_eq!(connection_id(), 0);
+    }
+
+    #[test]
+    fn test_rules_filter_warnings() {
+        let mut session = Session::default();
+        session.rules.insert("empty-alt-text".to_string(), RuleLevel::Off);
+        let warnings = vec![
+            Diagnostic::new("empty-alt-text", "a"),
+            Diagnostic::new("unresolved-link", "b"),
+        ];
+        let kept = session.filter_warnings(warnings);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].code, "unresolved-link");
+    }
+}
@@ -0,0 +1,108 @@
+//! Reading source documents from disk
+//!
+//! Lets the sidecar load files itself instead of receiving their contents
+//! JSON-escaped over stdin. Enforces a size limit and decodes the encodings
+//! editors commonly produce (UTF-8 with or without BOM, UTF-16 with BOM).
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::sandbox::{self, OutsideRoot};
+
+/// Default upper bound for a single source file
+pub const DEFAULT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    TooLarge { size: u64, limit: u64 },
+    InvalidEncoding(String),
+    OutsideRoot(OutsideRoot),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "{}", e),
+            ReadError::TooLarge { size, limit } => {
+                write!(f, "file is {} bytes, exceeding the {} byte limit", size, limit)
+            }
+            ReadError::InvalidEncoding(msg) => write!(f, "invalid encoding: {}", msg),
+            ReadError::OutsideRoot(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// Read a source file as text, refusing files larger than `max_bytes` and
+/// files outside the project root
+pub fn read_source(path: &Path, max_bytes: u64) -> Result<String, ReadError> {
+    sandbox::check(path).map_err(ReadError::OutsideRoot)?;
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if size > max_bytes {
+        return Err(ReadError::TooLarge { size, limit: max_bytes });
+    }
+    
+    // The file may grow between stat and read; never read past the limit
+    let mut bytes = Vec::with_capacity(size as usize);
+    file.take(max_bytes + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > max_bytes {
+        return Err(ReadError::TooLarge { size: bytes.len() as u64, limit: max_bytes });
+    }
+    
+    decode(bytes)
+}
+
+/// Decode raw bytes, honoring (and stripping) a byte order mark
+pub fn decode(bytes: Vec<u8>) -> Result<String, ReadError> {
+    match bytes.as_slice() {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => {
+            String::from_utf8(rest.to_vec()).map_err(|e| ReadError::InvalidEncoding(e.to_string()))
+        }
+        [0xFF, 0xFE, rest @ ..] => decode_utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => decode_utf16(rest, u16::from_be_bytes),
+        _ => String::from_utf8(bytes).map_err(|e| ReadError::InvalidEncoding(e.to_string())),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> Result<String, ReadError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(ReadError::InvalidEncoding("odd number of bytes in UTF-16 input".to_string()));
+    }
+    let units = bytes.chunks_exact(2).map(|pair| to_unit([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| ReadError::InvalidEncoding(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_encodings() {
+        assert_eq!(decode(b"# Hi".to_vec()).unwrap(), "# Hi");
+        assert_eq!(decode(b"\xEF\xBB\xBF# Hi".to_vec()).unwrap(), "# Hi");
+        assert_eq!(decode(vec![0xFF, 0xFE, b'#', 0, b' ', 0, b'H', 0]).unwrap(), "# H");
+        assert_eq!(decode(vec![0xFE, 0xFF, 0, b'#']).unwrap(), "#");
+        assert!(matches!(decode(vec![0xC3, 0x28]), Err(ReadError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.md");
+        std::fs::write(&path, "x".repeat(100)).unwrap();
+        
+        assert_eq!(read_source(&path, 100).unwrap().len(), 100);
+        assert!(matches!(read_source(&path, 99), Err(ReadError::TooLarge { size: 100, limit: 99 })));
+    }
+}
@@ -0,0 +1,114 @@
+//! Source map generation
+//!
+//! Markdown is compiled into a JS module whose lines do not correspond 1:1
+//! to source lines, so we emit a line-level Source Map v3: every generated
+//! line maps to the start line of the markdown block it was rendered from.
+
+use serde_json::{json, Value};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Pairs of (generated line, source line), both 0-based, in generated order
+pub type LineMap = Vec<(usize, usize)>;
+
+/// Build a Source Map v3 object for a module generated from `file`.
+///
+/// `source_line_offset` is added to every source line, accounting for lines
+/// (such as frontmatter) stripped before the body was transformed. Generated
+/// lines without an explicit entry inherit the previous entry's source line.
+pub fn build(file: &str, source: &str, line_map: &[(usize, usize)], source_line_offset: usize) -> Value {
+    let mut mappings = String::new();
+    let mut previous_source_line: i64 = 0;
+    let mut current: Option<usize> = None;
+    let mut entries = line_map.iter().peekable();
+    let last_line = line_map.last().map(|(g, _)| *g).unwrap_or(0);
+    
+    for generated_line in 0..=last_line {
+        if generated_line > 0 {
+            mappings.push(';');
+        }
+        while let Some((_, source_line)) = entries.next_if(|(g, _)| *g <= generated_line) {
+            current = Some(source_line + source_line_offset);
+        }
+        if let Some(source_line) = current {
+            // [generated column, source index, source line, source column]
+            encode_vlq(0, &mut mappings);
+            encode_vlq(0, &mut mappings);
+            encode_vlq(source_line as i64 - previous_source_line, &mut mappings);
+            encode_vlq(0, &mut mappings);
+            previous_source_line = source_line as i64;
+        }
+    }
+    
+    json!({
+        "version": 3,
+        "file": file,
+        "sources": [file],
+        "sourcesContent": [source],
+        "names": [],
+        "mappings": mappings,
+    })
+}
+
+/// Base64 VLQ encoding as used by source maps
+pub fn encode_vlq(value: i64, out: &mut String) {
+    let mut vlq = if value < 0 { ((-value) << 1) | 1 } else { value << 1 } as u64;
+    loop {
+        let mut digit = (vlq & 0b11111) as usize;
+        vlq >>= 5;
+        if vlq > 0 {
+            digit |= 0b100000;
+        }
+        out.push(BASE64[digit] as char);
+        if vlq == 0 {
+            break;
+        }
+    }
+}
+
+/// 0-based line number of a byte offset
+pub fn line_of(source: &str, offset: usize) -> usize {
+    source.as_bytes()[..offset.min(source.len())]
+        .iter()
+        .filter(|b| **b == b'\n')
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vlq(value: i64) -> String {
+        let mut s = String::new();
+        encode_vlq(value, &mut s);
+        s
+    }
+
+    #[test]
+    fn test_encode_vlq() {
+        assert_eq!(vlq(0), "A");
+        assert_eq!(vlq(1), "C");
+        assert_eq!(vlq(-1), "D");
+        assert_eq!(vlq(15), "e");
+        assert_eq!(vlq(16), "gB");
+        assert_eq!(vlq(-17), "jB");
+    }
+
+    #[test]
+    fn test_build_mappings() {
+        let map = build("a.md", "# A\n\ntext\n", &[(1, 0), (3, 2)], 3);
+        // Line 0 unmapped, line 1 -> source 3, line 2 inherits, line 3 -> source 5
+        assert_eq!(map["mappings"], ";AAGA;AAAA;AAEA");
+        assert_eq!(map["sources"][0], "a.md");
+        
+        let map = build("a.md", "", &[(0, 0), (1, 0)], 0);
+        assert_eq!(map["mappings"], "AAAA;AAAA");
+    }
+
+    #[test]
+    fn test_line_of() {
+        assert_eq!(line_of("a\nb\nc", 0), 0);
+        assert_eq!(line_of("a\nb\nc", 2), 1);
+        assert_eq!(line_of("a\nb\nc", 100), 2);
+    }
+}
@@ -0,0 +1,180 @@
+//! Content-addressable transform store shared across projects
+//!
+//! The per-project cache under `--cache-dir` keys entries by file path, so
+//! two checkouts of a site, or packages of a monorepo that share documents,
+//! each render everything once. The shared store keys results by content
+//! options and file name alone ([`crate::cache::content_key`]) and lives in
+//! one place for every sidecar on the machine (`--shared-cache-dir`, by
+//! default under the user cache directory).
+//!
+//! Rendered output still mentions where the document lives: the generated
+//! header, source maps and resolved dependencies. The file name is part of
+//! the key, so objects are stored with only the document's directory
+//! replaced by a placeholder and get the requesting document's directory
+//! back on a hit. Documents whose output
+//! can't be moved that way (the directory appears in the content, or a
+//! dependency lies outside it) skip the store and are rendered as usual.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde_json::Value;
+
+use crate::cache::{DiskCache, Entry, GcPolicy};
+use crate::utils::normalize_path;
+
+/// Subdirectory of the store root holding objects
+const OBJECTS_DIR: &str = "objects";
+/// Stands in for the document's directory in stored objects
+const DIR_PLACEHOLDER: &str = "\u{0}document-dir\u{0}";
+
+static STORE: OnceLock<Option<DiskCache>> = OnceLock::new();
+
+/// Enable the shared store rooted at `root`. Must be called before the
+/// first use of [`global`] to take effect.
+pub fn init(root: Option<&Path>, policy: GcPolicy, compression_level: i32) {
+    let store = STORE.get_or_init(|| {
+        root.map(|root| DiskCache::new(root.join(OBJECTS_DIR), policy).with_compression(compression_level))
+    });
+    if let Some(store) = store {
+        store.collect_in_background();
+    }
+}
+
+/// The shared store, if enabled
+pub fn global() -> Option<&'static DiskCache> {
+    STORE.get_or_init(|| None).as_ref()
+}
+
+/// `fastmd/store` under the platform's user cache directory
+pub fn default_root() -> Option<PathBuf> {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library").join("Caches"))
+    } else if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| home().map(|home| home.join(".cache")))
+    };
+    base.map(|base| base.join("fastmd").join("store"))
+}
+
+/// The directory `file` is relocated by, if its output can be relocated
+/// at all: the path must be absolute, already normalized, and need no
+/// escaping inside generated string literals
+pub fn document_dir(file: &str) -> Option<&str> {
+    if !Path::new(file).is_absolute() || normalize_path(file) != file {
+        return None;
+    }
+    let (dir, _) = file.rsplit_once('/')?;
+    let plain = !dir.is_empty() && !dir.contains(['"', '\'', '\\', '`', '$']) && !dir.contains(char::is_control);
+    plain.then_some(dir)
+}
+
+/// `entry`, rendered from a document in `dir`, as an object for the store;
+/// `None` if moving it to another directory would change more than paths
+pub fn detach(entry: &Entry, dir: &str, source: &str, frontmatter: Option<&Value>) -> Option<Entry> {
+    let frontmatter = frontmatter.map(Value::to_string).unwrap_or_default();
+    // Occurrences that come from the document itself must not move with it
+    if [source, &frontmatter].iter().any(|text| text.contains(dir) || text.contains(DIR_PLACEHOLDER)) {
+        return None;
+    }
+    let inside = format!("{}/", dir);
+    if entry.dependencies.iter().any(|dep| Path::new(dep).is_absolute() && !dep.starts_with(&inside)) {
+        return None;
+    }
+    Some(replace(entry, dir, DIR_PLACEHOLDER))
+}
+
+/// A stored object as rendered from a document in `dir`
+pub fn attach(object: &Entry, dir: &str) -> Entry {
+    replace(object, DIR_PLACEHOLDER, dir)
+}
+
+fn replace(entry: &Entry, from: &str, to: &str) -> Entry {
+    let mut entry = entry.clone();
+    entry.code = entry.code.replace(from, to);
+    if let Some(map) = &mut entry.map {
+        replace_in_value(map, from, to);
+    }
+    for dependency in &mut entry.dependencies {
+        *dependency = dependency.replace(from, to);
+    }
+    for warning in &mut entry.warnings {
+        warning.message = warning.message.replace(from, to);
+    }
+    entry
+}
+
+fn replace_in_value(value: &mut Value, from: &str, to: &str) {
+    match value {
+        Value::String(s) if s.contains(from) => *s = s.replace(from, to),
+        Value::Array(items) => items.iter_mut().for_each(|item| replace_in_value(item, from, to)),
+        Value::Object(fields) => fields.values_mut().for_each(|field| replace_in_value(field, from, to)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(dir: &str) -> Entry {
+        Entry {
+            code: format!("// Generated from: {}/a.md\nexport default `<img src=\"./x.png\">`;\n", dir),
+            map: Some(json!({ "version": 3, "sources": [format!("{}/a.md", dir)] })),
+            dependencies: vec![format!("{}/x.png", dir), "react".to_string()],
+            warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_document_dir() {
+        assert_eq!(document_dir("/site/docs/a.md"), Some("/site/docs"));
+        assert_eq!(document_dir("docs/a.md"), None);
+        assert_eq!(document_dir("/a.md"), None);
+        assert_eq!(document_dir("/site/$docs/a.md"), None);
+    }
+
+    #[test]
+    fn test_objects_move_between_directories() {
+        let source = "![](./x.png)\n";
+        let object = detach(&entry("/one/docs"), "/one/docs", source, None).unwrap();
+        assert!(!object.code.contains("/one/docs"));
+        assert_eq!(attach(&object, "/two/docs"), entry("/two/docs"));
+        assert_eq!(attach(&object, "/one/docs"), entry("/one/docs"));
+    }
+
+    #[test]
+    fn test_renamed_documents_miss() {
+        let source = "# Title\n";
+        let options = crate::transform::TransformOptions::default();
+        let render = |file: &str| crate::transform::render_document(file, source, source, &options, None).unwrap();
+        let key = |file: &str| crate::cache::content_key(file, source, &options, None);
+
+        let object = detach(&render("/one/docs/a.md"), "/one/docs", source, None).unwrap();
+        assert_eq!(key("/two/docs/a.md"), key("/one/docs/a.md"));
+        assert_eq!(attach(&object, "/two/docs"), render("/two/docs/a.md"));
+        // Output names the file, so another name can't reuse the object
+        assert_ne!(key("/two/docs/b.md"), key("/one/docs/a.md"));
+        assert_ne!(attach(&object, "/two/docs"), render("/two/docs/b.md"));
+    }
+
+    #[test]
+    fn test_unmovable_output_is_not_stored() {
+        let mentions_dir = "See /one/docs/b.md\n";
+        assert_eq!(detach(&entry("/one/docs"), "/one/docs", mentions_dir, None), None);
+
+        let frontmatter = json!({ "image": "/one/docs/x.png" });
+        assert_eq!(detach(&entry("/one/docs"), "/one/docs", "", Some(&frontmatter)), None);
+
+        let mut outside = entry("/one/docs");
+        outside.dependencies.push("/one/shared/y.png".to_string());
+        assert_eq!(detach(&outside, "/one/docs", "", None), None);
+    }
+}
@@ -0,0 +1,1032 @@
+//! Markdown/MDX transformation pipeline
+//!
+//! Converts source documents into ES modules. Handlers and the parallel
+//! workers both go through this module so that every entry point produces
+//! identical output for the same input and options.
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::OnceLock;
+
+use dashmap::DashMap;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::abbr;
+use crate::blocks;
+use crate::cache;
+use crate::cancel;
+use crate::deps;
+use crate::diagnostics::{self, Diagnostic, ErrorKind, TransformError};
+use crate::js;
+use crate::jsx;
+use crate::minify::minify_html;
+use crate::output::{self, Heading, ModuleParts, OutputFormat, Shape};
+use crate::sandbox;
+use crate::sourcemap::{self, LineMap};
+use crate::utils::normalize_path;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+pub struct TransformOptions {
+    pub mode: Option<String>,
+    pub sourcemap: Option<bool>,
+    pub framework: Option<String>,
+    /// Collapse insignificant whitespace and strip comments in generated HTML
+    pub minify: Option<bool>,
+    /// Guarantee byte-stable output across runs and machines
+    pub deterministic: Option<bool>,
+    /// Expand `*[TERM]: expansion` definitions into `<abbr>` elements
+    pub abbreviations: Option<bool>,
+    /// Remove abbreviation definition lines from the output (default: true)
+    pub strip_abbreviation_definitions: Option<bool>,
+    /// Prefix for root-relative link and image URLs, e.g. `/docs`
+    pub base_url: Option<String>,
+    /// Check frontmatter against the configured schema, reporting warnings
+    pub validate_frontmatter: Option<bool>,
+    /// Merge `_defaults.yaml` / `_defaults.json` from parent directories
+    /// under the document's frontmatter
+    pub cascade_defaults: Option<bool>,
+    /// Shape of the generated markdown module (MDX is always ESM)
+    pub format: Option<OutputFormat>,
+    /// Also export `html`, `frontmatter` and `headings` by name
+    pub named_exports: Option<bool>,
+    /// Module the JSX automatic runtime is imported from when MDX is
+    /// compiled for react/preact (default: the framework name)
+    pub jsx_import_source: Option<String>,
+    /// Options this version does not recognise, reported as warnings
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, Value>,
+}
+
+impl TransformOptions {
+    pub fn minify(&self) -> bool {
+        self.minify.unwrap_or(false)
+    }
+
+    pub fn deterministic(&self) -> bool {
+        self.deterministic.unwrap_or(false)
+    }
+
+    pub fn abbreviations(&self) -> bool {
+        self.abbreviations.unwrap_or(false)
+    }
+
+    pub fn validate_frontmatter(&self) -> bool {
+        self.validate_frontmatter.unwrap_or(false)
+    }
+
+    pub fn cascade_defaults(&self) -> bool {
+        self.cascade_defaults.unwrap_or(false)
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format.unwrap_or_default()
+    }
+
+    pub fn named_exports(&self) -> bool {
+        self.named_exports.unwrap_or(false)
+    }
+
+    /// Fill unset options from `defaults`
+    pub fn or(self, defaults: &TransformOptions) -> TransformOptions {
+        TransformOptions {
+            mode: self.mode.or_else(|| defaults.mode.clone()),
+            sourcemap: self.sourcemap.or(defaults.sourcemap),
+            framework: self.framework.or_else(|| defaults.framework.clone()),
+            minify: self.minify.or(defaults.minify),
+            deterministic: self.deterministic.or(defaults.deterministic),
+            abbreviations: self.abbreviations.or(defaults.abbreviations),
+            strip_abbreviation_definitions: self.strip_abbreviation_definitions.or(defaults.strip_abbreviation_definitions),
+            base_url: self.base_url.or_else(|| defaults.base_url.clone()),
+            validate_frontmatter: self.validate_frontmatter.or(defaults.validate_frontmatter),
+            cascade_defaults: self.cascade_defaults.or(defaults.cascade_defaults),
+            format: self.format.or(defaults.format),
+            named_exports: self.named_exports.or(defaults.named_exports),
+            jsx_import_source: self.jsx_import_source.or_else(|| defaults.jsx_import_source.clone()),
+            unknown: self.unknown,
+        }
+    }
+
+    pub fn warnings(&self) -> Vec<Diagnostic> {
+        self.unknown
+            .keys()
+            .map(|key| Diagnostic::new("unknown-option", format!("Unknown transform option `{}`", key)))
+            .collect()
+    }
+}
+
+pub fn parser_options(deterministic: bool) -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_SMART_PUNCTUATION);
+    if deterministic {
+        // Explicit `{#id}` attributes take precedence over generated slugs
+        options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+    }
+    options
+}
+
+/// Events parsed between cancellation checks
+const CANCEL_CHECK_EVENTS: usize = 1024;
+
+/// Exercises every enabled extension, so warming up touches each code path
+const WARM_UP_DOCUMENT: &str = "# Warm *up*\n\n\
+    Some **strong** and ~~struck~~ \"quoted\" text[^1] with a [link](/ \"title\") and `code`.\n\n\
+    - [x] done\n- [ ] todo\n\n\
+    | a | b |\n|---|:-:|\n| 1 | 2 |\n\n\
+    > quote\n\n\
+    ```js\nlet x = 1 < 2;\n```\n\n\
+    <div>html</div>\n\n\
+    [^1]: Footnote.\n";
+
+/// Render a sample document, so the first real requests on this thread
+/// don't pay for cold caches and lazy setup
+pub fn warm_up() {
+    let _ = transform_markdown(WARM_UP_DOCUMENT, "warm-up.md", &TransformOptions::default(), None);
+}
+
+/// Output of a document transform
+#[derive(Debug, Clone)]
+pub struct Rendered {
+    pub code: String,
+    /// Local files and modules the document references
+    pub dependencies: Vec<String>,
+    /// Generated line -> source line pairs for source map generation
+    pub line_map: LineMap,
+    /// Non-fatal problems, positioned relative to the transformed content
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Flags content problems that do not prevent rendering
+struct Linter<'a> {
+    content: &'a str,
+    file_path: &'a str,
+    /// Only check link targets when the document itself lives on disk
+    check_files: bool,
+    /// Targets outside it are reported unresolved without being looked up,
+    /// so diagnostics can't tell what exists elsewhere on the host
+    sandbox_root: Option<&'static Path>,
+    /// Start offset and alt text of the image being visited
+    image: Option<(usize, String)>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    fn new(content: &'a str, file_path: &'a str) -> Self {
+        Linter {
+            content,
+            file_path,
+            check_files: sandbox::check(Path::new(file_path)).is_ok() && Path::new(file_path).is_file(),
+            sandbox_root: sandbox::root(),
+            image: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn visit(&mut self, event: &Event, range: &Range<usize>) {
+        match event {
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                self.check_target(dest_url, range.start);
+                self.image = Some((range.start, String::new()));
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => self.check_target(dest_url, range.start),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, alt)) = &mut self.image {
+                    alt.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Image) => {
+                if let Some((start, alt)) = self.image.take() {
+                    if alt.trim().is_empty() {
+                        self.warn(Diagnostic::new("empty-alt-text", "Image has no alt text"), start);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_target(&mut self, url: &str, offset: usize) {
+        if !self.check_files {
+            return;
+        }
+        if let Some(target) = deps::resolve_local(self.file_path, url) {
+            let target = Path::new(&target);
+            let inside = self.sandbox_root.is_none_or(|root| sandbox::check_in(root, target).is_ok());
+            if !inside || !target.exists() {
+                let warning = Diagnostic::new("unresolved-link", format!("Link target `{}` does not exist", url));
+                self.warn(warning, offset);
+            }
+        }
+    }
+
+    fn warn(&mut self, warning: Diagnostic, offset: usize) {
+        self.warnings.push(warning.at_offset(self.content, offset));
+    }
+}
+
+pub fn starts_block(event: &Event) -> bool {
+    matches!(event, Event::Start(_) | Event::Rule)
+}
+
+pub fn next_depth(depth: usize, event: &Event) -> usize {
+    match event {
+        Event::Start(_) => depth + 1,
+        Event::End(_) => depth.saturating_sub(1),
+        _ => depth,
+    }
+}
+
+/// Writer that counts newlines as HTML is produced
+struct LineCountingWriter<'a> {
+    out: &'a mut String,
+    newlines: Rc<Cell<usize>>,
+}
+
+impl fmt::Write for LineCountingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.newlines.set(self.newlines.get() + s.bytes().filter(|b| *b == b'\n').count());
+        self.out.push_str(s);
+        Ok(())
+    }
+}
+
+/// Render events to HTML, mapping the output line of each top-level block
+/// to the source line recorded for it in `block_lines`
+pub fn render_html(events: Vec<Event<'_>>, block_lines: &[usize]) -> (String, LineMap) {
+    let mut html_output = String::new();
+    let line_map = render_html_into(&mut html_output, events, block_lines);
+    (html_output, line_map)
+}
+
+/// [`render_html`], appending to `html_output`
+fn render_html_into(html_output: &mut String, events: Vec<Event<'_>>, block_lines: &[usize]) -> LineMap {
+    let newlines = Rc::new(Cell::new(0));
+    let mut line_map = Vec::with_capacity(block_lines.len());
+    let mut depth = 0usize;
+    let mut block = 0usize;
+    
+    let writer = LineCountingWriter {
+        out: html_output,
+        newlines: Rc::clone(&newlines),
+    };
+    let events = events.into_iter().inspect(|event| {
+        // Events are pulled right before they are written, so the newline
+        // count is the output line this block starts on
+        if depth == 0 && starts_block(event) {
+            if let Some(source_line) = block_lines.get(block) {
+                line_map.push((newlines.get(), *source_line));
+            }
+            block += 1;
+        }
+        depth = next_depth(depth, event);
+    });
+    // Writing into a String cannot fail
+    let _ = html::write_html_fmt(writer, events);
+    
+    line_map
+}
+
+/// Error of a render given up because its request was cancelled
+const CANCELLED: &str = "Transform cancelled";
+
+/// Render a document's body; the part of a transform the disk cache
+/// stores. `source` is the whole document, `content` its body below the
+/// frontmatter. The same on request threads, pool workers and worker
+/// processes, so every way of transforming a document gives one output.
+pub fn render_document(
+    file: &str,
+    source: &str,
+    content: &str,
+    options: &TransformOptions,
+    frontmatter: Option<&Value>,
+) -> Result<cache::Entry, TransformError> {
+    let _span = tracing::trace_span!("render").entered();
+    // Frontmatter lines were stripped before transforming the body
+    let line_offset = source.lines().count().saturating_sub(content.lines().count());
+    let rendered = if file.ends_with(".mdx") {
+        // Positions from the body are moved below the frontmatter
+        transform_mdx(content, file, options).map_err(|error| match (error.line, error.column) {
+            (Some(line), Some(column)) => TransformError::new(error.kind, error.message).at(source, line - 1 + line_offset, column - 1),
+            _ => error,
+        })
+    } else {
+        // For regular markdown, convert to HTML
+        transform_markdown(content, file, options, frontmatter).map_err(|e| {
+            let kind = if cancel::is_cancelled() { ErrorKind::Cancelled } else { ErrorKind::Render };
+            TransformError::new(kind, e)
+        })
+    }?;
+    
+    let map = if options.sourcemap.unwrap_or(false) {
+        Some(sourcemap::build(file, source, &rendered.line_map, line_offset))
+    } else {
+        None
+    };
+    
+    Ok(cache::Entry {
+        code: rendered.code,
+        map,
+        dependencies: rendered.dependencies,
+        warnings: rendered.warnings.into_iter().map(|warning| warning.shifted(line_offset)).collect(),
+    })
+}
+
+pub fn transform_markdown(
+    content: &str,
+    file_path: &str,
+    options: &TransformOptions,
+    frontmatter: Option<&Value>,
+) -> Result<Rendered, String> {
+    let deterministic = options.deterministic();
+    
+    let (abbreviations, content) = if options.abbreviations() {
+        abbr::collect_definitions(content, options.strip_abbreviation_definitions.unwrap_or(true))
+    } else {
+        (Vec::new(), content.to_string())
+    };
+    
+    // Parse markdown, remembering where each top-level block starts
+    let parse_span = tracing::trace_span!("parse").entered();
+    let mut events = Vec::new();
+    let mut block_lines = Vec::new();
+    let mut block_starts = Vec::new();
+    let mut depth = 0usize;
+    let mut linter = Linter::new(&content, file_path);
+    for (i, (event, range)) in Parser::new_ext(&content, parser_options(deterministic)).into_offset_iter().enumerate() {
+        if i % CANCEL_CHECK_EVENTS == 0 && cancel::is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        linter.visit(&event, &range);
+        if depth == 0 && starts_block(&event) {
+            block_lines.push(sourcemap::line_of(&content, range.start));
+            block_starts.push(range.start);
+        }
+        depth = next_depth(depth, &event);
+        events.push(event);
+    }
+    
+    let shape = Shape::of(options);
+    // Exported headings need ids in the HTML to link to
+    if deterministic || shape.needs_headings() {
+        events = assign_heading_ids(events);
+    }
+    let headings = collect_headings(&events);
+    if let Some(base_url) = &options.base_url {
+        events = rebase_links(events, base_url);
+    }
+    events = abbr::apply(events, &abbreviations);
+    let dependencies = deps::from_events(&events, file_path).into_iter().collect();
+    drop(parse_span);
+    
+    // Convert to HTML, reusing unchanged blocks of large documents
+    let html_span = tracing::trace_span!("html").entered();
+    let (mut html_output, mut line_map) = if blocks::applies(&events, block_starts.len()) {
+        let context = format!("{}\n{:?}", serde_json::to_string(options).unwrap_or_default(), abbreviations);
+        let (html, line_map, reused) =
+            blocks::render(file_path, &context, &content, &block_starts, &block_lines, events);
+        tracing::debug!("Reused {} of {} blocks of {}", reused, block_starts.len(), file_path);
+        (html, line_map)
+    } else {
+        render_html(events, &block_lines)
+    };
+    
+    if options.minify() {
+        html_output = minify_html(&html_output);
+        // Minified HTML is (mostly) a single line
+        line_map.truncate(1);
+    }
+    drop(html_span);
+    
+    let _span = tracing::trace_span!("module").entered();
+    let file = display_path(file_path, deterministic);
+    let parts = ModuleParts {
+        file: &file,
+        html: &html_output,
+        frontmatter,
+        headings: &headings,
+        raw: &content,
+    };
+    let (code, html_line) = output::render(&parts, shape);
+    let line_map = match html_line {
+        Some(start) => line_map.into_iter().map(|(generated, source)| (generated + start - 1, source)).collect(),
+        None => Vec::new(),
+    };
+    let warnings = linter.warnings;
+    
+    Ok(Rendered {
+        code,
+        dependencies,
+        line_map,
+        warnings,
+    })
+}
+
+/// Prefix root-relative link and image URLs with `base_url`
+fn rebase_links<'a>(events: Vec<Event<'a>>, base_url: &str) -> Vec<Event<'a>> {
+    let base = base_url.trim_end_matches('/');
+    let rebase = |url: CowStr<'a>| -> CowStr<'a> {
+        // `//host/path` is protocol-relative, not root-relative
+        if url.starts_with('/') && !url.starts_with("//") {
+            CowStr::from(format!("{}{}", base, url))
+        } else {
+            url
+        }
+    };
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::Link { link_type, dest_url, title, id }) => Event::Start(Tag::Link {
+                link_type,
+                dest_url: rebase(dest_url),
+                title,
+                id,
+            }),
+            Event::Start(Tag::Image { link_type, dest_url, title, id }) => Event::Start(Tag::Image {
+                link_type,
+                dest_url: rebase(dest_url),
+                title,
+                id,
+            }),
+            other => other,
+        })
+        .collect()
+}
+
+/// Escape text for use between the backticks of a JavaScript template literal
+pub fn escape_template_literal(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('`', "\\`")
+        .replace("${", "\\${")
+}
+
+/// The file path as it appears in generated code. Deterministic output
+/// uses forward slashes so the same checkout renders identically on every OS.
+fn display_path(file_path: &str, deterministic: bool) -> String {
+    if deterministic {
+        normalize_path(&file_path.replace('\\', "/"))
+    } else {
+        file_path.to_string()
+    }
+}
+
+/// Give every heading without an explicit id a slug derived from its text.
+/// Duplicates get `-1`, `-2`, ... suffixes in document order, so ids only
+/// depend on the document itself. Explicit ids are taken first, wherever
+/// they appear, so no slug collides with one.
+pub fn assign_heading_ids(mut events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut slugger = Slugger::default();
+    for event in &events {
+        if let Event::Start(Tag::Heading { id: Some(explicit), .. }) = event {
+            slugger.reserve(explicit);
+        }
+    }
+    
+    let mut i = 0;
+    while i < events.len() {
+        if let Event::Start(Tag::Heading { id: None, .. }) = &events[i] {
+            let mut text = String::new();
+            let mut j = i + 1;
+            while j < events.len() && !matches!(events[j], Event::End(TagEnd::Heading(_))) {
+                if let Event::Text(t) | Event::Code(t) = &events[j] {
+                    text.push_str(t);
+                }
+                j += 1;
+            }
+            let slug = slugger.slug(&text);
+            if let Event::Start(Tag::Heading { id, .. }) = &mut events[i] {
+                *id = Some(CowStr::from(slug));
+            }
+            i = j;
+        }
+        i += 1;
+    }
+    
+    events
+}
+
+/// Headings in document order; ids must already be assigned
+pub fn collect_headings(events: &[Event<'_>]) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut current: Option<Heading> = None;
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                current = Some(Heading {
+                    depth: *level as u8,
+                    slug: id.as_deref().unwrap_or_default().to_string(),
+                    text: String::new(),
+                });
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading) = &mut current {
+                    heading.text.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => headings.extend(current.take()),
+            _ => {}
+        }
+    }
+    headings
+}
+
+/// GitHub-style heading slug generator with per-document de-duplication
+#[derive(Debug, Default)]
+pub struct Slugger {
+    seen: HashSet<String>,
+}
+
+impl Slugger {
+    pub fn slug(&mut self, text: &str) -> String {
+        let base = slugify(text);
+        let base = if base.is_empty() { "section".to_string() } else { base };
+        let mut candidate = base.clone();
+        let mut n = 0;
+        while self.seen.contains(&candidate) {
+            n += 1;
+            candidate = format!("{}-{}", base, n);
+        }
+        self.seen.insert(candidate.clone());
+        candidate
+    }
+
+    fn reserve(&mut self, id: &str) {
+        self.seen.insert(id.to_string());
+    }
+}
+
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.trim().chars() {
+        if c.is_alphanumeric() || c == '_' || c == '-' {
+            slug.extend(c.to_lowercase());
+        } else if c.is_whitespace() {
+            slug.push('-');
+        }
+    }
+    slug
+}
+
+/// Hex SHA-256 of generated output, used to verify reproducible builds
+pub fn output_hash(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+/// Split a leading `---` frontmatter block into its raw YAML and the remaining body
+pub fn split_frontmatter(content: &str) -> Option<(String, String)> {
+    let lines: Vec<&str> = content.lines().collect();
+    
+    // Check if content starts with frontmatter delimiter
+    if lines.is_empty() || lines[0].trim() != "---" {
+        return None;
+    }
+    
+    // Find the closing delimiter; without one, treat all as content
+    let end = lines.iter().skip(1).position(|line| line.trim() == "---")? + 1;
+    
+    // Return YAML and content after the closing delimiter
+    Some((lines[1..end].join("\n"), lines[(end + 1)..].join("\n")))
+}
+
+/// Parsed frontmatter blocks by hash of their YAML. Metadata scans re-read
+/// thousands of documents whose frontmatter rarely changes between passes.
+static PARSED_FRONTMATTER: OnceLock<DashMap<blake3::Hash, Value>> = OnceLock::new();
+/// Memoized blocks beyond which the memo starts over
+const MAX_PARSED_FRONTMATTER: usize = 16_384;
+
+fn parsed_frontmatter() -> &'static DashMap<blake3::Hash, Value> {
+    PARSED_FRONTMATTER.get_or_init(DashMap::new)
+}
+
+/// Parse a frontmatter block, reusing the result for a block seen before.
+/// Only successes are memoized; errors are positioned in their document.
+fn parse_yaml(yaml: &str) -> Result<Value, serde_yaml::Error> {
+    let key = blake3::hash(yaml.as_bytes());
+    let memo = parsed_frontmatter();
+    if let Some(value) = memo.get(&key) {
+        return Ok(value.clone());
+    }
+    let value: Value = serde_yaml::from_str(yaml)?;
+    if memo.len() >= MAX_PARSED_FRONTMATTER {
+        memo.clear();
+    }
+    memo.insert(key, value.clone());
+    Ok(value)
+}
+
+/// Lenient frontmatter extraction: invalid YAML yields no frontmatter
+pub fn extract_frontmatter(content: &str) -> (Option<Value>, String) {
+    match split_frontmatter(content) {
+        Some((yaml, body)) => (parse_yaml(&yaml).ok(), body),
+        None => (None, content.to_string()),
+    }
+}
+
+/// Strict frontmatter extraction: invalid YAML is an error positioned in `content`
+pub fn parse_frontmatter(content: &str) -> Result<(Option<Value>, String), TransformError> {
+    let Some((yaml, body)) = split_frontmatter(content) else {
+        return Ok((None, content.to_string()));
+    };
+    
+    match parse_yaml(&yaml) {
+        Ok(frontmatter) => Ok((Some(frontmatter), body)),
+        Err(e) => {
+            let message = format!("Invalid frontmatter: {}", e);
+            let error = TransformError::new(ErrorKind::Frontmatter, message);
+            Err(match e.location() {
+                // YAML starts on the line after the opening delimiter
+                Some(location) => error.at(content, location.line(), location.column().saturating_sub(1)),
+                None => error,
+            })
+        }
+    }
+}
+
+/// Frontmatter and body of a document being transformed. Invalid YAML
+/// doesn't stop the body from rendering: the block is left out and
+/// reported as an `invalid-frontmatter` warning.
+pub fn parse_frontmatter_lenient(content: &str) -> (Option<Value>, String, Option<Diagnostic>) {
+    match parse_frontmatter(content) {
+        Ok((frontmatter, body)) => (frontmatter, body, None),
+        Err(error) => {
+            let body = split_frontmatter(content).map_or_else(|| content.to_string(), |(_, body)| body);
+            let mut warning = Diagnostic::new("invalid-frontmatter", error.message);
+            warning.line = error.line;
+            warning.column = error.column;
+            (None, body, Some(warning))
+        }
+    }
+}
+
+/// Problems with a frontmatter block that did not prevent parsing it
+pub fn frontmatter_warnings(content: &str, frontmatter: Option<&Value>) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    if content.lines().next().map(str::trim) == Some("---") && split_frontmatter(content).is_none() {
+        let warning = Diagnostic::new("unclosed-frontmatter", "Frontmatter has no closing `---`; treating it as content");
+        warnings.push(warning.at_offset(content, 0));
+    }
+    if let Some(value) = frontmatter {
+        if !value.is_object() && !value.is_null() {
+            let warning = Diagnostic::new("frontmatter-not-mapping", "Frontmatter is not a key/value mapping");
+            warnings.push(warning.at_offset(content, 0));
+        }
+    }
+    warnings
+}
+
+/// Modules an MDX document's ESM imports or re-exports from; none if its
+/// ESM doesn't parse
+pub fn mdx_import_specifiers(content: &str) -> Vec<String> {
+    js::split(content).map(|esm| esm.specifiers).unwrap_or_default()
+}
+
+/// Whether an MDX document declares exports other than the default export
+pub fn mdx_has_named_exports(content: &str) -> bool {
+    js::split(content).is_ok_and(|esm| esm.named_exports)
+}
+
+/// Compile an MDX document. Imports and exports are kept as written, a
+/// default export as the layout ([`js::LAYOUT`]); with a JSX runtime
+/// ([`jsx::import_source`]) the body becomes an `MDXContent` component,
+/// otherwise the default export is its text. Errors are positioned in
+/// `content`.
+pub fn transform_mdx(content: &str, file_path: &str, options: &TransformOptions) -> Result<Rendered, TransformError> {
+    let esm = js::split(content).map_err(|error| {
+        let (line, column) = diagnostics::position(content, error.offset);
+        TransformError::new(ErrorKind::Render, error.message).at(content, line, column)
+    })?;
+    let body_lines = &esm.body_lines;
+    let body = esm.body();
+    
+    let mut linter = Linter::new(&body, file_path);
+    let events: Vec<Event> = Parser::new_ext(&body, parser_options(false))
+        .into_offset_iter()
+        .map(|(event, range)| {
+            linter.visit(&event, &range);
+            event
+        })
+        .collect();
+    // Body lines were gathered from the whole document; map positions back
+    let warnings = linter
+        .warnings
+        .into_iter()
+        .map(|mut warning| {
+            warning.line = warning.line.and_then(|line| body_lines.get(line - 1)).map(|(source_line, _)| source_line + 1);
+            warning
+        })
+        .collect();
+    let mut dependencies = deps::from_events(&events, file_path);
+    dependencies.extend(deps::from_imports(&esm.specifiers, file_path));
+    
+    // Header and runtime import, then the document's imports and exports
+    let mut result = String::new();
+    let mut line_map = Vec::new();
+    
+    result.push_str(&format!("// Generated from: {}\n", display_path(file_path, options.deterministic())));
+    let mut generated_line = 1;
+    let import_source = jsx::import_source(options);
+    if let Some(source) = import_source {
+        result.push_str(&jsx::runtime_import(source));
+        generated_line += 1;
+    }
+    
+    for (source_line, line) in &esm.lines {
+        result.push_str(line);
+        result.push('\n');
+        line_map.push((generated_line, *source_line));
+        generated_line += 1;
+    }
+    
+    if import_source.is_some() {
+        let component = jsx::compile(&body, esm.layout).map_err(|error| {
+            let (line, column) = diagnostics::position(&body, error.offset);
+            TransformError::new(ErrorKind::Render, error.message).at(content, body_lines[line].0, column)
+        })?;
+        result.push('\n');
+        generated_line += 1;
+        for (line, body_line) in component.line_map {
+            line_map.push((generated_line + line - 1, body_lines[body_line].0));
+        }
+        result.push_str(&component.code);
+        return Ok(Rendered {
+            code: result,
+            dependencies: dependencies.into_iter().collect(),
+            line_map,
+            warnings,
+        });
+    }
+    
+    // Other frameworks get the body as a template literal
+    result.push_str("\nexport default `");
+    generated_line += 1;
+    for (i, (source_line, _)) in body_lines.iter().enumerate() {
+        line_map.push((generated_line + i, *source_line));
+    }
+    result.push_str(&escape_template_literal(&body));
+    result.push_str("`;\n");
+    
+    Ok(Rendered {
+        code: result,
+        dependencies: dependencies.into_iter().collect(),
+        line_map,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deterministic() -> TransformOptions {
+        TransformOptions {
+            deterministic: Some(true),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_warm_up_document() {
+        let html = transform_markdown(WARM_UP_DOCUMENT, "warm-up.md", &TransformOptions::default(), None).unwrap().code;
+        for tag in ["<h1>", "<strong>", "<del>", "<sup class=\"footnote-reference\">", "<input", "<table>", "<blockquote>", "<pre>", "<div>"] {
+            assert!(html.contains(tag), "{} missing from {}", tag, html);
+        }
+    }
+
+    #[test]
+    fn test_slugger_deduplicates() {
+        let mut slugger = Slugger::default();
+        assert_eq!(slugger.slug("Hello World"), "hello-world");
+        assert_eq!(slugger.slug("Hello World"), "hello-world-1");
+        assert_eq!(slugger.slug("Hello World"), "hello-world-2");
+        assert_eq!(slugger.slug("What's new?"), "whats-new");
+        assert_eq!(slugger.slug("!!!"), "section");
+    }
+
+    #[test]
+    fn test_deterministic_heading_ids() {
+        let input = "# Intro\n\n## Intro\n\n## Custom {#custom}\n";
+        let code = transform_markdown(input, "doc.md", &deterministic(), None).unwrap().code;
+        assert!(code.contains(r#"<h1 id="intro">"#));
+        assert!(code.contains(r#"<h2 id="intro-1">"#));
+        assert!(code.contains(r#"<h2 id="custom">"#));
+        
+        // An explicit id further down keeps its name; the slug moves aside
+        let input = "# Setup\n\n## Install {#setup}\n";
+        let code = transform_markdown(input, "doc.md", &deterministic(), None).unwrap().code;
+        assert!(code.contains(r#"<h1 id="setup-1">"#), "{}", code);
+        assert!(code.contains(r#"<h2 id="setup">"#), "{}", code);
+    }
+
+    #[test]
+    fn test_named_exports() {
+        let options = TransformOptions {
+            named_exports: Some(true),
+            ..Default::default()
+        };
+        let frontmatter = serde_json::json!({ "title": "Doc" });
+        let rendered = transform_markdown("# Intro\n\n## `API` usage\n", "doc.md", &options, Some(&frontmatter)).unwrap();
+        assert!(rendered.code.contains(r#"export const frontmatter = {"title":"Doc"};"#));
+        assert!(rendered.code.contains(r#"{"depth":2,"slug":"api-usage","text":"API usage"}"#));
+        assert!(rendered.code.contains(r#"<h2 id="api-usage">"#));
+        assert_eq!(rendered.line_map[0], (3, 0));
+    }
+
+    #[test]
+    fn test_mdx_import_specifiers() {
+        let mdx = "import Button from './Button.astro';\nimport { a, b } from \"pkg\"\nimport './styles.css';\n\n# Title\n";
+        assert_eq!(mdx_import_specifiers(mdx), vec!["./Button.astro", "pkg", "./styles.css"]);
+        assert!(!mdx_has_named_exports(mdx));
+        assert!(mdx_has_named_exports("export const meta = {};\n"));
+    }
+
+    #[test]
+    fn test_mdx_esm_blocks() {
+        let react = TransformOptions {
+            framework: Some("react".to_string()),
+            ..Default::default()
+        };
+        let mdx = "import {\n  a,\n  b\n} from \"pkg\";\nexport const meta = {\n  title: \"T\"\n};\n\n# Title\n\nexport default function Layout({ children }) {\n  return <main>{children}</main>;\n}\n\nA paragraph\nimport x from 'y' goes on\n";
+        let rendered = transform_mdx(mdx, "a.mdx", &react).unwrap();
+        let code = &rendered.code;
+        assert!(code.contains("import {\n  a,\n  b\n} from \"pkg\";\nexport const meta = {\n  title: \"T\"\n};\n"), "{}", code);
+        assert!(code.contains("const MDXLayout = function Layout({ children }) {\n  return <main>{children}</main>;\n}\n"), "{}", code);
+        assert!(code.contains("  return _jsx(MDXLayout, { ...props, children: _jsx(_createMdxContent, { ...props }) });"), "{}", code);
+        assert!(!code.contains("export default function Layout"));
+        assert_eq!(code.matches("export default").count(), 1);
+        assert!(code.contains(r#"_jsx(_components.p, { children: "A paragraph\nimport x from 'y' goes on" })"#), "{}", code);
+        assert_eq!(mdx_import_specifiers(mdx), vec!["pkg"]);
+        assert!(mdx_has_named_exports(mdx));
+        
+        // Without a JSX runtime the body is still the only default export
+        let rendered = transform_mdx("export default Layout;\n\nText\n", "a.mdx", &TransformOptions::default()).unwrap();
+        assert!(rendered.code.contains("const MDXLayout = Layout;\n"), "{}", rendered.code);
+        assert_eq!(rendered.code.matches("export default").count(), 1);
+        assert!(!mdx_has_named_exports("export default Layout;\n"));
+        
+        // A block that ends early goes on past a blank line
+        let mdx = "export const list = [\n  1,\n\n  2\n];\n\nText\n";
+        let rendered = transform_mdx(mdx, "a.mdx", &TransformOptions::default()).unwrap();
+        assert!(rendered.code.contains("export const list = [\n  1,\n\n  2\n];\n"), "{}", rendered.code);
+        assert!(rendered.code.contains("export default `\nText`"), "{}", rendered.code);
+        
+        // Invalid ESM is an error where it goes wrong, not part of the body
+        let error = transform_mdx("# Title\n\nimport { a from \"b\";\n", "a.mdx", &react).unwrap_err();
+        assert_eq!(error.line, Some(3));
+        let error = transform_mdx("export const a = 1;\nlet b = 2;\n", "a.mdx", &react).unwrap_err();
+        assert_eq!((error.line, error.column), (Some(2), Some(1)));
+        assert!(error.message.contains("only `import` and `export`"), "{}", error.message);
+        assert!(transform_mdx("export default A;\n\nexport default B;\n", "a.mdx", &react).is_err());
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let md = "![a](a.png)\n\n[b](./b.md)\n";
+        let rendered = transform_markdown(md, "/docs/x.md", &TransformOptions::default(), None).unwrap();
+        assert_eq!(rendered.dependencies, vec!["/docs/a.png", "/docs/b.md"]);
+        
+        let mdx = "import Card from './Card.astro';\n\n![c](c.png)\n";
+        let rendered = transform_mdx(mdx, "/docs/y.mdx", &TransformOptions::default()).unwrap();
+        assert_eq!(rendered.dependencies, vec!["/docs/Card.astro", "/docs/c.png"]);
+    }
+
+    #[test]
+    fn test_frontmatter_error_position() {
+        let source = "---\ntitle: ok\ntags: [a, b\n---\nbody\n";
+        let err = parse_frontmatter(source).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Frontmatter);
+        assert!(err.line.unwrap() >= 3);
+        
+        let (frontmatter, body) = parse_frontmatter("---\ntitle: ok\n---\nbody").unwrap();
+        assert_eq!(frontmatter.unwrap()["title"], "ok");
+        assert_eq!(body, "body");
+        assert_eq!(extract_frontmatter(source).0, None);
+    }
+
+    #[test]
+    fn test_invalid_frontmatter_is_a_warning() {
+        let source = "---\ntitle: ok\ntags: [a, b\n---\nbody\n";
+        let (frontmatter, body, warning) = parse_frontmatter_lenient(source);
+        assert_eq!(frontmatter, None);
+        assert_eq!(body, "body");
+        let warning = warning.unwrap();
+        assert_eq!(warning.code, "invalid-frontmatter");
+        assert!(warning.message.starts_with("Invalid frontmatter"));
+        assert!(warning.line.unwrap() >= 3);
+        
+        let (frontmatter, body, warning) = parse_frontmatter_lenient("---\ntitle: ok\n---\nbody");
+        assert_eq!(frontmatter.unwrap()["title"], "ok");
+        assert_eq!(body, "body");
+        assert!(warning.is_none());
+    }
+
+    #[test]
+    fn test_frontmatter_is_memoized_by_block() {
+        let yaml = "title: memoized\ntags: [a, b]";
+        let (first, _) = parse_frontmatter(&format!("---\n{}\n---\none", yaml)).unwrap();
+        assert!(parsed_frontmatter().contains_key(&blake3::hash(yaml.as_bytes())));
+        let (second, body) = parse_frontmatter(&format!("---\n{}\n---\ntwo", yaml)).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(body, "two");
+    }
+
+    #[test]
+    fn test_warnings() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("doc.md");
+        std::fs::write(&file, "").unwrap();
+        std::fs::write(dir.path().join("exists.md"), "").unwrap();
+        let file = file.to_string_lossy();
+        
+        let md = "# Title\n\n![](a.png) and ![Alt](exists.md)\n\n[gone](missing.md) [ok](exists.md) [web](https://x.dev)\n";
+        let rendered = transform_markdown(md, &file, &TransformOptions::default(), None).unwrap();
+        let codes: Vec<_> = rendered.warnings.iter().map(|w| (w.code.as_ref(), w.line)).collect();
+        assert_eq!(codes, vec![
+            ("unresolved-link", Some(3)),
+            ("empty-alt-text", Some(3)),
+            ("unresolved-link", Some(5)),
+        ]);
+        
+        // Virtual documents are not checked against the filesystem
+        let rendered = transform_markdown(md, "virtual/doc.md", &TransformOptions::default(), None).unwrap();
+        assert_eq!(rendered.warnings.len(), 1);
+
+        // Nor are targets outside the sandbox, which read as unresolved
+        std::fs::create_dir(dir.path().join("site")).unwrap();
+        let inner = dir.path().join("site/doc.md");
+        std::fs::write(&inner, "").unwrap();
+        let inner = inner.to_string_lossy();
+        let mut linter = Linter::new("[up](../exists.md)", &inner);
+        let root = dir.path().join("site").canonicalize().unwrap();
+        linter.sandbox_root = Some(Box::leak(root.into_boxed_path()));
+        linter.check_target("../exists.md", 0);
+        assert_eq!(linter.warnings[0].code, "unresolved-link");
+        
+        let options: TransformOptions = serde_json::from_value(serde_json::json!({ "minify": true, "colour": 1 })).unwrap();
+        assert_eq!(options.warnings()[0].code, "unknown-option");
+        
+        assert_eq!(frontmatter_warnings("---\ntitle: x\n", None)[0].code, "unclosed-frontmatter");
+        assert_eq!(frontmatter_warnings("", Some(&Value::from("x")))[0].code, "frontmatter-not-mapping");
+    }
+
+    #[test]
+    fn test_base_url() {
+        let options = TransformOptions {
+            base_url: Some("/docs/".to_string()),
+            ..Default::default()
+        };
+        let md = "[a](/guide) [b](./local) [c](//cdn.dev/x) ![i](/img.png)";
+        let code = transform_markdown(md, "a.md", &options, None).unwrap().code;
+        assert!(code.contains(r#"href="/docs/guide""#));
+        assert!(code.contains(r#"href="./local""#));
+        assert!(code.contains(r#"href="//cdn.dev/x""#));
+        assert!(code.contains(r#"src="/docs/img.png""#));
+    }
+
+    #[test]
+    fn test_line_map() {
+        let md = "# Title\n\nFirst paragraph\nwraps.\n\n- item\n";
+        let rendered = transform_markdown(md, "a.md", &TransformOptions::default(), None).unwrap();
+        assert_eq!(rendered.line_map, vec![(1, 0), (2, 2), (4, 5)]);
+        
+        let mdx = "import A from './a.js';\n\n# Title\n";
+        let rendered = transform_mdx(mdx, "a.mdx", &TransformOptions::default()).unwrap();
+        assert_eq!(rendered.line_map, vec![(1, 0), (3, 1), (4, 2)]);
+        
+        let react = TransformOptions {
+            framework: Some("react".to_string()),
+            ..Default::default()
+        };
+        let rendered = transform_mdx(mdx, "a.mdx", &react).unwrap();
+        assert_eq!(rendered.code.lines().nth(1), Some("import { Fragment as _Fragment, jsx as _jsx, jsxs as _jsxs } from \"react/jsx-runtime\";"));
+        assert_eq!(rendered.code.lines().nth(8), Some("      _jsx(_components.h1, { children: \"Title\" })"));
+        assert_eq!(rendered.line_map, vec![(2, 0), (8, 2)]);
+
+        // Syntax errors point into the whole document
+        let source = "---\ntitle: A\n---\nimport A from './a.js';\n\n<A>\n\n</div>\n";
+        let content = "import A from './a.js';\n\n<A>\n\n</div>\n";
+        let error = render_document("a.mdx", source, content, &react, None).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Render);
+        assert_eq!((error.line, error.column), (Some(8), Some(1)));
+        assert!(error.message.contains("`</div>`"), "{}", error.message);
+    }
+
+    #[test]
+    fn test_deterministic_output_is_stable() {
+        let input = "# Title\n\nText with a note[^1].\n\n[^1]: The note.\n";
+        let first = transform_markdown(input, "docs\\a.md", &deterministic(), None).unwrap().code;
+        let second = transform_markdown(input, "docs/a.md", &deterministic(), None).unwrap().code;
+        assert_eq!(output_hash(&first), output_hash(&second));
+        assert!(first.starts_with("// Generated from: docs/a.md\n"));
+    }
+}
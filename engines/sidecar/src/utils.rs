@@ -1,31 +1,104 @@
 use std::path::Path;
 
+/// How [`normalize_path_with`] reads the platform-specific parts of a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathStyle {
+    /// `\` separates segments, as on Windows
+    pub backslashes: bool,
+    /// Lowercase drive letters, so `C:/x` and `c:/x` come out the same
+    pub lowercase_drive: bool,
+}
+
+impl PathStyle {
+    /// Paths as this platform writes them
+    pub const NATIVE: PathStyle = PathStyle {
+        backslashes: cfg!(target_os = "windows"),
+        lowercase_drive: false,
+    };
+}
+
 /// Normalize a file path for consistent processing
-#[allow(dead_code)]
 pub fn normalize_path(path: &str) -> String {
-    let path = Path::new(path);
+    normalize_path_with(path, PathStyle::NATIVE)
+}
+
+/// Normalize a path lexically (without touching the file system): forward
+/// slashes, no empty or `.` segments, `..` collapsed into the segment
+/// before it (and dropped at the root), no trailing slash. Verbatim
+/// (`\\?\`) prefixes are removed and UNC shares (`\\server\share`)
+/// become `//server/share`.
+pub fn normalize_path_with(path: &str, style: PathStyle) -> String {
+    let path = if style.backslashes { path.replace('\\', "/") } else { path.to_string() };
+    let mut rest = path.as_str();
+    let mut unc = None;
+    if let Some(verbatim) = rest.strip_prefix("//?/").or_else(|| rest.strip_prefix("//./")) {
+        rest = verbatim;
+        if verbatim.get(..4).is_some_and(|prefix| prefix.eq_ignore_ascii_case("UNC/")) {
+            unc = Some(&verbatim[4..]);
+        }
+    } else if rest.starts_with("//") && !rest.starts_with("///") {
+        unc = Some(&rest[2..]);
+    }
     
-    // Convert to string, replacing backslashes on Windows
-    let mut normalized = path.to_string_lossy().to_string();
+    let mut root = String::new();
+    let rooted = match unc {
+        // `//server/share` is the root of everything below it
+        Some(unc) => {
+            let mut parts = unc.splitn(3, '/');
+            root = format!("//{}/{}", parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+            rest = parts.next().unwrap_or_default();
+            true
+        }
+        None => {
+            let bytes = rest.as_bytes();
+            if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+                root.push(if style.lowercase_drive { bytes[0].to_ascii_lowercase() } else { bytes[0] } as char);
+                root.push(':');
+                rest = &rest[2..];
+            }
+            rest.starts_with('/')
+        }
+    };
     
-    #[cfg(target_os = "windows")]
-    {
-        normalized = normalized.replace('\\', "/");
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in rest.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => match segments.last() {
+                Some(&last) if last != ".." => {
+                    segments.pop();
+                }
+                // Nothing is above the root
+                _ if rooted => {}
+                _ => segments.push(".."),
+            },
+            segment => segments.push(segment),
+        }
     }
     
-    // Remove duplicate slashes
-    while normalized.contains("//") {
-        normalized = normalized.replace("//", "/");
+    let mut normalized = root;
+    if rooted && !(unc.is_some() && segments.is_empty()) {
+        normalized.push('/');
     }
-    
-    // Remove trailing slash unless root
-    if normalized.len() > 1 && normalized.ends_with('/') {
-        normalized.pop();
+    normalized.push_str(&segments.join("/"));
+    if normalized.is_empty() && !path.is_empty() {
+        normalized.push('.');
     }
-    
     normalized
 }
 
+/// Resolve `relative` against the directory containing `file`, collapsing
+/// `.` and `..` segments lexically (without touching the file system)
+pub fn join_relative(file: &str, relative: &str) -> String {
+    let base = Path::new(file).parent().unwrap_or(Path::new(""));
+    normalize_path(&base.join(relative).to_string_lossy())
+}
+
+/// Whether `path` is a markdown or MDX document rather than an asset
+pub fn is_document(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "md" || ext == "mdx" || ext == "markdown")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -38,6 +111,45 @@ mod tests {
         assert_eq!(normalize_path("/"), "/");
     }
     
+    #[test]
+    fn test_join_relative() {
+        assert_eq!(join_relative("/site/docs/a.md", "img/b.png"), "/site/docs/img/b.png");
+        assert_eq!(join_relative("/site/docs/a.md", "./b.md"), "/site/docs/b.md");
+        assert_eq!(join_relative("/site/docs/a.md", "../c.md"), "/site/c.md");
+        assert_eq!(join_relative("a.md", "../b.md"), "../b.md");
+    }
+    
+    #[test]
+    fn test_normalize_dot_segments() {
+        assert_eq!(normalize_path("/site/./docs/../blog/a.md"), "/site/blog/a.md");
+        assert_eq!(normalize_path("/../a.md"), "/a.md");
+        assert_eq!(normalize_path("docs/../../a.md"), "../a.md");
+        assert_eq!(normalize_path("./docs/.."), ".");
+        assert_eq!(normalize_path(""), "");
+    }
+    
+    #[test]
+    fn test_normalize_windows_prefixes() {
+        let windows = PathStyle {
+            backslashes: true,
+            lowercase_drive: false,
+        };
+        assert_eq!(normalize_path_with("C:\\docs\\.\\a.md", windows), "C:/docs/a.md");
+        assert_eq!(normalize_path_with("\\\\?\\C:\\docs\\a.md", windows), "C:/docs/a.md");
+        assert_eq!(normalize_path_with("\\\\server\\share\\docs\\..\\a.md", windows), "//server/share/a.md");
+        assert_eq!(normalize_path_with("\\\\?\\UNC\\server\\share\\a.md", windows), "//server/share/a.md");
+        assert_eq!(normalize_path_with("\\\\server\\share\\..", windows), "//server/share");
+        assert_eq!(normalize_path_with("c:a.md", windows), "c:a.md");
+        
+        let keys = PathStyle {
+            lowercase_drive: true,
+            ..windows
+        };
+        assert_eq!(normalize_path_with("C:\\docs\\a.md", keys), normalize_path_with("c:/docs/a.md", keys));
+        // Elsewhere a backslash is part of the file name
+        assert_eq!(normalize_path_with("a\\b.md", PathStyle { backslashes: false, ..keys }), "a\\b.md");
+    }
+    
     #[cfg(target_os = "windows")]
     #[test]
     fn test_normalize_windows_path() {
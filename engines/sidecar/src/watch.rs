@@ -0,0 +1,355 @@
+//! File watching for the `watch` / `unwatch` RPCs
+//!
+//! Each watch observes a root directory and reports changes to paths
+//! matching its globs as `filesChanged` notifications. Bursts of events
+//! (editors writing through temp files, `git checkout`) are debounced and
+//! coalesced to one change per path before they reach the host. Changes
+//! wait at most [`MAX_WAIT_PERIODS`] debounce periods, so a steady stream
+//! of events (a build writing into the watched tree) still gets reported.
+//!
+//! A watch can also hand each flushed batch to an [`OnChange`] hook, which
+//! the `watch` handler uses to revalidate cached transforms in the
+//! background.
+//!
+//! Watches also observe the project [config](crate::config) file, wherever
+//! it is. An edit is applied and reported as a `configChanged`
+//! notification, with the documents to transform again when it changes
+//! their output.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError};
+use dashmap::DashMap;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::blocks;
+use crate::config;
+use crate::depgraph;
+use crate::hmr;
+use crate::outbox::Outbox;
+use crate::scan::Patterns;
+
+pub const DEFAULT_DEBOUNCE_MS: u64 = 50;
+/// Debounce periods after its first change that a batch is flushed even
+/// though events keep arriving
+const MAX_WAIT_PERIODS: u32 = 10;
+
+/// Called with the created or modified paths of each flushed batch and the
+/// existing documents depending on any changed path
+pub type OnChange = Box<dyn Fn(Vec<PathBuf>) + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileChange {
+    Create,
+    Modify,
+    Remove,
+}
+
+impl FileChange {
+    /// Net effect of `self` followed by `next`; `None` if they cancel out
+    fn then(self, next: FileChange) -> Option<FileChange> {
+        match (self, next) {
+            (FileChange::Create, FileChange::Remove) => None,
+            (FileChange::Create, _) => Some(FileChange::Create),
+            (FileChange::Remove, FileChange::Create) => Some(FileChange::Modify),
+            (_, next) => Some(next),
+        }
+    }
+}
+
+/// A running watch; dropping it stops the watcher and its debounce thread
+struct Watch {
+    connection_id: u64,
+    _watcher: RecommendedWatcher,
+}
+
+static WATCHES: OnceLock<DashMap<u64, Watch>> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn watches() -> &'static DashMap<u64, Watch> {
+    WATCHES.get_or_init(DashMap::new)
+}
+
+/// Start watching `root` for changes matching `patterns`, reported through
+/// `outbox`. Returns the watch id used to stop it again.
+pub fn watch(
+    root: &Path,
+    patterns: &[String],
+    debounce: Duration,
+    connection_id: u64,
+    outbox: Outbox,
+    on_change: Option<OnChange>,
+) -> Result<u64, String> {
+    let patterns = Patterns::new(patterns)?;
+    let root = root
+        .canonicalize()
+        .map_err(|e| format!("Cannot watch {}: {}", root.display(), e))?;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    let (sender, events) = unbounded();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })
+    .map_err(|e| e.to_string())?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| format!("Cannot watch {}: {}", root.display(), e))?;
+    if let Some(dir) = config::watched_dir().filter(|dir| !dir.starts_with(&root)) {
+        if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+            tracing::warn!("Cannot watch config directory {}: {}", dir.display(), e);
+        }
+    }
+
+    thread::spawn(move || debounce_loop(id, root, patterns, debounce, events, outbox, on_change));
+    watches().insert(id, Watch { connection_id, _watcher: watcher });
+    Ok(id)
+}
+
+/// Stop a watch owned by `connection_id`; returns whether it existed
+pub fn unwatch(id: u64, connection_id: u64) -> bool {
+    watches()
+        .remove_if(&id, |_, watch| watch.connection_id == connection_id)
+        .is_some()
+}
+
+/// Stop every watch of a connection that went away
+pub fn unwatch_connection(connection_id: u64) {
+    watches().retain(|_, watch| watch.connection_id != connection_id);
+}
+
+/// Collect events until none arrive for `debounce`, or for at most
+/// [`MAX_WAIT_PERIODS`] periods, then emit one notification. Ends when the
+/// watcher is dropped.
+fn debounce_loop(
+    id: u64,
+    root: PathBuf,
+    patterns: Patterns,
+    debounce: Duration,
+    events: Receiver<notify::Result<Event>>,
+    outbox: Outbox,
+    on_change: Option<OnChange>,
+) {
+    let mut pending = BTreeMap::new();
+    let mut config_changed = false;
+    let max_wait = debounce * MAX_WAIT_PERIODS;
+    // When the first change of the pending batch arrived
+    let mut first_change: Option<Instant> = None;
+    loop {
+        let wait = first_change.map_or(debounce, |first| debounce.min(max_wait.saturating_sub(first.elapsed())));
+        let quiet = match events.recv_timeout(wait) {
+            Ok(Ok(event)) => {
+                for (path, kind) in changes(&event) {
+                    config_changed |= config::is_config_file(&path);
+                    let matches = path
+                        .strip_prefix(&root)
+                        .is_ok_and(|relative| patterns.is_match(relative));
+                    if matches {
+                        coalesce(&mut pending, path, kind);
+                    }
+                }
+                false
+            }
+            Ok(Err(e)) => {
+                tracing::warn!("Watch {} error: {}", id, e);
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => true,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if pending.is_empty() && !config_changed {
+            first_change = None;
+            continue;
+        }
+        let first = *first_change.get_or_insert_with(Instant::now);
+        if quiet || first.elapsed() >= max_wait {
+            if std::mem::take(&mut config_changed) {
+                reload_config(id, &outbox);
+            }
+            flush(id, &mut pending, &outbox, on_change.as_ref());
+            first_change = None;
+        }
+    }
+}
+
+/// Changed paths of one event, with renames split into remove + create
+fn changes(event: &Event) -> Vec<(PathBuf, FileChange)> {
+    let kind = match event.kind {
+        EventKind::Create(_) => FileChange::Create,
+        EventKind::Remove(_) => FileChange::Remove,
+        EventKind::Modify(ModifyKind::Name(mode)) => {
+            return match (mode, event.paths.as_slice()) {
+                (RenameMode::Both, [from, to]) => vec![(from.clone(), FileChange::Remove), (to.clone(), FileChange::Create)],
+                (RenameMode::From, paths) => paths.iter().map(|p| (p.clone(), FileChange::Remove)).collect(),
+                (_, paths) => paths
+                    .iter()
+                    .map(|p| {
+                        let kind = if p.exists() { FileChange::Create } else { FileChange::Remove };
+                        (p.clone(), kind)
+                    })
+                    .collect(),
+            };
+        }
+        EventKind::Modify(ModifyKind::Metadata(_)) => return Vec::new(),
+        EventKind::Modify(_) => FileChange::Modify,
+        EventKind::Access(_) | EventKind::Any | EventKind::Other => return Vec::new(),
+    };
+    event.paths.iter().map(|p| (p.clone(), kind)).collect()
+}
+
+fn coalesce(pending: &mut BTreeMap<PathBuf, FileChange>, path: PathBuf, kind: FileChange) {
+    let merged = match pending.get(&path) {
+        Some(previous) => previous.then(kind),
+        None => Some(kind),
+    };
+    match merged {
+        Some(kind) => {
+            pending.insert(path, kind);
+        }
+        None => {
+            pending.remove(&path);
+        }
+    }
+}
+
+fn flush(id: u64, pending: &mut BTreeMap<PathBuf, FileChange>, outbox: &Outbox, on_change: Option<&OnChange>) {
+    if pending.is_empty() {
+        return;
+    }
+    let pending = std::mem::take(pending);
+    let changes: Vec<_> = pending
+        .iter()
+        .map(|(path, kind)| json!({ "path": path.to_string_lossy(), "kind": kind }))
+        .collect();
+    let paths: Vec<String> = changes
+        .iter()
+        .filter_map(|change| change["path"].as_str().map(str::to_string))
+        .collect();
+    let affected = depgraph::graph().read().affected(&paths);
+
+    if let Some(on_change) = on_change {
+        let mut existing: Vec<PathBuf> = pending
+            .into_iter()
+            .filter(|(_, kind)| *kind != FileChange::Remove)
+            .map(|(path, _)| path)
+            .collect();
+        existing.extend(affected.iter().map(PathBuf::from).filter(|path| path.is_file()));
+        existing.sort();
+        existing.dedup();
+        on_change(existing);
+    }
+
+    outbox.notify("filesChanged", json!({
+        "watchId": id,
+        "changes": changes,
+        "affected": affected,
+    }));
+}
+
+/// Apply an edited config file and tell the host what changed
+fn reload_config(id: u64, outbox: &Outbox) {
+    let reload = match config::reload() {
+        Ok(Some(reload)) => reload,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::warn!("Keeping the previous config: {}", e);
+            outbox.notify("configChanged", json!({ "watchId": id, "error": e }));
+            return;
+        }
+    };
+    let mut affected = Vec::new();
+    if reload.affects_output() {
+        // Cached output is keyed by options and stops matching by itself,
+        // but change tracking would report the next render as unchanged
+        hmr::tracker().forget(|_| true);
+        blocks::forget(|_| true);
+        affected = depgraph::graph().read().documents();
+    }
+    let mut params = serde_json::to_value(&reload).unwrap_or_default();
+    params["watchId"] = json!(id);
+    params["affected"] = json!(affected);
+    outbox.notify("configChanged", params);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coalesce() {
+        let mut pending = BTreeMap::new();
+        coalesce(&mut pending, PathBuf::from("a.md"), FileChange::Create);
+        coalesce(&mut pending, PathBuf::from("a.md"), FileChange::Modify);
+        coalesce(&mut pending, PathBuf::from("b.md"), FileChange::Remove);
+        coalesce(&mut pending, PathBuf::from("b.md"), FileChange::Create);
+        coalesce(&mut pending, PathBuf::from("c.md"), FileChange::Create);
+        coalesce(&mut pending, PathBuf::from("c.md"), FileChange::Remove);
+
+        assert_eq!(pending.get(Path::new("a.md")), Some(&FileChange::Create));
+        assert_eq!(pending.get(Path::new("b.md")), Some(&FileChange::Modify));
+        assert_eq!(pending.get(Path::new("c.md")), None);
+    }
+
+    #[test]
+    fn test_watch_reports_matching_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (outbox, outgoing) = Outbox::new(crate::codec::Codec::Json);
+        let patterns = vec!["**/*.md".to_string()];
+        let (changed, revalidated) = unbounded();
+        let on_change: OnChange = Box::new(move |paths| {
+            let _ = changed.send(paths);
+        });
+        let id = watch(dir.path(), &patterns, Duration::from_millis(20), 0, outbox, Some(on_change)).unwrap();
+
+        std::fs::write(dir.path().join("skip.txt"), "x").unwrap();
+        std::fs::write(dir.path().join("doc.md"), "# Doc").unwrap();
+
+        let body = outgoing.recv_timeout(Duration::from_secs(5)).unwrap();
+        let message: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(message["method"], "filesChanged");
+        assert_eq!(message["params"]["watchId"], id);
+        let changes = message["params"]["changes"].as_array().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0]["path"].as_str().unwrap().ends_with("doc.md"));
+        assert_eq!(changes[0]["kind"], "create");
+        let paths = revalidated.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(paths.len() == 1 && paths[0].ends_with("doc.md"));
+
+        assert!(!unwatch(id, 1));
+        assert!(unwatch(id, 0));
+    }
+
+    #[test]
+    fn test_continuous_events_are_flushed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let (outbox, outgoing) = Outbox::new(crate::codec::Codec::Json);
+        let patterns = Patterns::new(&["**/*.md".to_string()]).unwrap();
+        let (sender, events) = unbounded();
+        let debounce = Duration::from_millis(40);
+        let loop_root = root.clone();
+        thread::spawn(move || debounce_loop(0, loop_root, patterns, debounce, events, outbox, None));
+
+        // Never `debounce` of quiet until well past the maximum wait
+        let started = Instant::now();
+        let mut i = 0;
+        while started.elapsed() < debounce * MAX_WAIT_PERIODS * 3 {
+            let event = Event::new(EventKind::Create(notify::event::CreateKind::File)).add_path(root.join(format!("{}.md", i)));
+            sender.send(Ok(event)).unwrap();
+            i += 1;
+            if outgoing.try_recv().is_ok() {
+                return;
+            }
+            thread::sleep(debounce / 4);
+        }
+        panic!("No notification while events kept arriving");
+    }
+}
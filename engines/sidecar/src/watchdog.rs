@@ -0,0 +1,113 @@
+//! Orphan detection
+//!
+//! Stdin EOF covers a host that exits cleanly, but a host that is killed can
+//! leave the pipe open (e.g. inherited by a grandchild). The watchdog exits
+//! the sidecar when the parent process disappears or, optionally, when the
+//! host stops sending messages for longer than the heartbeat timeout.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Default)]
+pub struct WatchdogConfig {
+    /// Exit once this process is gone
+    pub parent_pid: Option<u32>,
+    /// Exit when no message arrives for this long
+    pub heartbeat_timeout: Option<Duration>,
+}
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+/// Milliseconds since [`STARTED`] at which the last message arrived
+static LAST_ACTIVITY_MS: AtomicU64 = AtomicU64::new(0);
+
+fn started() -> Instant {
+    *STARTED.get_or_init(Instant::now)
+}
+
+/// Record that the host is alive; called for every incoming message
+pub fn touch() {
+    LAST_ACTIVITY_MS.store(started().elapsed().as_millis() as u64, Ordering::Relaxed);
+}
+
+fn idle_for() -> Duration {
+    let last = Duration::from_millis(LAST_ACTIVITY_MS.load(Ordering::Relaxed));
+    started().elapsed().saturating_sub(last)
+}
+
+/// Parent of this process at startup, unless we were started by init
+/// (in which case there is no host to watch)
+pub fn default_parent_pid() -> Option<u32> {
+    #[cfg(unix)]
+    {
+        Some(std::os::unix::process::parent_id()).filter(|pid| *pid > 1)
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+/// Spawn the watchdog thread; `on_orphaned` runs once before the process exits
+pub fn spawn(config: WatchdogConfig, on_orphaned: fn()) -> Option<thread::JoinHandle<()>> {
+    if config.parent_pid.is_none() && config.heartbeat_timeout.is_none() {
+        return None;
+    }
+    touch();
+    
+    Some(thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+        
+        let reason = if config.parent_pid.is_some_and(|pid| !process_alive(pid)) {
+            "parent process exited"
+        } else if config.heartbeat_timeout.is_some_and(|timeout| idle_for() > timeout) {
+            "heartbeat timed out"
+        } else {
+            continue;
+        };
+        
+        warn!("Host is gone ({}), shutting down", reason);
+        on_orphaned();
+        std::process::exit(0);
+    }))
+}
+
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    // Signal 0 only checks for existence; EPERM still means the pid exists
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+pub fn process_alive(_pid: u32) -> bool {
+    // No portable check; rely on stdin EOF and the heartbeat instead
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_process_alive() {
+        assert!(process_alive(std::process::id()));
+        // Spawn and reap a child so its pid is known to be gone
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        assert!(!process_alive(pid));
+    }
+
+    #[test]
+    fn test_touch_resets_idle_time() {
+        touch();
+        assert!(idle_for() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,239 @@
+//! WebSocket server mode
+//!
+//! `--ws <port>` speaks the same JSON-RPC protocol over WebSocket frames,
+//! one message per frame. The connection is bidirectional, so notifications
+//! (progress, invalidations, logs) reach browser-based tooling as they happen.
+//!
+//! Binding to loopback doesn't keep browsers out: any page the user visits
+//! can open a socket to localhost. Browsers always send an `Origin` header,
+//! so handshakes carrying one are refused unless it is a loopback origin or
+//! was allowed with `--ws-allow-origin`. Clients outside a browser send
+//! none and are accepted.
+
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tracing::{debug, error, info, warn};
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tungstenite::http::{header, StatusCode};
+use tungstenite::{Error as WsError, Message, WebSocket};
+
+use crate::codec::Codec;
+use crate::connection::{Connection, ShutdownScope};
+use crate::outbox::Outbox;
+
+/// How long a read waits before checking for outgoing messages
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Serve WebSocket clients on localhost until the process exits, accepting
+/// browser pages from loopback origins and `allowed_origins`
+pub fn serve(port: u16, codec: Codec, allowed_origins: Vec<String>) -> anyhow::Result<()> {
+    let allowed_origins: Arc<[String]> = allowed_origins.into();
+    // Only bind loopback; the sidecar reads arbitrary paths on request
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    info!("WebSocket server listening on ws://127.0.0.1:{}", port);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let allowed_origins = allowed_origins.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve_client(stream, codec, &allowed_origins) {
+                error!("WebSocket client failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn serve_client(stream: TcpStream, codec: Codec, allowed_origins: &[String]) -> anyhow::Result<()> {
+    let mut socket = tungstenite::accept_hdr(stream, OriginCheck(allowed_origins))
+        .map_err(|e| anyhow::anyhow!("WebSocket handshake failed: {}", e))?;
+    // Reads time out so one thread can interleave reading and pushing
+    socket.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+    debug!("WebSocket client connected");
+    
+    let (outbox, outgoing) = Outbox::new(codec);
+    // Other clients share the sidecar; `shutdown` only ends this one
+    let mut connection = Connection::new(outbox, codec, ShutdownScope::Connection);
+    
+    loop {
+        while let Ok(body) = outgoing.try_recv() {
+            socket.send(frame(body, codec))?;
+        }
+        
+        let body = match socket.read() {
+            Ok(Message::Text(text)) => text.into_bytes(),
+            Ok(Message::Binary(bytes)) => bytes,
+            Ok(Message::Close(_)) => break,
+            // Pings are answered by tungstenite on the next write
+            Ok(_) => continue,
+            Err(WsError::Io(e)) if is_timeout(&e) => continue,
+            Err(WsError::ConnectionClosed | WsError::AlreadyClosed) => break,
+            Err(e) => return Err(e.into()),
+        };
+        
+        if connection.handle_body(&body).is_some() {
+            break;
+        }
+    }
+    
+    // Deliver replies to requests that were still running
+    connection.finish();
+    for body in outgoing {
+        socket.send(frame(body, codec))?;
+    }
+    let _ = socket.close(None);
+    flush_close(&mut socket);
+    debug!("WebSocket client disconnected");
+    Ok(())
+}
+
+/// Refuses handshakes from pages of origins that aren't allowed
+struct OriginCheck<'a>(&'a [String]);
+
+impl Callback for OriginCheck<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        let origin = request.headers().get(header::ORIGIN).map(|origin| origin.to_str().unwrap_or_default());
+        match origin {
+            Some(origin) if !origin_allowed(origin, self.0) => {
+                warn!("Refused WebSocket client from origin {}", origin);
+                let mut refusal = ErrorResponse::new(Some("Origin not allowed".to_string()));
+                *refusal.status_mut() = StatusCode::FORBIDDEN;
+                Err(refusal)
+            }
+            _ => Ok(response),
+        }
+    }
+}
+
+/// Whether a page from `origin` may connect: loopback pages, such as a dev
+/// server's, and the origins allowed on the command line
+fn origin_allowed(origin: &str, allowed_origins: &[String]) -> bool {
+    if allowed_origins.iter().any(|allowed| allowed == origin) {
+        return true;
+    }
+    let Some(("http" | "https", rest)) = origin.split_once("://") else {
+        return false;
+    };
+    let host = match rest.strip_prefix('[') {
+        Some(v6) => v6.split(']').next(),
+        None => rest.split([':', '/']).next(),
+    };
+    matches!(host, Some("localhost" | "127.0.0.1" | "::1"))
+}
+
+/// JSON travels as text frames, MessagePack as binary frames
+fn frame(body: Vec<u8>, codec: Codec) -> Message {
+    match codec {
+        Codec::Json => Message::Text(String::from_utf8_lossy(&body).into_owned()),
+        Codec::MessagePack => Message::Binary(body),
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Give the client a moment to acknowledge the close frame
+fn flush_close(socket: &mut WebSocket<TcpStream>) {
+    for _ in 0..10 {
+        match socket.read() {
+            Err(WsError::Io(e)) if is_timeout(&e) => continue,
+            Ok(_) => continue,
+            Err(_) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_roundtrip() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_client(stream, Codec::Json, &[]).unwrap();
+        });
+
+        let (mut client, _) = tungstenite::connect(format!("ws://127.0.0.1:{}", port)).unwrap();
+        client
+            .send(Message::Text(r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#.to_string()))
+            .unwrap();
+        let reply = client.read().unwrap().into_text().unwrap();
+        assert_eq!(reply, r#"{"jsonrpc":"2.0","id":1,"result":{"pong":true}}"#);
+        client.close(None).unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_closes_only_this_client() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_client(stream, Codec::Json, &[]).unwrap();
+        });
+
+        let (mut client, _) = tungstenite::connect(format!("ws://127.0.0.1:{}", port)).unwrap();
+        // Answered in order inside a batch, then the connection closes
+        client
+            .send(Message::Text(
+                r#"[{"jsonrpc":"2.0","id":1,"method":"shutdown"},{"jsonrpc":"2.0","id":2,"method":"ping"}]"#.to_string(),
+            ))
+            .unwrap();
+        let reply = client.read().unwrap().into_text().unwrap();
+        assert_eq!(
+            reply,
+            r#"[{"jsonrpc":"2.0","id":1,"result":null},{"jsonrpc":"2.0","id":2,"result":{"pong":true}}]"#
+        );
+        assert!(matches!(client.read(), Ok(Message::Close(_))));
+        // The process is still here to see the client off
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_foreign_origin_is_refused() {
+        use tungstenite::client::IntoClientRequest;
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let allowed = vec!["https://docs.example".to_string()];
+        thread::spawn(move || {
+            for stream in listener.incoming().take(3) {
+                let _ = serve_client(stream.unwrap(), Codec::Json, &allowed);
+            }
+        });
+        // The status a handshake from `origin` was refused with, if any
+        let refusal = |origin: &str| {
+            let mut request = format!("ws://127.0.0.1:{}", port).into_client_request().unwrap();
+            request.headers_mut().insert(header::ORIGIN, origin.parse().unwrap());
+            match tungstenite::connect(request) {
+                Ok((mut client, _)) => {
+                    client.close(None).unwrap();
+                    None
+                }
+                Err(WsError::Http(response)) => Some(response.status()),
+                Err(e) => panic!("Handshake failed: {}", e),
+            }
+        };
+
+        assert_eq!(refusal("https://evil.example"), Some(StatusCode::FORBIDDEN));
+        assert_eq!(refusal("http://localhost:5173"), None);
+        assert_eq!(refusal("https://docs.example"), None);
+
+        assert!(!origin_allowed("http://localhost.evil.example", &[]));
+        assert!(origin_allowed("http://[::1]:3000", &[]));
+        assert!(!origin_allowed("null", &[]));
+    }
+}
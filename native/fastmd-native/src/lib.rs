@@ -35,15 +35,8 @@ pub fn deps_digest(files_json: &str) -> String {
 
 #[wasm_bindgen]
 pub fn normalize_content(input: &str) -> String {
-    // Strip UTF-8 BOM represented as U+FEFF if present
-    let s = if input.chars().next() == Some('\u{feff}') {
-        input.chars().skip(1).collect::<String>()
-    } else {
-        input.to_string()
-    };
-    // Replace CRLF first, then remaining CR
-    let s = s.replace("\r\n", "\n");
-    s.replace('\r', "\n")
+    // Strip BOM, then CRLF and lone CR; shared with the sidecar
+    fastmd_text::normalize(input, &fastmd_text::NormalizeOptions::default()).content
 }
 
 #[cfg(test)]
@@ -0,0 +1,126 @@
+//! Text normalization shared by the wasm module and the sidecar
+//!
+//! Both engines must produce byte-identical content for the same input,
+//! otherwise cache keys and digests diverge depending on which engine the
+//! host happens to load.
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+const BOM: char = '\u{feff}';
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NormalizeOptions {
+    /// Strip a leading byte order mark
+    pub remove_bom: bool,
+    /// Convert `\r\n` to `\n`
+    pub normalize_crlf: bool,
+    /// Convert lone `\r` to `\n`
+    pub normalize_cr: bool,
+    /// Compose to Unicode normalization form C
+    pub nfc: bool,
+    /// Make non-empty content end with exactly one `\n`
+    pub final_newline: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        NormalizeOptions {
+            remove_bom: true,
+            normalize_crlf: true,
+            normalize_cr: true,
+            nfc: false,
+            final_newline: false,
+        }
+    }
+}
+
+/// A transformation that changed the content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Transformation {
+    Bom,
+    Crlf,
+    Cr,
+    Nfc,
+    FinalNewline,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalized {
+    pub content: String,
+    /// Transformations that changed something, in the order applied
+    pub applied: Vec<Transformation>,
+}
+
+pub fn normalize(input: &str, options: &NormalizeOptions) -> Normalized {
+    let mut content = input.to_string();
+    let mut applied = Vec::new();
+
+    if options.remove_bom {
+        if let Some(stripped) = content.strip_prefix(BOM) {
+            content = stripped.to_string();
+            applied.push(Transformation::Bom);
+        }
+    }
+    if options.normalize_crlf && content.contains("\r\n") {
+        content = content.replace("\r\n", "\n");
+        applied.push(Transformation::Crlf);
+    }
+    if options.normalize_cr && content.contains('\r') {
+        content = content.replace('\r', "\n");
+        applied.push(Transformation::Cr);
+    }
+    if options.nfc && !is_nfc(&content) {
+        content = content.nfc().collect();
+        applied.push(Transformation::Nfc);
+    }
+    if options.final_newline && !content.is_empty() {
+        let trimmed_len = content.trim_end_matches('\n').len();
+        if content.len() != trimmed_len + 1 {
+            content.truncate(trimmed_len);
+            content.push('\n');
+            applied.push(Transformation::FinalNewline);
+        }
+    }
+
+    Normalized { content, applied }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_strip_bom_and_line_endings() {
+        let input = "\u{feff}line1\r\nline2\rline3\n";
+        let result = normalize(input, &NormalizeOptions::default());
+        assert_eq!(result.content, "line1\nline2\nline3\n");
+        assert_eq!(result.applied, vec![Transformation::Bom, Transformation::Crlf, Transformation::Cr]);
+    }
+
+    #[test]
+    fn unchanged_input_reports_nothing() {
+        let result = normalize("a\nb", &NormalizeOptions::default());
+        assert_eq!(result.content, "a\nb");
+        assert!(result.applied.is_empty());
+    }
+
+    #[test]
+    fn nfc_and_final_newline() {
+        let options = NormalizeOptions {
+            nfc: true,
+            final_newline: true,
+            ..Default::default()
+        };
+        // "e" + combining acute accent
+        let result = normalize("caf\u{65}\u{301}\n\n\n", &options);
+        assert_eq!(result.content, "caf\u{e9}\n");
+        assert_eq!(result.applied, vec![Transformation::Nfc, Transformation::FinalNewline]);
+
+        let missing = normalize("x", &options);
+        assert_eq!(missing.content, "x\n");
+        assert_eq!(normalize("", &options).content, "");
+    }
+}
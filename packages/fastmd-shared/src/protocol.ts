@@ -42,7 +42,11 @@ export const RPC_ERROR_CODES = {
   // Custom error codes
   TRANSFORM_ERROR: -32001,
   CACHE_ERROR: -32002,
-  IO_ERROR: -32003
+  IO_ERROR: -32003,
+  POOL_BUSY: -32004,
+  TRANSFORM_TIMEOUT: -32005,
+  TRANSFORM_TOO_LARGE: -32006,
+  PATH_OUTSIDE_ROOT: -32007
 } as const;
 
 // Method names for sidecar operations
@@ -72,7 +76,9 @@ export interface TransformRequest {
   options?: {
     mode?: 'development' | 'production';
     sourcemap?: boolean;
-    framework?: 'astro' | 'vite';
+    framework?: 'astro' | 'vite' | 'react' | 'preact';
+    /** JSX runtime module for react/preact MDX output (default: the framework) */
+    jsxImportSource?: string;
   };
 }
 
@@ -87,23 +93,38 @@ export interface NormalizeRequest {
   content: string;
   removeBom?: boolean;
   normalizeLf?: boolean;
+  nfc?: boolean;
+  finalNewline?: boolean;
 }
 
 export interface NormalizeResponse {
   content: string;
   changed: boolean;
+  applied: Array<'bom' | 'crlf' | 'cr' | 'nfc' | 'finalNewline'>;
 }
 
 export interface ComputeDigestRequest {
-  files: Array<{
+  files?: Array<{
     path: string;
     size: number;
     mtime: number;
   }>;
+  /** Globs expanded and stat-ed by the sidecar, relative to `root` */
+  patterns?: string[];
+  root?: string;
+  respectIgnore?: boolean;
+  hidden?: boolean;
+  /** Mixed into the digest so caches invalidate when configuration changes */
+  context?: {
+    options?: Record<string, unknown>;
+    engine?: string;
+    salt?: string;
+  };
 }
 
 export interface ComputeDigestResponse {
   digest: string;
+  files: number;
 }
 
 export interface CacheGetRequest {